@@ -0,0 +1,119 @@
+local function gen(n)
+    return coroutine.wrap(function()
+        for i = 1, n do
+            coroutine.yield(i)
+        end
+    end)
+end
+for i in gen(3) do print(i) end
+
+local co = coroutine.create(function(a, b)
+    print("start", a, b)
+    local c = coroutine.yield(a + b)
+    print("got", c)
+    local d, e = coroutine.yield(c * 2)
+    print("got", d, e)
+    return d + e, "done"
+end)
+print(coroutine.status(co))
+print(coroutine.resume(co, 1, 2))
+print(coroutine.status(co))
+print(coroutine.resume(co, 10))
+print(coroutine.resume(co, 3, 4))
+print(coroutine.status(co))
+print(coroutine.resume(co))
+
+local function inner(x) coroutine.yield(x) return x + 1 end
+local function outer() local y = inner(1) y = inner(y) return y end
+local co2 = coroutine.create(outer)
+print(coroutine.resume(co2))
+print(coroutine.resume(co2))
+print(coroutine.resume(co2))
+
+local co3 = coroutine.create(function() error("oops") end)
+print(coroutine.resume(co3))
+print(coroutine.status(co3))
+print(coroutine.close(co3))
+
+print(coroutine.isyieldable())
+print(coroutine.resume(coroutine.create(function() return coroutine.isyieldable() end)))
+local main, ismain = coroutine.running()
+print(type(main), ismain)
+print(pcall(coroutine.yield, 1))
+print(coroutine.resume(coroutine.create(function() return pcall(coroutine.yield, 1) end)))
+
+local count = 0
+local co4 = coroutine.wrap(function()
+    local n = 0
+    local function inc() n = n + 1 count = count + 1 return n end
+    while true do coroutine.yield(inc) end
+end)
+local inc = co4()
+print(inc(), inc(), count)
+co4()
+print(inc(), count)
+
+local co5
+co5 = coroutine.create(function()
+    print(coroutine.status(co5))
+    local co6 = coroutine.create(function() print(coroutine.status(co5)) end)
+    coroutine.resume(co6)
+end)
+coroutine.resume(co5)
+
+local co7 = coroutine.create(function()
+    local x <close> = setmetatable({}, {__close = function(v, e) print("closing", e) end})
+    coroutine.yield(1)
+    print("not reached")
+end)
+print(coroutine.resume(co7))
+print(coroutine.close(co7))
+print(coroutine.status(co7))
+do
+    local a <close> = setmetatable({}, {__close = function() print("closed a") end})
+    local b <const> = 5
+    print("in block", b)
+end
+print(pcall(function()
+    local a <close> = setmetatable({}, {__close = function(v, e) print("closed on error", e) end})
+    error("fail")
+end))
+print(coroutine.resume(co))
+local t = {}
+t[co] = "thread key"
+print(t[co])
+local function f()
+    local x <close> = setmetatable({}, {__close = function() print("closed before g returns") end})
+    return (function() print("in g") return 1 end)()
+end
+print(f())
+local deep = coroutine.create(function() local function r() return 1 + r() end return r() end)
+print(coroutine.resume(deep))
+local t = setmetatable({}, {__index = function(t, k) return coroutine.yield(k) end})
+print(coroutine.resume(coroutine.create(function() return t.x end)))
+local co = coroutine.wrap(function()
+    for v in function(s, c) if c < 3 then coroutine.yield(c) return c + 1 end end, nil, 0 do end
+    return "end"
+end)
+print(co(), co(), co(), co())
+print(pcall(co))
+print(coroutine.resume(coroutine.running()))
+print(coroutine.wrap(print)("builtin body"))
+local co8 = coroutine.create(function(x)
+    local ok, a, b = pcall(function(y) return coroutine.yield(y + 1) * 2, "done" end, x)
+    local ok2, err = pcall(function() coroutine.yield("again") error("boom") end)
+    return ok, a, b, ok2, err
+end)
+print(coroutine.resume(co8, 1))
+print(coroutine.resume(co8, 21))
+print(coroutine.resume(co8))
+local proxy = setmetatable({}, {
+    __index = function(t, k) return coroutine.yield("get " .. k) end,
+    __newindex = function(t, k, v) rawset(t, k, coroutine.yield("set " .. k)) end
+})
+local co9 = coroutine.wrap(function() proxy.y = proxy.x return rawget(proxy, "y") end)
+print(co9(), co9(10), co9(20))
+local callable = setmetatable({}, {__call = function(self, a) return coroutine.yield(a) + 1 end})
+local co10 = coroutine.wrap(function() return callable(1), pcall(callable, 2) end)
+print(co10(), co10(10), co10(20))
+print(pcall(coroutine.yield, 1))
@@ -0,0 +1 @@
+return "from file", 42
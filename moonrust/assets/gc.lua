@@ -0,0 +1,23 @@
+local function cycles(n)
+    for i = 1, n do
+        local t = {}
+        t.self = t
+        local function f() return f, t end
+    end
+end
+
+local keep = {}
+keep.self = keep
+cycles(5000)
+collectgarbage()
+local before = collectgarbage("count")
+cycles(5000)
+collectgarbage("collect")
+print(collectgarbage("count") == before)
+print(keep.self == keep)
+
+print(collectgarbage("generational"))
+cycles(5000)
+print(collectgarbage("step"))
+print(collectgarbage("incremental", 100))
+print(collectgarbage("incremental"))
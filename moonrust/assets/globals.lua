@@ -0,0 +1,41 @@
+x = 1
+print(_G.x, _G._G == _G, _ENV == _G)
+_G.y = 2
+print(y)
+local count = 0
+for k, v in pairs(_G) do
+    count = count + 1
+end
+print(count > 10)
+local t = {10, 20, 30}
+local sum = 0
+for i, v in ipairs(t) do
+    sum = sum + i * v
+end
+print(sum)
+local keys = 0
+for k, v in next, {a = 1, b = 2} do
+    keys = keys + v
+end
+print(keys)
+local defaults = setmetatable({}, {__index = {color = "red"}})
+print(defaults.color, rawget(defaults, "color"))
+local proxy = setmetatable({}, {__newindex = function(t, k, v)
+    rawset(t, k, v * 2)
+end})
+proxy.a = 5
+print(proxy.a)
+local sandbox = {print = print, z = 7}
+do
+    local _ENV = sandbox
+    w = z * 2
+    print(w)
+end
+print(sandbox.w, rawget(_G, "w"))
+local function f()
+    local _ENV = {print = print}
+    function g() return 3 end
+    print(g())
+end
+f()
+print(rawget(_G, "g"), type(g))
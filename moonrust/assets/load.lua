@@ -0,0 +1,22 @@
+local add = load("return 1 + 2")
+print(add())
+local f, err = load("x = = 1", "=broken")
+print(f, err)
+local parts = {"return ", "10", " * 2"}
+local i = 0
+local reader = load(function()
+    i = i + 1
+    return parts[i]
+end)
+print(reader())
+local sandbox = {y = 5}
+local g = load("y = y + 1 return y", "sandbox", "t", sandbox)
+print(g())
+print(sandbox.y, y)
+local sum = load("local a, b = ... return a + b")
+print(sum(3, 4))
+print(load("return 1", "binary", "b"))
+print(dofile("assets/dofile_module.lua"))
+print(loadfile("assets/missing.lua"))
+local chunk = loadfile("assets/dofile_module.lua")
+print(chunk())
@@ -0,0 +1,7 @@
+local M = {}
+M.count = 0
+M.inc = function()
+    M.count = M.count + 1
+    return M.count
+end
+return M
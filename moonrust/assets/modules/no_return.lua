@@ -0,0 +1 @@
+loaded_flag = true
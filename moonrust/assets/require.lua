@@ -0,0 +1,14 @@
+package.path = "assets/modules/?.lua;assets/modules/?/init.lua"
+local counter = require("counter")
+print(counter.inc(), counter.inc())
+local again = require("counter")
+print(again.inc())
+print(require("shapes").name)
+print(require("no_return"), loaded_flag)
+package.preload["virtual"] = function(name, data)
+    return {name = name, data = data}
+end
+local v = require("virtual")
+print(v.name, v.data)
+print(package.searchpath("counter", package.path))
+print(package.searchpath("missing", "a/?.lua;b/?.lua"))
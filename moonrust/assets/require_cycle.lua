@@ -0,0 +1,2 @@
+package.path = "assets/modules/?.lua"
+require("cycle_a")
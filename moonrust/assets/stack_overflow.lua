@@ -0,0 +1,11 @@
+local function f(n) return 1 + f(n + 1) end
+print(pcall(f, 1))
+local t = setmetatable({}, {__index = function(t, k) return t[k] end})
+print(pcall(function() return t.x end))
+local function nest() return coroutine.wrap(nest)() end
+print(pcall(nest))
+print(pcall(error, "boom"))
+print(pcall(function(a, b) return a + b, a * b end, 3, 4))
+local deep = ""
+for i = 1, 300 do deep = deep .. "not " end
+print(load("return " .. deep .. "true"))
@@ -0,0 +1,12 @@
+setmetatable(_G, {
+    __newindex = function(t, k, v)
+        error("assignment to undeclared global '" .. k .. "'")
+    end,
+    __index = function(t, k)
+        error("undefined global '" .. k .. "'")
+    end
+})
+rawset(_G, "declared", 1)
+declared = declared + 1
+print(declared)
+print(undeclared)
@@ -0,0 +1,32 @@
+local function count(n, acc)
+    if n == 0 then
+        return acc
+    end
+    return count(n - 1, acc + 1)
+end
+print(count(1000000, 0))
+
+function state_a(n)
+    if n == 0 then
+        return "done", n
+    end
+    return state_b(n - 1)
+end
+
+function state_b(n)
+    return state_a(n)
+end
+print(state_a(1000000))
+
+local function sum(...)
+    local total = 0
+    for i, v in ipairs({...}) do
+        total = total + v
+    end
+    return total
+end
+
+local function forward(...)
+    return sum(...)
+end
+print(forward(1, 2, 3), (forward(4, 5)))
@@ -0,0 +1,48 @@
+local function count(t)
+    local n = 0
+    for k, v in pairs(t) do
+        n = n + 1
+    end
+    return n
+end
+
+local cache = setmetatable({}, {__mode = "k"})
+local key = {}
+cache[key] = "kept"
+cache[{}] = "collected"
+collectgarbage()
+print(count(cache), cache[key])
+
+local values = setmetatable({}, {__mode = "v"})
+values[1] = {}
+values[2] = key
+values.name = "strings are not collected"
+collectgarbage()
+print(values[1], values[2] == key, values.name)
+
+local ephemerons = setmetatable({}, {__mode = "k"})
+local k = {}
+ephemerons[k] = {k}
+ephemerons[key] = {key}
+k = nil
+collectgarbage()
+print(count(ephemerons), ephemerons[key][1] == key)
+
+local order = {}
+for i = 1, 3 do
+    setmetatable({}, {__gc = function() order[#order + 1] = i end})
+end
+collectgarbage()
+print(order[1], order[2], order[3])
+
+local finalized = 0
+local obj = setmetatable({name = "phoenix"}, {__gc = function(o)
+    finalized = finalized + 1
+    saved = o
+end})
+obj = nil
+collectgarbage()
+print(saved.name, finalized)
+saved = nil
+collectgarbage()
+print(finalized)
@@ -0,0 +1,10 @@
+fn main() {
+    let src = std::fs::read_to_string("/tmp/chain.lua").unwrap();
+    eprintln!("parse {}", src.len());
+    let ast = moonrust::parser::parse_chunk(&src).unwrap();
+    eprintln!("compile");
+    let proto = moonrust::compiler::compile(&ast, "x").unwrap();
+    eprintln!("drop");
+    drop(ast);
+    eprintln!("done {}", proto.code.len());
+}
@@ -1,112 +1,199 @@
-use std::fmt::Display;
+// The Display impls print the AST back as Lua source: nested blocks are
+// indented, operators get parentheses only where the priorities require them
+// and string literals are escaped, so that the output parses to the same AST.
+use std::cell::RefCell;
+use std::fmt::{Display, Write};
+use std::rc::Rc;
+
+mod json;
+mod optimize;
+
+/// Position of a node in its source: the line and column (in characters) of
+/// its first and last characters, from 1. Nodes that don't come from a source,
+/// such as the ones built by hand, have the default span on line 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Span {
+    /// Line of the first character, 0 if unknown
+    pub fn line(&self) -> usize {
+        self.start.0
+    }
+
+    /// Span from the start of this one to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
-    Assignment((Vec<Var>, Vec<Expression>, bool)), // bool flag: true if local assn, false otherwise
-    FunctionCall(FunctionCall),
-    Break,
-    DoBlock(Block),
-    While((Expression, Block)),
-    Repeat((Block, Expression)),
-    If((Expression, Block, Vec<(Expression, Block)>, Option<Block>)),
-    ForNum((String, Expression, Expression, Option<Expression>, Block)), // for i = 1+2+3, ...
-    ForGeneric((Vec<String>, Vec<Expression>, Block)),
-    FunctionDecl((String, ParList, Block)),
-    LocalFuncDecl((String, ParList, Block)),
-    Semicolon,
+    // bool flag: true if local assn, false otherwise
+    Assignment((Vec<Var>, Vec<Expression>, bool), Span),
+    FunctionCall(FunctionCall, Span),
+    Break(Span),
+    DoBlock(Block, Span),
+    While((Expression, Block), Span),
+    Repeat((Block, Expression), Span),
+    If(
+        (Expression, Block, Vec<(Expression, Block)>, Option<Block>),
+        Span,
+    ),
+    ForNum(
+        (String, Expression, Expression, Option<Expression>, Block),
+        Span,
+    ), // for i = 1+2+3, ...
+    ForGeneric((Vec<String>, Vec<Expression>, Block), Span),
+    FunctionDecl((String, Rc<FuncBody>), Span),
+    LocalFuncDecl((String, Rc<FuncBody>), Span),
+    // local x <const>, y <close> = ...
+    LocalAttribs((Vec<(String, Option<Attrib>)>, Vec<Expression>), Span),
+    Semicolon(Span),
+}
+
+/// Attribute of a local variable
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Attrib {
+    Const, // Can't be assigned to
+    Close, // Closed by its __close metamethod when it goes out of scope
+}
+
+impl Display for Attrib {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Attrib::Const => write!(f, "<const>"),
+            Attrib::Close => write!(f, "<close>"),
+        }
+    }
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Assignment(_, span)
+            | Statement::FunctionCall(_, span)
+            | Statement::Break(span)
+            | Statement::DoBlock(_, span)
+            | Statement::While(_, span)
+            | Statement::Repeat(_, span)
+            | Statement::If(_, span)
+            | Statement::ForNum(_, span)
+            | Statement::ForGeneric(_, span)
+            | Statement::FunctionDecl(_, span)
+            | Statement::LocalFuncDecl(_, span)
+            | Statement::LocalAttribs(_, span)
+            | Statement::Semicolon(span) => *span,
+        }
+    }
+
+    /// Whether the flow never goes past the statement: a break, or a block
+    /// that always leaves with a break or return
+    pub(crate) fn leaves_block(&self) -> bool {
+        let block_leaves = |block: &Block| {
+            block.return_stat.is_some() || block.statements.iter().any(Statement::leaves_block)
+        };
+        match self {
+            Statement::Break(_) => true,
+            Statement::DoBlock(block, _) => block_leaves(block),
+            Statement::If((_, then_block, elseifs, Some(else_block)), _) => {
+                block_leaves(then_block)
+                    && elseifs.iter().all(|(_, block)| block_leaves(block))
+                    && block_leaves(else_block)
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::Assignment((vars, exps, local)) => {
+            Statement::Assignment((vars, exps, local), _) => {
                 if *local {
                     write!(f, "local ")?;
                 }
-                format_list(vars, f, false)?;
-                write!(f, " = ")?;
-                format_list(exps, f, false)?;
+                format_list(vars, f)?;
+                // `local x` is parsed as `local x = nil`
+                if !(*local && exps.iter().all(|exp| matches!(exp, Expression::Nil(_)))) {
+                    write!(f, " = ")?;
+                    format_list(exps, f)?;
+                }
             }
-            Statement::FunctionCall(fncall) => fncall.fmt(f)?,
-            Statement::Break => write!(f, "break")?,
-            Statement::DoBlock(block) => {
-                writeln!(f, "do")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+            Statement::FunctionCall(fncall, _) => fncall.fmt(f)?,
+            Statement::Break(_) => write!(f, "break")?,
+            Statement::DoBlock(block, span) => {
+                write!(f, "do\n{}end", Nested(block, span.start, span.end))?
             }
-            Statement::While((exp, block)) => {
-                write!(f, "while ")?;
-                exp.fmt(f)?;
-                writeln!(f, " do")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+            Statement::While((exp, block), span) => {
+                let block = Nested(block, exp.span().end, span.end);
+                write!(f, "while {exp} do\n{block}end")?
             }
-            Statement::Repeat((block, exp)) => {
-                writeln!(f, "repeat")?;
-                block.fmt(f)?;
-                write!(f, "until ")?;
-                exp.fmt(f)?;
+            Statement::Repeat((block, exp), span) => {
+                let block = Nested(block, span.start, exp.span().start);
+                write!(f, "repeat\n{block}until {exp}")?
             }
-            Statement::If((cond, then_block, elseifs, else_block)) => {
-                write!(f, "if ")?;
-                cond.fmt(f)?;
-                writeln!(f, " then")?;
-                then_block.fmt(f)?;
-                elseifs.iter().try_for_each(|elseif| {
-                    write!(f, "elseif ")?;
-                    elseif.0.fmt(f)?;
-                    writeln!(f, " then")?;
-                    elseif.1.fmt(f)
-                })?;
-                if let Some(elseb) = else_block {
-                    writeln!(f, "else")?;
-                    elseb.fmt(f)?;
+            Statement::If((cond, then_block, elseifs, else_block), span) => {
+                // Each block ends where the next clause starts
+                writeln!(f, "if {cond} then")?;
+                let (mut block, mut from) = (then_block, cond.span().end);
+                for (cond, next) in elseifs {
+                    let nested = Nested(block, from, cond.span().start);
+                    writeln!(f, "{nested}elseif {cond} then")?;
+                    (block, from) = (next, cond.span().end);
+                }
+                let else_start = else_block.as_ref().and_then(|_| {
+                    find_keyword(|layout| &layout.elses, block_end(block, from), span.end)
+                });
+                let to = else_start.unwrap_or(span.end);
+                write!(f, "{}", Nested(block, from, to))?;
+                if let Some(else_block) = else_block {
+                    write!(f, "else\n{}", Nested(else_block, to, span.end))?;
                 }
-                write!(f, " end")?;
+                write!(f, "end")?;
             }
-            Statement::ForNum((name, exp1, exp2, maybe_exp3, block)) => {
-                write!(f, "for ")?;
-                name.fmt(f)?;
-                write!(f, " = ")?;
-                exp1.fmt(f)?;
-                write!(f, ", ")?;
-                exp2.fmt(f)?;
+            Statement::ForNum((name, exp1, exp2, maybe_exp3, block), span) => {
+                write!(f, "for {name} = {exp1}, {exp2}")?;
                 if let Some(exp3) = maybe_exp3 {
-                    write!(f, ", ")?;
-                    exp3.fmt(f)?;
+                    write!(f, ", {exp3}")?;
                 }
-                writeln!(f, " do")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+                let from = maybe_exp3.as_ref().unwrap_or(exp2).span().end;
+                write!(f, " do\n{}end", Nested(block, from, span.end))?;
             }
-            Statement::ForGeneric((names, exps, block)) => {
+            Statement::ForGeneric((names, exps, block), span) => {
                 write!(f, "for ")?;
-                format_list(names, f, false)?;
+                format_list(names, f)?;
                 write!(f, " in ")?;
-                format_list(exps, f, false)?;
-                writeln!(f, " do")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+                format_list(exps, f)?;
+                let from = exps.last().map_or(span.start, |exp| exp.span().end);
+                write!(f, " do\n{}end", Nested(block, from, span.end))?;
             }
-            Statement::FunctionDecl((name, parlist, block)) => {
-                write!(f, "function ")?;
-                name.fmt(f)?;
-                write!(f, "(")?;
-                parlist.fmt(f)?;
-                writeln!(f, ")")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+            Statement::FunctionDecl((name, body), _) => write!(f, "function {name}{body}end")?,
+            Statement::LocalFuncDecl((name, body), _) => {
+                write!(f, "local function {name}{body}end")?
             }
-            Statement::LocalFuncDecl((name, parlist, block)) => {
-                write!(f, "local function ")?;
-                name.fmt(f)?;
-                write!(f, "(")?;
-                parlist.fmt(f)?;
-                writeln!(f, ")")?;
-                block.fmt(f)?;
-                write!(f, "end")?;
+            Statement::LocalAttribs((names, exps), _) => {
+                write!(f, "local ")?;
+                for (i, (name, attrib)) in names.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    name.fmt(f)?;
+                    if let Some(attrib) = attrib {
+                        write!(f, " {attrib}")?;
+                    }
+                }
+                if !exps.iter().all(|exp| matches!(exp, Expression::Nil(_))) {
+                    write!(f, " = ")?;
+                    format_list(exps, f)?;
+                }
             }
-            Statement::Semicolon => write!(f, ";")?,
+            Statement::Semicolon(_) => write!(f, ";")?,
         }
         writeln!(f)
     }
@@ -114,54 +201,125 @@ impl Display for Statement {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Nil,
-    False,
-    True,
-    Numeral(Numeral),
-    LiteralString(String),
-    DotDotDot, // Used for a variable number of arguments in things like functions
-    FunctionDef((ParList, Block)),
-    PrefixExp(Box<PrefixExp>),
-    TableConstructor(Vec<Field>),
-    BinaryOp((Box<Expression>, BinOp, Box<Expression>)),
-    UnaryOp((UnOp, Box<Expression>)),
+    Nil(Span),
+    False(Span),
+    True(Span),
+    Numeral(Numeral, Span),
+    LiteralString(String, Span),
+    DotDotDot(Span), // Used for a variable number of arguments in things like functions
+    FunctionDef(Rc<FuncBody>, Span),
+    PrefixExp(Box<PrefixExp>, Span),
+    TableConstructor(Vec<Field>, Span),
+    BinaryOp((Box<Expression>, BinOp, Box<Expression>), Span),
+    UnaryOp((UnOp, Box<Expression>), Span),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Nil(span)
+            | Expression::False(span)
+            | Expression::True(span)
+            | Expression::Numeral(_, span)
+            | Expression::LiteralString(_, span)
+            | Expression::DotDotDot(span)
+            | Expression::FunctionDef(_, span)
+            | Expression::PrefixExp(_, span)
+            | Expression::TableConstructor(_, span)
+            | Expression::BinaryOp(_, span)
+            | Expression::UnaryOp(_, span) => *span,
+        }
+    }
+
+    // Priority of the expression as an operand, see BinOp::priority
+    fn priority(&self) -> u8 {
+        match self {
+            Expression::BinaryOp((_, op, _), _) => op.priority(),
+            Expression::UnaryOp(_, _) => UNARY_PRIORITY,
+            Expression::Numeral(Numeral::Integer(i), _) if *i < 0 => UNARY_PRIORITY,
+            Expression::Numeral(Numeral::Float(n), _) if n.is_sign_negative() => UNARY_PRIORITY,
+            _ => u8::MAX,
+        }
+    }
+}
+
+// A chain of binary operators is as long as the source makes it, too deep to
+// be dropped recursively, so its operations are taken apart in a loop
+impl Drop for Expression {
+    fn drop(&mut self) {
+        let Expression::BinaryOp((left, _, right), _) = self else {
+            return;
+        };
+        let mut pending = vec![take(left), take(right)];
+        while let Some(mut exp) = pending.pop() {
+            // The operation is dropped with leaves in place of its operands
+            if let Expression::BinaryOp((left, _, right), _) = &mut exp {
+                pending.push(take(left));
+                pending.push(take(right));
+            }
+        }
+    }
+}
+
+// Take an expression out of the tree, leaving a nil in its place
+fn take(exp: &mut Expression) -> Expression {
+    std::mem::replace(exp, Expression::Nil(Span::default()))
+}
+
+// Write an operand, in parentheses if it would bind to another operator
+fn format_operand(
+    exp: &Expression,
+    parens: bool,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    if parens {
+        write!(f, "({exp})")
+    } else {
+        exp.fmt(f)
+    }
 }
 
 impl Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expression::Nil => write!(f, "nil"),
-            Expression::False => write!(f, "false"),
-            Expression::True => write!(f, "true"),
-            Expression::Numeral(numeral) => numeral.fmt(f),
-            Expression::LiteralString(string) => {
-                write!(f, "\"")?;
-                string.fmt(f)?;
-                write!(f, "\"")
-            }
-            Expression::DotDotDot => write!(f, "..."),
-            Expression::FunctionDef((parlist, block)) => {
-                write!(f, "function")?;
-                write!(f, "(")?;
-                parlist.fmt(f)?;
-                writeln!(f, ")")?;
-                block.fmt(f)?;
-                writeln!(f, "end")
-            }
-            Expression::PrefixExp(pexp) => pexp.fmt(f),
-            Expression::TableConstructor(fields) => {
-                writeln!(f, "{{")?;
-                format_list(fields, f, true)?;
-                writeln!(f, "}}")
-            }
-            Expression::BinaryOp((exp1, binop, exp2)) => {
-                exp1.fmt(f)?;
+            Expression::Nil(_) => write!(f, "nil"),
+            Expression::False(_) => write!(f, "false"),
+            Expression::True(_) => write!(f, "true"),
+            Expression::Numeral(numeral, _) => numeral.fmt(f),
+            Expression::LiteralString(string, _) => format_string(string, f),
+            Expression::DotDotDot(_) => write!(f, "..."),
+            Expression::FunctionDef(body, _) => write!(f, "function{body}end"),
+            Expression::PrefixExp(pexp, _) => pexp.fmt(f),
+            Expression::TableConstructor(fields, _) => format_table(fields, f),
+            Expression::BinaryOp((exp1, binop, exp2), _) => {
+                let priority = binop.priority();
+                let right_assoc = matches!(binop, BinOp::Concat | BinOp::Pow);
+                let left_parens =
+                    exp1.priority() < priority || (exp1.priority() == priority && right_assoc);
+                // The exponent may be a negative numeral, as in 2 ^ -1
+                let right_parens = match (binop, &**exp2) {
+                    (BinOp::Pow, Expression::Numeral(_, _)) => false,
+                    _ => {
+                        exp2.priority() < priority || (exp2.priority() == priority && !right_assoc)
+                    }
+                };
+                format_operand(exp1, left_parens, f)?;
                 binop.fmt(f)?;
-                exp2.fmt(f)
+                format_operand(exp2, right_parens, f)
             }
-            Expression::UnaryOp((unop, exp)) => {
+            Expression::UnaryOp((unop, exp), _) => {
                 unop.fmt(f)?;
-                exp.fmt(f)
+                // Keep - - x from turning into a comment, and `not` from
+                // sticking to its operand
+                let negative = match &**exp {
+                    Expression::UnaryOp((op, _), _) => *op == UnOp::Negate,
+                    Expression::Numeral(_, _) => exp.priority() == UNARY_PRIORITY,
+                    _ => false,
+                };
+                if *unop == UnOp::LogicalNot || (*unop == UnOp::Negate && negative) {
+                    write!(f, " ")?;
+                }
+                format_operand(exp, exp.priority() < UNARY_PRIORITY, f)
             }
         }
     }
@@ -192,6 +350,34 @@ pub enum BinOp {
     LogicalOr,
 }
 
+// Priority of unary operators, between the multiplicative ones and `^`
+const UNARY_PRIORITY: u8 = 12;
+
+impl BinOp {
+    // Priority of the operator, as in the reference implementation: a higher
+    // priority binds tighter
+    fn priority(self) -> u8 {
+        match self {
+            BinOp::LogicalOr => 1,
+            BinOp::LogicalAnd => 2,
+            BinOp::LessThan
+            | BinOp::LessEq
+            | BinOp::GreaterThan
+            | BinOp::GreaterEq
+            | BinOp::Equal
+            | BinOp::NotEqual => 3,
+            BinOp::BitOr => 4,
+            BinOp::BitXor => 5,
+            BinOp::BitAnd => 6,
+            BinOp::ShiftLeft | BinOp::ShiftRight => 7,
+            BinOp::Concat => 9,
+            BinOp::Add | BinOp::Sub => 10,
+            BinOp::Mult | BinOp::Div | BinOp::IntegerDiv | BinOp::Mod => 11,
+            BinOp::Pow => 14,
+        }
+    }
+}
+
 impl Display for BinOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -220,7 +406,7 @@ impl Display for BinOp {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum UnOp {
     Negate,
     LogicalNot,
@@ -250,7 +436,12 @@ impl Display for Numeral {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Integer(i) => i.fmt(f),
-            Self::Float(fl) => fl.fmt(f),
+            // Debug keeps the fraction of integral floats (1.0, not 1)
+            Self::Float(fl) if fl.is_nan() => write!(f, "(0.0 / 0.0)"),
+            Self::Float(fl) if fl.is_infinite() => {
+                write!(f, "{}1e999", if *fl < 0.0 { "-" } else { "" })
+            }
+            Self::Float(fl) => write!(f, "{fl:?}"),
         }
     }
 }
@@ -267,7 +458,7 @@ impl Display for PrefixExp {
         match self {
             Self::Var(var) => var.fmt(f),
             Self::FunctionCall(fncall) => fncall.fmt(f),
-            Self::Exp(exp) => exp.fmt(f),
+            Self::Exp(exp) => write!(f, "({exp})"),
         }
     }
 }
@@ -307,19 +498,11 @@ impl Display for Args {
         match self {
             Self::ExpList(exps) => {
                 write!(f, "(")?;
-                format_list(exps, f, false)?;
+                format_list(exps, f)?;
                 write!(f, ")")
             }
-            Self::TableConstructor(fields) => {
-                writeln!(f, "{{")?;
-                format_list(fields, f, true)?;
-                writeln!(f, "}}")
-            }
-            Self::LiteralString(string) => {
-                write!(f, "\"")?;
-                string.fmt(f)?;
-                write!(f, "\"")
-            }
+            Self::TableConstructor(fields) => format_table(fields, f),
+            Self::LiteralString(string) => format_string(string, f),
         }
     }
 }
@@ -335,7 +518,7 @@ impl Display for ParList {
             }
             Ok(())
         } else {
-            format_list(&self.0, f, false)?;
+            format_list(&self.0, f)?;
 
             if self.1 {
                 write!(f, ", ...")
@@ -346,6 +529,32 @@ impl Display for ParList {
     }
 }
 
+// Parameters and body of a function. Shared behind an Rc so that every
+// closure created from the definition can refer to it without borrowing the AST
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuncBody {
+    pub par_list: ParList,
+    pub block: Block,
+    pub span: Span, // From `function` to `end`
+}
+
+impl FuncBody {
+    pub fn new(par_list: ParList, block: Block, span: Span) -> Rc<Self> {
+        Rc::new(FuncBody {
+            par_list,
+            block,
+            span,
+        })
+    }
+}
+
+impl Display for FuncBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "({})", self.par_list)?;
+        Nested(&self.block, self.span.start, self.span.end).fmt(f)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Field {
     Bracketed((Expression, Expression)),
@@ -406,17 +615,7 @@ pub struct Block {
 
 impl Display for Block {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.statements.iter().try_for_each(|statement| {
-            write!(f, "    ")?;
-            statement.fmt(f)
-        })?;
-
-        if let Some(ret) = &self.return_stat {
-            write!(f, "    return ")?;
-            ret.iter().try_for_each(|exp| exp.fmt(f))
-        } else {
-            Ok(())
-        }
+        Nested(self, Pos::default(), Pos::default()).fmt(f)
     }
 }
 
@@ -425,34 +624,413 @@ pub struct AST(pub Block);
 
 impl Display for AST {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0
-            .statements
+        format_block(&self.0, Pos::default(), (usize::MAX, usize::MAX), f)
+    }
+}
+
+impl AST {
+    /// Print the AST like `Display`, with the comments of its source: each one
+    /// before the statement it precedes, or at the end of the statement it is
+    /// in or whose last line it starts on.
+    pub fn format(&self, layout: Layout) -> String {
+        let previous = LAYOUT.with(|current| current.replace(layout));
+        let formatted = self.to_string();
+        LAYOUT.with(|current| current.replace(previous));
+        formatted
+    }
+
+    /// The AST with the default span on every node, to compare the code of
+    /// chunks regardless of where it is in their sources
+    pub fn without_spans(mut self) -> AST {
+        self.0.clear_spans();
+        self
+    }
+}
+
+// Setting the default span on every node of the tree, see AST::without_spans
+
+impl Block {
+    fn clear_spans(&mut self) {
+        for statement in &mut self.statements {
+            statement.clear_spans();
+        }
+        self.return_stat
+            .iter_mut()
+            .flatten()
+            .for_each(Expression::clear_spans);
+    }
+}
+
+impl Statement {
+    fn clear_spans(&mut self) {
+        match self {
+            Statement::Assignment((vars, exps, _), span) => {
+                vars.iter_mut().for_each(Var::clear_spans);
+                exps.iter_mut().for_each(Expression::clear_spans);
+                *span = Span::default();
+            }
+            Statement::FunctionCall(fncall, span) => {
+                fncall.clear_spans();
+                *span = Span::default();
+            }
+            Statement::Break(span) | Statement::Semicolon(span) => *span = Span::default(),
+            Statement::DoBlock(block, span) => {
+                block.clear_spans();
+                *span = Span::default();
+            }
+            Statement::While((exp, block), span) | Statement::Repeat((block, exp), span) => {
+                exp.clear_spans();
+                block.clear_spans();
+                *span = Span::default();
+            }
+            Statement::If((cond, then_block, elseifs, else_block), span) => {
+                cond.clear_spans();
+                then_block.clear_spans();
+                for (cond, block) in elseifs {
+                    cond.clear_spans();
+                    block.clear_spans();
+                }
+                else_block.iter_mut().for_each(Block::clear_spans);
+                *span = Span::default();
+            }
+            Statement::ForNum((_, exp1, exp2, exp3, block), span) => {
+                exp1.clear_spans();
+                exp2.clear_spans();
+                exp3.iter_mut().for_each(Expression::clear_spans);
+                block.clear_spans();
+                *span = Span::default();
+            }
+            Statement::ForGeneric((_, exps, block), span) => {
+                exps.iter_mut().for_each(Expression::clear_spans);
+                block.clear_spans();
+                *span = Span::default();
+            }
+            Statement::FunctionDecl((_, body), span)
+            | Statement::LocalFuncDecl((_, body), span) => {
+                Rc::make_mut(body).clear_spans();
+                *span = Span::default();
+            }
+            Statement::LocalAttribs((_, exps), span) => {
+                exps.iter_mut().for_each(Expression::clear_spans);
+                *span = Span::default();
+            }
+        }
+    }
+}
+
+impl Expression {
+    fn clear_spans(&mut self) {
+        match self {
+            Expression::Nil(span)
+            | Expression::False(span)
+            | Expression::True(span)
+            | Expression::Numeral(_, span)
+            | Expression::LiteralString(_, span)
+            | Expression::DotDotDot(span) => *span = Span::default(),
+            Expression::FunctionDef(body, span) => {
+                Rc::make_mut(body).clear_spans();
+                *span = Span::default();
+            }
+            Expression::PrefixExp(pexp, span) => {
+                pexp.clear_spans();
+                *span = Span::default();
+            }
+            Expression::TableConstructor(fields, span) => {
+                fields.iter_mut().for_each(Field::clear_spans);
+                *span = Span::default();
+            }
+            Expression::BinaryOp((exp1, _, exp2), span) => {
+                exp1.clear_spans();
+                exp2.clear_spans();
+                *span = Span::default();
+            }
+            Expression::UnaryOp((_, exp), span) => {
+                exp.clear_spans();
+                *span = Span::default();
+            }
+        }
+    }
+}
+
+impl FuncBody {
+    fn clear_spans(&mut self) {
+        self.block.clear_spans();
+        self.span = Span::default();
+    }
+}
+
+impl PrefixExp {
+    fn clear_spans(&mut self) {
+        match self {
+            PrefixExp::Var(var) => var.clear_spans(),
+            PrefixExp::FunctionCall(fncall) => fncall.clear_spans(),
+            PrefixExp::Exp(exp) => exp.clear_spans(),
+        }
+    }
+}
+
+impl FunctionCall {
+    fn clear_spans(&mut self) {
+        let (FunctionCall::Standard((pexp, args)) | FunctionCall::Method((pexp, _, args))) = self;
+        pexp.clear_spans();
+        args.clear_spans();
+    }
+}
+
+impl Args {
+    fn clear_spans(&mut self) {
+        match self {
+            Args::ExpList(exps) => exps.iter_mut().for_each(Expression::clear_spans),
+            Args::TableConstructor(fields) => fields.iter_mut().for_each(Field::clear_spans),
+            Args::LiteralString(_) => (),
+        }
+    }
+}
+
+impl Field {
+    fn clear_spans(&mut self) {
+        match self {
+            Field::Bracketed((key, val)) => {
+                key.clear_spans();
+                val.clear_spans();
+            }
+            Field::Name((_, val)) | Field::Unnamed(val) => val.clear_spans(),
+        }
+    }
+}
+
+impl Var {
+    fn clear_spans(&mut self) {
+        match self {
+            Var::Name(_) => (),
+            Var::Bracket((pexp, exp)) => {
+                pexp.clear_spans();
+                exp.clear_spans();
+            }
+            Var::Dot((pexp, _)) => pexp.clear_spans(),
+        }
+    }
+}
+
+/// What the formatter needs of a source besides its AST: its comments, and the
+/// positions of its `else` and `return` keywords, which have no node of their
+/// own, to know which block the comments around them are in. Positions are
+/// the line and column of the first character, in source order.
+#[derive(Debug, Default)]
+pub struct Layout {
+    pub comments: Vec<(Pos, String)>,
+    pub elses: Vec<Pos>,
+    pub returns: Vec<Pos>,
+}
+
+/// Line and column of a character of the source, from 1
+pub type Pos = (usize, usize);
+
+thread_local! {
+    // Layout of the AST printed by `AST::format`, whose comments are removed
+    // as they are printed. Plain `Display` has an empty one.
+    static LAYOUT: RefCell<Layout> = RefCell::new(Layout::default());
+}
+
+// Remove the comments that start between `from` and `to`, to print them
+fn take_comments(from: Pos, to: Pos) -> Vec<(Pos, String)> {
+    LAYOUT.with(|layout| {
+        let comments = &mut layout.borrow_mut().comments;
+        let first = comments.partition_point(|(pos, _)| *pos < from);
+        let end = comments.partition_point(|(pos, _)| *pos < to).max(first);
+        comments.drain(first..end).collect()
+    })
+}
+
+// Position of the first keyword of a kind between `from` and `to`
+fn find_keyword(keywords: fn(&Layout) -> &Vec<Pos>, from: Pos, to: Pos) -> Option<Pos> {
+    LAYOUT.with(|layout| {
+        let layout = layout.borrow();
+        keywords(&layout)
             .iter()
-            .try_for_each(|statement| statement.fmt(f))?;
+            .copied()
+            .find(|pos| (from..to).contains(pos))
+    })
+}
+
+// End of the last node of a block, or `from` if it is empty
+fn block_end(block: &Block, from: Pos) -> Pos {
+    let last = match &block.return_stat {
+        Some(exps) => exps.last().map(Expression::span),
+        None => block.statements.last().map(Statement::span),
+    };
+    last.map_or(from, |span| span.end)
+}
 
-        if let Some(ret) = &self.0.return_stat {
-            ret.iter().try_for_each(|exp| exp.fmt(f))?;
+// A comment starting at `pos` as printed: without trailing blanks, and with the
+// lines after the first moved left by the indentation it had, since the
+// indentation of the block is added to them
+fn comment_text((pos, comment): &(Pos, String)) -> String {
+    let mut lines = comment.trim_end().lines();
+    let mut text = lines.next().unwrap_or_default().to_string();
+    let rest: Vec<&str> = lines.map(str::trim_end).collect();
+    let indent = |line: &str| line.chars().take_while(|c| c.is_whitespace()).count();
+    let shift = rest
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| indent(line))
+        .fold(pos.1 - 1, usize::min);
+    for line in rest {
+        text.push('\n');
+        text.extend(line.chars().skip(shift));
+    }
+    text
+}
+
+// Write the statements of a block with the comments of the layout that start
+// between `from` and `to`
+fn format_block(block: &Block, from: Pos, to: Pos, f: &mut impl Write) -> std::fmt::Result {
+    // Each statement is printed once, since printing takes the comments in it
+    let texts: Vec<String> = block.statements.iter().map(Statement::to_string).collect();
+    let mut statements = block.statements.iter().zip(texts).peekable();
+    let mut nodes: Vec<(String, Span)> = vec![];
+    let mut semicolon = None; // Start of a semicolon written before the next statement
+    while let Some((statement, text)) = statements.next() {
+        let span = statement.span();
+        if let Some(start) = semicolon.take() {
+            nodes.push((format!(";{text}"), Span { start, ..span }));
+            continue;
+        }
+        if !matches!(statement, Statement::Semicolon(_)) {
+            nodes.push((text, span));
+            continue;
+        }
+        // A semicolon starts the statement after it when that one would
+        // otherwise continue the previous one, as `(f)()` does, and ends the
+        // previous statement otherwise
+        let ambiguous = statements
+            .peek()
+            .is_some_and(|(_, next)| next.starts_with('('));
+        match nodes.last_mut() {
+            _ if ambiguous => semicolon = Some(span.start),
+            Some((previous, previous_span)) => {
+                previous.truncate(previous.trim_end_matches('\n').len());
+                previous.push_str(";\n");
+                previous_span.end = span.end;
+            }
+            None => nodes.push((text, span)),
+        }
+    }
+    if let Some(exps) = &block.return_stat {
+        let mut text = String::from("return");
+        if !exps.is_empty() {
+            text.push(' ');
+            format_list(exps, &mut text)?;
+        }
+        let after = nodes.last().map_or(from, |(_, span)| span.end);
+        let start = find_keyword(|layout| &layout.returns, after, to)
+            .or_else(|| exps.first().map(|exp| exp.span().start))
+            .unwrap_or_default();
+        let end = exps.last().map_or(start, |exp| exp.span().end);
+        nodes.push((text, Span { start, end }));
+    }
+    for (i, (text, span)) in nodes.iter().enumerate() {
+        for comment in take_comments(from, span.start) {
+            writeln!(f, "{}", comment_text(&comment))?;
         }
+        f.write_str(text.strip_suffix('\n').unwrap_or(text))?;
+        // The comments inside the node, and the ones after it on its last line
+        let next = nodes.get(i + 1).map_or(to, |(_, span)| span.start);
+        let trailing = take_comments(span.start, next.min((span.end.0 + 1, 0)));
+        for (i, comment) in trailing.iter().enumerate() {
+            let separator = if i == 0 { ' ' } else { '\n' };
+            write!(f, "{separator}{}", comment_text(comment))?;
+        }
+        writeln!(f)?;
+    }
+    for comment in take_comments(from, to) {
+        writeln!(f, "{}", comment_text(&comment))?;
+    }
+    Ok(())
+}
 
-        writeln!(f)
+// A nested block, indented, with the comments that start between two positions
+struct Nested<'a>(&'a Block, Pos, Pos);
+
+impl Display for Nested<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Nested(block, from, to) = *self;
+        format_block(
+            block,
+            from,
+            to,
+            &mut Indented {
+                f,
+                line_start: true,
+            },
+        )
     }
 }
 
-fn format_list(
-    elements: &[impl Display],
-    f: &mut std::fmt::Formatter<'_>,
-    lines: bool,
-) -> std::fmt::Result {
+// Writes every line one level deeper
+struct Indented<'a, 'b> {
+    f: &'a mut std::fmt::Formatter<'b>,
+    line_start: bool,
+}
+
+impl Write for Indented<'_, '_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start && line != "\n" {
+                self.f.write_str("    ")?;
+            }
+            self.f.write_str(line)?;
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+fn format_list(elements: &[impl Display], f: &mut impl Write) -> std::fmt::Result {
     let mut el_iter = elements.iter().peekable();
     while let Some(element) = el_iter.next() {
-        element.fmt(f)?;
+        write!(f, "{element}")?;
         if el_iter.peek().is_some() {
-            if lines {
-                writeln!(f, ", ")?;
-            } else {
-                write!(f, ", ")?;
-            }
+            write!(f, ", ")?;
         }
     }
     Ok(())
 }
+
+// A table constructor, on one line unless a field spans several lines, as a
+// function does: then every field goes on its own line, one level deeper
+fn format_table(fields: &[Field], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let fields: Vec<String> = fields.iter().map(Field::to_string).collect();
+    if !fields.iter().any(|field| field.contains('\n')) {
+        write!(f, "{{")?;
+        format_list(&fields, f)?;
+        return write!(f, "}}");
+    }
+    writeln!(f, "{{")?;
+    let mut indented = Indented {
+        f: &mut *f,
+        line_start: true,
+    };
+    for (i, field) in fields.iter().enumerate() {
+        let separator = if i + 1 < fields.len() { "," } else { "" };
+        writeln!(indented, "{field}{separator}")?;
+    }
+    write!(f, "}}")
+}
+
+// A string literal, with the escape sequences the parser understands
+fn format_string(string: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in string.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{{{:X}}}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
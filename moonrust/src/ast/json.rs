@@ -0,0 +1,761 @@
+//! JSON form of the AST, for tools that read or generate MoonRust programs.
+//!
+//! Every node is an object whose `kind` field names it. The chunk is
+//! `{"kind": "Chunk", "version": 1, "block": Block}`, and the other nodes are:
+//!
+//! - `Block`: `statements` (list of statements) and `return` (list of
+//!   expressions, or null without a return statement)
+//! - Statements:
+//!   - `Assign`: `local` (bool), `targets` (list of `Name`, `Index` or `Dot`)
+//!     and `values` (list of expressions)
+//!   - `LocalAttribs`: `names` (list of `{"name": string, "attrib": "const",
+//!     "close" or null}`) and `values`
+//!   - `Call` and `MethodCall` nodes, as for expressions
+//!   - `Break` and `Semicolon`
+//!   - `Do`: `block`
+//!   - `While`: `condition` and `block`; `Repeat`: `block` and `condition`
+//!   - `If`: `condition`, `then` (block), `elseifs` (list of `{"condition",
+//!     "block"}`) and `else` (block or null)
+//!   - `NumericFor`: `name`, `start`, `limit`, `step` (expression or null)
+//!     and `block`
+//!   - `GenericFor`: `names` (list of strings), `values` and `block`
+//!   - `FunctionDecl` and `LocalFunction`: `name` and `function` (a `Function`)
+//! - Expressions:
+//!   - `Nil`, `True`, `False` and `Vararg`
+//!   - `Integer` and `Float`: `value`, a number (or "inf", "-inf" or "nan" for
+//!     floats JSON can't represent)
+//!   - `String`: `value`
+//!   - `Function`: `params` (list of strings), `vararg` (bool) and `block`
+//!   - `Table`: `fields`, a list of `{"kind": "Positional", "value"}`,
+//!     `{"kind": "Named", "name", "value"}` or `{"kind": "Indexed", "key",
+//!     "value"}`
+//!   - `Binary`: `operator` (the Lua operator, e.g. "+" or "and"), `left` and
+//!     `right`; `Unary`: `operator` ("-", "not", "#" or "~") and `operand`
+//!   - Prefix expressions: `Name`: `name`; `Index`: `object` and `key`;
+//!     `Dot`: `object` and `name`; `Paren` (an expression in parentheses):
+//!     `expression`; `Call`: `function` and `args`; `MethodCall`: `object`,
+//!     `method` and `args`. `object` and `function` are prefix expressions,
+//!     and `args` is a list of expressions, a `Table` or a `String`.
+//!
+//! Statements, expressions and `Function` nodes have a `span` field, the
+//! position of the node in the source: `{"start": [line, column], "end":
+//! [line, column]}`, with the line and column of its first and last
+//! characters, from 1. It is null, or may be left out, for nodes without a
+//! position.
+use super::*;
+use crate::parser::ASTParseError;
+use serde_json::{json, Map, Value};
+
+/// Version of the schema, in the `version` field of the chunk
+pub const SCHEMA_VERSION: i64 = 1;
+
+const BINOPS: [BinOp; 21] = [
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mult,
+    BinOp::Div,
+    BinOp::IntegerDiv,
+    BinOp::Pow,
+    BinOp::Mod,
+    BinOp::BitAnd,
+    BinOp::BitXor,
+    BinOp::BitOr,
+    BinOp::ShiftRight,
+    BinOp::ShiftLeft,
+    BinOp::Concat,
+    BinOp::LessThan,
+    BinOp::LessEq,
+    BinOp::GreaterThan,
+    BinOp::GreaterEq,
+    BinOp::Equal,
+    BinOp::NotEqual,
+    BinOp::LogicalAnd,
+    BinOp::LogicalOr,
+];
+
+const UNOPS: [UnOp; 4] = [UnOp::Negate, UnOp::LogicalNot, UnOp::Length, UnOp::BitNot];
+
+impl AST {
+    /// Serialize the AST to JSON, see the schema above
+    pub fn to_json(&self) -> String {
+        let chunk = json!({
+            "kind": "Chunk",
+            "version": SCHEMA_VERSION,
+            "block": block(&self.0),
+        });
+        chunk.to_string()
+    }
+
+    /// Read an AST serialized by `to_json`, or generated by another tool
+    pub fn from_json(json: &str) -> Result<AST, ASTParseError> {
+        let chunk: Value = serde_json::from_str(json).map_err(|err| error(&err.to_string()))?;
+        let chunk = Node::new(&chunk)?;
+        chunk.expect("Chunk")?;
+        if chunk.get("version")?.as_i64() != Some(SCHEMA_VERSION) {
+            return Err(error(&format!(
+                "unsupported version, expected {SCHEMA_VERSION}"
+            )));
+        }
+        Ok(AST(read_block(chunk.get("block")?)?))
+    }
+}
+
+fn block(block: &Block) -> Value {
+    json!({
+        "kind": "Block",
+        "statements": block.statements.iter().map(statement).collect::<Vec<_>>(),
+        "return": block.return_stat.as_deref().map(exps),
+    })
+}
+
+fn exps(exps: &[Expression]) -> Value {
+    exps.iter().map(exp).collect()
+}
+
+fn statement(statement: &Statement) -> Value {
+    let mut value = match statement {
+        Statement::Assignment((vars, values, local), _) => json!({
+            "kind": "Assign",
+            "local": local,
+            "targets": vars.iter().map(var).collect::<Vec<_>>(),
+            "values": exps(values),
+        }),
+        Statement::FunctionCall(fncall, _) => call(fncall),
+        Statement::Break(_) => json!({"kind": "Break"}),
+        Statement::DoBlock(body, _) => json!({"kind": "Do", "block": block(body)}),
+        Statement::While((cond, body), _) => json!({
+            "kind": "While",
+            "condition": exp(cond),
+            "block": block(body),
+        }),
+        Statement::Repeat((body, cond), _) => json!({
+            "kind": "Repeat",
+            "block": block(body),
+            "condition": exp(cond),
+        }),
+        Statement::If((cond, then_block, elseifs, else_block), _) => json!({
+            "kind": "If",
+            "condition": exp(cond),
+            "then": block(then_block),
+            "elseifs": elseifs
+                .iter()
+                .map(|(cond, body)| json!({"condition": exp(cond), "block": block(body)}))
+                .collect::<Vec<_>>(),
+            "else": else_block.as_ref().map(block),
+        }),
+        Statement::ForNum((name, start, limit, step, body), _) => json!({
+            "kind": "NumericFor",
+            "name": name,
+            "start": exp(start),
+            "limit": exp(limit),
+            "step": step.as_ref().map(exp),
+            "block": block(body),
+        }),
+        Statement::ForGeneric((names, values, body), _) => json!({
+            "kind": "GenericFor",
+            "names": names,
+            "values": exps(values),
+            "block": block(body),
+        }),
+        Statement::FunctionDecl((name, body), _) => json!({
+            "kind": "FunctionDecl",
+            "name": name,
+            "function": function(body),
+        }),
+        Statement::LocalFuncDecl((name, body), _) => json!({
+            "kind": "LocalFunction",
+            "name": name,
+            "function": function(body),
+        }),
+        Statement::LocalAttribs((names, values), _) => json!({
+            "kind": "LocalAttribs",
+            "names": names
+                .iter()
+                .map(|(name, attrib)| json!({
+                    "name": name,
+                    "attrib": attrib.map(|attrib| match attrib {
+                        Attrib::Const => "const",
+                        Attrib::Close => "close",
+                    }),
+                }))
+                .collect::<Vec<_>>(),
+            "values": exps(values),
+        }),
+        Statement::Semicolon(_) => json!({"kind": "Semicolon"}),
+    };
+    value["span"] = span(statement.span());
+    value
+}
+
+fn exp(exp: &Expression) -> Value {
+    let mut value = match exp {
+        Expression::Nil(_) => json!({"kind": "Nil"}),
+        Expression::False(_) => json!({"kind": "False"}),
+        Expression::True(_) => json!({"kind": "True"}),
+        Expression::Numeral(Numeral::Integer(i), _) => json!({"kind": "Integer", "value": i}),
+        Expression::Numeral(Numeral::Float(n), _) => {
+            let value = match n {
+                n if n.is_nan() => json!("nan"),
+                n if n.is_infinite() => json!(if *n > 0.0 { "inf" } else { "-inf" }),
+                n => json!(n),
+            };
+            json!({"kind": "Float", "value": value})
+        }
+        Expression::LiteralString(s, _) => json!({"kind": "String", "value": s}),
+        Expression::DotDotDot(_) => json!({"kind": "Vararg"}),
+        Expression::FunctionDef(body, _) => function(body),
+        Expression::PrefixExp(pexp, _) => prefix(pexp),
+        Expression::TableConstructor(fields, _) => table(fields),
+        Expression::BinaryOp((left, op, right), _) => json!({
+            "kind": "Binary",
+            "operator": op.to_string().trim(),
+            "left": self::exp(left),
+            "right": self::exp(right),
+        }),
+        Expression::UnaryOp((op, operand), _) => json!({
+            "kind": "Unary",
+            "operator": op.to_string(),
+            "operand": self::exp(operand),
+        }),
+    };
+    value["span"] = span(exp.span());
+    value
+}
+
+fn function(body: &FuncBody) -> Value {
+    json!({
+        "kind": "Function",
+        "params": body.par_list.0,
+        "vararg": body.par_list.1,
+        "block": block(&body.block),
+        "span": span(body.span),
+    })
+}
+
+// Position of a node, null if it has none
+fn span(span: Span) -> Value {
+    if span.line() == 0 {
+        Value::Null
+    } else {
+        json!({"start": span.start, "end": span.end})
+    }
+}
+
+fn table(fields: &[Field]) -> Value {
+    let fields: Vec<Value> = fields
+        .iter()
+        .map(|field| match field {
+            Field::Unnamed(value) => json!({"kind": "Positional", "value": exp(value)}),
+            Field::Name((name, value)) => {
+                json!({"kind": "Named", "name": name, "value": exp(value)})
+            }
+            Field::Bracketed((key, value)) => {
+                json!({"kind": "Indexed", "key": exp(key), "value": exp(value)})
+            }
+        })
+        .collect();
+    json!({"kind": "Table", "fields": fields})
+}
+
+fn prefix(pexp: &PrefixExp) -> Value {
+    match pexp {
+        PrefixExp::Var(v) => var(v),
+        PrefixExp::FunctionCall(fncall) => call(fncall),
+        PrefixExp::Exp(e) => json!({"kind": "Paren", "expression": exp(e)}),
+    }
+}
+
+fn var(var: &Var) -> Value {
+    match var {
+        Var::Name(name) => json!({"kind": "Name", "name": name}),
+        Var::Bracket((object, key)) => json!({
+            "kind": "Index",
+            "object": prefix(object),
+            "key": exp(key),
+        }),
+        Var::Dot((object, name)) => json!({
+            "kind": "Dot",
+            "object": prefix(object),
+            "name": name,
+        }),
+    }
+}
+
+fn call(fncall: &FunctionCall) -> Value {
+    match fncall {
+        FunctionCall::Standard((function, call_args)) => json!({
+            "kind": "Call",
+            "function": prefix(function),
+            "args": args(call_args),
+        }),
+        FunctionCall::Method((object, method, call_args)) => json!({
+            "kind": "MethodCall",
+            "object": prefix(object),
+            "method": method,
+            "args": args(call_args),
+        }),
+    }
+}
+
+fn args(args: &Args) -> Value {
+    match args {
+        Args::ExpList(values) => exps(values),
+        Args::TableConstructor(fields) => table(fields),
+        Args::LiteralString(s) => json!({"kind": "String", "value": s}),
+    }
+}
+
+fn error(msg: &str) -> ASTParseError {
+    ASTParseError::new(&format!("Could not read JSON AST: {msg}"))
+}
+
+// An object of the JSON form, with its kind
+struct Node<'a> {
+    kind: &'a str,
+    fields: &'a Map<String, Value>,
+}
+
+impl<'a> Node<'a> {
+    fn new(value: &'a Value) -> Result<Self, ASTParseError> {
+        let fields = value
+            .as_object()
+            .ok_or_else(|| error(&format!("expected a node, got {value}")))?;
+        let kind = fields
+            .get("kind")
+            .and_then(Value::as_str)
+            .ok_or_else(|| error("node without a kind"))?;
+        Ok(Node { kind, fields })
+    }
+
+    fn expect(&self, kind: &str) -> Result<(), ASTParseError> {
+        if self.kind == kind {
+            Ok(())
+        } else {
+            Err(error(&format!("expected a {kind} node, got {}", self.kind)))
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<&'a Value, ASTParseError> {
+        self.fields
+            .get(name)
+            .ok_or_else(|| error(&format!("missing field '{name}' in {} node", self.kind)))
+    }
+
+    fn string(&self, name: &str) -> Result<String, ASTParseError> {
+        self.get(name)?
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| self.invalid(name, "a string"))
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, ASTParseError> {
+        self.get(name)?
+            .as_bool()
+            .ok_or_else(|| self.invalid(name, "a boolean"))
+    }
+
+    fn list(&self, name: &str) -> Result<&'a Vec<Value>, ASTParseError> {
+        self.get(name)?
+            .as_array()
+            .ok_or_else(|| self.invalid(name, "a list"))
+    }
+
+    // Span of the node, the default one if it has none
+    fn span(&self) -> Result<Span, ASTParseError> {
+        let Some(span) = self.opt("span") else {
+            return Ok(Span::default());
+        };
+        let position = |name| match span.get(name)?.as_array()?.as_slice() {
+            [line, col] => Some((line.as_u64()? as usize, col.as_u64()? as usize)),
+            _ => None,
+        };
+        match (position("start"), position("end")) {
+            (Some(start), Some(end)) => Ok(Span { start, end }),
+            _ => Err(self.invalid("span", "null or a span")),
+        }
+    }
+
+    // A field that is null when absent
+    fn opt(&self, name: &str) -> Option<&'a Value> {
+        self.fields.get(name).filter(|value| !value.is_null())
+    }
+
+    fn exp(&self, name: &str) -> Result<Expression, ASTParseError> {
+        read_exp(self.get(name)?)
+    }
+
+    fn exps(&self, name: &str) -> Result<Vec<Expression>, ASTParseError> {
+        self.list(name)?.iter().map(read_exp).collect()
+    }
+
+    fn block(&self, name: &str) -> Result<Block, ASTParseError> {
+        read_block(self.get(name)?)
+    }
+
+    fn prefix(&self, name: &str) -> Result<Box<PrefixExp>, ASTParseError> {
+        read_prefix(self.get(name)?).map(Box::new)
+    }
+
+    fn invalid(&self, name: &str, expected: &str) -> ASTParseError {
+        error(&format!(
+            "field '{name}' of {} node must be {expected}",
+            self.kind
+        ))
+    }
+}
+
+fn read_block(value: &Value) -> Result<Block, ASTParseError> {
+    let node = Node::new(value)?;
+    node.expect("Block")?;
+    Ok(Block {
+        statements: node
+            .list("statements")?
+            .iter()
+            .map(read_statement)
+            .collect::<Result<_, _>>()?,
+        return_stat: match node.opt("return") {
+            Some(_) => Some(node.exps("return")?),
+            None => None,
+        },
+    })
+}
+
+fn read_statement(value: &Value) -> Result<Statement, ASTParseError> {
+    let node = Node::new(value)?;
+    let span = node.span()?;
+    let statement = match node.kind {
+        "Assign" => Statement::Assignment(
+            (
+                node.list("targets")?
+                    .iter()
+                    .map(read_var)
+                    .collect::<Result<_, _>>()?,
+                node.exps("values")?,
+                node.bool("local")?,
+            ),
+            span,
+        ),
+        "LocalAttribs" => Statement::LocalAttribs(
+            (
+                node.list("names")?
+                    .iter()
+                    .map(|name| {
+                        let name = Node {
+                            kind: "LocalAttribs name",
+                            fields: name
+                                .as_object()
+                                .ok_or_else(|| error("invalid local name"))?,
+                        };
+                        let attrib = match name.opt("attrib").and_then(Value::as_str) {
+                            Some("const") => Some(Attrib::Const),
+                            Some("close") => Some(Attrib::Close),
+                            Some(attrib) => {
+                                return Err(error(&format!("unknown attribute '{attrib}'")))
+                            }
+                            None => None,
+                        };
+                        Ok((name.string("name")?, attrib))
+                    })
+                    .collect::<Result<_, _>>()?,
+                node.exps("values")?,
+            ),
+            span,
+        ),
+        "Call" | "MethodCall" => Statement::FunctionCall(read_call(&node)?, span),
+        "Break" => Statement::Break(span),
+        "Semicolon" => Statement::Semicolon(span),
+        "Do" => Statement::DoBlock(node.block("block")?, span),
+        "While" => Statement::While((node.exp("condition")?, node.block("block")?), span),
+        "Repeat" => Statement::Repeat((node.block("block")?, node.exp("condition")?), span),
+        "If" => Statement::If(
+            (
+                node.exp("condition")?,
+                node.block("then")?,
+                node.list("elseifs")?
+                    .iter()
+                    .map(|elseif| {
+                        let elseif = Node {
+                            kind: "If elseif",
+                            fields: elseif.as_object().ok_or_else(|| error("invalid elseif"))?,
+                        };
+                        Ok((elseif.exp("condition")?, elseif.block("block")?))
+                    })
+                    .collect::<Result<_, _>>()?,
+                match node.opt("else") {
+                    Some(block) => Some(read_block(block)?),
+                    None => None,
+                },
+            ),
+            span,
+        ),
+        "NumericFor" => Statement::ForNum(
+            (
+                node.string("name")?,
+                node.exp("start")?,
+                node.exp("limit")?,
+                match node.opt("step") {
+                    Some(step) => Some(read_exp(step)?),
+                    None => None,
+                },
+                node.block("block")?,
+            ),
+            span,
+        ),
+        "GenericFor" => Statement::ForGeneric(
+            (
+                node.list("names")?
+                    .iter()
+                    .map(|name| {
+                        name.as_str()
+                            .map(String::from)
+                            .ok_or_else(|| node.invalid("names", "a list of strings"))
+                    })
+                    .collect::<Result<_, _>>()?,
+                node.exps("values")?,
+                node.block("block")?,
+            ),
+            span,
+        ),
+        "FunctionDecl" => Statement::FunctionDecl(
+            (node.string("name")?, read_function(node.get("function")?)?),
+            span,
+        ),
+        "LocalFunction" => Statement::LocalFuncDecl(
+            (node.string("name")?, read_function(node.get("function")?)?),
+            span,
+        ),
+        kind => return Err(error(&format!("unknown statement kind '{kind}'"))),
+    };
+    Ok(statement)
+}
+
+fn read_exp(value: &Value) -> Result<Expression, ASTParseError> {
+    let node = Node::new(value)?;
+    let span = node.span()?;
+    let exp = match node.kind {
+        "Nil" => Expression::Nil(span),
+        "True" => Expression::True(span),
+        "False" => Expression::False(span),
+        "Vararg" => Expression::DotDotDot(span),
+        "Integer" => Expression::Numeral(
+            Numeral::Integer(
+                node.get("value")?
+                    .as_i64()
+                    .ok_or_else(|| node.invalid("value", "an integer"))?,
+            ),
+            span,
+        ),
+        "Float" => {
+            let value = node.get("value")?;
+            let n = match value.as_str() {
+                Some("inf") => f64::INFINITY,
+                Some("-inf") => f64::NEG_INFINITY,
+                Some("nan") => f64::NAN,
+                _ => value
+                    .as_f64()
+                    .ok_or_else(|| node.invalid("value", "a number"))?,
+            };
+            Expression::Numeral(Numeral::Float(n), span)
+        }
+        "String" => Expression::LiteralString(node.string("value")?, span),
+        "Function" => Expression::FunctionDef(read_function(value)?, span),
+        "Table" => Expression::TableConstructor(read_fields(&node)?, span),
+        "Binary" => {
+            let symbol = node.string("operator")?;
+            let op = BINOPS
+                .into_iter()
+                .find(|op| op.to_string().trim() == symbol)
+                .ok_or_else(|| error(&format!("unknown binary operator '{symbol}'")))?;
+            Expression::BinaryOp(
+                (
+                    Box::new(node.exp("left")?),
+                    op,
+                    Box::new(node.exp("right")?),
+                ),
+                span,
+            )
+        }
+        "Unary" => {
+            let symbol = node.string("operator")?;
+            let op = UNOPS
+                .into_iter()
+                .find(|op| op.to_string() == symbol)
+                .ok_or_else(|| error(&format!("unknown unary operator '{symbol}'")))?;
+            Expression::UnaryOp((op, Box::new(node.exp("operand")?)), span)
+        }
+        _ => Expression::PrefixExp(Box::new(read_prefix(value)?), span),
+    };
+    Ok(exp)
+}
+
+fn read_function(value: &Value) -> Result<Rc<FuncBody>, ASTParseError> {
+    let node = Node::new(value)?;
+    node.expect("Function")?;
+    let params = node
+        .list("params")?
+        .iter()
+        .map(|param| {
+            param
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| node.invalid("params", "a list of strings"))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(FuncBody::new(
+        ParList(params, node.bool("vararg")?),
+        node.block("block")?,
+        node.span()?,
+    ))
+}
+
+fn read_fields(node: &Node) -> Result<Vec<Field>, ASTParseError> {
+    node.list("fields")?
+        .iter()
+        .map(|field| {
+            let field = Node::new(field)?;
+            match field.kind {
+                "Positional" => Ok(Field::Unnamed(field.exp("value")?)),
+                "Named" => Ok(Field::Name((field.string("name")?, field.exp("value")?))),
+                "Indexed" => Ok(Field::Bracketed((field.exp("key")?, field.exp("value")?))),
+                kind => Err(error(&format!("unknown field kind '{kind}'"))),
+            }
+        })
+        .collect()
+}
+
+fn read_prefix(value: &Value) -> Result<PrefixExp, ASTParseError> {
+    let node = Node::new(value)?;
+    match node.kind {
+        "Name" | "Index" | "Dot" => Ok(PrefixExp::Var(read_var(value)?)),
+        "Call" | "MethodCall" => Ok(PrefixExp::FunctionCall(read_call(&node)?)),
+        "Paren" => Ok(PrefixExp::Exp(node.exp("expression")?)),
+        kind => Err(error(&format!("unknown expression kind '{kind}'"))),
+    }
+}
+
+fn read_var(value: &Value) -> Result<Var, ASTParseError> {
+    let node = Node::new(value)?;
+    match node.kind {
+        "Name" => Ok(Var::Name(node.string("name")?)),
+        "Index" => Ok(Var::Bracket((node.prefix("object")?, node.exp("key")?))),
+        "Dot" => Ok(Var::Dot((node.prefix("object")?, node.string("name")?))),
+        kind => Err(error(&format!("cannot assign to a {kind} node"))),
+    }
+}
+
+fn read_call(node: &Node) -> Result<FunctionCall, ASTParseError> {
+    let args = node.get("args")?;
+    let args = match args.as_array() {
+        Some(values) => Args::ExpList(values.iter().map(read_exp).collect::<Result<_, _>>()?),
+        None => {
+            let arg = Node::new(args)?;
+            match arg.kind {
+                "Table" => Args::TableConstructor(read_fields(&arg)?),
+                "String" => Args::LiteralString(arg.string("value")?),
+                kind => return Err(error(&format!("invalid args of kind '{kind}'"))),
+            }
+        }
+    };
+    match node.kind {
+        "Call" => Ok(FunctionCall::Standard((node.prefix("function")?, args))),
+        _ => Ok(FunctionCall::Method((
+            node.prefix("object")?,
+            node.string("method")?,
+            args,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let src = "local a <const>, b = 1, 2.5
+        t = {1, x = \"s\", [a] = -b}
+        function f(x, ...) return (x + 1) * 2 ^ -1, ... end
+        if not t.x then f{} elseif a > b then t:m\"s\" else end
+        for i = 1, 3 do break end
+        for k, v in pairs(t) do print(k .. v, #t) end
+        repeat local c until true
+        while false do ; end
+        do return end";
+        let ast: AST = crate::parser::parse_chunk(src).unwrap();
+        let json = ast.to_json();
+        let read = AST::from_json(&json).unwrap();
+        assert_eq!(read, ast);
+        // Spans don't take part in the comparison of the nodes
+        assert_eq!(read.to_json(), json);
+    }
+
+    #[test]
+    fn test_schema() {
+        let ast: AST = crate::parser::parse_chunk("x = f(1)").unwrap();
+        let expected = json!({
+            "kind": "Chunk",
+            "version": 1,
+            "block": {
+                "kind": "Block",
+                "statements": [{
+                    "kind": "Assign",
+                    "local": false,
+                    "targets": [{"kind": "Name", "name": "x"}],
+                    "values": [{
+                        "kind": "Call",
+                        "function": {"kind": "Name", "name": "f"},
+                        "args": [{
+                            "kind": "Integer",
+                            "value": 1,
+                            "span": {"start": [1, 7], "end": [1, 7]},
+                        }],
+                        "span": {"start": [1, 5], "end": [1, 8]},
+                    }],
+                    "span": {"start": [1, 1], "end": [1, 8]},
+                }],
+                "return": null,
+            },
+        });
+        assert_eq!(
+            serde_json::from_str::<Value>(&ast.to_json()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_spans_are_optional() {
+        let json = r#"{"kind": "Chunk", "version": 1, "block": {"kind": "Block",
+            "statements": [{"kind": "Break", "span": {"start": [2, 3], "end": [2, 7]}},
+            {"kind": "Semicolon"}], "return": null}}"#;
+        let ast = AST::from_json(json).unwrap();
+        assert_eq!(ast.0.statements[0].span().start, (2, 3));
+        assert_eq!(ast.0.statements[1].span().line(), 0);
+        let json = r#"{"kind": "Chunk", "version": 1, "block": {"kind": "Block",
+            "statements": [{"kind": "Break", "span": [2, 3]}], "return": null}}"#;
+        assert_eq!(
+            AST::from_json(json),
+            Err(ASTParseError::new(
+                "Could not read JSON AST: field 'span' of Break node must be null or a span"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let json = r#"{"kind": "Chunk", "version": 1, "block": {"kind": "Block",
+            "statements": [{"kind": "Assign", "local": false, "targets": [{"kind": "Nil"}],
+            "values": []}], "return": null}}"#;
+        assert_eq!(
+            AST::from_json(json),
+            Err(ASTParseError::new(
+                "Could not read JSON AST: cannot assign to a Nil node"
+            ))
+        );
+        assert_eq!(
+            AST::from_json(r#"{"kind": "Chunk", "version": 2}"#),
+            Err(ASTParseError::new(
+                "Could not read JSON AST: unsupported version, expected 1"
+            ))
+        );
+    }
+}
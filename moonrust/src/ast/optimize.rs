@@ -0,0 +1,390 @@
+//! Optimization pass over the AST, run between the parser and the compiler.
+//!
+//! Operators whose operands are constants are folded with the functions the
+//! VM runs them with, `LuaValue::binary_op` and `LuaValue::unary_op`, so that
+//! a folded chunk computes the same values. An operation that raises an error,
+//! such as an integer division by zero, is left in place to raise it when it
+//! runs. Branches of `if` and `while` statements whose condition is a constant
+//! that never selects them are removed, and so are the statements after a
+//! `break` or a block that always leaves.
+use super::*;
+use crate::interpreter::{LuaString, LuaVal, LuaValue};
+
+impl AST {
+    /// Fold the constant operations of the chunk and remove its dead code
+    pub fn optimize(self) -> AST {
+        AST(block(self.0))
+    }
+}
+
+fn block(block: Block) -> Block {
+    let mut statements = vec![];
+    for stat in block.statements {
+        let Some(stat) = statement(stat) else {
+            continue;
+        };
+        let leaves = stat.leaves_block();
+        statements.push(stat);
+        if leaves {
+            return Block {
+                statements,
+                return_stat: None,
+            };
+        }
+    }
+    Block {
+        statements,
+        return_stat: block.return_stat.map(exps),
+    }
+}
+
+// The optimized statement, or None if it does nothing
+fn statement(stat: Statement) -> Option<Statement> {
+    let stat = match stat {
+        Statement::Assignment((vars, values, local), span) => {
+            let vars = vars.into_iter().map(var).collect();
+            Statement::Assignment((vars, exps(values), local), span)
+        }
+        Statement::FunctionCall(call, span) => Statement::FunctionCall(function_call(call), span),
+        Statement::DoBlock(body, span) => Statement::DoBlock(block(body), span),
+        Statement::While((cond, body), span) => {
+            let cond = exp(cond);
+            if truth(&cond) == Some(false) {
+                return None;
+            }
+            Statement::While((cond, block(body)), span)
+        }
+        Statement::Repeat((body, cond), span) => Statement::Repeat((block(body), exp(cond)), span),
+        Statement::If((cond, then_block, elseifs, else_block), span) => {
+            return if_statement(cond, then_block, elseifs, else_block, span)
+        }
+        Statement::ForNum((name, start, limit, step, body), span) => {
+            let body = block(body);
+            Statement::ForNum((name, exp(start), exp(limit), step.map(exp), body), span)
+        }
+        Statement::ForGeneric((names, values, body), span) => {
+            Statement::ForGeneric((names, exps(values), block(body)), span)
+        }
+        Statement::FunctionDecl((name, body), span) => {
+            Statement::FunctionDecl((name, func_body(body)), span)
+        }
+        Statement::LocalFuncDecl((name, body), span) => {
+            Statement::LocalFuncDecl((name, func_body(body)), span)
+        }
+        Statement::LocalAttribs((names, values), span) => {
+            Statement::LocalAttribs((names, exps(values)), span)
+        }
+        stat @ (Statement::Break(_) | Statement::Semicolon(_)) => stat,
+    };
+    Some(stat)
+}
+
+// Branches whose condition is a false constant are dropped, and a true
+// constant makes its branch the last one, run as the else block. Without a
+// branch left, only the else block remains, in a do block for its locals.
+fn if_statement(
+    cond: Expression,
+    then_block: Block,
+    elseifs: Vec<(Expression, Block)>,
+    else_block: Option<Block>,
+    span: Span,
+) -> Option<Statement> {
+    let mut branches = vec![];
+    let mut last = None;
+    for (cond, body) in std::iter::once((cond, then_block)).chain(elseifs) {
+        let cond = exp(cond);
+        match truth(&cond) {
+            Some(false) => (),
+            Some(true) => {
+                last = Some(block(body));
+                break;
+            }
+            None => branches.push((cond, block(body))),
+        }
+    }
+    let else_block = match last {
+        Some(body) => Some(body),
+        None => else_block.map(block),
+    };
+    let mut branches = branches.into_iter();
+    match branches.next() {
+        Some((cond, body)) => Some(Statement::If(
+            (cond, body, branches.collect(), else_block),
+            span,
+        )),
+        None => else_block.map(|body| Statement::DoBlock(body, span)),
+    }
+}
+
+fn func_body(body: Rc<FuncBody>) -> Rc<FuncBody> {
+    let body = Rc::unwrap_or_clone(body);
+    FuncBody::new(body.par_list, block(body.block), body.span)
+}
+
+fn exps(exps: Vec<Expression>) -> Vec<Expression> {
+    exps.into_iter().map(exp).collect()
+}
+
+// An operation of a chain of binary operators, see `exp`
+enum Task {
+    Optimize(Expression),
+    Fold(BinOp, Span),
+}
+
+// A chain of binary operators is as long as the source makes it, so its
+// operands are optimized in a loop, with a stack of the pending operations
+fn exp(e: Expression) -> Expression {
+    let mut tasks = vec![Task::Optimize(e)];
+    let mut operands = vec![];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Optimize(mut e) => match &mut e {
+                Expression::BinaryOp((left, op, right), span) => {
+                    tasks.push(Task::Fold(*op, *span));
+                    tasks.push(Task::Optimize(take(right)));
+                    tasks.push(Task::Optimize(take(left)));
+                }
+                _ => operands.push(operand(e)),
+            },
+            Task::Fold(op, span) => {
+                let right = operands.pop().expect("right operand");
+                let left = operands.pop().expect("left operand");
+                operands.push(binary_op(left, op, right, span));
+            }
+        }
+    }
+    operands.pop().expect("optimized expression")
+}
+
+// The operation on optimized operands
+fn binary_op(left: Expression, op: BinOp, right: Expression, span: Span) -> Expression {
+    match (&op, truth(&left)) {
+        // The left operand decides without evaluating the right one
+        (BinOp::LogicalAnd, Some(false)) | (BinOp::LogicalOr, Some(true)) => left,
+        (BinOp::LogicalAnd, Some(true)) | (BinOp::LogicalOr, Some(false)) => single_value(right),
+        _ => {
+            let folded = match (constant(&left), constant(&right)) {
+                (Some(l), Some(r)) => LuaValue::binary_op(&op, l, r).ok(),
+                _ => None,
+            };
+            folded
+                .and_then(|val| from_value(val, span))
+                .unwrap_or_else(|| {
+                    Expression::BinaryOp((Box::new(left), op, Box::new(right)), span)
+                })
+        }
+    }
+}
+
+// An expression other than a binary operation. Expressions are taken apart
+// through a mutable reference, since they can't be moved out of, see their
+// `Drop`.
+fn operand(mut e: Expression) -> Expression {
+    match &mut e {
+        Expression::UnaryOp((op, operand), span) => {
+            let (op, span) = (*op, *span);
+            let operand = exp(take(operand));
+            constant(&operand)
+                .and_then(|val| LuaValue::unary_op(&op, val).ok())
+                .and_then(|val| from_value(val, span))
+                .unwrap_or_else(|| Expression::UnaryOp((op, Box::new(operand)), span))
+        }
+        Expression::FunctionDef(body, span) => {
+            let (body, span) = (Rc::clone(body), *span);
+            // The prototype isn't shared anymore, and is optimized in place
+            drop(e);
+            Expression::FunctionDef(func_body(body), span)
+        }
+        Expression::PrefixExp(prefix, span) => {
+            let span = *span;
+            match std::mem::replace(prefix.as_mut(), PrefixExp::Exp(Expression::Nil(span))) {
+                // Parentheses only matter around an expression giving several values
+                PrefixExp::Exp(inner) => {
+                    let inner = exp(inner);
+                    match constant(&inner) {
+                        Some(_) => inner,
+                        None => Expression::PrefixExp(Box::new(PrefixExp::Exp(inner)), span),
+                    }
+                }
+                prefix => Expression::PrefixExp(Box::new(prefix_exp(prefix)), span),
+            }
+        }
+        Expression::TableConstructor(fields, span) => Expression::TableConstructor(
+            std::mem::take(fields).into_iter().map(field).collect(),
+            *span,
+        ),
+        _ => e,
+    }
+}
+
+// The expression truncated to its first value, as the operand of an operator
+fn single_value(e: Expression) -> Expression {
+    match e {
+        Expression::DotDotDot(span) => Expression::PrefixExp(Box::new(PrefixExp::Exp(e)), span),
+        Expression::PrefixExp(ref prefix, span)
+            if matches!(**prefix, PrefixExp::FunctionCall(_)) =>
+        {
+            Expression::PrefixExp(Box::new(PrefixExp::Exp(e)), span)
+        }
+        e => e,
+    }
+}
+
+fn prefix_exp(prefix: PrefixExp) -> PrefixExp {
+    match prefix {
+        PrefixExp::Var(v) => PrefixExp::Var(var(v)),
+        PrefixExp::FunctionCall(call) => PrefixExp::FunctionCall(function_call(call)),
+        PrefixExp::Exp(e) => PrefixExp::Exp(exp(e)),
+    }
+}
+
+fn var(v: Var) -> Var {
+    match v {
+        Var::Name(name) => Var::Name(name),
+        Var::Bracket((prefix, key)) => Var::Bracket((Box::new(prefix_exp(*prefix)), exp(key))),
+        Var::Dot((prefix, name)) => Var::Dot((Box::new(prefix_exp(*prefix)), name)),
+    }
+}
+
+fn function_call(call: FunctionCall) -> FunctionCall {
+    match call {
+        FunctionCall::Standard((prefix, arguments)) => {
+            FunctionCall::Standard((Box::new(prefix_exp(*prefix)), args(arguments)))
+        }
+        FunctionCall::Method((prefix, name, arguments)) => {
+            FunctionCall::Method((Box::new(prefix_exp(*prefix)), name, args(arguments)))
+        }
+    }
+}
+
+fn args(arguments: Args) -> Args {
+    match arguments {
+        Args::ExpList(values) => Args::ExpList(exps(values)),
+        Args::TableConstructor(fields) => {
+            Args::TableConstructor(fields.into_iter().map(field).collect())
+        }
+        Args::LiteralString(s) => Args::LiteralString(s),
+    }
+}
+
+fn field(f: Field) -> Field {
+    match f {
+        Field::Bracketed((key, val)) => Field::Bracketed((exp(key), exp(val))),
+        Field::Name((name, val)) => Field::Name((name, exp(val))),
+        Field::Unnamed(val) => Field::Unnamed(exp(val)),
+    }
+}
+
+// Value of a constant expression
+fn constant(e: &Expression) -> Option<LuaValue> {
+    let val = match e {
+        Expression::Nil(_) => LuaVal::LuaNil,
+        Expression::False(_) => LuaVal::LuaBool(false),
+        Expression::True(_) => LuaVal::LuaBool(true),
+        Expression::Numeral(Numeral::Integer(i), _) => LuaVal::LuaInt(*i),
+        Expression::Numeral(Numeral::Float(f), _) => LuaVal::LuaFloat(*f),
+        Expression::LiteralString(s, _) => LuaVal::LuaString(LuaString::from(s.as_str())),
+        _ => return None,
+    };
+    Some(LuaValue::new(val))
+}
+
+// Whether a constant expression is true, None if it isn't a constant
+fn truth(e: &Expression) -> Option<bool> {
+    constant(e).map(|val| val.is_true())
+}
+
+// Constant expression of a value, with the span of the expression it was
+// folded from, None for values that have none
+fn from_value(val: LuaValue, span: Span) -> Option<Expression> {
+    let e = match &val.0 {
+        LuaVal::LuaNil => Expression::Nil(span),
+        LuaVal::LuaBool(false) => Expression::False(span),
+        LuaVal::LuaBool(true) => Expression::True(span),
+        LuaVal::LuaInt(i) => Expression::Numeral(Numeral::Integer(*i), span),
+        LuaVal::LuaFloat(f) => Expression::Numeral(Numeral::Float(*f), span),
+        LuaVal::LuaString(s) => Expression::LiteralString(s.as_str().to_string(), span),
+        _ => return None,
+    };
+    Some(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::environment::Env;
+    use crate::parser::parse_chunk;
+
+    fn optimize(src: &str) -> String {
+        parse_chunk(src).unwrap().optimize().to_string()
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let src = "x = 2 ^ 10 * 3
+        y = \"a\" .. \"b\" .. 1
+        z = (1 + 2) * -4 < 0, #\"abc\", not nil
+        w = 1 < 2 and 3, nil or \"s\", false and f(), 0 or f()
+        v = a + 1 * 2";
+        let expected = "x = 3072.0
+y = \"ab1\"
+z = true, 3, true
+w = 3, \"s\", false, 0
+v = a + 2
+";
+        assert_eq!(optimize(src), expected);
+    }
+
+    #[test]
+    fn test_keep_errors_and_multiple_values() {
+        let src = "x = 1 // 0, 1 % 0, \"a\" + 1, -{}, 1 < \"2\"
+        return true and f(), nil or ..., (g())";
+        let expected = "x = 1 // 0, 1 % 0, \"a\" + 1, -{}, 1 < \"2\"
+return (f()), (...), (g())
+";
+        assert_eq!(optimize(src), expected);
+    }
+
+    #[test]
+    fn test_remove_dead_code() {
+        let src = "if false then a() elseif x then b() elseif 1 then c() else d() end
+        if nil then e() elseif \"s\" then local f = 1 end
+        if false then g() end
+        while false do h() end
+        function i() while x do break j() end do return end k() end";
+        let expected = "if x then
+    b()
+else
+    c()
+end
+do
+    local f = 1
+end
+function i()
+    while x do
+        break
+    end
+    do
+        return
+    end
+end
+";
+        assert_eq!(optimize(src), expected);
+    }
+
+    #[test]
+    fn test_same_results() {
+        let src = "local t = {}
+        for i = 1, 3 do t[i] = i * 2 ^ 2 .. \"!\" end
+        if 1 > 2 then return 0 end
+        return t[1], t[3], 7 // 2 * 3, 7 / 2, 2 ^ 53 + 1, -(-9223372036854775807 - 1)";
+        let ast = parse_chunk(src).unwrap();
+        let optimized = ast.clone().optimize();
+        assert_ne!(optimized, ast);
+        let run = |ast: AST| -> Vec<String> {
+            let vals = ast.exec_with_return(&mut Env::new()).unwrap();
+            vals.iter().map(LuaValue::to_string).collect()
+        };
+        assert_eq!(run(optimized), run(ast));
+    }
+}
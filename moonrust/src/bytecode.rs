@@ -0,0 +1,269 @@
+// Register bytecode executed by the VM, produced by the compiler
+use crate::ast::{BinOp, UnOp};
+use crate::interpreter::LuaValue;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// Index of a register in the frame of the running function
+pub type Reg = u8;
+
+/// Operand that is either a register or a constant: values below `RK_CONST`
+/// are registers, the others are `RK_CONST` + the index of a constant
+pub type RK = u16;
+pub const RK_CONST: RK = 256;
+
+/// Largest constant index usable as an `RK` operand
+pub const MAX_RK_CONST: usize = (RK::MAX - RK_CONST) as usize;
+
+/// Number of registers a function can use
+pub const MAX_REGISTERS: usize = 250;
+
+/// Number of list items of a table constructor stored by a single `SetList`
+pub const FIELDS_PER_FLUSH: usize = 50;
+
+// Counts of values (arguments, results, registers) are encoded as count + 1,
+// so that 0 can mean "up to the top of the stack", i.e. every value produced
+// by the previous call or vararg expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    /// R[a] = R[b]
+    Move(Reg, Reg),
+    /// R[a] = K[b]
+    LoadK(Reg, u32),
+    /// R[a], ..., R[a + b - 1] = nil
+    LoadNil(Reg, u8),
+    /// R[a] = b
+    LoadBool(Reg, bool),
+    /// R[a] = Up[b]
+    GetUpval(Reg, u8),
+    /// Up[b] = R[a]
+    SetUpval(Reg, u8),
+    /// R[a] = Up[b][RK(c)]
+    GetTabUp(Reg, u8, RK),
+    /// Up[a][RK(b)] = RK(c)
+    SetTabUp(u8, RK, RK),
+    /// R[a] = R[b][RK(c)]
+    GetTable(Reg, Reg, RK),
+    /// R[a][RK(b)] = RK(c)
+    SetTable(Reg, RK, RK),
+    /// R[a] = R[b][RK(c)], where the value must be a function (method call)
+    GetMethod(Reg, Reg, RK),
+    /// R[a] = {}
+    NewTable(Reg),
+    /// R[a][c + i] = R[a + 1 + i] for the b - 1 values above R[a]
+    SetList(Reg, u8, u32),
+    /// R[a] = op R[b]
+    Unary(UnOp, Reg, Reg),
+    /// R[a] = RK(b) op RK(c)
+    Binary(BinOp, Reg, RK, RK),
+    /// pc += a
+    Jmp(i32),
+    /// if R[a] tests as b then pc += c
+    JmpIf(Reg, bool, i32),
+    /// R[a], ..., R[a + c - 2] = R[a](R[a + 1], ..., R[a + b - 1])
+    Call(Reg, u8, u8),
+    /// return R[a](R[a + 1], ..., R[a + b - 1]), reusing the frame for a Lua function
+    TailCall(Reg, u8),
+    /// return R[a], ..., R[a + b - 2]
+    Return(Reg, u8),
+    /// Check the numeric for loop R[a] (initial), R[a + 1] (limit), R[a + 2] (step)
+    /// and skip it (pc += b) if it runs zero times
+    ForPrep(Reg, i32),
+    /// Step the numeric for loop R[a] and jump back (pc += b) unless it ended
+    ForLoop(Reg, i32),
+    /// Check the iterator R[a] of a generic for loop and jump to its `TForCall` (pc += b)
+    TForPrep(Reg, i32),
+    /// R[a + 3], ..., R[a + 2 + b] = R[a](R[a + 1], R[a + 2])
+    TForCall(Reg, u8),
+    /// if R[a + 3] ~= nil then { R[a + 2] = R[a + 3]; pc += b }
+    TForLoop(Reg, i32),
+    /// R[a] = closure(protos[b])
+    Closure(Reg, u32),
+    /// R[a], ..., R[a + b - 2] = ...
+    VarArg(Reg, u8),
+    /// Close the upvalues of the registers R[a] and above, and call the
+    /// `__close` metamethods of the to-be-closed variables among them
+    Close(Reg),
+    /// Mark R[a], the local named K[b], as a to-be-closed variable
+    Tbc(Reg, u32),
+}
+
+/// Where a function finds one of its upvalues when the closure is created
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpvalDesc {
+    pub name: String,
+    /// A register of the enclosing function if true, otherwise one of its upvalues
+    pub in_stack: bool,
+    pub index: u8,
+}
+
+/// Local variable of a function, in the register following the locals
+/// active before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocVar {
+    pub name: String,
+    /// First instruction where the variable is active
+    pub start_pc: usize,
+    /// First instruction where it is no longer active
+    pub end_pc: usize,
+}
+
+/// Compiled function
+#[derive(Debug, PartialEq, Default)]
+pub struct Proto {
+    pub source: String,
+    pub num_params: u8,
+    pub is_vararg: bool,
+    pub max_stack: u8,
+    pub code: Vec<Instr>,
+    pub constants: Vec<LuaValue>,
+    pub protos: Vec<Rc<Proto>>,
+    pub upvalues: Vec<UpvalDesc>,
+    /// Line of each instruction, 0 if it comes from nodes without a span
+    pub lineinfo: Vec<u32>,
+    /// Lines of the `function` keyword and of the `end` of the function, 0
+    /// for the main function of a chunk
+    pub line_defined: u32,
+    pub last_line_defined: u32,
+    /// Local variables in the order of their declaration
+    pub locvars: Vec<LocVar>,
+}
+
+impl Proto {
+    /// Line of the instruction at `pc`, if it is known
+    pub fn line(&self, pc: usize) -> Option<u32> {
+        self.lineinfo.get(pc).copied().filter(|line| *line > 0)
+    }
+
+    /// Name of the `n`th local variable active at `pc`, counting from 1,
+    /// which is in register `n - 1`
+    pub fn local_name(&self, n: usize, pc: usize) -> Option<&str> {
+        self.locvars
+            .iter()
+            .filter(|var| var.start_pc <= pc && pc < var.end_pc)
+            .nth(n.checked_sub(1)?)
+            .map(|var| var.name.as_str())
+    }
+}
+
+// Print an operand of the listing
+fn rk(f: &mut Formatter<'_>, operand: RK) -> fmt::Result {
+    if operand < RK_CONST {
+        write!(f, " R{operand}")
+    } else {
+        write!(f, " K{}", operand - RK_CONST)
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instr::Move(a, b) => write!(f, "MOVE R{a} R{b}"),
+            Instr::LoadK(a, b) => write!(f, "LOADK R{a} K{b}"),
+            Instr::LoadNil(a, b) => write!(f, "LOADNIL R{a} {b}"),
+            Instr::LoadBool(a, b) => write!(f, "LOADBOOL R{a} {b}"),
+            Instr::GetUpval(a, b) => write!(f, "GETUPVAL R{a} U{b}"),
+            Instr::SetUpval(a, b) => write!(f, "SETUPVAL R{a} U{b}"),
+            Instr::GetTabUp(a, b, c) => {
+                write!(f, "GETTABUP R{a} U{b}")?;
+                rk(f, c)
+            }
+            Instr::SetTabUp(a, b, c) => {
+                write!(f, "SETTABUP U{a}")?;
+                rk(f, b)?;
+                rk(f, c)
+            }
+            Instr::GetTable(a, b, c) => {
+                write!(f, "GETTABLE R{a} R{b}")?;
+                rk(f, c)
+            }
+            Instr::SetTable(a, b, c) => {
+                write!(f, "SETTABLE R{a}")?;
+                rk(f, b)?;
+                rk(f, c)
+            }
+            Instr::GetMethod(a, b, c) => {
+                write!(f, "GETMETHOD R{a} R{b}")?;
+                rk(f, c)
+            }
+            Instr::NewTable(a) => write!(f, "NEWTABLE R{a}"),
+            Instr::SetList(a, b, c) => write!(f, "SETLIST R{a} {b} {c}"),
+            Instr::Unary(op, a, b) => write!(f, "UNARY '{}' R{a} R{b}", op.to_string().trim()),
+            Instr::Binary(op, a, b, c) => {
+                write!(f, "BINARY '{}' R{a}", op.to_string().trim())?;
+                rk(f, b)?;
+                rk(f, c)
+            }
+            Instr::Jmp(a) => write!(f, "JMP {a}"),
+            Instr::JmpIf(a, b, c) => write!(f, "JMPIF R{a} {b} {c}"),
+            Instr::Call(a, b, c) => write!(f, "CALL R{a} {b} {c}"),
+            Instr::TailCall(a, b) => write!(f, "TAILCALL R{a} {b}"),
+            Instr::Return(a, b) => write!(f, "RETURN R{a} {b}"),
+            Instr::ForPrep(a, b) => write!(f, "FORPREP R{a} {b}"),
+            Instr::ForLoop(a, b) => write!(f, "FORLOOP R{a} {b}"),
+            Instr::TForPrep(a, b) => write!(f, "TFORPREP R{a} {b}"),
+            Instr::TForCall(a, b) => write!(f, "TFORCALL R{a} {b}"),
+            Instr::TForLoop(a, b) => write!(f, "TFORLOOP R{a} {b}"),
+            Instr::Closure(a, b) => write!(f, "CLOSURE R{a} F{b}"),
+            Instr::VarArg(a, b) => write!(f, "VARARG R{a} {b}"),
+            Instr::Close(a) => write!(f, "CLOSE R{a}"),
+            Instr::Tbc(a, b) => write!(f, "TBC R{a} K{b}"),
+        }
+    }
+}
+
+// Listing of the function and the functions nested in it, like `luac -l`
+impl Display for Proto {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let lines = match self.lineinfo.iter().all(|line| *line == 0) {
+            true => String::new(),
+            false => format!(":{},{}", self.line_defined, self.last_line_defined),
+        };
+        writeln!(
+            f,
+            "function <{}{lines}> ({} instructions)",
+            self.source,
+            self.code.len()
+        )?;
+        writeln!(
+            f,
+            "{}{} params, {} slots, {} upvalues, {} constants, {} functions",
+            self.num_params,
+            if self.is_vararg { "+" } else { "" },
+            self.max_stack,
+            self.upvalues.len(),
+            self.constants.len(),
+            self.protos.len()
+        )?;
+        for (pc, instr) in self.code.iter().enumerate() {
+            match self.line(pc) {
+                Some(line) => writeln!(f, "\t{}\t[{line}]\t{instr}", pc + 1)?,
+                None => writeln!(f, "\t{}\t{instr}", pc + 1)?,
+            }
+        }
+        for (i, constant) in self.constants.iter().enumerate() {
+            match constant.is_string() {
+                true => writeln!(f, "\tK{i}\t\"{constant}\"")?,
+                false => writeln!(f, "\tK{i}\t{constant}")?,
+            }
+        }
+        for (i, var) in self.locvars.iter().enumerate() {
+            writeln!(f, "\tL{i}\t{}\t{}\t{}", var.name, var.start_pc + 1, var.end_pc + 1)?;
+        }
+        for (i, upval) in self.upvalues.iter().enumerate() {
+            writeln!(
+                f,
+                "\tU{i}\t{}\t{}\t{}",
+                upval.name,
+                if upval.in_stack { "R" } else { "U" },
+                upval.index
+            )?;
+        }
+        for proto in &self.protos {
+            writeln!(f)?;
+            write!(f, "{proto}")?;
+        }
+        Ok(())
+    }
+}
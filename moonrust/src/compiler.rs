@@ -0,0 +1,1210 @@
+// Compiler from the AST to the register bytecode run by the VM
+use crate::ast::*;
+use crate::bytecode::*;
+use crate::interpreter::{ASTExecError, LuaString, LuaVal, LuaValue};
+use crate::resolver::{self, EnvVar, Resolution, VarKind};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Compile a chunk into the prototype of its main function. The main function
+/// is a vararg function whose only upvalue is `_ENV`. Every instruction gets
+/// the line of the statement it comes from, taken from the spans of the AST,
+/// for the debug interface.
+pub fn compile(ast: &AST, source: &str) -> Result<Rc<Proto>, ASTExecError> {
+    let resolution = resolver::resolve(ast)?;
+    let end_line = last_line(&ast.0);
+    let mut compiler = Compiler {
+        funcs: vec![],
+        source: source.to_string(),
+        resolution,
+    };
+    let upvalues = compiler.resolution.main_upvalues().to_vec();
+    compiler.open_function(&ParList(vec![], true), upvalues);
+    compiler.function_body(&[], &ast.0, end_line)?;
+    Ok(Rc::new(compiler.close_function()))
+}
+
+// Line of the end of a block, 0 if it has no span
+fn last_line(block: &Block) -> u32 {
+    let last = match &block.return_stat {
+        Some(exps) => exps.last().map(Expression::span),
+        None => block.statements.last().map(Statement::span),
+    };
+    last.map_or(0, |span| span.end.0 as u32)
+}
+
+// Constants are deduplicated by value; integers and floats with the same
+// mathematical value are different constants
+#[derive(Hash, Eq, PartialEq)]
+enum ConstKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(String),
+}
+
+struct BlockScope {
+    num_locals: usize, // Active locals when the block was entered
+    is_loop: bool,
+    breaks: Vec<usize>, // Jumps to the end of the loop
+    has_upval: bool,    // A local of the block (or of a nested one) is captured or to be closed
+    has_tbc: bool,      // A local of the block is to be closed
+}
+
+// State of a function being compiled
+struct FuncState {
+    proto: Proto,
+    constants: HashMap<ConstKey, u32>,
+    num_locals: usize, // Active locals, in the registers below this one
+    blocks: Vec<BlockScope>,
+    free_reg: usize, // First register not used by locals or temporaries
+    active_vars: Vec<usize>, // Indices in `locvars` of the active locals
+    line: u32,               // Line of the instructions emitted
+}
+
+impl FuncState {
+    // Debug information of a local becoming active at the next instruction
+    fn add_locvar(&mut self, name: &str) {
+        self.active_vars.push(self.proto.locvars.len());
+        self.proto.locvars.push(LocVar {
+            name: name.to_string(),
+            start_pc: self.proto.code.len(),
+            end_pc: self.proto.code.len(),
+        });
+    }
+}
+
+struct Compiler<'a> {
+    funcs: Vec<FuncState>, // Enclosing functions, the innermost last
+    source: String,
+    resolution: Resolution<'a>,
+}
+
+impl<'a> Compiler<'a> {
+    fn fs(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().expect("no function being compiled")
+    }
+
+    fn open_function(&mut self, par_list: &ParList, upvalues: Vec<UpvalDesc>) {
+        self.funcs.push(FuncState {
+            proto: Proto {
+                source: self.source.clone(),
+                is_vararg: par_list.1,
+                upvalues,
+                ..Proto::default()
+            },
+            constants: HashMap::new(),
+            num_locals: 0,
+            blocks: vec![],
+            free_reg: 0,
+            active_vars: vec![],
+            line: 0,
+        });
+    }
+
+    fn close_function(&mut self) -> Proto {
+        let mut fs = self.funcs.pop().expect("no function being compiled");
+        let end = fs.proto.code.len();
+        for var in fs.active_vars {
+            fs.proto.locvars[var].end_pc = end;
+        }
+        fs.proto
+    }
+
+    // Give the instructions emitted from now on the line of a span, if it is
+    // known
+    fn set_line(&mut self, span: Span) {
+        if span.line() > 0 {
+            self.fs().line = span.line() as u32;
+        }
+    }
+
+    fn function_body(
+        &mut self,
+        params: &'a [String],
+        block: &'a Block,
+        end_line: u32,
+    ) -> Result<(), ASTExecError> {
+        // Parameters are the first locals of the outermost block
+        self.enter_block(false);
+        self.reserve_regs(params.len())?;
+        for name in params {
+            self.activate_local(name);
+        }
+        self.block(block)?;
+        // Returning closes every upvalue of the frame, so the outer block
+        // doesn't need to be left
+        self.fs().line = end_line;
+        self.emit(Instr::Return(0, 1));
+        Ok(())
+    }
+
+    // Compile a function nested in the current one into register `dest`
+    fn function(&mut self, body: &'a FuncBody, dest: Reg) -> Result<(), ASTExecError> {
+        let upvalues = self.resolution.upvalues(body).to_vec();
+        self.open_function(&body.par_list, upvalues);
+        let num_params = body.par_list.0.len();
+        self.fs().proto.num_params = u8::try_from(num_params)
+            .map_err(|_| ASTExecError::new("too many parameters in function"))?;
+        let (line, end_line) = (body.span.start.0 as u32, body.span.end.0 as u32);
+        let fs = self.fs();
+        fs.proto.line_defined = line;
+        fs.proto.last_line_defined = end_line;
+        fs.line = line;
+        self.function_body(&body.par_list.0, &body.block, end_line)?;
+        let proto = self.close_function();
+
+        let fs = self.fs();
+        let index = fs.proto.protos.len() as u32;
+        fs.proto.protos.push(Rc::new(proto));
+        self.emit(Instr::Closure(dest, index));
+        Ok(())
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        let fs = self.fs();
+        fs.proto.lineinfo.push(fs.line);
+        fs.proto.code.push(instr);
+        fs.proto.code.len() - 1
+    }
+
+    fn pc(&mut self) -> usize {
+        self.fs().proto.code.len()
+    }
+
+    // Point the jump at `pc` to `target`
+    fn patch(&mut self, pc: usize, target: usize) {
+        let offset = target as i32 - (pc as i32 + 1);
+        let instr = &mut self.fs().proto.code[pc];
+        match instr {
+            Instr::Jmp(jump)
+            | Instr::JmpIf(_, _, jump)
+            | Instr::ForPrep(_, jump)
+            | Instr::ForLoop(_, jump)
+            | Instr::TForPrep(_, jump)
+            | Instr::TForLoop(_, jump) => *jump = offset,
+            _ => panic!("patching a non-jump instruction"),
+        }
+    }
+
+    fn patch_here(&mut self, pc: usize) {
+        let target = self.pc();
+        self.patch(pc, target);
+    }
+
+    fn alloc_reg(&mut self) -> Result<Reg, ASTExecError> {
+        self.reserve_regs(1)?;
+        Ok((self.fs().free_reg - 1) as Reg)
+    }
+
+    fn reserve_regs(&mut self, n: usize) -> Result<(), ASTExecError> {
+        let fs = self.fs();
+        fs.free_reg += n;
+        if fs.free_reg > MAX_REGISTERS {
+            return Err(ASTExecError::new(
+                "function or expression needs too many registers",
+            ));
+        }
+        fs.proto.max_stack = fs.proto.max_stack.max(fs.free_reg as u8);
+        Ok(())
+    }
+
+    fn free_reg(&mut self) -> usize {
+        self.fs().free_reg
+    }
+
+    fn set_free_reg(&mut self, reg: usize) {
+        self.fs().free_reg = reg;
+    }
+
+    fn constant(&mut self, key: ConstKey) -> u32 {
+        let fs = self.fs();
+        if let Some(index) = fs.constants.get(&key) {
+            return *index;
+        }
+        let val = match &key {
+            ConstKey::Nil => LuaVal::LuaNil,
+            ConstKey::Bool(b) => LuaVal::LuaBool(*b),
+            ConstKey::Int(i) => LuaVal::LuaInt(*i),
+            ConstKey::Float(bits) => LuaVal::LuaFloat(f64::from_bits(*bits)),
+            ConstKey::Str(s) => LuaVal::LuaString(LuaString::from(s.as_str())),
+        };
+        let index = fs.proto.constants.len() as u32;
+        fs.proto.constants.push(LuaValue::new(val));
+        fs.constants.insert(key, index);
+        index
+    }
+
+    fn string_constant(&mut self, s: &str) -> u32 {
+        self.constant(ConstKey::Str(s.to_string()))
+    }
+
+    // Constant as an operand, loaded into a register if there are too many constants
+    fn rk_constant(&mut self, key: ConstKey) -> Result<RK, ASTExecError> {
+        let index = self.constant(key);
+        if index as usize <= MAX_RK_CONST {
+            return Ok(RK_CONST + index as RK);
+        }
+        let reg = self.alloc_reg()?;
+        self.emit(Instr::LoadK(reg, index));
+        Ok(reg as RK)
+    }
+
+    fn enter_block(&mut self, is_loop: bool) {
+        let fs = self.fs();
+        let num_locals = fs.num_locals;
+        fs.blocks.push(BlockScope {
+            num_locals,
+            is_loop,
+            breaks: vec![],
+            has_upval: false,
+            has_tbc: false,
+        });
+    }
+
+    // Leave the innermost block: its locals go out of scope and, with `close`,
+    // captured locals get their own copy of the value
+    fn leave_block(&mut self, close: bool) {
+        let fs = self.fs();
+        let block = fs.blocks.pop().expect("no block to leave");
+        let first_reg = block.num_locals;
+        fs.num_locals = block.num_locals;
+        fs.free_reg = first_reg;
+        let end = fs.proto.code.len();
+        for var in fs.active_vars.drain(first_reg..) {
+            fs.proto.locvars[var].end_pc = end;
+        }
+        if let Some(parent) = fs.blocks.last_mut() {
+            parent.has_upval |= block.has_upval;
+        }
+
+        // Breaks jump after the loop, where the locals of the loop are closed
+        for pc in block.breaks {
+            self.patch_here(pc);
+        }
+        if close && block.has_upval {
+            self.emit(Instr::Close(first_reg as Reg));
+        }
+    }
+
+    // Make a declared local visible, in the register chosen by the resolver
+    fn activate_local(&mut self, name: &'a String) {
+        let local = self.resolution.local(name);
+        let fs = self.fs();
+        debug_assert_eq!(local.reg as usize, fs.num_locals, "register of '{name}'");
+        fs.num_locals += 1;
+        if local.captured {
+            fs.blocks.last_mut().expect("no block").has_upval = true;
+        }
+        fs.add_locvar(name);
+    }
+
+    // Locals holding the state of a for loop, which have no name in the source
+    fn activate_hidden(&mut self, count: usize) {
+        let fs = self.fs();
+        fs.num_locals += count;
+        for _ in 0..count {
+            fs.add_locvar("(for state)");
+        }
+    }
+
+    // Registers below this one hold active locals
+    fn num_active_regs(&mut self) -> usize {
+        self.fs().num_locals
+    }
+
+    fn resolve_name(&self, name: &'a String) -> VarKind {
+        self.resolution.name(name)
+    }
+
+    fn block(&mut self, block: &'a Block) -> Result<(), ASTExecError> {
+        for statement in &block.statements {
+            self.set_line(statement.span());
+            self.statement(statement)?;
+            // Temporaries are not kept between statements
+            let num_active = self.num_active_regs();
+            self.set_free_reg(num_active);
+        }
+        if let Some(explist) = &block.return_stat {
+            // A return without values keeps the line of what comes before it
+            if let Some(exp) = explist.first() {
+                self.set_line(exp.span());
+            }
+            // The variables to be closed are closed after the call returns
+            let has_tbc = self.fs().blocks.iter().any(|block| block.has_tbc);
+            if let [Expression::PrefixExp(prefixexp, _)] = explist.as_slice() {
+                if let PrefixExp::FunctionCall(funcall) = prefixexp.as_ref() {
+                    if !has_tbc {
+                        return self.tail_call(funcall);
+                    }
+                }
+            }
+            let base = self.free_reg();
+            let count = self.explist_to_regs(explist, None)?;
+            self.emit(Instr::Return(base as Reg, count_operand(count)));
+        }
+        Ok(())
+    }
+
+    fn scoped_block(&mut self, block: &'a Block) -> Result<(), ASTExecError> {
+        self.enter_block(false);
+        self.block(block)?;
+        self.leave_block(true);
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &'a Statement) -> Result<(), ASTExecError> {
+        match statement {
+            Statement::Semicolon(_) => {}
+            Statement::Assignment((varlist, explist, true), _) => {
+                self.explist_to_regs(explist, Some(varlist.len()))?;
+                for var in varlist {
+                    match var {
+                        Var::Name(name) => self.activate_local(name),
+                        _ => unreachable!("the resolver only accepts names"),
+                    }
+                }
+            }
+            Statement::Assignment((varlist, explist, false), _) => {
+                self.assignment(varlist, explist)?
+            }
+            Statement::LocalAttribs((names, explist), _) => {
+                let closed: Vec<&String> = names
+                    .iter()
+                    .filter(|(_, attrib)| *attrib == Some(Attrib::Close))
+                    .map(|(name, _)| name)
+                    .collect();
+                if closed.len() > 1 {
+                    return Err(ASTExecError::new(
+                        "multiple to-be-closed variables in local list",
+                    ));
+                }
+                self.explist_to_regs(explist, Some(names.len()))?;
+                for (name, _) in names {
+                    self.activate_local(name);
+                }
+                if let Some(name) = closed.first() {
+                    let reg = self.resolution.local(name).reg;
+                    let index = self.string_constant(name);
+                    self.emit(Instr::Tbc(reg, index));
+                    let block = self.fs().blocks.last_mut().expect("no block");
+                    block.has_upval = true;
+                    block.has_tbc = true;
+                }
+            }
+            Statement::FunctionCall(funcall, _) => {
+                self.call(funcall, Some(0))?;
+            }
+            Statement::Break(_) => {
+                let jump = self.emit(Instr::Jmp(0));
+                match self
+                    .fs()
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| block.is_loop)
+                {
+                    Some(block) => block.breaks.push(jump),
+                    None => {
+                        return Err(ASTExecError::new(
+                            "Break statement can only be used inside a while, repeat, or for loop",
+                        ))
+                    }
+                }
+            }
+            Statement::DoBlock(block, _) => self.scoped_block(block)?,
+            Statement::While((exp, block), _) => {
+                self.enter_block(true);
+                let start = self.pc();
+                let cond = self.exp_to_any_reg(exp)?;
+                let exit = self.emit(Instr::JmpIf(cond, false, 0));
+                self.fs().blocks.last_mut().unwrap().breaks.push(exit);
+                let num_active = self.num_active_regs();
+                self.set_free_reg(num_active);
+                self.scoped_block(block)?;
+                let back = self.emit(Instr::Jmp(0));
+                self.patch(back, start);
+                self.leave_block(true);
+            }
+            Statement::Repeat((block, exp), _) => {
+                // The condition can refer to the locals of the block
+                self.enter_block(true);
+                let start = self.pc();
+                self.enter_block(false);
+                self.block(block)?;
+                let cond = self.exp_to_any_reg(exp)?;
+                let first_reg = self.fs().blocks.last().unwrap().num_locals;
+                if self.fs().blocks.last().unwrap().has_upval {
+                    self.emit(Instr::Close(first_reg as Reg));
+                }
+                let back = self.emit(Instr::JmpIf(cond, false, 0));
+                self.patch(back, start);
+                self.leave_block(false);
+                self.leave_block(true);
+            }
+            Statement::If((exp, block, elseifs, elseblock), _) => {
+                let mut exits = vec![];
+                let conditions = std::iter::once((exp, block))
+                    .chain(elseifs.iter().map(|(exp, block)| (exp, block)));
+                for (exp, block) in conditions {
+                    let cond = self.exp_to_any_reg(exp)?;
+                    let next = self.emit(Instr::JmpIf(cond, false, 0));
+                    let num_active = self.num_active_regs();
+                    self.set_free_reg(num_active);
+                    self.scoped_block(block)?;
+                    exits.push(self.emit(Instr::Jmp(0)));
+                    self.patch_here(next);
+                }
+                if let Some(elseblock) = elseblock {
+                    self.scoped_block(elseblock)?;
+                }
+                for exit in exits {
+                    self.patch_here(exit);
+                }
+            }
+            Statement::ForNum((name, initial, limit, step, block), _) => {
+                // The loop instructions have the line of the for, as in Lua
+                let line = self.fs().line;
+                self.enter_block(true);
+                let base = self.free_reg();
+                self.exp_to_next_reg(initial)?;
+                self.exp_to_next_reg(limit)?;
+                match step {
+                    Some(step) => {
+                        self.exp_to_next_reg(step)?;
+                    }
+                    None => {
+                        let one = self.constant(ConstKey::Int(1));
+                        let reg = self.alloc_reg()?;
+                        self.emit(Instr::LoadK(reg, one));
+                    }
+                }
+                self.activate_hidden(3);
+                let prep = self.emit(Instr::ForPrep(base as Reg, 0));
+
+                let body = self.pc();
+                self.enter_block(false);
+                self.alloc_reg()?;
+                self.activate_local(name);
+                self.block(block)?;
+                self.leave_block(true);
+                self.fs().line = line;
+                let step = self.emit(Instr::ForLoop(base as Reg, 0));
+                self.patch(step, body);
+                self.patch_here(prep);
+                self.leave_block(true);
+            }
+            Statement::ForGeneric((names, explist, block), _) => {
+                // Iterator function, state and control variable
+                let line = self.fs().line;
+                self.enter_block(true);
+                let base = self.free_reg();
+                self.explist_to_regs(explist, Some(3))?;
+                self.activate_hidden(3);
+                let prep = self.emit(Instr::TForPrep(base as Reg, 0));
+
+                let body = self.pc();
+                self.enter_block(false);
+                self.reserve_regs(names.len())?;
+                for name in names {
+                    self.activate_local(name);
+                }
+                self.block(block)?;
+                self.leave_block(true);
+                self.patch_here(prep);
+                self.fs().line = line;
+                let count = u8::try_from(names.len())
+                    .map_err(|_| ASTExecError::new("too many variables in for loop"))?;
+                self.emit(Instr::TForCall(base as Reg, count));
+                let next = self.emit(Instr::TForLoop(base as Reg, 0));
+                self.patch(next, body);
+                self.leave_block(true);
+            }
+            Statement::FunctionDecl((name, body), _) => match self.resolve_name(name) {
+                VarKind::Local(reg) => self.function(body, reg)?,
+                VarKind::Upvalue(index) => {
+                    let reg = self.alloc_reg()?;
+                    self.function(body, reg)?;
+                    self.emit(Instr::SetUpval(reg, index));
+                }
+                VarKind::Global(env) => {
+                    let reg = self.alloc_reg()?;
+                    self.function(body, reg)?;
+                    self.store_global(env, name, reg as RK)?;
+                }
+            },
+            Statement::LocalFuncDecl((name, body), _) => {
+                // The function can refer to itself
+                let reg = self.alloc_reg()?;
+                self.activate_local(name);
+                self.function(body, reg)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, varlist: &'a [Var], explist: &'a [Expression]) -> Result<(), ASTExecError> {
+        // Single assignments are evaluated straight into their target
+        if let ([var], [exp]) = (varlist, explist) {
+            if let Var::Name(name) = var {
+                if let VarKind::Local(reg) = self.resolve_name(name) {
+                    return self.exp_to_reg(exp, reg);
+                }
+            }
+            let target = self.assignment_target(var)?;
+            let val = self.exp_to_rk(exp)?;
+            return self.store(var, target, val);
+        }
+
+        // Tables and keys of the targets are evaluated before the values
+        let mut targets = Vec::with_capacity(varlist.len());
+        for var in varlist {
+            targets.push(self.assignment_target(var)?);
+        }
+        let base = self.free_reg();
+        self.explist_to_regs(explist, Some(varlist.len()))?;
+        for (i, (var, target)) in varlist.iter().zip(targets).enumerate() {
+            self.store(var, target, (base + i) as RK)?;
+        }
+        Ok(())
+    }
+
+    // Table and key of an indexed assignment target
+    fn assignment_target(&mut self, var: &'a Var) -> Result<Option<(Reg, RK)>, ASTExecError> {
+        match var {
+            Var::Name(_) => Ok(None),
+            Var::Bracket((prefixexp, exp)) => {
+                let table = self.prefixexp_to_any_reg(prefixexp)?;
+                let key = self.exp_to_rk(exp)?;
+                Ok(Some((table, key)))
+            }
+            Var::Dot((prefixexp, field)) => {
+                let table = self.prefixexp_to_any_reg(prefixexp)?;
+                let key = self.rk_constant(ConstKey::Str(field.clone()))?;
+                Ok(Some((table, key)))
+            }
+        }
+    }
+
+    fn store(&mut self, var: &'a Var, target: Option<(Reg, RK)>, val: RK) -> Result<(), ASTExecError> {
+        if let Some((table, key)) = target {
+            self.emit(Instr::SetTable(table, key, val));
+            return Ok(());
+        }
+        let name = match var {
+            Var::Name(name) => name,
+            _ => unreachable!("indexed assignments have a target"),
+        };
+        match self.resolve_name(name) {
+            VarKind::Local(reg) => {
+                self.move_rk(reg, val)?;
+            }
+            VarKind::Upvalue(index) => {
+                let reg = self.rk_to_reg(val)?;
+                self.emit(Instr::SetUpval(reg, index));
+            }
+            VarKind::Global(env) => self.store_global(env, name, val)?,
+        }
+        Ok(())
+    }
+
+    fn store_global(&mut self, env: EnvVar, name: &str, val: RK) -> Result<(), ASTExecError> {
+        let key = self.rk_constant(ConstKey::Str(name.to_string()))?;
+        match env {
+            EnvVar::Upvalue(env) => self.emit(Instr::SetTabUp(env, key, val)),
+            EnvVar::Local(env) => self.emit(Instr::SetTable(env, key, val)),
+        };
+        Ok(())
+    }
+
+    fn move_rk(&mut self, dest: Reg, val: RK) -> Result<(), ASTExecError> {
+        if val < RK_CONST {
+            if val != dest as RK {
+                self.emit(Instr::Move(dest, val as Reg));
+            }
+        } else {
+            self.emit(Instr::LoadK(dest, (val - RK_CONST) as u32));
+        }
+        Ok(())
+    }
+
+    fn rk_to_reg(&mut self, val: RK) -> Result<Reg, ASTExecError> {
+        if val < RK_CONST {
+            return Ok(val as Reg);
+        }
+        let reg = self.alloc_reg()?;
+        self.move_rk(reg, val)?;
+        Ok(reg)
+    }
+
+    // Evaluate the expressions into consecutive registers starting at the first
+    // free one. With `want`, exactly that many values are produced; otherwise
+    // all values are kept and None is returned if their number is only known
+    // at runtime (the last expression is a call or `...`).
+    fn explist_to_regs(
+        &mut self,
+        explist: &'a [Expression],
+        want: Option<usize>,
+    ) -> Result<Option<usize>, ASTExecError> {
+        let len = explist.len();
+        for (i, exp) in explist.iter().enumerate() {
+            if i == len - 1 && is_multi(exp) {
+                let needed = want.map(|want| want.saturating_sub(i));
+                self.multi_to_next_regs(exp, needed)?;
+                return Ok(want);
+            }
+            match want {
+                Some(want) if i >= want => {
+                    // Extra values are evaluated and thrown away
+                    let free = self.free_reg();
+                    self.exp_to_next_reg(exp)?;
+                    self.set_free_reg(free);
+                }
+                _ => {
+                    self.exp_to_next_reg(exp)?;
+                }
+            }
+        }
+        match want {
+            Some(want) => {
+                if len < want {
+                    let first = self.free_reg() as Reg;
+                    self.reserve_regs(want - len)?;
+                    self.emit(Instr::LoadNil(first, (want - len) as u8));
+                }
+                Ok(Some(want))
+            }
+            None => Ok(Some(len)),
+        }
+    }
+
+    // Evaluate a call or `...` into registers starting at the first free one,
+    // keeping `nresults` values or all of them
+    fn multi_to_next_regs(
+        &mut self,
+        exp: &'a Expression,
+        nresults: Option<usize>,
+    ) -> Result<(), ASTExecError> {
+        match exp {
+            Expression::DotDotDot(_) => {
+                let base = self.free_reg() as Reg;
+                self.emit(Instr::VarArg(base, count_operand(nresults)));
+                self.reserve_regs(nresults.unwrap_or(0))?;
+            }
+            Expression::PrefixExp(prefixexp, _) => match prefixexp.as_ref() {
+                PrefixExp::FunctionCall(funcall) => {
+                    self.call(funcall, nresults)?;
+                }
+                _ => unreachable!("not a multi-value expression"),
+            },
+            _ => unreachable!("not a multi-value expression"),
+        }
+        Ok(())
+    }
+
+    // Compile a call with its function in the first free register. The results
+    // are left in registers starting at that one.
+    fn call(
+        &mut self,
+        funcall: &'a FunctionCall,
+        nresults: Option<usize>,
+    ) -> Result<Reg, ASTExecError> {
+        let base = self.alloc_reg()?;
+        let args = match funcall {
+            FunctionCall::Standard((func, args)) => {
+                self.prefixexp_to_reg(func, base)?;
+                args
+            }
+            FunctionCall::Method((object, method, args)) => {
+                self.prefixexp_to_reg(object, base)?;
+                let name = self.rk_constant(ConstKey::Str(method.clone()))?;
+                self.emit(Instr::GetMethod(base, base, name));
+                self.set_free_reg(base as usize + 1);
+                args
+            }
+        };
+        let nargs = match args {
+            Args::ExpList(explist) => self.explist_to_regs(explist, None)?,
+            Args::TableConstructor(fields) => {
+                let reg = self.alloc_reg()?;
+                self.table_constructor(fields, reg)?;
+                Some(1)
+            }
+            Args::LiteralString(s) => {
+                let reg = self.alloc_reg()?;
+                let index = self.string_constant(s);
+                self.emit(Instr::LoadK(reg, index));
+                Some(1)
+            }
+        };
+        self.emit(Instr::Call(
+            base,
+            count_operand(nargs),
+            count_operand(nresults),
+        ));
+        self.set_free_reg(base as usize);
+        self.reserve_regs(nresults.unwrap_or(0))?;
+        Ok(base)
+    }
+
+    // `return f(args)`: the called function takes over the frame, so that tail
+    // recursion runs in constant space. The return passes on the results when
+    // the function is not a Lua function.
+    fn tail_call(&mut self, funcall: &'a FunctionCall) -> Result<(), ASTExecError> {
+        let base = self.call(funcall, None)?;
+        let code = &mut self.fs().proto.code;
+        if let Some(Instr::Call(a, b, _)) = code.last() {
+            *code.last_mut().unwrap() = Instr::TailCall(*a, *b);
+        }
+        self.emit(Instr::Return(base, 0));
+        Ok(())
+    }
+
+    fn exp_to_next_reg(&mut self, exp: &'a Expression) -> Result<Reg, ASTExecError> {
+        let reg = self.alloc_reg()?;
+        self.exp_to_reg(exp, reg)?;
+        Ok(reg)
+    }
+
+    // Register holding the value of the expression: the register of a local
+    // variable, or else a new register
+    fn exp_to_any_reg(&mut self, exp: &'a Expression) -> Result<Reg, ASTExecError> {
+        if let Expression::PrefixExp(prefixexp, _) = exp {
+            return self.prefixexp_to_any_reg(prefixexp);
+        }
+        self.exp_to_next_reg(exp)
+    }
+
+    fn prefixexp_to_any_reg(&mut self, prefixexp: &'a PrefixExp) -> Result<Reg, ASTExecError> {
+        if let PrefixExp::Var(Var::Name(name)) = prefixexp {
+            if let VarKind::Local(reg) = self.resolve_name(name) {
+                return Ok(reg);
+            }
+        }
+        let reg = self.alloc_reg()?;
+        self.prefixexp_to_reg(prefixexp, reg)?;
+        Ok(reg)
+    }
+
+    // Operand for the expression: a constant or a register
+    fn exp_to_rk(&mut self, exp: &'a Expression) -> Result<RK, ASTExecError> {
+        match exp {
+            Expression::Nil(_) => self.rk_constant(ConstKey::Nil),
+            Expression::True(_) => self.rk_constant(ConstKey::Bool(true)),
+            Expression::False(_) => self.rk_constant(ConstKey::Bool(false)),
+            Expression::Numeral(n, _) => self.rk_constant(numeral_key(n)),
+            Expression::LiteralString(s, _) => self.rk_constant(ConstKey::Str(s.clone())),
+            _ => Ok(self.exp_to_any_reg(exp)? as RK),
+        }
+    }
+
+    // Evaluate the expression into register `dest`
+    fn exp_to_reg(&mut self, exp: &'a Expression, dest: Reg) -> Result<(), ASTExecError> {
+        let free = self.free_reg();
+        match exp {
+            Expression::Nil(_) => {
+                self.emit(Instr::LoadNil(dest, 1));
+            }
+            Expression::True(_) => {
+                self.emit(Instr::LoadBool(dest, true));
+            }
+            Expression::False(_) => {
+                self.emit(Instr::LoadBool(dest, false));
+            }
+            Expression::Numeral(n, _) => {
+                let index = self.constant(numeral_key(n));
+                self.emit(Instr::LoadK(dest, index));
+            }
+            Expression::LiteralString(s, _) => {
+                let index = self.string_constant(s);
+                self.emit(Instr::LoadK(dest, index));
+            }
+            Expression::DotDotDot(_) => {
+                self.emit(Instr::VarArg(dest, 2));
+            }
+            Expression::FunctionDef(body, _) => self.function(body, dest)?,
+            Expression::PrefixExp(prefixexp, _) => self.prefixexp_to_reg(prefixexp, dest)?,
+            Expression::TableConstructor(fields, _) => {
+                // The items of the list are stored in the registers above the table
+                if (dest as usize) + 1 == free && (dest as usize) >= self.num_active_regs() {
+                    self.table_constructor(fields, dest)?;
+                } else {
+                    let table = self.alloc_reg()?;
+                    self.table_constructor(fields, table)?;
+                    self.emit(Instr::Move(dest, table));
+                }
+            }
+            Expression::BinaryOp((_, op, _), _) => match op {
+                BinOp::LogicalAnd | BinOp::LogicalOr => {
+                    // The left operand is stored before the right one is
+                    // evaluated, so a local variable can't be the target
+                    if (dest as usize) < self.num_active_regs() {
+                        let tmp = self.alloc_reg()?;
+                        self.exp_to_reg(exp, tmp)?;
+                        self.emit(Instr::Move(dest, tmp));
+                    } else {
+                        self.logical_chain(exp, dest)?;
+                    }
+                }
+                BinOp::Concat | BinOp::Pow => self.right_chain(exp, dest)?,
+                _ => self.left_chain(exp, dest)?,
+            },
+            Expression::UnaryOp((op, exp), _) => {
+                let operand = self.exp_to_any_reg(exp)?;
+                self.emit(Instr::Unary(*op, dest, operand));
+            }
+        }
+        self.set_free_reg(free);
+        Ok(())
+    }
+
+    // Chains of binary operators are as long as the source makes them, so they
+    // are compiled in loops rather than recursively.
+
+    // Evaluate `a and b or c ...` into `dest`, a register above the locals
+    fn logical_chain(&mut self, exp: &'a Expression, dest: Reg) -> Result<(), ASTExecError> {
+        let mut links = vec![];
+        let mut first = exp;
+        while let Expression::BinaryOp(
+            (left, op @ (BinOp::LogicalAnd | BinOp::LogicalOr), right),
+            _,
+        ) = first
+        {
+            links.push((*op, right));
+            first = left;
+        }
+        self.exp_to_reg(first, dest)?;
+        for (op, right) in links.into_iter().rev() {
+            let short_circuit = op == BinOp::LogicalOr;
+            let jump = self.emit(Instr::JmpIf(dest, short_circuit, 0));
+            self.exp_to_reg(right, dest)?;
+            self.patch_here(jump);
+        }
+        Ok(())
+    }
+
+    // Evaluate a chain of left associative operators, `a + b * c - d ...`,
+    // into `dest`, from its innermost operation
+    fn left_chain(&mut self, exp: &'a Expression, dest: Reg) -> Result<(), ASTExecError> {
+        let mut links = vec![];
+        let mut first = exp;
+        while let Expression::BinaryOp((left, op, right), _) = first {
+            if matches!(
+                op,
+                BinOp::LogicalAnd | BinOp::LogicalOr | BinOp::Concat | BinOp::Pow
+            ) {
+                break;
+            }
+            links.push((*op, right));
+            first = left;
+        }
+        // A local variable can't hold the partial results, a later operand may
+        // read it
+        let target = if links.len() > 1 && (dest as usize) < self.num_active_regs() {
+            self.alloc_reg()?
+        } else {
+            dest
+        };
+        let free = self.free_reg();
+        let mut left = self.exp_to_rk(first)?;
+        for (op, right) in links.into_iter().rev() {
+            let right = self.exp_to_rk(right)?;
+            self.emit(Instr::Binary(op, target, left, right));
+            self.set_free_reg(free);
+            left = target as RK;
+        }
+        if target != dest {
+            self.emit(Instr::Move(dest, target));
+        }
+        Ok(())
+    }
+
+    // Evaluate a chain of a right associative operator, `a .. b .. c ...`, into
+    // `dest`. Every operand is evaluated before the operations run from the
+    // right.
+    fn right_chain(&mut self, exp: &'a Expression, dest: Reg) -> Result<(), ASTExecError> {
+        let Expression::BinaryOp((_, op, _), _) = exp else {
+            unreachable!("chain of a binary operator");
+        };
+        let mut operands = vec![];
+        let mut last = exp;
+        while let Expression::BinaryOp((left, link_op, right), _) = last {
+            if link_op != op {
+                break;
+            }
+            operands.push(self.exp_to_rk(left)?);
+            last = right;
+        }
+        let mut right = self.exp_to_rk(last)?;
+        // Partial results are kept in a register of their own, since a local
+        // variable `dest` may still be read by an operation
+        let partial = if operands.len() > 1 && (dest as usize) < self.num_active_regs() {
+            self.alloc_reg()?
+        } else {
+            dest
+        };
+        while let Some(left) = operands.pop() {
+            let target = if operands.is_empty() { dest } else { partial };
+            self.emit(Instr::Binary(*op, target, left, right));
+            right = target as RK;
+        }
+        Ok(())
+    }
+
+    fn prefixexp_to_reg(&mut self, prefixexp: &'a PrefixExp, dest: Reg) -> Result<(), ASTExecError> {
+        let free = self.free_reg();
+        match prefixexp {
+            PrefixExp::Var(Var::Name(name)) => match self.resolve_name(name) {
+                VarKind::Local(reg) => {
+                    if reg != dest {
+                        self.emit(Instr::Move(dest, reg));
+                    }
+                }
+                VarKind::Upvalue(index) => {
+                    self.emit(Instr::GetUpval(dest, index));
+                }
+                VarKind::Global(env) => {
+                    let key = self.rk_constant(ConstKey::Str(name.clone()))?;
+                    match env {
+                        EnvVar::Upvalue(env) => self.emit(Instr::GetTabUp(dest, env, key)),
+                        EnvVar::Local(env) => self.emit(Instr::GetTable(dest, env, key)),
+                    };
+                }
+            },
+            PrefixExp::Var(Var::Bracket((prefixexp, exp))) => {
+                let table = self.prefixexp_to_any_reg(prefixexp)?;
+                let key = self.exp_to_rk(exp)?;
+                self.emit(Instr::GetTable(dest, table, key));
+            }
+            PrefixExp::Var(Var::Dot((prefixexp, field))) => {
+                let table = self.prefixexp_to_any_reg(prefixexp)?;
+                let key = self.rk_constant(ConstKey::Str(field.clone()))?;
+                self.emit(Instr::GetTable(dest, table, key));
+            }
+            PrefixExp::FunctionCall(funcall) => {
+                let base = self.call(funcall, Some(1))?;
+                self.emit(Instr::Move(dest, base));
+            }
+            // Parentheses keep only the first value
+            PrefixExp::Exp(exp) => self.exp_to_reg(exp, dest)?,
+        }
+        self.set_free_reg(free);
+        Ok(())
+    }
+
+    // Fill the new table in register `table`, the first register above it must be free
+    fn table_constructor(&mut self, fields: &'a [Field], table: Reg) -> Result<(), ASTExecError> {
+        self.emit(Instr::NewTable(table));
+        let mut pending = 0;
+        let mut next_index = 1;
+        for (i, field) in fields.iter().enumerate() {
+            let free = self.free_reg();
+            match field {
+                Field::Bracketed((key, val)) => {
+                    let key = self.exp_to_rk(key)?;
+                    let val = self.exp_to_rk(val)?;
+                    self.emit(Instr::SetTable(table, key, val));
+                    self.set_free_reg(free);
+                }
+                Field::Name((name, val)) => {
+                    let key = self.rk_constant(ConstKey::Str(name.clone()))?;
+                    let val = self.exp_to_rk(val)?;
+                    self.emit(Instr::SetTable(table, key, val));
+                    self.set_free_reg(free);
+                }
+                Field::Unnamed(exp) if i == fields.len() - 1 && is_multi(exp) => {
+                    // Every value of the last item is stored
+                    self.multi_to_next_regs(exp, None)?;
+                    self.emit(Instr::SetList(table, 0, next_index));
+                    self.set_free_reg(table as usize + 1);
+                    return Ok(());
+                }
+                Field::Unnamed(exp) => {
+                    self.exp_to_next_reg(exp)?;
+                    pending += 1;
+                    if pending == FIELDS_PER_FLUSH {
+                        self.emit(Instr::SetList(table, (pending + 1) as u8, next_index));
+                        next_index += pending as u32;
+                        pending = 0;
+                        self.set_free_reg(table as usize + 1);
+                    }
+                }
+            }
+        }
+        if pending > 0 {
+            self.emit(Instr::SetList(table, (pending + 1) as u8, next_index));
+            self.set_free_reg(table as usize + 1);
+        }
+        Ok(())
+    }
+}
+
+// Calls and `...` can produce any number of values
+fn is_multi(exp: &Expression) -> bool {
+    match exp {
+        Expression::DotDotDot(_) => true,
+        Expression::PrefixExp(prefixexp, _) => {
+            matches!(prefixexp.as_ref(), PrefixExp::FunctionCall(_))
+        }
+        _ => false,
+    }
+}
+
+// Operand for a number of values, see `Instr`
+fn count_operand(count: Option<usize>) -> u8 {
+    match count {
+        Some(count) => (count + 1) as u8,
+        None => 0,
+    }
+}
+
+fn numeral_key(n: &Numeral) -> ConstKey {
+    match n {
+        Numeral::Integer(i) => ConstKey::Int(*i),
+        Numeral::Float(f) => ConstKey::Float(f.to_bits()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_src(src: &str) -> Result<Rc<Proto>, ASTExecError> {
+        compile(&src.parse::<AST>().unwrap(), "test")
+    }
+
+    #[test]
+    fn test_compile_locals_in_registers() {
+        let proto = compile_src("local a = 1 local b = a").unwrap();
+        assert_eq!(
+            proto.code,
+            vec![Instr::LoadK(0, 0), Instr::Move(1, 0), Instr::Return(0, 1)]
+        );
+        assert_eq!(proto.max_stack, 2);
+    }
+
+    #[test]
+    fn test_compile_globals_through_env() {
+        let proto = compile_src("x = y").unwrap();
+        assert_eq!(
+            proto.code,
+            vec![
+                Instr::GetTabUp(0, 0, RK_CONST),
+                Instr::SetTabUp(0, RK_CONST + 1, 0),
+                Instr::Return(0, 1),
+            ]
+        );
+        assert_eq!(
+            proto.constants,
+            vec![
+                LuaValue::new(LuaVal::LuaString(LuaString::from("y"))),
+                LuaValue::new(LuaVal::LuaString(LuaString::from("x"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_upvalue_descriptors() {
+        let proto =
+            compile_src("local n = 0 function f() return function() n = n + 1 print(n) end end")
+                .unwrap();
+        // f needs _ENV for the global read by the inner function
+        let f = &proto.protos[0];
+        assert_eq!(
+            f.upvalues,
+            vec![
+                UpvalDesc {
+                    name: String::from("n"),
+                    in_stack: true,
+                    index: 0,
+                },
+                UpvalDesc {
+                    name: String::from("_ENV"),
+                    in_stack: false,
+                    index: 0,
+                },
+            ]
+        );
+        // The inner function reaches n and _ENV through the upvalues of f
+        let inner = &f.protos[0];
+        assert_eq!(
+            inner.upvalues,
+            vec![
+                UpvalDesc {
+                    name: String::from("n"),
+                    in_stack: false,
+                    index: 0,
+                },
+                UpvalDesc {
+                    name: String::from("_ENV"),
+                    in_stack: false,
+                    index: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_jump_targets() {
+        let proto = compile_src("while x do end").unwrap();
+        assert_eq!(
+            proto.code,
+            vec![
+                Instr::GetTabUp(0, 0, RK_CONST),
+                Instr::JmpIf(0, false, 1),
+                Instr::Jmp(-3),
+                Instr::Return(0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_close_captured_loop_locals() {
+        let proto =
+            compile_src("while true do local x = 1 f = function() return x end end").unwrap();
+        assert!(proto.code.contains(&Instr::Close(0)));
+
+        let proto = compile_src("while true do local x = 1 end").unwrap();
+        assert!(!proto
+            .code
+            .iter()
+            .any(|instr| matches!(instr, Instr::Close(_))));
+    }
+
+    #[test]
+    fn test_compile_break_outside_loop() {
+        assert_eq!(
+            compile_src("if true then break end"),
+            Err(ASTExecError::new(
+                "Break statement can only be used inside a while, repeat, or for loop"
+            ))
+        );
+    }
+    #[test]
+    fn test_compile_lines_and_local_names() {
+        let src = "local a = 1\nlocal function f()\n  return a\nend\n";
+        let proto = compile(&src.parse::<AST>().unwrap(), "=test").unwrap();
+        assert_eq!(proto.lineinfo, vec![1, 2, 4]);
+        assert_eq!(proto.local_name(1, 1), Some("a"));
+        assert_eq!(proto.local_name(2, 0), None);
+        assert_eq!(proto.local_name(2, 1), Some("f"));
+        let f = &proto.protos[0];
+        assert_eq!((f.line_defined, f.last_line_defined), (2, 4));
+        assert_eq!(f.line(0), Some(3));
+
+        // The lines come from the spans, which the JSON form of the AST keeps
+        let ast = AST::from_json(&src.parse::<AST>().unwrap().to_json()).unwrap();
+        let proto = compile(&ast.optimize(), "=test").unwrap();
+        assert_eq!(proto.lineinfo, vec![1, 2, 4]);
+        assert_eq!(proto.protos[0].line_defined, 2);
+
+        // Nodes built without a source have no lines
+        let ast = AST(Block {
+            statements: vec![],
+            return_stat: Some(vec![Expression::Nil(Span::default())]),
+        });
+        let proto = compile(&ast, "=test").unwrap();
+        assert!(proto.line(0).is_none());
+    }
+}
@@ -0,0 +1,607 @@
+// Debug interface for the tools built on the VM (coverage, profilers, step
+// debuggers): hooks called on the events of the running scripts, and the
+// inspection of the running functions, their locals and their upvalues.
+//
+// The hook of an environment is either a Rust `Hook` or a Lua function set by
+// `debug.sethook`, and sees the events of every coroutine. Hooks are off while
+// a hook runs, so a hook doesn't see the events of its own code. Line and
+// count events need the VM to look at every instruction: while such a hook is
+// set, the meter of the limits ends a batch at every instruction, so that
+// scripts without hooks don't pay for them.
+//
+// Lines come from the spans the parser records in the AST; the functions of
+// an AST built without a source have no line events, and are all reported as
+// main functions. Built-in functions don't run in frames of the
+// VM: they have no level, and call and return events are only raised for Lua
+// functions.
+use crate::bytecode::{Instr, Proto, Reg, RK_CONST};
+use crate::interpreter::environment::Env;
+use crate::interpreter::{chunk_id, ASTExecError, LuaString, LuaVal, LuaValue};
+use crate::vm::CallKind;
+use std::fmt;
+use std::rc::Rc;
+
+/// Event of a running script passed to a hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A Lua function was called, before its first instruction
+    Call,
+    /// A Lua function was called by a tail call, replacing its caller
+    TailCall,
+    /// A Lua function is about to return
+    Return,
+    /// The script is about to run a new line, or jumped back in the code
+    Line(u32),
+    /// The script ran the number of instructions of the mask
+    Count,
+}
+
+impl HookEvent {
+    /// Name of the event, as passed to a Lua hook
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::TailCall => "tail call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+/// Events a hook is called for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookMask {
+    /// Calls and tail calls
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+    /// Number of instructions between two count events
+    pub count: Option<u32>,
+}
+
+impl HookMask {
+    /// Mask of `debug.sethook`: the events "c", "r" and "l" of `mask`, and a
+    /// count event every `count` instructions if it isn't 0
+    pub fn parse(mask: &str, count: u32) -> Self {
+        HookMask {
+            call: mask.contains('c'),
+            ret: mask.contains('r'),
+            line: mask.contains('l'),
+            count: (count > 0).then_some(count),
+        }
+    }
+}
+
+// Events of the mask as a string of `debug.gethook`
+impl fmt::Display for HookMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, event) in [(self.call, 'c'), (self.ret, 'r'), (self.line, 'l')] {
+            if set {
+                write!(f, "{event}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hook called by the VM on the events of its mask, see `Env::set_hook`.
+/// An error stops the script like an error of its code.
+pub trait Hook {
+    fn on_event(&mut self, env: &mut Env, event: HookEvent) -> Result<(), ASTExecError>;
+}
+
+impl<F> Hook for F
+where
+    F: FnMut(&mut Env, HookEvent) -> Result<(), ASTExecError>,
+{
+    fn on_event(&mut self, env: &mut Env, event: HookEvent) -> Result<(), ASTExecError> {
+        self(env, event)
+    }
+}
+
+pub(crate) enum HookFn {
+    Lua(LuaValue),
+    Rust(Box<dyn Hook>),
+}
+
+/// Hook of an environment
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hook: Option<HookFn>,
+    pub(crate) mask: HookMask,
+    countdown: u32, // Instructions left before the next count event
+    running: bool,  // Hooks are off while one runs
+    replaced: bool, // The hook running was replaced
+}
+
+impl Hooks {
+    /// Replace the hook, or remove it with `None`
+    pub fn set(&mut self, hook: Option<HookFn>, mask: HookMask) {
+        self.mask = match hook {
+            Some(_) => mask,
+            None => HookMask::default(),
+        };
+        self.hook = hook;
+        self.countdown = self.mask.count.unwrap_or(0);
+        self.replaced = true;
+    }
+
+    pub fn hook(&self) -> Option<&HookFn> {
+        self.hook.as_ref()
+    }
+}
+
+// Environments are compared by their values, not by their hooks
+impl PartialEq for Hooks {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks").field("mask", &self.mask).finish()
+    }
+}
+
+/// Call the hook with an event, unless a hook is running
+pub(crate) fn fire(env: &mut Env, event: HookEvent) -> Result<(), ASTExecError> {
+    let hooks = env.hooks();
+    if hooks.running {
+        return Ok(());
+    }
+    let hook = match &hooks.hook {
+        Some(HookFn::Lua(func)) => HookFn::Lua(func.clone()),
+        Some(HookFn::Rust(_)) => hooks.hook.take().unwrap(),
+        None => return Ok(()),
+    };
+    hooks.running = true;
+    hooks.replaced = false;
+    let result = match hook {
+        HookFn::Lua(func) => {
+            let mut args = vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
+                event.name(),
+            )))];
+            if let HookEvent::Line(line) = event {
+                args.push(LuaValue::new(LuaVal::LuaInt(line as i64)));
+            }
+            func.call(args, env).map(|_| ())
+        }
+        HookFn::Rust(mut hook) => {
+            let result = hook.on_event(env, event);
+            let hooks = env.hooks();
+            if !hooks.replaced {
+                hooks.hook = Some(HookFn::Rust(hook));
+            }
+            result
+        }
+    };
+    env.hooks().running = false;
+    result
+}
+
+/// Raise the count and line events of the instruction at `pc`, about to run
+/// after the one at `old_pc` in the same frame
+pub(crate) fn instruction(
+    env: &mut Env,
+    proto: &Proto,
+    pc: usize,
+    old_pc: Option<usize>,
+) -> Result<(), ASTExecError> {
+    let hooks = env.hooks();
+    if hooks.running || hooks.hook.is_none() {
+        return Ok(());
+    }
+    if let Some(count) = hooks.mask.count {
+        hooks.countdown -= 1;
+        if hooks.countdown == 0 {
+            hooks.countdown = count;
+            fire(env, HookEvent::Count)?;
+        }
+    }
+    if !env.hooks().mask.line {
+        return Ok(());
+    }
+    if let Some(line) = proto.line(pc) {
+        let new_line = match old_pc {
+            Some(old_pc) => pc <= old_pc || proto.line(old_pc) != Some(line),
+            None => true,
+        };
+        if new_line {
+            fire(env, HookEvent::Line(line))?;
+        }
+    }
+    Ok(())
+}
+
+/// Kind of function, as reported by `debug.getinfo`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Lua,
+    /// Main function of a chunk
+    Main,
+    Builtin,
+}
+
+impl FunctionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FunctionKind::Lua => "Lua",
+            FunctionKind::Main => "main",
+            FunctionKind::Builtin => "C",
+        }
+    }
+}
+
+/// Description of a function, or of a function running at a level of the stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub function: LuaValue,
+    pub kind: FunctionKind,
+    /// Name of the chunk defining the function
+    pub source: String,
+    /// Name of the chunk as shown in error messages
+    pub short_src: String,
+    /// Line running, for a function running at a level with line information
+    pub current_line: Option<u32>,
+    /// Lines of the definition of the function, 0 for a main function
+    pub line_defined: Option<u32>,
+    pub last_line_defined: Option<u32>,
+    /// How the caller named the function: a global, local, method, field,
+    /// upvalue or for iterator, and the name
+    pub name: Option<(&'static str, String)>,
+    pub num_upvalues: usize,
+    pub num_params: usize,
+    pub is_vararg: bool,
+    pub is_tail_call: bool,
+    /// Lines with code, in increasing order
+    pub active_lines: Vec<u32>,
+}
+
+/// Description of a function, without the state of a call
+pub fn function_info(func: &LuaValue) -> FunctionInfo {
+    let LuaVal::Function(closure) = &func.0 else {
+        return FunctionInfo {
+            function: func.clone(),
+            kind: FunctionKind::Builtin,
+            source: String::from("=[C]"),
+            short_src: String::from("[C]"),
+            current_line: None,
+            line_defined: None,
+            last_line_defined: None,
+            name: None,
+            num_upvalues: 0,
+            num_params: 0,
+            is_vararg: true,
+            is_tail_call: false,
+            active_lines: vec![],
+        };
+    };
+    let proto = &closure.proto;
+    let mut active_lines = proto.lineinfo.clone();
+    active_lines.retain(|line| *line > 0);
+    active_lines.sort_unstable();
+    active_lines.dedup();
+    FunctionInfo {
+        function: func.clone(),
+        kind: match proto.line_defined {
+            0 => FunctionKind::Main,
+            _ => FunctionKind::Lua,
+        },
+        source: proto.source.clone(),
+        short_src: chunk_id(&proto.source),
+        current_line: None,
+        line_defined: Some(proto.line_defined),
+        last_line_defined: Some(proto.last_line_defined),
+        name: None,
+        num_upvalues: closure.upvalues.len(),
+        num_params: proto.num_params as usize,
+        is_vararg: proto.is_vararg,
+        is_tail_call: false,
+        active_lines,
+    }
+}
+
+/// Description of the Lua function running at `level` of the running thread,
+/// 0 being the innermost
+pub fn frame_info(env: &mut Env, level: usize) -> Option<FunctionInfo> {
+    let thread = env.thread();
+    let frame = thread.frame(level)?;
+    let func = LuaValue::new(LuaVal::Function(Rc::clone(&frame.closure)));
+    let mut info = function_info(&func);
+    info.current_line = frame.closure.proto.line(frame.current_pc());
+    info.is_tail_call = frame.kind == CallKind::TailCall;
+    if frame.kind == CallKind::Call {
+        info.name = thread
+            .frame(level + 1)
+            .and_then(|caller| call_name(&caller.closure.proto, caller.current_pc()));
+    }
+    Some(info)
+}
+
+// Name of the function called by the instruction at `pc`, from the
+// instruction that loaded it into its register
+fn call_name(proto: &Proto, pc: usize) -> Option<(&'static str, String)> {
+    let func = match proto.code.get(pc)? {
+        Instr::Call(a, _, _) => *a,
+        Instr::TForCall(_, _) => return Some(("for iterator", String::from("for iterator"))),
+        _ => return None,
+    };
+    let constant = |operand: u16| {
+        let index = operand.checked_sub(RK_CONST)? as usize;
+        let constant = &proto.constants[index];
+        constant.is_string().then(|| constant.to_string())
+    };
+    // The value of a register is only known if no jump led to the call
+    for (loader, instr) in proto.code[..pc].iter().enumerate().rev() {
+        match *instr {
+            Instr::Jmp(_)
+            | Instr::JmpIf(..)
+            | Instr::ForPrep(..)
+            | Instr::ForLoop(..)
+            | Instr::TForPrep(..)
+            | Instr::TForLoop(..) => return None,
+            _ if !sets_register(instr, func) => continue,
+            Instr::GetTabUp(_, up, key) => {
+                let kind = match proto.upvalues[up as usize].name.as_str() {
+                    "_ENV" => "global",
+                    _ => "field",
+                };
+                return Some((kind, constant(key)?));
+            }
+            Instr::GetTable(_, _, key) => return Some(("field", constant(key)?)),
+            Instr::GetMethod(_, _, key) => return Some(("method", constant(key)?)),
+            Instr::GetUpval(_, up) => {
+                return Some(("upvalue", proto.upvalues[up as usize].name.clone()))
+            }
+            Instr::Move(_, local) => {
+                let name = proto.local_name(local as usize + 1, loader)?;
+                return Some(("local", name.to_string()));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+// Whether the instruction may set the register
+fn sets_register(instr: &Instr, reg: Reg) -> bool {
+    match *instr {
+        Instr::Move(a, _)
+        | Instr::LoadK(a, _)
+        | Instr::LoadBool(a, _)
+        | Instr::GetUpval(a, _)
+        | Instr::GetTabUp(a, _, _)
+        | Instr::GetTable(a, _, _)
+        | Instr::GetMethod(a, _, _)
+        | Instr::NewTable(a)
+        | Instr::Unary(_, a, _)
+        | Instr::Binary(_, a, _, _)
+        | Instr::Closure(a, _) => a == reg,
+        Instr::LoadNil(a, b) => (a as usize..a as usize + b as usize).contains(&(reg as usize)),
+        Instr::Call(a, _, _) | Instr::VarArg(a, _) | Instr::TForCall(a, _) => reg >= a,
+        _ => false,
+    }
+}
+
+/// Name and value of the `n`th local variable active in the function running
+/// at `level`, counting from 1. Negative numbers are the extra arguments of a
+/// vararg function, named "(vararg)".
+pub fn local(env: &mut Env, level: usize, n: i64) -> Option<(String, LuaValue)> {
+    let thread = env.thread();
+    let frame = thread.frame(level)?;
+    if n < 0 {
+        let val = frame.varargs.get(n.unsigned_abs() as usize - 1)?;
+        return Some((String::from("(vararg)"), val.clone()));
+    }
+    let proto = &frame.closure.proto;
+    let name = proto
+        .local_name(n as usize, frame.current_pc())?
+        .to_string();
+    let val = thread.stack()[frame.base + n as usize - 1].clone();
+    Some((name, val))
+}
+
+/// Assign the `n`th local variable of the function running at `level`,
+/// returning its name, or None if there is no such variable or it holds the
+/// hidden state of a for loop
+pub fn set_local(env: &mut Env, level: usize, n: i64, val: LuaValue) -> Option<String> {
+    let thread = env.thread();
+    let frame = thread.frame_mut(level)?;
+    if n < 0 {
+        let vararg = frame.varargs.get_mut(n.unsigned_abs() as usize - 1)?;
+        *vararg = val;
+        return Some(String::from("(vararg)"));
+    }
+    let proto = &frame.closure.proto;
+    let name = proto
+        .local_name(n as usize, frame.current_pc())?
+        .to_string();
+    // The hidden state of a for loop is read by the loop instructions, which
+    // expect the values they stored there
+    if name.starts_with('(') {
+        return None;
+    }
+    let index = frame.base + n as usize - 1;
+    thread.stack_mut()[index] = val;
+    Some(name)
+}
+
+/// Name of the `n`th parameter of a Lua function, counting from 1
+pub fn param_name(func: &LuaValue, n: usize) -> Option<String> {
+    match &func.0 {
+        LuaVal::Function(closure) if n <= closure.proto.num_params as usize => {
+            closure.proto.local_name(n, 0).map(str::to_string)
+        }
+        _ => None,
+    }
+}
+
+/// Name and value of the `n`th upvalue of a Lua function, counting from 1
+pub fn upvalue(env: &mut Env, func: &LuaValue, n: usize) -> Option<(String, LuaValue)> {
+    let LuaVal::Function(closure) = &func.0 else {
+        return None;
+    };
+    let upval = closure.upvalues.get(n.checked_sub(1)?)?;
+    let name = closure.proto.upvalues[n - 1].name.clone();
+    Some((name, env.thread().get_upvalue(upval)))
+}
+
+/// Assign the `n`th upvalue of a Lua function, returning its name, or None if
+/// there is no such upvalue
+pub fn set_upvalue(env: &mut Env, func: &LuaValue, n: usize, val: LuaValue) -> Option<String> {
+    let LuaVal::Function(closure) = &func.0 else {
+        return None;
+    };
+    let upval = closure.upvalues.get(n.checked_sub(1)?)?;
+    env.thread().set_upvalue(upval, val);
+    Some(closure.proto.upvalues[n - 1].name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AST;
+    use std::cell::RefCell;
+
+    fn run(src: &str, env: &mut Env) -> Vec<String> {
+        let ast = src.parse::<AST>().unwrap();
+        let vals = ast.exec_chunk(env, "=test").unwrap();
+        vals.iter().map(LuaValue::to_string).collect()
+    }
+
+    #[test]
+    fn test_line_hook() {
+        let src = "local lines = {}
+debug.sethook(function(event, line) lines[#lines + 1] = line end, \"l\")
+local x = 0
+for i = 1, 2 do
+  x = x + i
+end
+debug.sethook()
+return lines[1], lines[2], lines[3], lines[4], lines[5], lines[6], lines[7]";
+        assert_eq!(
+            run(src, &mut Env::new()),
+            ["3", "4", "5", "4", "5", "4", "7"]
+        );
+    }
+
+    #[test]
+    fn test_call_return_and_count_hooks() {
+        let src = "local calls, returns = 0, 0
+local function f(n) if n > 0 then return f(n - 1) end end
+debug.sethook(function(event)
+  if event == \"return\" then returns = returns + 1 else calls = calls + 1 end
+end, \"cr\")
+f(3)
+debug.sethook()
+local counts = 0
+debug.sethook(function(event) counts = counts + 1 end, \"\", 10)
+for i = 1, 100 do end
+local hook, mask, count = debug.gethook()
+debug.sethook()
+return calls, returns, counts > 5, mask, count, debug.gethook()";
+        // f(3) makes three tail calls, which return once
+        assert_eq!(
+            run(src, &mut Env::new()),
+            ["4", "1", "true", "", "10", "nil"]
+        );
+    }
+
+    #[test]
+    fn test_getinfo() {
+        let src = "local t = {}
+t.field = function() return debug.getinfo(1, \"n\") end
+t.method = function(self) return debug.getinfo(1, \"n\") end
+local function loc()
+  local info = debug.getinfo(1)
+  return info.name, info.namewhat, info.what, info.currentline, info.linedefined,
+    info.lastlinedefined, info.short_src, info.source, info.nparams
+end
+glob = function() return debug.getinfo(1, \"n\").namewhat end
+local main = debug.getinfo(1, \"S\")
+return t.field().name, t.field().namewhat, t:method().namewhat, glob(), main.what,
+  debug.getinfo(print).what, debug.getinfo(100), loc()";
+        assert_eq!(
+            run(src, &mut Env::new()),
+            [
+                "field", "field", "method", "global", "main", "C", "nil", "loc", "local", "Lua",
+                "5", "4", "8", "test", "=test", "0"
+            ]
+        );
+        assert_eq!(
+            run("return pcall(debug.getinfo, 1.5)", &mut Env::new()),
+            [
+                "false",
+                "bad argument #1 to 'getinfo' (number has no integer representation)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locals_and_upvalues() {
+        let src = "local function f(a, ...)
+  local b = a * 2
+  debug.setlocal(1, 2, 10)
+  local n1, v1 = debug.getlocal(1, 1)
+  local n2, v2 = debug.getlocal(1, 2)
+  return n1, v1, n2, v2, debug.getlocal(1, -2)
+end
+local x = 1
+local function g() return x end
+local name, val = debug.getupvalue(g, 1)
+debug.setupvalue(g, 1, 5)
+return name, val, g(), x, debug.getlocal(f, 1), f(3, 4, 5)";
+        assert_eq!(
+            run(src, &mut Env::new()),
+            ["x", "1", "5", "5", "a", "a", "3", "b", "10", "(vararg)", "5"]
+        );
+    }
+
+    #[test]
+    fn test_rust_hook() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        let mut env = Env::new();
+        env.set_hook(
+            Box::new(move |env: &mut Env, event: HookEvent| {
+                if event == HookEvent::Call {
+                    let info = frame_info(env, 0).unwrap();
+                    let name = info.name.map_or(String::new(), |(_, name)| name);
+                    seen.borrow_mut().push((name, info.current_line));
+                }
+                Ok(())
+            }),
+            HookMask::parse("c", 0),
+        );
+        run(
+            "local function f() end\nf()\nlocal function g() f() end\ng()",
+            &mut env,
+        );
+        assert_eq!(
+            *events.borrow(),
+            [
+                (String::new(), Some(1)),
+                (String::from("f"), Some(1)),
+                (String::from("g"), Some(3)),
+                (String::from("f"), Some(1)),
+            ]
+        );
+
+        // Removing the hook from Rust also removes it for the scripts
+        env.remove_hook();
+        assert_eq!(run("return debug.gethook()", &mut env), ["nil"]);
+    }
+
+    #[test]
+    fn test_hooks_are_off_in_hooks() {
+        let src = "local events = 0
+debug.sethook(function() events = events + 1 local x = 1 x = x + 1 end, \"crl\")
+local y = 1
+debug.sethook()
+return events";
+        // The lines of the two statements, and no events of the hook itself
+        assert_eq!(run(src, &mut Env::new()), ["2"]);
+    }
+}
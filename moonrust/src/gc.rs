@@ -0,0 +1,854 @@
+// Tracing garbage collector for the reference cycles between Lua objects.
+//
+// Values are reference counted, which frees every object except those in a
+// cycle (a table containing itself, a closure stored in a table that one of its
+// upvalues refers to, ...). The heap keeps weak references to the tables,
+// closures and coroutines created by the VM, and a collection looks for the
+// objects that are only kept alive by references from other objects of the
+// heap. An object with more references than the heap holds to it is referenced
+// from outside (a register, the global environment, a Rust value of the host
+// program) and is a root. Every object that can't be reached from a root is
+// garbage: clearing its contents breaks the cycles, and reference counting
+// frees it.
+//
+// References from weak tables (`__mode`) are not followed when marking, and
+// the entries referring to garbage are removed. Tables with a `__gc` finalizer
+// are held by the heap until a collection finds them unreachable; they are
+// then resurrected until their finalizer has run.
+use crate::interpreter::environment::Env;
+use crate::interpreter::{LuaString, LuaTable, LuaVal, LuaValue, TableKey};
+use crate::vm::{Closure, Coroutine, Thread, UpvalRef, Upvalue};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+// Objects created before the first automatic collection
+const MIN_THRESHOLD: usize = 1024;
+
+/// Collection mode, named after the options of `collectgarbage`. The collector
+/// doesn't interleave its work with the program: every collection runs to the end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GcMode {
+    /// Full collections, once the heap grows to `pause`% of its live size
+    Incremental,
+    /// Minor collections of the young objects, and a major one when the heap grows too much
+    Generational,
+}
+
+impl GcMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GcMode::Incremental => "incremental",
+            GcMode::Generational => "generational",
+        }
+    }
+}
+
+// Object created by the VM
+enum GcRef {
+    Table(Weak<LuaTable>),
+    Closure(Weak<Closure>),
+    Coroutine(Weak<Coroutine>),
+}
+
+impl GcRef {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            GcRef::Table(table) => table.upgrade().map(Node::Table),
+            GcRef::Closure(closure) => closure.upgrade().map(Node::Closure),
+            GcRef::Coroutine(co) => co.upgrade().map(Node::Coroutine),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            GcRef::Table(table) => table.strong_count() > 0,
+            GcRef::Closure(closure) => closure.strong_count() > 0,
+            GcRef::Coroutine(co) => co.strong_count() > 0,
+        }
+    }
+}
+
+// Object taking part in a collection
+enum Node {
+    Table(Rc<LuaTable>),
+    Closure(Rc<Closure>),
+    Upvalue(UpvalRef),
+    Coroutine(Rc<Coroutine>),
+}
+
+impl Node {
+    fn ptr(&self) -> *const () {
+        match self {
+            Node::Table(table) => Rc::as_ptr(table) as *const (),
+            Node::Closure(closure) => Rc::as_ptr(closure) as *const (),
+            Node::Upvalue(upval) => Rc::as_ptr(upval) as *const (),
+            Node::Coroutine(co) => Rc::as_ptr(co) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Table(table) => Rc::strong_count(table),
+            Node::Closure(closure) => Rc::strong_count(closure),
+            Node::Upvalue(upval) => Rc::strong_count(upval),
+            Node::Coroutine(co) => Rc::strong_count(co),
+        }
+    }
+
+    // Call `f` with every object referenced by this one, including the weak references.
+    // False if the object is being modified and can't be traversed.
+    fn trace(&self, f: &mut dyn FnMut(Child)) -> bool {
+        match self {
+            Node::Table(table) => match table.try_borrow_parts() {
+                Some((array, hash, metatable)) => {
+                    metatable.iter().for_each(|mt| f(Child::Value(mt)));
+                    array.iter().for_each(|val| f(Child::Value(val)));
+                    for (key, val) in hash.iter() {
+                        f(Child::Key(key));
+                        f(Child::Value(val));
+                    }
+                    true
+                }
+                None => false,
+            },
+            Node::Closure(closure) => {
+                closure
+                    .upvalues
+                    .iter()
+                    .for_each(|upval| f(Child::Upvalue(upval)));
+                true
+            }
+            Node::Upvalue(upval) => match upval.try_borrow() {
+                Ok(upval) => {
+                    if let Upvalue::Closed(val) = &*upval {
+                        f(Child::Value(val));
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+            Node::Coroutine(co) => co.trace(f),
+        }
+    }
+}
+
+// Reference from one object to another
+pub(crate) enum Child<'a> {
+    Value(&'a LuaValue),
+    Key(&'a TableKey),
+    Upvalue(&'a UpvalRef),
+    Closure(&'a Rc<Closure>), // Function running in a coroutine
+}
+
+impl Child<'_> {
+    fn ptr(&self) -> Option<*const ()> {
+        match self {
+            Child::Value(val) => value_ptr(val),
+            Child::Key(key) => key_ptr(key),
+            Child::Upvalue(upval) => Some(Rc::as_ptr(upval) as *const ()),
+            Child::Closure(closure) => Some(Rc::as_ptr(closure) as *const ()),
+        }
+    }
+
+    fn to_node(&self) -> Option<Node> {
+        match self {
+            Child::Value(val) => match &val.0 {
+                LuaVal::LuaTable(table) => Some(Node::Table(Rc::clone(table))),
+                LuaVal::Function(closure) => Some(Node::Closure(Rc::clone(closure))),
+                LuaVal::Thread(co) | LuaVal::CoroutineWrapped(co) => {
+                    Some(Node::Coroutine(Rc::clone(co)))
+                }
+                _ => None,
+            },
+            Child::Key(TableKey::Table(table)) => Some(Node::Table(Rc::clone(&table.0))),
+            Child::Key(TableKey::Function(func)) => Some(Node::Closure(Rc::clone(&func.0))),
+            Child::Key(TableKey::Thread(co)) => Some(Node::Coroutine(Rc::clone(&co.0))),
+            Child::Key(_) => None,
+            Child::Upvalue(upval) => Some(Node::Upvalue(Rc::clone(upval))),
+            Child::Closure(closure) => Some(Node::Closure(Rc::clone(closure))),
+        }
+    }
+}
+
+fn string_value(val: &LuaValue) -> Option<LuaString> {
+    match &val.0 {
+        LuaVal::LuaString(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn value_ptr(val: &LuaValue) -> Option<*const ()> {
+    match &val.0 {
+        LuaVal::LuaTable(table) => Some(Rc::as_ptr(table) as *const ()),
+        LuaVal::Function(closure) => Some(Rc::as_ptr(closure) as *const ()),
+        LuaVal::Thread(co) | LuaVal::CoroutineWrapped(co) => Some(Rc::as_ptr(co) as *const ()),
+        _ => None,
+    }
+}
+
+fn key_ptr(key: &TableKey) -> Option<*const ()> {
+    match key {
+        TableKey::Table(table) => Some(Rc::as_ptr(&table.0) as *const ()),
+        TableKey::Function(func) => Some(Rc::as_ptr(&func.0) as *const ()),
+        TableKey::Thread(co) => Some(Rc::as_ptr(&co.0) as *const ()),
+        _ => None,
+    }
+}
+
+// References of a table that don't keep their objects alive, set by `__mode`
+#[derive(Clone, Copy, Default)]
+struct Weakness {
+    keys: bool,
+    values: bool,
+}
+
+impl Weakness {
+    fn of(table: &LuaTable) -> Weakness {
+        let Some((_, _, metatable)) = table.try_borrow_parts() else {
+            return Weakness::default();
+        };
+        let Some(LuaVal::LuaTable(mt)) = metatable.as_ref().map(|mt| &mt.0) else {
+            return Weakness::default();
+        };
+        let Some((_, hash, _)) = mt.try_borrow_parts() else {
+            return Weakness::default();
+        };
+        match hash
+            .get(&TableKey::String(LuaString::from("__mode")))
+            .map(|mode| &mode.0)
+        {
+            Some(LuaVal::LuaString(mode)) => Weakness {
+                keys: mode.contains('k'),
+                values: mode.contains('v'),
+            },
+            _ => Weakness::default(),
+        }
+    }
+}
+
+// State of a collection: the objects it looks at and which of them are reachable
+#[derive(Default)]
+struct Collection {
+    nodes: Vec<Node>,
+    index: HashMap<*const (), usize>,
+    weakness: Vec<Weakness>,
+    marked: Vec<bool>,
+    pending: Vec<usize>,
+    ephemerons: Vec<usize>, // Reachable tables with weak keys and strong values
+}
+
+impl Collection {
+    fn add(&mut self, node: Node) {
+        if let Entry::Vacant(entry) = self.index.entry(node.ptr()) {
+            entry.insert(self.nodes.len());
+            self.nodes.push(node);
+        }
+    }
+
+    // Add the objects referenced by the nodes: every object of the heap for
+    // a full collection, which also finds the tables created outside of the
+    // VM, and the upvalues of the young closures for a minor one
+    fn discover(&mut self, minor: bool) {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let mut children = vec![];
+            self.nodes[i].trace(&mut |child| {
+                if minor && !matches!(child, Child::Upvalue(_)) {
+                    return;
+                }
+                if let Some(Entry::Vacant(entry)) = child.ptr().map(|ptr| self.index.entry(ptr)) {
+                    if let Some(node) = child.to_node() {
+                        entry.insert(self.nodes.len() + children.len());
+                        children.push(node);
+                    }
+                }
+            });
+            self.nodes.extend(children);
+            i += 1;
+        }
+        self.weakness = self
+            .nodes
+            .iter()
+            .map(|node| match node {
+                Node::Table(table) => Weakness::of(table),
+                _ => Weakness::default(),
+            })
+            .collect();
+        self.marked = vec![false; self.nodes.len()];
+    }
+
+    // An object the collection doesn't look at is assumed to be alive
+    fn is_dead(&self, ptr: Option<*const ()>) -> bool {
+        ptr.and_then(|ptr| self.index.get(&ptr))
+            .is_some_and(|&i| !self.marked[i])
+    }
+
+    // Mark the pending objects and everything they keep alive
+    fn propagate(&mut self) {
+        loop {
+            while let Some(i) = self.pending.pop() {
+                if !mem::replace(&mut self.marked[i], true) {
+                    self.mark_children(i);
+                }
+            }
+            // The value of an entry with a weak key is reachable once its key is
+            let mut found = false;
+            for &i in &self.ephemerons {
+                let Node::Table(table) = &self.nodes[i] else {
+                    continue;
+                };
+                let Some((_, hash, _)) = table.try_borrow_parts() else {
+                    continue;
+                };
+                for (key, val) in hash.iter() {
+                    if self.is_dead(key_ptr(key)) {
+                        continue;
+                    }
+                    if let Some(&j) = value_ptr(val).and_then(|ptr| self.index.get(&ptr)) {
+                        if !self.marked[j] {
+                            self.pending.push(j);
+                            found = true;
+                        }
+                    }
+                }
+            }
+            if !found {
+                break;
+            }
+        }
+    }
+
+    fn mark_children(&mut self, i: usize) {
+        let Collection {
+            nodes,
+            index,
+            weakness,
+            marked,
+            pending,
+            ephemerons,
+        } = self;
+        let mut mark = |ptr: Option<*const ()>| {
+            if let Some(&j) = ptr.and_then(|ptr| index.get(&ptr)) {
+                if !marked[j] {
+                    pending.push(j);
+                }
+            }
+        };
+        match &nodes[i] {
+            Node::Table(table) => {
+                let Some((array, hash, metatable)) = table.try_borrow_parts() else {
+                    return;
+                };
+                let weak = weakness[i];
+                mark(metatable.as_ref().and_then(value_ptr));
+                if !weak.values {
+                    array.iter().for_each(|val| mark(value_ptr(val)));
+                }
+                if weak.keys && !weak.values {
+                    // The values are marked with their keys by `propagate`
+                    ephemerons.push(i);
+                    return;
+                }
+                for (key, val) in hash.iter() {
+                    if !weak.keys {
+                        mark(key_ptr(key));
+                    }
+                    if !weak.values {
+                        mark(value_ptr(val));
+                    }
+                }
+            }
+            node => {
+                node.trace(&mut |child| mark(child.ptr()));
+            }
+        }
+    }
+
+    // Remove the entries of the reachable weak tables referring to unreachable
+    // objects, by their values or by their keys
+    fn clear_weak(&self, by_keys: bool) -> Vec<Garbage> {
+        let mut trash = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let Node::Table(table) = node else {
+                continue;
+            };
+            let weak = self.weakness[i];
+            if !self.marked[i] || !(if by_keys { weak.keys } else { weak.values }) {
+                continue;
+            }
+            let removed = table.remove_entries(|key, val| match by_keys {
+                true => self.is_dead(key_ptr(key)),
+                false => self.is_dead(value_ptr(val)),
+            });
+            trash.push(Garbage::Entries(removed));
+        }
+        trash
+    }
+}
+
+/// Objects created by the VM and the state of the collector
+pub struct Heap {
+    objects: Vec<GcRef>,
+    old: usize, // objects[..old] survived a collection, used by the generational mode
+    // Tables with a finalizer, by address, with the order of their registration
+    finalizable: HashMap<*const LuaTable, (u64, Rc<LuaTable>)>,
+    finalizers_registered: u64,
+    to_finalize: Vec<Rc<LuaTable>>, // Unreachable tables, the last one is finalized first
+    finalizing: bool,
+    mode: GcMode,
+    pause: i64,     // Incremental: collect once the heap grows to pause% of its live size
+    minor_mul: i64, // Generational: minor collection after minor_mul% new objects
+    major_mul: i64, // Generational: major collection once the heap grows by major_mul%
+    debt: usize,    // Objects created since the last collection
+    live: usize,    // Objects alive after the last collection
+    major_base: usize, // Objects alive after the last major collection
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: vec![],
+            old: 0,
+            finalizable: HashMap::new(),
+            finalizers_registered: 0,
+            to_finalize: vec![],
+            finalizing: false,
+            mode: GcMode::Incremental,
+            pause: 200,
+            minor_mul: 20,
+            major_mul: 100,
+            debt: 0,
+            live: 0,
+            major_base: 0,
+        }
+    }
+
+    pub fn track_table(&mut self, table: &Rc<LuaTable>) {
+        self.objects.push(GcRef::Table(Rc::downgrade(table)));
+        self.debt += 1;
+    }
+
+    pub fn track_closure(&mut self, closure: &Rc<Closure>) {
+        self.objects.push(GcRef::Closure(Rc::downgrade(closure)));
+        self.debt += 1;
+    }
+
+    pub fn track_coroutine(&mut self, co: &Rc<Coroutine>) {
+        self.objects.push(GcRef::Coroutine(Rc::downgrade(co)));
+        self.debt += 1;
+    }
+
+    /// Mark the table for finalization, when it gets a metatable with a `__gc` field.
+    /// The heap keeps it alive until a collection finds it unreachable.
+    pub fn register_finalizer(&mut self, table: &Rc<LuaTable>) {
+        if let Entry::Vacant(entry) = self.finalizable.entry(Rc::as_ptr(table)) {
+            entry.insert((self.finalizers_registered, Rc::clone(table)));
+            self.finalizers_registered += 1;
+        }
+    }
+
+    pub fn mode(&self) -> GcMode {
+        self.mode
+    }
+
+    /// Switch to the incremental mode, returning the previous mode.
+    /// A pause of 0 keeps its current value.
+    pub fn set_incremental(&mut self, pause: i64) -> GcMode {
+        if pause != 0 {
+            self.pause = pause;
+        }
+        mem::replace(&mut self.mode, GcMode::Incremental)
+    }
+
+    /// Switch to the generational mode, returning the previous mode.
+    /// Parameters that are 0 keep their current value.
+    pub fn set_generational(&mut self, minor_mul: i64, major_mul: i64) -> GcMode {
+        if minor_mul != 0 {
+            self.minor_mul = minor_mul;
+        }
+        if major_mul != 0 {
+            self.major_mul = major_mul;
+        }
+        let previous = mem::replace(&mut self.mode, GcMode::Generational);
+        if previous != GcMode::Generational {
+            // Start from a full collection, whose survivors are old
+            self.full_collect();
+        }
+        previous
+    }
+
+    /// Run an automatic collection if enough objects were created since the last one
+    pub fn check(&mut self) {
+        match self.mode {
+            GcMode::Incremental => {
+                let threshold = (self.live as i64 * self.pause / 100) as usize;
+                if self.live + self.debt >= threshold.max(MIN_THRESHOLD) {
+                    self.full_collect();
+                }
+            }
+            GcMode::Generational => {
+                let threshold = (self.live as i64 * self.minor_mul / 100) as usize;
+                if self.debt >= threshold.max(MIN_THRESHOLD) {
+                    self.step();
+                }
+            }
+        }
+    }
+
+    /// Run one collection, which always completes: a minor collection in
+    /// generational mode, followed by a major one if the heap grew too much,
+    /// and a full collection in incremental mode
+    pub fn step(&mut self) {
+        match self.mode {
+            GcMode::Incremental => self.full_collect(),
+            GcMode::Generational => {
+                self.collect(true);
+                let threshold = (self.major_base as i64 * (100 + self.major_mul) / 100) as usize;
+                if self.live > threshold.max(MIN_THRESHOLD) {
+                    self.full_collect();
+                }
+            }
+        }
+    }
+
+    /// Collect every unreachable object
+    pub fn full_collect(&mut self) {
+        self.collect(false);
+        self.major_base = self.live;
+    }
+
+    /// Estimate of the memory used by the live objects, in bytes
+    pub fn count(&self) -> usize {
+        self.objects
+            .iter()
+            .filter_map(GcRef::upgrade)
+            .map(|node| match &node {
+                Node::Table(table) => table.size_estimate(),
+                Node::Closure(closure) => {
+                    mem::size_of::<Closure>()
+                        + closure.upvalues.len()
+                            * (mem::size_of::<UpvalRef>() + mem::size_of::<RefCell<Upvalue>>())
+                }
+                Node::Upvalue(_) => mem::size_of::<RefCell<Upvalue>>(),
+                Node::Coroutine(_) => mem::size_of::<Coroutine>(),
+            })
+            .sum()
+    }
+
+    /// Estimate of the memory used by the live objects, the strings they refer
+    /// to and the strings among `values`, in bytes. A shared string counts once.
+    pub fn count_with_strings(&self, values: &[LuaValue]) -> usize {
+        let mut strings = HashSet::new();
+        let mut bytes = 0;
+        let mut visit = |child: Child| {
+            let s = match child {
+                Child::Value(val) => string_value(val),
+                Child::Key(TableKey::String(s)) => Some(s.clone()),
+                Child::Upvalue(upval) => match upval.try_borrow().as_deref() {
+                    Ok(Upvalue::Closed(val)) => string_value(val),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(s) = s {
+                if strings.insert(s.as_str().as_ptr()) {
+                    bytes += s.as_str().len();
+                }
+            }
+        };
+        for node in self.objects.iter().filter_map(GcRef::upgrade) {
+            node.trace(&mut visit);
+        }
+        values.iter().for_each(|val| visit(Child::Value(val)));
+        self.count() + bytes
+    }
+
+    // A minor collection only looks for garbage among the objects created since
+    // the last collection: references from old objects count as roots
+    fn collect(&mut self, minor: bool) {
+        let first = if minor { self.old } else { 0 };
+        let mut gc = Collection::default();
+        for node in self.objects[first..].iter().filter_map(GcRef::upgrade) {
+            gc.add(node);
+        }
+        for (_, table) in self.finalizable.values() {
+            gc.add(Node::Table(Rc::clone(table)));
+        }
+        gc.discover(minor);
+
+        // Count the references between the nodes. The heap holds one more
+        // reference to the tables with a finalizer.
+        let mut internal = vec![0; gc.nodes.len()];
+        let mut traced = vec![true; gc.nodes.len()];
+        for (i, node) in gc.nodes.iter().enumerate() {
+            traced[i] = node.trace(&mut |child| {
+                if let Some(j) = child.ptr().and_then(|ptr| gc.index.get(&ptr)) {
+                    internal[*j] += 1;
+                }
+            });
+        }
+        for ptr in self.finalizable.keys() {
+            internal[gc.index[&(*ptr as *const ())]] += 1;
+        }
+
+        // Mark from the roots, the objects also referenced from outside.
+        // The collection holds one reference to every node.
+        gc.pending = (0..gc.nodes.len())
+            .filter(|&i| !traced[i] || gc.nodes[i].strong_count() - 1 > internal[i])
+            .collect();
+        gc.propagate();
+
+        // Weak values are removed before the finalized objects are resurrected,
+        // weak keys after, as in the reference implementation
+        let mut trash = gc.clear_weak(false);
+
+        // Resurrect the unreachable tables with a finalizer, which run in the
+        // reverse order of their registration
+        let mut unreachable: Vec<(u64, Rc<LuaTable>)> = vec![];
+        self.finalizable.retain(|ptr, (order, table)| {
+            if gc.is_dead(Some(*ptr as *const ())) {
+                unreachable.push((*order, Rc::clone(table)));
+                false
+            } else {
+                true
+            }
+        });
+        unreachable.sort_by_key(|(order, _)| *order);
+        for (_, table) in unreachable {
+            gc.pending.push(gc.index[&(Rc::as_ptr(&table) as *const ())]);
+            self.to_finalize.push(table);
+        }
+        gc.propagate();
+        trash.extend(gc.clear_weak(true));
+
+        // Clear the garbage. The contents are dropped once every cycle is broken.
+        for (node, _) in gc.nodes.iter().zip(&gc.marked).filter(|(_, marked)| !**marked) {
+            match node {
+                Node::Table(table) => trash.push(Garbage::Table(table.take_contents())),
+                Node::Upvalue(upval) => {
+                    let nil = Upvalue::Closed(LuaValue::new(LuaVal::LuaNil));
+                    trash.push(Garbage::Upvalue(upval.replace(nil)));
+                }
+                Node::Coroutine(co) => trash.push(Garbage::Thread(co.take_thread())),
+                // The upvalues of a closure are cleared on their own
+                Node::Closure(_) => (),
+            }
+        }
+        drop(trash);
+        drop(gc);
+
+        self.objects.retain(GcRef::is_alive);
+        // Every survivor is old for the generational mode
+        self.old = self.objects.len();
+        self.live = self.objects.len();
+        self.debt = 0;
+    }
+}
+
+// Contents of an unreachable object, or entries removed from a weak table
+#[allow(dead_code)]
+enum Garbage {
+    Table((Vec<LuaValue>, HashMap<TableKey, LuaValue>, Option<LuaValue>)),
+    Upvalue(Upvalue),
+    Thread(Thread),
+    Entries(Vec<(TableKey, LuaValue)>),
+}
+
+/// Run an automatic collection if needed, and the finalizers it made pending
+pub fn check(env: &mut Env) {
+    env.heap().check();
+    if !env.heap().to_finalize.is_empty() {
+        run_finalizers(env);
+    }
+}
+
+/// Call the `__gc` metamethods of the tables found unreachable by the collector.
+/// A finalizer can store its table again (resurrect it): the table is then
+/// freed by the first collection after it becomes unreachable again.
+pub fn run_finalizers(env: &mut Env) {
+    // A collection during a finalizer leaves its finalizers to this loop
+    if mem::replace(&mut env.heap().finalizing, true) {
+        return;
+    }
+    while let Some(table) = env.heap().to_finalize.pop() {
+        if let Some(finalizer) = table.get_metamethod("__gc") {
+            // As in the reference implementation, errors in finalizers are ignored
+            let _ = finalizer.call(vec![LuaValue::new(LuaVal::LuaTable(table))], env);
+        }
+    }
+    env.heap().finalizing = false;
+}
+
+/// Call the finalizers of every table marked for finalization, when the
+/// environment is closed
+pub fn finalize_all(env: &mut Env) {
+    let heap = env.heap();
+    let mut tables: Vec<(u64, Rc<LuaTable>)> = heap.finalizable.drain().map(|(_, t)| t).collect();
+    tables.sort_by_key(|(order, _)| *order);
+    heap.to_finalize.extend(tables.into_iter().map(|(_, table)| table));
+    run_finalizers(env);
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Environments are compared by their values, not by their collector
+impl PartialEq for Heap {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heap")
+            .field("objects", &self.objects.len())
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::environment::Env;
+    use crate::interpreter::LuaString;
+    use crate::AST;
+
+    fn table() -> Rc<LuaTable> {
+        Rc::new(LuaTable::new())
+    }
+
+    fn table_value(table: &Rc<LuaTable>) -> LuaValue {
+        LuaValue::new(LuaVal::LuaTable(Rc::clone(table)))
+    }
+
+    #[test]
+    fn test_collect_table_cycle() {
+        let mut heap = Heap::new();
+        let (a, b) = (table(), table());
+        a.insert_ident("b", table_value(&b));
+        b.insert_ident("a", table_value(&a));
+        heap.track_table(&a);
+        heap.track_table(&b);
+        let weak = Rc::downgrade(&a);
+        drop((a, b));
+        assert!(weak.upgrade().is_some());
+
+        heap.full_collect();
+        assert!(weak.upgrade().is_none());
+        assert_eq!(heap.count(), 0);
+    }
+
+    #[test]
+    fn test_keep_reachable_objects() {
+        let mut heap = Heap::new();
+        let (root, a) = (table(), table());
+        root.insert_ident("a", table_value(&a));
+        a.insert_ident("self", table_value(&a));
+        a.set_metatable(Some(table_value(&root)));
+        heap.track_table(&root);
+        heap.track_table(&a);
+        let weak = Rc::downgrade(&a);
+        drop(a);
+
+        heap.full_collect();
+        let a = weak.upgrade().unwrap();
+        assert!(a.get(TableKey::String(LuaString::from("self"))).is_some());
+        assert!(a.get_metatable().is_some());
+    }
+
+    #[test]
+    fn test_collect_closure_cycle() {
+        let mut env = Env::new();
+        let ast = "local function f() return f end
+            g = f"
+            .parse::<AST>()
+            .unwrap();
+        ast.exec(&mut env).unwrap();
+        let LuaVal::Function(f) = env.get_global("g").unwrap().0 else {
+            panic!("g is not a function")
+        };
+        let weak = Rc::downgrade(&f);
+        drop(f);
+
+        env.heap().full_collect();
+        assert!(weak.upgrade().is_some());
+        env.insert_global("g".to_string(), LuaValue::new(LuaVal::LuaNil));
+        env.heap().full_collect();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collect_library_table_cycle() {
+        let mut env = Env::new();
+        let ast = "local info = debug.getinfo(function() end, \"L\")
+            local lines = info.activelines
+            lines.self = lines
+            g = lines"
+            .parse::<AST>()
+            .unwrap();
+        ast.exec(&mut env).unwrap();
+        let LuaVal::LuaTable(lines) = env.get_global("g").unwrap().0 else {
+            panic!("g is not a table")
+        };
+        let weak = Rc::downgrade(&lines);
+        drop(lines);
+
+        env.insert_global("g".to_string(), LuaValue::new(LuaVal::LuaNil));
+        env.heap().full_collect();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_minor_collection_keeps_old_objects() {
+        let mut heap = Heap::new();
+        heap.set_generational(0, 0);
+        let old = table();
+        old.insert_ident("self", table_value(&old));
+        heap.track_table(&old);
+        // The table survives a collection while referenced, and becomes old
+        heap.step();
+        let weak_old = Rc::downgrade(&old);
+        drop(old);
+
+        let young = table();
+        young.insert_ident("self", table_value(&young));
+        heap.track_table(&young);
+        let weak_young = Rc::downgrade(&young);
+        drop(young);
+
+        // A minor collection only frees the young cycle
+        heap.step();
+        assert!(weak_old.upgrade().is_some());
+        assert!(weak_young.upgrade().is_none());
+        heap.full_collect();
+        assert!(weak_old.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_drop_env_frees_globals() {
+        let mut env = Env::new();
+        let ast = "t = {}
+            t.t = t
+            function f() return t end"
+            .parse::<AST>()
+            .unwrap();
+        ast.exec(&mut env).unwrap();
+        let LuaVal::LuaTable(t) = env.get_global("t").unwrap().0 else {
+            panic!("t is not a table")
+        };
+        let weak = Rc::downgrade(&t);
+        drop(t);
+
+        drop(env);
+        assert!(weak.upgrade().is_none());
+    }
+}
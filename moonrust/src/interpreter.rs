@@ -1,53 +1,132 @@
 use crate::ast::*;
+use crate::compiler;
 use crate::interpreter::environment::Env;
+use crate::limits::Limit;
+pub use crate::interpreter::string::LuaString;
+use crate::vm::{self, Closure, Coroutine};
 use std::collections::HashMap;
+use std::cell::{Cell, Ref, RefCell};
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::{cell::RefCell, rc::Rc};
-
-use self::environment::LocalEnv;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
+pub mod base;
+pub mod coroutine;
+pub mod debug;
 pub mod environment;
 pub mod expression;
-pub mod statement;
+pub mod package;
+pub mod string;
 
-#[derive(Debug, PartialEq)]
-pub enum LuaVal<'a> {
-    LuaTable(LuaTable<'a>),
+// Scalars are stored inline, every other value is a reference
+#[derive(Debug, PartialEq, Clone)]
+pub enum LuaVal {
+    LuaTable(Rc<LuaTable>),
     LuaNil,
     LuaBool(bool),
-    LuaNum([u8; 8], bool), // numerals as an array of 8 bytes, bool for is_float
-    LuaString(String),
-    Function(LuaFunction<'a>),
+    LuaInt(i64),
+    LuaFloat(f64),
+    LuaString(LuaString),
+    Function(Rc<Closure>),
     Print,
     TestPrint(Rc<RefCell<Vec<String>>>),
     Read,
     Random,
+    Load,
+    LoadFile,
+    DoFile,
+    Require(Rc<RefCell<Vec<String>>>), // Names of the modules being loaded
+    PreloadSearcher,
+    LuaSearcher,
+    SearchPath,
+    SetMetatable,
+    GetMetatable,
+    RawGet,
+    RawSet,
+    RawEqual,
+    RawLen,
+    Next,
+    Pairs,
+    IPairs,
+    IPairsIter,
+    Error,
+    PCall,
+    Type,
+    CollectGarbage,
+    Thread(Rc<Coroutine>),
+    CoroutineCreate,
+    CoroutineResume,
+    CoroutineYield,
+    CoroutineStatus,
+    CoroutineWrap,
+    CoroutineWrapped(Rc<Coroutine>), // Function returned by coroutine.wrap
+    CoroutineIsYieldable,
+    CoroutineRunning,
+    CoroutineClose,
+    DebugSetHook,
+    DebugGetHook,
+    DebugGetInfo,
+    DebugGetLocal,
+    DebugSetLocal,
+    DebugGetUpvalue,
+    DebugSetUpvalue,
+    RustFunction(RustFunction),
 }
 
-// Lua function captures environment in function call
-#[derive(Debug, PartialEq)]
-pub struct LuaFunction<'a> {
-    par_list: &'a ParList,
-    block: &'a Block,
-    captured_env: LocalEnv<'a>,
+type RustFn = dyn Fn(Vec<LuaValue>, &mut Env) -> Result<Vec<LuaValue>, ASTExecError>;
+
+// Function implemented in Rust, e.g. registered by the host program.
+// The closure is boxed so that the reference is a thin pointer.
+#[derive(Clone)]
+pub struct RustFunction(Rc<Box<RustFn>>);
+
+impl RustFunction {
+    pub fn new<F>(func: F) -> Self
+    where
+        F: Fn(Vec<LuaValue>, &mut Env) -> Result<Vec<LuaValue>, ASTExecError> + 'static,
+    {
+        RustFunction(Rc::new(Box::new(func)))
+    }
+
+    pub fn call(&self, args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        (self.0)(args, env)
+    }
+}
+
+impl PartialEq for RustFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for RustFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RustFunction({:p})", Rc::as_ptr(&self.0))
+    }
 }
 
-// Wrapper around LuaVal to allow multiple owners
+// Wrapper around LuaVal. Cloning copies scalars and shares the referenced
+// strings, tables and functions.
 #[derive(Debug, PartialEq, Clone)]
-pub struct LuaValue<'a>(Rc<LuaVal<'a>>);
-impl<'a> LuaValue<'a> {
-    pub fn new(val: LuaVal<'a>) -> Self {
-        LuaValue(Rc::new(val))
+pub struct LuaValue(pub(crate) LuaVal);
+impl LuaValue {
+    pub fn new(val: LuaVal) -> Self {
+        LuaValue(val)
     }
 
-    pub fn clone_rc(&self) -> LuaValue<'a> {
-        LuaValue(Rc::clone(&self.0))
+    /// Raw equality of references: tables and functions are equal only to themselves
+    pub(crate) fn ref_eq(&self, other: &LuaValue) -> bool {
+        match (&self.0, &other.0) {
+            (LuaVal::LuaTable(t1), LuaVal::LuaTable(t2)) => Rc::ptr_eq(t1, t2),
+            (LuaVal::Function(f1), LuaVal::Function(f2)) => Rc::ptr_eq(f1, f2),
+            (val1, val2) => val1 == val2,
+        }
     }
 
     pub fn is_false(&self) -> bool {
         // All values different from nil and false test true
-        matches!(&*self.0, LuaVal::LuaNil | LuaVal::LuaBool(false))
+        matches!(&self.0, LuaVal::LuaNil | LuaVal::LuaBool(false))
     }
 
     pub fn is_true(&self) -> bool {
@@ -55,346 +134,684 @@ impl<'a> LuaValue<'a> {
     }
 
     pub fn is_numeral(&self) -> bool {
-        matches!(&*self.0, LuaVal::LuaNum(_, _))
+        matches!(&self.0, LuaVal::LuaInt(_) | LuaVal::LuaFloat(_))
     }
 
     pub fn is_string(&self) -> bool {
-        matches!(&*self.0, LuaVal::LuaString(_))
+        matches!(&self.0, LuaVal::LuaString(_))
     }
 
     pub fn is_zero(&self) -> bool {
-        match &*self.0 {
-            LuaVal::LuaNum(num, is_float) => {
-                if *is_float {
-                    let num = f64::from_be_bytes(*num);
-                    num == 0.0
-                } else {
-                    let num = i64::from_be_bytes(*num);
-                    num == 0
-                }
-            }
+        match &self.0 {
+            LuaVal::LuaInt(num) => *num == 0,
+            LuaVal::LuaFloat(num) => *num == 0.0,
             _ => false,
         }
     }
 
     pub fn is_positive(&self) -> bool {
-        match &*self.0 {
-            LuaVal::LuaNum(num, is_float) => {
-                if *is_float {
-                    let num = f64::from_be_bytes(*num);
-                    num > 0.0
-                } else {
-                    let num = i64::from_be_bytes(*num);
-                    num > 0
-                }
-            }
+        match &self.0 {
+            LuaVal::LuaInt(num) => *num > 0,
+            LuaVal::LuaFloat(num) => *num > 0.0,
             _ => false,
         }
     }
 
     pub fn is_negative(&self) -> bool {
-        match &*self.0 {
-            LuaVal::LuaNum(num, is_float) => {
-                if *is_float {
-                    let num = f64::from_be_bytes(*num);
-                    num < 0.0
-                } else {
-                    let num = i64::from_be_bytes(*num);
-                    num < 0
-                }
-            }
+        match &self.0 {
+            LuaVal::LuaInt(num) => *num < 0,
+            LuaVal::LuaFloat(num) => *num < 0.0,
             _ => false,
         }
     }
 
     pub fn is_nil(&self) -> bool {
-        matches!(&*self.0, LuaVal::LuaNil)
+        matches!(&self.0, LuaVal::LuaNil)
+    }
+
+    /// Name of the value's type, as returned by Lua's `type` function
+    pub fn type_name(&self) -> &'static str {
+        match &self.0 {
+            LuaVal::LuaNil => "nil",
+            LuaVal::LuaBool(_) => "boolean",
+            LuaVal::LuaInt(_) | LuaVal::LuaFloat(_) => "number",
+            LuaVal::LuaString(_) => "string",
+            LuaVal::LuaTable(_) => "table",
+            LuaVal::Thread(_) => "thread",
+            // Lua functions and every built-in function
+            _ => "function",
+        }
     }
 
     pub fn is_greater_or_equal(&self, num: i64) -> Result<bool, ASTExecError> {
-        match &*self.0 {
-            LuaVal::LuaNum(n, is_float) => {
-                if *is_float {
-                    let n = f64::from_be_bytes(*n);
-                    if n.floor() as i64 >= num {
-                        Ok(true)
-                    } else {
-                        Ok(false)
-                    }
-                } else {
-                    let n = i64::from_be_bytes(*n);
-                    Ok(n >= num)
-                }
-            }
-            _ => Err(ASTExecError(String::from(
+        match &self.0 {
+            LuaVal::LuaInt(n) => Ok(*n >= num),
+            LuaVal::LuaFloat(n) => Ok(n.floor() as i64 >= num),
+            _ => Err(ASTExecError::from(String::from(
                 "Cannot compare values (types cannot be compared)",
             ))),
         }
     }
 
     pub fn is_less_or_equal(&self, num: i64) -> Result<bool, ASTExecError> {
-        match &*self.0 {
-            LuaVal::LuaNum(n, is_float) => {
-                if *is_float {
-                    let n = f64::from_be_bytes(*n);
-                    if n.ceil() as i64 <= num {
-                        Ok(true)
-                    } else {
-                        Ok(false)
-                    }
-                } else {
-                    let n = i64::from_be_bytes(*n);
-                    Ok(n <= num)
-                }
-            }
-            _ => Err(ASTExecError(String::from(
+        match &self.0 {
+            LuaVal::LuaInt(n) => Ok(*n <= num),
+            LuaVal::LuaFloat(n) => Ok(n.ceil() as i64 <= num),
+            _ => Err(ASTExecError::from(String::from(
                 "Cannot compare values (types cannot be compared)",
             ))),
         }
     }
 
-    pub fn negate_bool(self) -> Result<LuaValue<'a>, ASTExecError> {
-        match self.0.as_ref() {
+    pub fn negate_bool(self) -> Result<LuaValue, ASTExecError> {
+        match &self.0 {
             LuaVal::LuaBool(b) => Ok(LuaValue::new(LuaVal::LuaBool(!b))),
-            _ => Err(ASTExecError(String::from(
+            _ => Err(ASTExecError::from(String::from(
                 "Cannot negate value (only boolean can be negated)",
             ))),
         }
     }
 
     pub fn into_int(self) -> Result<i64, ASTExecError> {
-        match self.0.as_ref() {
-            LuaVal::LuaNum(n, is_float) => {
-                if *is_float {
-                    let n = f64::from_be_bytes(*n);
-                    if n.floor() == n.ceil() {
-                        Ok(n.floor() as i64)
-                    } else {
-                        Err(ASTExecError(String::from(
-                            "Cannot convert float that does not have exact integer value to integer"
-                        )))
-                    }
+        match &self.0 {
+            LuaVal::LuaInt(n) => Ok(*n),
+            LuaVal::LuaFloat(n) => {
+                if n.floor() == n.ceil() {
+                    Ok(n.floor() as i64)
                 } else {
-                    Ok(i64::from_be_bytes(*n))
+                    Err(ASTExecError::from(String::from(
+                        "Cannot convert float that does not have exact integer value to integer"
+                    )))
                 }
             }
-            _ => Err(ASTExecError(String::from(
+            _ => Err(ASTExecError::from(String::from(
                 "Cannot convert value to integer (types cannot be converted)",
             ))),
         }
     }
 
-    pub fn into_string(self) -> Result<String, ASTExecError> {
-        match self.0.as_ref() {
-            LuaVal::LuaNum(n, is_float) => {
-                if *is_float {
-                    let n = f64::from_be_bytes(*n);
-                    if n.floor() != n.ceil() {
-                        Ok(n.to_string())
-                    } else {
-                        // If n = 23.0, make it print as 23.0 instead of 23
-                        Ok(format!("{:.1}", n))
-                    }
+    /// The value as a string, sharing the storage of string values
+    pub fn into_string(self) -> Result<LuaString, ASTExecError> {
+        match &self.0 {
+            LuaVal::LuaInt(n) => Ok(LuaString::from(n.to_string())),
+            LuaVal::LuaFloat(n) => {
+                if n.floor() != n.ceil() {
+                    Ok(LuaString::from(n.to_string()))
                 } else {
-                    Ok(i64::from_be_bytes(*n).to_string())
+                    // If n = 23.0, make it print as 23.0 instead of 23
+                    Ok(LuaString::from(format!("{:.1}", n)))
                 }
             }
             LuaVal::LuaString(s) => Ok(s.clone()),
-            _ => Err(ASTExecError(String::from(
+            _ => Err(ASTExecError::from(String::from(
                 "Cannot convert value to String (types cannot be converted)",
             ))),
         }
     }
 
-    fn extract_first_return_val(return_vals: Vec<LuaValue>) -> LuaValue {
+    pub(crate) fn extract_first_return_val(return_vals: Vec<LuaValue>) -> LuaValue {
         if return_vals.is_empty() {
             // If no return values, return nil
             LuaValue::new(LuaVal::LuaNil)
         } else {
-            return_vals[0].clone_rc()
+            return_vals[0].clone()
         }
     }
 }
 
-impl<'a> Display for LuaValue<'a> {
+impl Display for LuaValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match &*self.0 {
+        match &self.0 {
             LuaVal::LuaNil => write!(f, "nil"),
             LuaVal::LuaBool(b) => write!(f, "{b}"),
-            LuaVal::LuaNum(n, is_float) => {
-                if *is_float {
-                    let n = f64::from_be_bytes(*n);
-                    if n.floor() != n.ceil() {
-                        write!(f, "{n}")
-                    } else {
-                        // If n = 23.0, make it print as 23.0 instead of 23
-                        write!(f, "{:.1}", n)
-                    }
+            LuaVal::LuaInt(n) => write!(f, "{n}"),
+            LuaVal::LuaFloat(n) => {
+                if n.floor() != n.ceil() {
+                    write!(f, "{n}")
                 } else {
-                    write!(f, "{}", i64::from_be_bytes(*n))
+                    // If n = 23.0, make it print as 23.0 instead of 23
+                    write!(f, "{:.1}", n)
                 }
             }
             LuaVal::LuaString(s) => write!(f, "{}", s),
-            LuaVal::LuaTable(t) => write!(f, "{:p}", t),
+            LuaVal::LuaTable(t) => write!(f, "{:p}", Rc::as_ptr(t)),
             // Display function as reference
-            LuaVal::Function(func) => write!(f, "{:p}", func),
+            LuaVal::Function(func) => write!(f, "{:p}", Rc::as_ptr(func)),
             LuaVal::Print => write!(f, "print"),
             LuaVal::TestPrint(_) => write!(f, "print"),
             LuaVal::Read => write!(f, "read"),
             LuaVal::Random => write!(f, "random"),
+            LuaVal::Load => write!(f, "load"),
+            LuaVal::LoadFile => write!(f, "loadfile"),
+            LuaVal::DoFile => write!(f, "dofile"),
+            LuaVal::Require(_) => write!(f, "require"),
+            LuaVal::PreloadSearcher => write!(f, "searcher_preload"),
+            LuaVal::LuaSearcher => write!(f, "searcher_Lua"),
+            LuaVal::SearchPath => write!(f, "searchpath"),
+            LuaVal::SetMetatable => write!(f, "setmetatable"),
+            LuaVal::GetMetatable => write!(f, "getmetatable"),
+            LuaVal::RawGet => write!(f, "rawget"),
+            LuaVal::RawSet => write!(f, "rawset"),
+            LuaVal::RawEqual => write!(f, "rawequal"),
+            LuaVal::RawLen => write!(f, "rawlen"),
+            LuaVal::Next => write!(f, "next"),
+            LuaVal::Pairs => write!(f, "pairs"),
+            LuaVal::IPairs => write!(f, "ipairs"),
+            LuaVal::IPairsIter => write!(f, "ipairs_iterator"),
+            LuaVal::Error => write!(f, "error"),
+            LuaVal::PCall => write!(f, "pcall"),
+            LuaVal::Type => write!(f, "type"),
+            LuaVal::CollectGarbage => write!(f, "collectgarbage"),
+            LuaVal::Thread(co) => write!(f, "{:p}", Rc::as_ptr(co)),
+            LuaVal::CoroutineCreate => write!(f, "create"),
+            LuaVal::CoroutineResume => write!(f, "resume"),
+            LuaVal::CoroutineYield => write!(f, "yield"),
+            LuaVal::CoroutineStatus => write!(f, "status"),
+            LuaVal::CoroutineWrap => write!(f, "wrap"),
+            LuaVal::CoroutineWrapped(co) => write!(f, "{:p}", Rc::as_ptr(co)),
+            LuaVal::CoroutineIsYieldable => write!(f, "isyieldable"),
+            LuaVal::CoroutineRunning => write!(f, "running"),
+            LuaVal::CoroutineClose => write!(f, "close"),
+            LuaVal::DebugSetHook => write!(f, "sethook"),
+            LuaVal::DebugGetHook => write!(f, "gethook"),
+            LuaVal::DebugGetInfo => write!(f, "getinfo"),
+            LuaVal::DebugGetLocal => write!(f, "getlocal"),
+            LuaVal::DebugSetLocal => write!(f, "setlocal"),
+            LuaVal::DebugGetUpvalue => write!(f, "getupvalue"),
+            LuaVal::DebugSetUpvalue => write!(f, "setupvalue"),
+            LuaVal::RustFunction(func) => write!(f, "{:p}", Rc::as_ptr(&func.0)),
         }
     }
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub enum TableKey {
-    String(String),
-    Number([u8; 8]),
+    String(LuaString),
+    Number(i64), // Integer keys, including floats with an exact integer value
+    Float(FloatKey),
+    Table(RefKey<LuaTable>),
+    Function(RefKey<Closure>),
+    Thread(RefKey<Coroutine>),
+}
+
+/// Float key that has no integer value and is not NaN, so that its bits
+/// compare like its value
+#[derive(Debug, Clone, Copy)]
+pub struct FloatKey(pub(crate) f64);
+
+impl FloatKey {
+    pub fn value(self) -> f64 {
+        self.0
+    }
 }
 
+impl PartialEq for FloatKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for FloatKey {}
+
+impl Hash for FloatKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// Table, function or thread used as a key, compared and hashed by reference
+pub struct RefKey<T>(pub Rc<T>);
+
+impl<T> Clone for RefKey<T> {
+    fn clone(&self) -> Self {
+        RefKey(Rc::clone(&self.0))
+    }
+}
+
+impl<T> PartialEq for RefKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Eq for RefKey<T> {}
+
+impl<T> Hash for RefKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+    }
+}
+
+impl<T> fmt::Debug for RefKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:p}", Rc::as_ptr(&self.0))
+    }
+}
+
+impl TableKey {
+    // Floats with an exact integer value are stored as integer keys, NaN is not a key
+    pub fn from_value(val: &LuaValue) -> Option<TableKey> {
+        match &val.0 {
+            LuaVal::LuaInt(num) => Some(TableKey::Number(*num)),
+            LuaVal::LuaFloat(num) => {
+                // Check if float has no significant decimal places and fits an integer
+                if num.is_nan() {
+                    None
+                } else if num % 1.0 == 0.0 && *num >= -(2f64.powi(63)) && *num < 2f64.powi(63) {
+                    Some(TableKey::Number(*num as i64))
+                } else {
+                    Some(TableKey::Float(FloatKey(*num)))
+                }
+            }
+            LuaVal::LuaString(name) => Some(TableKey::String(name.clone())),
+            LuaVal::LuaTable(table) => Some(TableKey::Table(RefKey(Rc::clone(table)))),
+            LuaVal::Function(func) => Some(TableKey::Function(RefKey(Rc::clone(func)))),
+            LuaVal::Thread(co) => Some(TableKey::Thread(RefKey(Rc::clone(co)))),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> LuaValue {
+        match self {
+            TableKey::String(s) => LuaValue::new(LuaVal::LuaString(s.clone())),
+            TableKey::Number(num) => LuaValue::new(LuaVal::LuaInt(*num)),
+            TableKey::Float(num) => LuaValue::new(LuaVal::LuaFloat(num.value())),
+            TableKey::Table(table) => LuaValue::new(LuaVal::LuaTable(Rc::clone(&table.0))),
+            TableKey::Function(func) => LuaValue::new(LuaVal::Function(Rc::clone(&func.0))),
+            TableKey::Thread(co) => LuaValue::new(LuaVal::Thread(Rc::clone(&co.0))),
+        }
+    }
+}
+
+// Borrowed array part, hash part and metatable of a table
+pub(crate) type TableParts<'a> = (
+    Ref<'a, Vec<LuaValue>>,
+    Ref<'a, HashMap<TableKey, LuaValue>>,
+    Ref<'a, Option<LuaValue>>,
+);
+
 // Instead of overwriting the entire Rc
-#[derive(Debug, PartialEq, Clone)]
-pub struct LuaTable<'a>(RefCell<HashMap<TableKey, LuaValue<'a>>>);
-impl<'a> LuaTable<'a> {
+#[derive(Clone)]
+pub struct LuaTable {
+    array: RefCell<Vec<LuaValue>>, // Values of the integer keys 1..=n
+    hash: RefCell<HashMap<TableKey, LuaValue>>, // Every other key
+    metatable: RefCell<Option<LuaValue>>,
+    read_only: Cell<bool>, // Lua code can't modify it, only the host can
+}
+
+impl LuaTable {
     pub fn new() -> Self {
-        LuaTable(RefCell::new(HashMap::new()))
+        LuaTable {
+            array: RefCell::new(Vec::new()),
+            hash: RefCell::new(HashMap::new()),
+            metatable: RefCell::new(None),
+            read_only: Cell::new(false),
+        }
     }
 
-    pub fn insert(&self, key: LuaValue<'a>, val: LuaValue<'a>) -> Result<(), ASTExecError> {
-        let key = match key.0.as_ref() {
-            LuaVal::LuaNum(num_bytes, is_float) => {
-                let num_bytes = if *is_float {
-                    let num = f64::from_be_bytes(*num_bytes);
-                    // Check if float has no significant decimal places
-                    if num % 1.0 == 0.0 {
-                        (num as i64).to_be_bytes()
-                    } else {
-                        *num_bytes
-                    }
-                } else {
-                    *num_bytes
-                };
+    /// Refuse the assignments and the metatable changes made by Lua code.
+    /// The `insert_*` functions of the host still modify the table.
+    pub fn set_read_only(&self) {
+        self.read_only.set(true);
+    }
 
-                TableKey::Number(num_bytes)
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.get()
+    }
+
+    pub fn insert(&self, key: LuaValue, val: LuaValue) -> Result<(), ASTExecError> {
+        if self.is_read_only() {
+            return Err(ASTExecError::new("attempt to modify a read-only table"));
+        }
+        let key = match TableKey::from_value(&key) {
+            Some(key) => key,
+            None if matches!(key.0, LuaVal::LuaFloat(n) if n.is_nan()) => {
+                return Err(ASTExecError::from(String::from("table index is NaN")))
             }
-            LuaVal::LuaString(name) => TableKey::String(name.clone()),
-            _ => {
-                return Err(ASTExecError(format!(
+            None => {
+                return Err(ASTExecError::from(format!(
                     "Cannot add '{key}' as key into a table"
                 )))
             }
         };
-        self.0.borrow_mut().insert(key, val);
+        self.insert_key(key, val);
         Ok(())
     }
 
-    pub fn insert_ident(&self, key: String, val: LuaValue<'a>) {
-        self.0.borrow_mut().insert(TableKey::String(key), val);
+    pub fn insert_ident(&self, key: &str, val: LuaValue) {
+        self.hash.borrow_mut().insert(TableKey::String(LuaString::from(key)), val);
     }
 
-    pub fn insert_int(&self, key: i64, val: LuaValue<'a>) {
-        self.0
-            .borrow_mut()
-            .insert(TableKey::Number(key.to_be_bytes()), val);
+    pub fn insert_int(&self, key: i64, val: LuaValue) {
+        let mut array = self.array.borrow_mut();
+        let len = array.len() as i64;
+        if 1 <= key && key <= len {
+            array[(key - 1) as usize] = val;
+        } else if key == len + 1 && !val.is_nil() {
+            // Grow the array part, then move the keys that follow it out of the hash part
+            let mut hash = self.hash.borrow_mut();
+            hash.remove(&TableKey::Number(key));
+            array.push(val);
+            loop {
+                let next = TableKey::Number(array.len() as i64 + 1);
+                match hash.get(&next) {
+                    Some(val) if !val.is_nil() => {
+                        let val = hash.remove(&next).unwrap();
+                        array.push(val);
+                    }
+                    _ => break,
+                }
+            }
+        } else {
+            self.hash
+                .borrow_mut()
+                .insert(TableKey::Number(key), val);
+        }
     }
 
-    pub fn get(&self, key: TableKey) -> Option<LuaValue<'a>> {
-        self.0.borrow().get(&key).map(|res| res.clone_rc())
+    fn insert_key(&self, key: TableKey, val: LuaValue) {
+        match key {
+            TableKey::Number(idx) => self.insert_int(idx, val),
+            key => {
+                self.hash.borrow_mut().insert(key, val);
+            }
+        }
     }
 
-    /// Returns the first integer index that comes before an absent index
-    pub fn calculate_border(&self) -> usize {
-        let mut idx: i64 = 1; // tables in Lua are 1 indexed
-        let table = self.0.borrow();
-
-        // loop through table
-        while (idx as usize) < table.len() {
-            let number = idx.to_be_bytes();
-            let table_key = TableKey::Number(number);
-            // if current idx's associated value is nil
-            if table.get(&table_key).is_none() || table.get_key_value(&table_key).unwrap().1.is_nil(){
-                // return previous idx
-                return (idx as usize) - 1;
+    pub fn get(&self, key: TableKey) -> Option<LuaValue> {
+        if let TableKey::Number(idx) = key {
+            if let Some(val) = self.get_int(idx) {
+                return Some(val);
             }
-            idx += 1;
         }
+        self.hash.borrow().get(&key).cloned()
+    }
 
-        if table.len() == 0 {
-            return 0;
+    // Value in the array part
+    fn get_int(&self, key: i64) -> Option<LuaValue> {
+        let array = self.array.borrow();
+        if 1 <= key && key <= array.len() as i64 {
+            Some(array[(key - 1) as usize].clone())
+        } else {
+            None
         }
+    }
 
-        idx as usize
+    pub fn get_metatable(&self) -> Option<LuaValue> {
+        self.metatable.borrow().as_ref().map(|mt| mt.clone())
     }
-}
 
-impl<'a> Default for LuaTable<'a> {
-    fn default() -> Self {
-        Self::new()
+    pub fn set_metatable(&self, metatable: Option<LuaValue>) {
+        *self.metatable.borrow_mut() = metatable;
     }
-}
 
-impl AST {
-    pub fn exec<'a, 'b>(&'a self, env: &'b mut Env<'a>) -> Result<(), ASTExecError> {
-        self.0.exec(env)?;
-        Ok(())
+    // Field of the metatable, e.g. "__index"
+    pub fn get_metamethod(&self, event: &str) -> Option<LuaValue> {
+        match &self.metatable.borrow().as_ref()?.0 {
+            LuaVal::LuaTable(mt) => mt
+                .get(TableKey::String(LuaString::from(event)))
+                .filter(|val| !val.is_nil()),
+            _ => None,
+        }
     }
-}
 
-impl Block {
-    fn exec<'a, 'b>(
-        &'a self,
-        env: &'b mut Env<'a>,
-    ) -> Result<Option<Vec<LuaValue<'a>>>, ASTExecError> {
-        let return_vals = self.exec_without_pop(env)?;
-        // Remove environment when exiting a scope
-        env.pop_local_env();
-        // Add another table in caller's scope
-        // Avoiding local variable being inserted after closure is created
-        env.extend_local_without_scope();
-
-        Ok(return_vals)
-    }
-
-    // Used for repeat-until loops (need to refer to local variables inside the loop)
-    fn exec_without_pop<'a, 'b>(
-        &'a self,
-        env: &'b mut Env<'a>,
-    ) -> Result<Option<Vec<LuaValue<'a>>>, ASTExecError> {
-        // Extend environment when entering a new scope
-        env.extend_local_env();
-
-        // Execute each statement
-        for statement in &self.statements {
-            let return_vals = statement.exec(env)?;
-            if return_vals.is_none() {
-                // Break statement
-                return Ok(None);
-            } else if let Some(return_vals) = return_vals {
-                if !return_vals.is_empty() {
-                    // Return statement inside statement list
-                    return Ok(Some(return_vals));
+    /// The entry following `key` in traversal order (the first one for None),
+    /// skipping fields set to nil. Err if `key` is not in the table.
+    /// The array part is traversed first, in order, then the hash part.
+    pub fn next(
+        &self,
+        key: Option<&TableKey>,
+    ) -> Result<Option<(TableKey, LuaValue)>, ASTExecError> {
+        let array = self.array.borrow();
+        // Position in the array part to continue from
+        let array_start = match key {
+            None => Some(0),
+            Some(&TableKey::Number(idx)) => {
+                if 1 <= idx && idx <= array.len() as i64 {
+                    Some(idx as usize)
+                } else {
+                    None
+                }
+            }
+            Some(_) => None,
+        };
+
+        let hash = self.hash.borrow();
+        let mut iter = hash.iter();
+        match array_start {
+            Some(start) => {
+                for (i, val) in array.iter().enumerate().skip(start) {
+                    if !val.is_nil() {
+                        let key = TableKey::Number(i as i64 + 1);
+                        return Ok(Some((key, val.clone())));
+                    }
+                }
+            }
+            None => {
+                let key = key.unwrap();
+                if !hash.contains_key(key) {
+                    return Err(ASTExecError::from(String::from("invalid key to 'next'")));
+                }
+                for (k, _) in iter.by_ref() {
+                    if k == key {
+                        break;
+                    }
                 }
             }
         }
+        Ok(iter
+            .find(|(_, val)| !val.is_nil())
+            .map(|(k, val)| (k.clone(), val.clone())))
+    }
 
-        // Optional return statement
-        let explist = match &self.return_stat {
-            Some(explist) => explist,
-            // Returning empty vector means there was no return statement
-            None => return Ok(Some(vec![])),
+    /// Returns an integer index whose value is non-nil followed by an absent index,
+    /// or 0 if index 1 is absent. Binary search in the array part when it ends with
+    /// nil, otherwise the array length unless the sequence continues in the hash part.
+    pub fn calculate_border(&self) -> usize {
+        let array = self.array.borrow();
+        match array.last() {
+            Some(last) if last.is_nil() => {
+                // array[..lo] has a non-nil last value (or is empty), array[..hi] does not
+                let (mut lo, mut hi) = (0, array.len());
+                while hi - lo > 1 {
+                    let mid = (lo + hi) / 2;
+                    if array[mid - 1].is_nil() {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                lo
+            }
+            _ => self.hash_border(array.len()),
+        }
+    }
+
+    // Border of a sequence continuing past the array part, which ends at `len`
+    fn hash_border(&self, len: usize) -> usize {
+        let hash = self.hash.borrow();
+        let present = |idx: usize| {
+            hash.get(&TableKey::Number(idx as i64))
+                .is_some_and(|val| !val.is_nil())
         };
+        if !present(len + 1) {
+            return len;
+        }
+        // Find an absent index by doubling, then binary search between the two
+        let (mut lo, mut hi) = (len + 1, (len + 1) * 2);
+        while present(hi) {
+            lo = hi;
+            if hi > i64::MAX as usize / 2 {
+                // Pathological table, fall back to a linear search
+                let mut idx = len + 1;
+                while present(idx + 1) {
+                    idx += 1;
+                }
+                return idx;
+            }
+            hi *= 2;
+        }
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if present(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    // Every non-nil entry, keyed like the hash part
+    fn entries(&self) -> HashMap<TableKey, LuaValue> {
+        let mut entries: HashMap<TableKey, LuaValue> = self
+            .hash
+            .borrow()
+            .iter()
+            .filter(|(_, val)| !val.is_nil())
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
+        for (i, val) in self.array.borrow().iter().enumerate() {
+            if !val.is_nil() {
+                entries.insert(TableKey::Number(i as i64 + 1), val.clone());
+            }
+        }
+        entries
+    }
+
+    /// The array part, hash part and metatable, borrowed for the collector.
+    /// None if the table is being modified and can't be traversed.
+    pub(crate) fn try_borrow_parts(&self) -> Option<TableParts<'_>> {
+        match (
+            self.array.try_borrow(),
+            self.hash.try_borrow(),
+            self.metatable.try_borrow(),
+        ) {
+            (Ok(array), Ok(hash), Ok(metatable)) => Some((array, hash, metatable)),
+            _ => None,
+        }
+    }
 
-        let mut return_vals = Vec::with_capacity(explist.len());
-        for (i, exp) in explist.iter().enumerate() {
-            // For each expression, only the first return value is used,
-            // but last expression can use multiple return values
-            if i == explist.len() - 1 {
-                return_vals.append(&mut exp.eval(env).unwrap());
-                break;
+    /// Remove the entries for which `dead` is true, returning them.
+    /// Entries of the array part are set to nil, so that the border doesn't move.
+    pub(crate) fn remove_entries(
+        &self,
+        mut dead: impl FnMut(&TableKey, &LuaValue) -> bool,
+    ) -> Vec<(TableKey, LuaValue)> {
+        let mut removed = vec![];
+        for (i, val) in self.array.borrow_mut().iter_mut().enumerate() {
+            let key = TableKey::Number(i as i64 + 1);
+            if !val.is_nil() && dead(&key, val) {
+                removed.push((key, std::mem::replace(val, LuaValue::new(LuaVal::LuaNil))));
             }
-            return_vals.push(LuaValue::extract_first_return_val(exp.eval(env).unwrap()));
         }
-        Ok(Some(return_vals))
+        let mut hash = self.hash.borrow_mut();
+        let keys: Vec<TableKey> = hash
+            .iter()
+            .filter(|(key, val)| dead(key, val))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            let val = hash.remove(&key).unwrap();
+            removed.push((key, val));
+        }
+        removed
+    }
+
+    /// Empty the table, returning its contents, to break the cycles it is part of
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn take_contents(
+        &self,
+    ) -> (Vec<LuaValue>, HashMap<TableKey, LuaValue>, Option<LuaValue>) {
+        (
+            self.array.take(),
+            self.hash.take(),
+            self.metatable.take(),
+        )
+    }
+
+    // Approximate size of the table in bytes
+    pub(crate) fn size_estimate(&self) -> usize {
+        std::mem::size_of::<LuaTable>()
+            + self.array.borrow().capacity() * std::mem::size_of::<LuaValue>()
+            + self.hash.borrow().capacity()
+                * (std::mem::size_of::<TableKey>() + std::mem::size_of::<LuaValue>())
+    }
+}
+
+impl From<HashMap<TableKey, LuaValue>> for LuaTable {
+    fn from(entries: HashMap<TableKey, LuaValue>) -> Self {
+        let table = LuaTable::new();
+        for (key, val) in entries {
+            table.insert_key(key, val);
+        }
+        table
+    }
+}
+
+// Tables can contain themselves (e.g. _G._G), so the same table is equal
+// to itself without comparing its entries
+impl PartialEq for LuaTable {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+            || (self.entries() == other.entries() && self.metatable == other.metatable)
+    }
+}
+
+thread_local! {
+    // Tables being printed, to print nested references to them by address
+    static DEBUG_VISITING: RefCell<Vec<*const LuaTable>> = const { RefCell::new(Vec::new()) };
+}
+
+impl fmt::Debug for LuaTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let ptr = self as *const LuaTable;
+        if DEBUG_VISITING.with(|visiting| visiting.borrow().contains(&ptr)) {
+            return write!(f, "LuaTable({ptr:p})");
+        }
+        DEBUG_VISITING.with(|visiting| visiting.borrow_mut().push(ptr));
+        let result = f
+            .debug_struct("LuaTable")
+            .field("array", &self.array.borrow())
+            .field("hash", &self.hash.borrow())
+            .field("metatable", &self.metatable.borrow())
+            .finish();
+        DEBUG_VISITING.with(|visiting| visiting.borrow_mut().pop());
+        result
+    }
+}
+
+impl Default for LuaTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AST {
+    /// Execute the chunk
+    pub fn exec(&self, env: &mut Env) -> Result<(), ASTExecError> {
+        self.exec_with_return(env)?;
+        Ok(())
+    }
+
+    /// Execute the chunk and return the values of its top-level return statement.
+    /// The chunk is compiled to bytecode and runs on the VM.
+    pub fn exec_with_return(
+        &self,
+        env: &mut Env,
+    ) -> Result<Vec<LuaValue>, ASTExecError> {
+        let proto = compiler::compile(self, "?")?;
+        let main = Rc::new(Closure::main(proto, env.get_global_env()));
+        vm::call_closure(&main, env.get_varargs(), env)
+    }
+
+    /// Execute the chunk and return the values of its top-level return
+    /// statement. The functions of the chunk know the name of their chunk,
+    /// `source`, for the debug interface.
+    pub fn exec_chunk(&self, env: &mut Env, source: &str) -> Result<Vec<LuaValue>, ASTExecError> {
+        let proto = compiler::compile(self, source)?;
+        let main = Rc::new(Closure::main(proto, env.get_global_env()));
+        vm::call_closure(&main, env.get_varargs(), env)
     }
 }
 
@@ -406,20 +823,20 @@ mod tests {
     #[test]
     fn accepts_calculate_border(){
 
-        let table = LuaTable(RefCell::new(HashMap::from([
+        let table = LuaTable::from(HashMap::from([
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(2)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(2),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(3)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Number(3),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
-        ])));
+        ]));
         let result = 3;
         assert_eq!(table.calculate_border(), result);
 
@@ -428,37 +845,187 @@ mod tests {
     #[test]
     fn accepts_calculate_border2() {
 
-        let table2 = LuaTable(RefCell::new(HashMap::from([
+        let table2 = LuaTable::from(HashMap::from([
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(2)),
+                TableKey::Number(2),
                 LuaValue::new(LuaVal::LuaNil),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(3)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Number(3),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
-        ])));
+        ]));
         let result2 = 1;
         assert_eq!(table2.calculate_border(), result2);
     }
 
     #[test]
     fn accepts_calculate_border3() {
-        let table3 = LuaTable(RefCell::new(HashMap::new()));
+        let table3 = LuaTable::from(HashMap::new());
         let result3 = 0;
         assert_eq!(table3.calculate_border(), result3);
     }
+
+    fn lua_int(n: i64) -> LuaValue {
+        LuaValue::new(LuaVal::LuaInt(n))
+    }
+
+    #[test]
+    fn test_table_array_part() {
+        let table = LuaTable::new();
+        for i in 1..=100 {
+            table.insert_int(i, lua_int(i * 10));
+        }
+        assert_eq!(table.array.borrow().len(), 100);
+        assert!(table.hash.borrow().is_empty());
+        assert_eq!(table.calculate_border(), 100);
+        assert_eq!(table.get(TableKey::Number(7)), Some(lua_int(70)));
+
+        // Float keys with an integer value use the array part too
+        let key = LuaValue::new(LuaVal::LuaFloat(101f64));
+        table.insert(key, lua_int(1010)).unwrap();
+        assert_eq!(table.array.borrow().len(), 101);
+
+        // Removing the last values leaves a border inside the array part
+        table.insert_int(101, LuaValue::new(LuaVal::LuaNil));
+        table.insert_int(100, LuaValue::new(LuaVal::LuaNil));
+        assert_eq!(table.calculate_border(), 99);
+    }
+
+    #[test]
+    fn test_table_migrate_hash_keys() {
+        // Filling in reverse order stores the keys in the hash part until 1 is set
+        let table = LuaTable::new();
+        for i in (2..=50).rev() {
+            table.insert_int(i, lua_int(i));
+        }
+        assert!(table.array.borrow().is_empty());
+        assert_eq!(table.calculate_border(), 0);
+        table.insert_int(1, lua_int(1));
+        assert_eq!(table.array.borrow().len(), 50);
+        assert!(table.hash.borrow().is_empty());
+        assert_eq!(table.calculate_border(), 50);
+    }
+
+    #[test]
+    fn test_table_border_in_hash_part() {
+        let table = LuaTable::new();
+        table.insert_int(1, lua_int(1));
+        // Keys past the array part, e.g. left there by setting the array's end to nil
+        for i in 3..=40 {
+            table.insert_int(i, lua_int(i));
+        }
+        table.hash.borrow_mut().insert(TableKey::Number(2), lua_int(2));
+        assert_eq!(table.calculate_border(), 40);
+    }
+
+    #[test]
+    fn test_value_size() {
+        // Scalars are unboxed and references are a single pointer
+        assert_eq!(std::mem::size_of::<LuaValue>(), 16);
+    }
+
+    #[test]
+    fn test_table_key_normalization() {
+        let float = |n: f64| LuaValue::new(LuaVal::LuaFloat(n));
+        assert_eq!(
+            TableKey::from_value(&float(2.0)),
+            Some(TableKey::Number(2))
+        );
+        assert_eq!(
+            TableKey::from_value(&float(-0.0)),
+            Some(TableKey::Number(0))
+        );
+        assert_eq!(
+            TableKey::from_value(&float(2.5)),
+            Some(TableKey::Float(FloatKey(2.5)))
+        );
+        // Integral floats out of the integer range stay floats
+        assert_eq!(
+            TableKey::from_value(&float(2f64.powi(63))),
+            Some(TableKey::Float(FloatKey(2f64.powi(63))))
+        );
+        assert_eq!(TableKey::from_value(&float(f64::NAN)), None);
+
+        let table = LuaTable::new();
+        assert_eq!(
+            table.insert(float(f64::NAN), lua_int(1)),
+            Err(ASTExecError::new("table index is NaN"))
+        );
+        table.insert(float(1.0), lua_int(1)).unwrap();
+        assert_eq!(table.get(TableKey::from_value(&lua_int(1)).unwrap()), Some(lua_int(1)));
+    }
+
+    #[test]
+    fn test_table_next() {
+        let table = LuaTable::new();
+        table.insert_int(1, lua_int(10));
+        table.insert_int(2, LuaValue::new(LuaVal::LuaNil));
+        table.insert_int(3, lua_int(30));
+        table.insert_ident("a", lua_int(1));
+
+        let mut keys = vec![];
+        let mut key = None;
+        while let Some((k, _)) = table.next(key.as_ref()).unwrap() {
+            keys.push(k.clone());
+            key = Some(k);
+        }
+        assert_eq!(keys[0], TableKey::Number(1));
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains(&TableKey::Number(3)));
+        assert!(keys.contains(&TableKey::String(LuaString::from("a"))));
+
+        assert_eq!(
+            table.next(Some(&TableKey::String(LuaString::from("b")))),
+            Err(ASTExecError::new("invalid key to 'next'"))
+        );
+    }
+}
+
+// Name of a chunk as shown in error messages, following the reference implementation:
+// "=name" is used as is, "@name" is a file name and anything else is the source itself
+pub(crate) fn chunk_id(chunkname: &str) -> String {
+    if let Some(name) = chunkname.strip_prefix('=') {
+        return name.to_string();
+    }
+    if let Some(file) = chunkname.strip_prefix('@') {
+        return file.to_string();
+    }
+    let first_line = chunkname.trim_start().lines().next().unwrap_or("");
+    if first_line.len() > 40 || first_line.len() < chunkname.trim().len() {
+        let short: String = first_line.chars().take(40).collect();
+        format!("[string \"{short}...\"]")
+    } else {
+        format!("[string \"{first_line}\"]")
+    }
 }
 
+/// Error raised while running Lua code. Errors raised by an execution limit
+/// also carry the limit, for the host to tell them apart.
 #[derive(Debug, PartialEq)]
-pub struct ASTExecError(String);
+pub struct ASTExecError(String, Option<Limit>);
 impl ASTExecError {
     pub fn new(msg: &str) -> Self {
-        ASTExecError(msg.to_string())
+        ASTExecError(msg.to_string(), None)
+    }
+
+    /// Error stopping a script that exceeded `limit`
+    pub fn limit_exceeded(limit: Limit) -> Self {
+        ASTExecError(limit.to_string(), Some(limit))
+    }
+
+    /// The execution limit that raised the error, if any
+    pub fn limit(&self) -> Option<Limit> {
+        self.1
+    }
+}
+impl From<String> for ASTExecError {
+    fn from(msg: String) -> Self {
+        ASTExecError(msg, None)
     }
 }
 impl Display for ASTExecError {
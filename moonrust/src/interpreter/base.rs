@@ -0,0 +1,289 @@
+// Functions of the basic library working with tables and metatables
+use crate::gc;
+use crate::interpreter::environment::Env;
+use crate::interpreter::{ASTExecError, LuaString, LuaTable, LuaVal, LuaValue, TableKey};
+use crate::limits;
+
+// setmetatable(table, metatable)
+// A table whose new metatable has a __gc field is marked for finalization
+pub fn setmetatable(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = table_arg(&args, 1, "setmetatable")?;
+    let metatable = match args.get(1).map(|mt| &mt.0) {
+        Some(LuaVal::LuaTable(_)) => Some(args[1].clone()),
+        Some(LuaVal::LuaNil) => None,
+        _ => {
+            return Err(ASTExecError::from(String::from(
+                "bad argument #2 to 'setmetatable' (nil or table expected)",
+            )))
+        }
+    };
+    if table.get_metamethod("__metatable").is_some() || table.is_read_only() {
+        return Err(ASTExecError::from(String::from(
+            "cannot change a protected metatable",
+        )));
+    }
+    table.set_metatable(metatable);
+    if let (LuaVal::LuaTable(table), Some(_)) = (&args[0].0, table.get_metamethod("__gc")) {
+        env.heap().register_finalizer(table);
+    }
+    Ok(vec![args[0].clone()])
+}
+
+// getmetatable(object)
+// The __metatable field of the metatable is returned instead of the metatable if present
+pub fn getmetatable(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let metatable = match args.first().map(|arg| &arg.0) {
+        Some(LuaVal::LuaTable(table)) => match table.get_metamethod("__metatable") {
+            Some(protected) => Some(protected),
+            None => table.get_metatable(),
+        },
+        Some(_) => None,
+        None => {
+            return Err(ASTExecError::from(String::from(
+                "bad argument #1 to 'getmetatable' (value expected)",
+            )))
+        }
+    };
+    Ok(vec![metatable.unwrap_or_else(nil)])
+}
+
+// rawget(table, key)
+pub fn rawget(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = table_arg(&args, 1, "rawget")?;
+    let val = args
+        .get(1)
+        .and_then(TableKey::from_value)
+        .and_then(|key| table.get(key));
+    Ok(vec![val.unwrap_or_else(nil)])
+}
+
+// rawset(table, key, value)
+pub fn rawset(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = table_arg(&args, 1, "rawset")?;
+    let key = args.get(1).map_or_else(nil, |key| key.clone());
+    let val = args.get(2).map_or_else(nil, |val| val.clone());
+    table.insert(key, val)?;
+    Ok(vec![args[0].clone()])
+}
+
+// rawequal(v1, v2)
+pub fn rawequal(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    if args.len() < 2 {
+        return Err(ASTExecError::from(format!(
+            "bad argument #{} to 'rawequal' (value expected)",
+            args.len() + 1
+        )));
+    }
+    let equal = match (&args[0].0, &args[1].0) {
+        (LuaVal::LuaNil, LuaVal::LuaNil) => true,
+        (LuaVal::LuaBool(b1), LuaVal::LuaBool(b2)) => b1 == b2,
+        (LuaVal::LuaString(s1), LuaVal::LuaString(s2)) => s1 == s2,
+        (LuaVal::LuaInt(n1), LuaVal::LuaInt(n2)) => n1 == n2,
+        _ if args[0].is_numeral() && args[1].is_numeral() => {
+            as_float(&args[0]) == as_float(&args[1])
+        }
+        // Tables and functions are equal only to themselves
+        _ => args[0].ref_eq(&args[1]),
+    };
+    Ok(vec![LuaValue::new(LuaVal::LuaBool(equal))])
+}
+
+// rawlen(v)
+pub fn rawlen(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let len = match args.first().map(|arg| &arg.0) {
+        Some(LuaVal::LuaTable(table)) => table.calculate_border() as i64,
+        Some(LuaVal::LuaString(s)) => s.len() as i64,
+        _ => return Err(ASTExecError::from(String::from("table or string expected"))),
+    };
+    Ok(vec![LuaValue::new(LuaVal::LuaInt(len))])
+}
+
+// next(table [, key])
+// Returns the next key and its value, or nil after the last key
+pub fn next(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = table_arg(&args, 1, "next")?;
+    let key = match args.get(1) {
+        Some(key) if !key.is_nil() => match TableKey::from_value(key) {
+            Some(key) => Some(key),
+            None => return Err(ASTExecError::from(String::from("invalid key to 'next'"))),
+        },
+        _ => None,
+    };
+    match table.next(key.as_ref())? {
+        Some((key, val)) => Ok(vec![key.to_value(), val]),
+        None => Ok(vec![nil()]),
+    }
+}
+
+// pairs(table)
+// Returns next, table, nil, unless the metatable has a __pairs field
+pub fn pairs(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = table_arg(&args, 1, "pairs")?;
+    if let Some(handler) = table.get_metamethod("__pairs") {
+        let mut vals = handler.call(vec![args[0].clone()], env)?;
+        vals.resize_with(3, nil);
+        return Ok(vals);
+    }
+    Ok(vec![LuaValue::new(LuaVal::Next), args[0].clone(), nil()])
+}
+
+// ipairs(table)
+// Iterates over the pairs (1, t[1]), (2, t[2]), ... up to the first nil value
+pub fn ipairs(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    table_arg(&args, 1, "ipairs")?;
+    Ok(vec![
+        LuaValue::new(LuaVal::IPairsIter),
+        args[0].clone(),
+        LuaValue::new(LuaVal::LuaInt(0_i64)),
+    ])
+}
+
+pub fn ipairs_iter(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let table = args.first().map_or_else(nil, |table| table.clone());
+    let i = match args.get(1) {
+        Some(i) => i.clone().into_int()? + 1,
+        None => 1,
+    };
+    let i = LuaValue::new(LuaVal::LuaInt(i));
+    let val = table.index(i.clone(), env)?;
+    if val.is_nil() {
+        Ok(vec![nil()])
+    } else {
+        Ok(vec![i, val])
+    }
+}
+
+// error(message)
+pub fn error(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let msg = args.first().map_or_else(nil, |msg| msg.clone());
+    Err(ASTExecError::from(msg.to_string()))
+}
+
+// pcall(f, ...)
+// Calls f in protected mode: returns true and the results of the call,
+// or false and the error message. The errors of the execution limits are
+// only caught if the limits are catchable, and interruptions never are.
+pub fn pcall(mut args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    if args.is_empty() {
+        return Err(ASTExecError::from(String::from(
+            "bad argument #1 to 'pcall' (value expected)",
+        )));
+    }
+    let func = args.remove(0);
+    match func.call(args, env) {
+        Ok(mut results) => {
+            results.insert(0, LuaValue::new(LuaVal::LuaBool(true)));
+            Ok(results)
+        }
+        Err(err) if !limits::catchable(env, &err) => Err(err),
+        Err(err) => Ok(vec![
+            LuaValue::new(LuaVal::LuaBool(false)),
+            LuaValue::new(LuaVal::LuaString(LuaString::from(err.0))),
+        ]),
+    }
+}
+
+// type(v)
+pub fn type_fn(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    match args.first() {
+        Some(val) => Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
+            val.type_name().to_string(),
+        )))]),
+        None => Err(ASTExecError::from(String::from(
+            "bad argument #1 to 'type' (value expected)",
+        ))),
+    }
+}
+
+// collectgarbage([opt [, ...]])
+// Collections are not incremental: "step" runs a whole collection (a minor one in
+// generational mode) and always reports a finished cycle, and the step multiplier
+// and size of "incremental" are checked but have no effect
+pub fn collectgarbage(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let opt = match args.first().map(|arg| &arg.0) {
+        None | Some(LuaVal::LuaNil) => String::from("collect"),
+        Some(LuaVal::LuaString(s)) => s.to_string(),
+        Some(_) => {
+            return Err(ASTExecError::from(format!(
+                "bad argument #1 to 'collectgarbage' (string expected, got {})",
+                args[0].type_name()
+            )))
+        }
+    };
+    let heap = env.heap();
+    match opt.as_str() {
+        "collect" => {
+            heap.full_collect();
+            gc::run_finalizers(env);
+            Ok(vec![LuaValue::new(LuaVal::LuaInt(0))])
+        }
+        "count" => Ok(vec![LuaValue::new(LuaVal::LuaFloat(
+            heap.count() as f64 / 1024.0,
+        ))]),
+        "step" => {
+            heap.step();
+            gc::run_finalizers(env);
+            Ok(vec![LuaValue::new(LuaVal::LuaBool(true))])
+        }
+        "incremental" => {
+            let pause = int_arg(&args, 2)?;
+            int_arg(&args, 3)?;
+            int_arg(&args, 4)?;
+            let previous = heap.set_incremental(pause);
+            Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
+                previous.name(),
+            )))])
+        }
+        "generational" => {
+            let previous = heap.set_generational(int_arg(&args, 2)?, int_arg(&args, 3)?);
+            Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
+                previous.name(),
+            )))])
+        }
+        _ => Err(ASTExecError::from(format!(
+            "bad argument #1 to 'collectgarbage' (invalid option '{opt}')"
+        ))),
+    }
+}
+
+// Optional integer parameter of collectgarbage, 0 when absent
+fn int_arg(args: &[LuaValue], n: usize) -> Result<i64, ASTExecError> {
+    match args.get(n - 1) {
+        None => Ok(0),
+        Some(arg) if arg.is_nil() => Ok(0),
+        Some(arg) if arg.is_numeral() => arg.clone().into_int(),
+        Some(arg) => Err(ASTExecError::from(format!(
+            "bad argument #{n} to 'collectgarbage' (number expected, got {})",
+            arg.type_name()
+        ))),
+    }
+}
+
+fn nil() -> LuaValue {
+    LuaValue::new(LuaVal::LuaNil)
+}
+
+fn as_float(val: &LuaValue) -> f64 {
+    match &val.0 {
+        LuaVal::LuaFloat(n) => *n,
+        LuaVal::LuaInt(n) => *n as f64,
+        _ => f64::NAN,
+    }
+}
+
+fn table_arg<'a>(
+    args: &'a [LuaValue],
+    n: usize,
+    fn_name: &str,
+) -> Result<&'a LuaTable, ASTExecError> {
+    match args.get(n - 1).map(|arg| &arg.0) {
+        Some(LuaVal::LuaTable(table)) => Ok(table),
+        arg => Err(ASTExecError::from(format!(
+            "bad argument #{n} to '{fn_name}' (table expected, got {})",
+            match arg {
+                Some(_) => args[n - 1].type_name(),
+                None => "no value",
+            }
+        ))),
+    }
+}
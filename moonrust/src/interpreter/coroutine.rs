@@ -0,0 +1,145 @@
+// The coroutine library. Coroutines run on the VM; a coroutine can yield from
+// any Lua function it calls, including through pcall and the `__index`,
+// `__newindex` and `__call` metamethods, but not from a function called by
+// Rust code.
+use crate::interpreter::environment::Env;
+use crate::interpreter::{ASTExecError, LuaString, LuaTable, LuaVal, LuaValue};
+use crate::limits;
+use crate::vm::{self, Coroutine};
+use std::rc::Rc;
+
+// Create the `coroutine` table
+pub fn new_coroutine_table(env: &mut Env) -> LuaValue {
+    let table = LuaTable::new();
+    let functions = [
+        ("create", LuaVal::CoroutineCreate),
+        ("resume", LuaVal::CoroutineResume),
+        ("yield", LuaVal::CoroutineYield),
+        ("status", LuaVal::CoroutineStatus),
+        ("wrap", LuaVal::CoroutineWrap),
+        ("isyieldable", LuaVal::CoroutineIsYieldable),
+        ("running", LuaVal::CoroutineRunning),
+        ("close", LuaVal::CoroutineClose),
+    ];
+    for (name, func) in functions {
+        table.insert_ident(name, LuaValue::new(func));
+    }
+    env.alloc_table(table)
+}
+
+// coroutine.create(f)
+pub fn create(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let co = new_coroutine(&args, "create", env)?;
+    Ok(vec![LuaValue::new(LuaVal::Thread(co))])
+}
+
+// coroutine.resume(co, ...)
+// Returns true and the values passed to yield or returned by the coroutine,
+// or false and the error message, caught like pcall does
+pub fn resume(mut args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let co = coroutine_arg(&args, "resume")?;
+    args.remove(0);
+    match vm::resume(&co, args, env) {
+        Ok(mut values) => {
+            values.insert(0, LuaValue::new(LuaVal::LuaBool(true)));
+            Ok(values)
+        }
+        Err(err) if !limits::catchable(env, &err) => Err(err),
+        Err(err) => Ok(vec![
+            LuaValue::new(LuaVal::LuaBool(false)),
+            LuaValue::new(LuaVal::LuaString(LuaString::from(err.to_string()))),
+        ]),
+    }
+}
+
+// coroutine.yield(...)
+// The VM suspends the coroutine when it runs the call to yield in its loop, so
+// a call from Rust code (a built-in, a hook, `__close`, ...) is always an error
+pub fn yield_fn(env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    Err(env
+        .thread()
+        .check_yield()
+        .err()
+        .unwrap_or_else(|| ASTExecError::new("attempt to yield across a C-call boundary")))
+}
+
+// coroutine.status(co)
+pub fn status(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let co = coroutine_arg(&args, "status")?;
+    Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
+        co.status().name(),
+    )))])
+}
+
+// coroutine.wrap(f)
+// Returns a function resuming the coroutine, which raises its errors
+pub fn wrap(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let co = new_coroutine(&args, "wrap", env)?;
+    Ok(vec![LuaValue::new(LuaVal::CoroutineWrapped(co))])
+}
+
+pub fn call_wrapped(
+    co: &Rc<Coroutine>,
+    args: Vec<LuaValue>,
+    env: &mut Env,
+) -> Result<Vec<LuaValue>, ASTExecError> {
+    vm::resume(co, args, env)
+}
+
+// coroutine.isyieldable()
+pub fn isyieldable(env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let yieldable = env.thread().check_yield().is_ok();
+    Ok(vec![LuaValue::new(LuaVal::LuaBool(yieldable))])
+}
+
+// coroutine.running()
+// Returns the running coroutine and true if it is the main one
+pub fn running(env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let is_main = env.coroutines().len() == 1;
+    Ok(vec![
+        LuaValue::new(LuaVal::Thread(env.running_coroutine())),
+        LuaValue::new(LuaVal::LuaBool(is_main)),
+    ])
+}
+
+// coroutine.close(co)
+// Kills a suspended or dead coroutine, returning true, or false and the error
+// that stopped the coroutine or that a __close metamethod raised
+pub fn close(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let co = coroutine_arg(&args, "close")?;
+    match vm::close(&co, env) {
+        Ok(()) => Ok(vec![LuaValue::new(LuaVal::LuaBool(true))]),
+        Err(err) if co.status() == vm::CoStatus::Dead => Ok(vec![
+            LuaValue::new(LuaVal::LuaBool(false)),
+            LuaValue::new(LuaVal::LuaString(LuaString::from(err.to_string()))),
+        ]),
+        Err(err) => Err(err),
+    }
+}
+
+fn new_coroutine(
+    args: &[LuaValue],
+    fn_name: &str,
+    env: &mut Env,
+) -> Result<Rc<Coroutine>, ASTExecError> {
+    match args.first() {
+        Some(func) if func.is_callable() => {
+            let co = Rc::new(Coroutine::new(func.clone(), env));
+            env.heap().track_coroutine(&co);
+            Ok(co)
+        }
+        arg => Err(ASTExecError::from(format!(
+            "bad argument #1 to '{fn_name}' (function expected, got {})",
+            arg.map_or("no value", |arg| arg.type_name())
+        ))),
+    }
+}
+
+fn coroutine_arg(args: &[LuaValue], fn_name: &str) -> Result<Rc<Coroutine>, ASTExecError> {
+    match args.first().map(|arg| &arg.0) {
+        Some(LuaVal::Thread(co)) => Ok(Rc::clone(co)),
+        _ => Err(ASTExecError::from(format!(
+            "bad argument #1 to '{fn_name}' (coroutine expected)"
+        ))),
+    }
+}
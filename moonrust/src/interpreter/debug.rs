@@ -0,0 +1,273 @@
+// The debug library, on top of the debug interface of the VM. Levels count
+// from 1, the function calling the library; level 0 is the library function
+// itself, which is a built-in and has no frame.
+use crate::debug::{self, FunctionInfo, HookFn, HookMask};
+use crate::interpreter::environment::Env;
+use crate::interpreter::{ASTExecError, LuaString, LuaTable, LuaVal, LuaValue};
+
+// Create the `debug` table
+pub fn new_debug_table(env: &mut Env) -> LuaValue {
+    let table = LuaTable::new();
+    let functions = [
+        ("sethook", LuaVal::DebugSetHook),
+        ("gethook", LuaVal::DebugGetHook),
+        ("getinfo", LuaVal::DebugGetInfo),
+        ("getlocal", LuaVal::DebugGetLocal),
+        ("setlocal", LuaVal::DebugSetLocal),
+        ("getupvalue", LuaVal::DebugGetUpvalue),
+        ("setupvalue", LuaVal::DebugSetUpvalue),
+    ];
+    for (name, func) in functions {
+        table.insert_ident(name, LuaValue::new(func));
+    }
+    env.alloc_table(table)
+}
+
+// debug.sethook([f, mask [, count]])
+// Calls f with the name of the event, and the line for a line event. Without
+// arguments, turns the hook off.
+pub fn sethook(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let func = match args.first() {
+        None => None,
+        Some(func) if func.is_nil() => None,
+        Some(func) if func.is_callable() => Some(func.clone()),
+        Some(arg) => {
+            return Err(ASTExecError::from(format!(
+                "bad argument #1 to 'sethook' (function expected, got {})",
+                arg.type_name()
+            )))
+        }
+    };
+    let Some(func) = func else {
+        env.install_hook(None, HookMask::default());
+        return Ok(vec![]);
+    };
+    let mask = match args.get(1).map(|arg| &arg.0) {
+        Some(LuaVal::LuaString(mask)) => mask.to_string(),
+        _ => {
+            return Err(ASTExecError::from(format!(
+                "bad argument #2 to 'sethook' (string expected, got {})",
+                args.get(1).map_or("no value", |arg| arg.type_name())
+            )))
+        }
+    };
+    let count = match args.get(2) {
+        Some(arg) if !arg.is_nil() => int_arg(&args, 3, "sethook")?,
+        _ => 0,
+    };
+    let count = u32::try_from(count.max(0)).unwrap_or(u32::MAX);
+    env.install_hook(Some(HookFn::Lua(func)), HookMask::parse(&mask, count));
+    Ok(vec![])
+}
+
+// debug.gethook()
+// Returns the hook function, "external hook" for a hook set by the host,
+// the mask and the count, or nil without a hook
+pub fn gethook(env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let hooks = env.hooks();
+    let func = match hooks.hook() {
+        Some(HookFn::Lua(func)) => func.clone(),
+        Some(HookFn::Rust(_)) => string("external hook"),
+        None => return Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    };
+    let mask = hooks.mask;
+    Ok(vec![
+        func,
+        string(&mask.to_string()),
+        LuaValue::new(LuaVal::LuaInt(mask.count.unwrap_or(0) as i64)),
+    ])
+}
+
+// debug.getinfo(f | level [, what])
+// Returns a table describing a function, or the function running at a level,
+// with the fields selected by the letters of `what`, or nil if there is no
+// such level
+pub fn getinfo(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let what = match args.get(1).map(|arg| &arg.0) {
+        None | Some(LuaVal::LuaNil) => String::from("flnStu"),
+        Some(LuaVal::LuaString(what)) => what.to_string(),
+        Some(_) => {
+            return Err(ASTExecError::from(format!(
+                "bad argument #2 to 'getinfo' (string expected, got {})",
+                args[1].type_name()
+            )))
+        }
+    };
+    if what.chars().any(|option| !"SlnrutfL".contains(option)) {
+        return Err(ASTExecError::new(
+            "bad argument #2 to 'getinfo' (invalid option)",
+        ));
+    }
+    let info = match args.first().map(|arg| &arg.0) {
+        Some(LuaVal::LuaInt(_) | LuaVal::LuaFloat(_)) => match int_arg(&args, 1, "getinfo")? {
+            0 => Some(debug::function_info(&LuaValue::new(LuaVal::DebugGetInfo))),
+            level if level > 0 => debug::frame_info(env, level as usize - 1),
+            _ => None,
+        },
+        Some(_) if args[0].is_callable() => Some(debug::function_info(&args[0])),
+        _ => {
+            return Err(ASTExecError::new(
+                "bad argument #1 to 'getinfo' (function or level expected)",
+            ))
+        }
+    };
+    match info {
+        Some(info) => Ok(vec![info_table(&info, &what, env)]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    }
+}
+
+// Fields of debug.getinfo selected by `what`
+fn info_table(info: &FunctionInfo, what: &str, env: &mut Env) -> LuaValue {
+    let table = LuaTable::new();
+    let int = |n: Option<u32>| LuaValue::new(LuaVal::LuaInt(n.map_or(-1, i64::from)));
+    let boolean = |b: bool| LuaValue::new(LuaVal::LuaBool(b));
+    if what.contains('S') {
+        table.insert_ident("source", string(&info.source));
+        table.insert_ident("short_src", string(&info.short_src));
+        table.insert_ident("what", string(info.kind.name()));
+        table.insert_ident("linedefined", int(info.line_defined));
+        table.insert_ident("lastlinedefined", int(info.last_line_defined));
+    }
+    if what.contains('l') {
+        table.insert_ident("currentline", int(info.current_line));
+    }
+    if what.contains('n') {
+        let (namewhat, name) = match &info.name {
+            Some((namewhat, name)) => (*namewhat, string(name)),
+            None => ("", LuaValue::new(LuaVal::LuaNil)),
+        };
+        table.insert_ident("name", name);
+        table.insert_ident("namewhat", string(namewhat));
+    }
+    if what.contains('u') {
+        table.insert_ident(
+            "nups",
+            LuaValue::new(LuaVal::LuaInt(info.num_upvalues as i64)),
+        );
+        table.insert_ident(
+            "nparams",
+            LuaValue::new(LuaVal::LuaInt(info.num_params as i64)),
+        );
+        table.insert_ident("isvararg", boolean(info.is_vararg));
+    }
+    if what.contains('t') {
+        table.insert_ident("istailcall", boolean(info.is_tail_call));
+    }
+    if what.contains('f') {
+        table.insert_ident("func", info.function.clone());
+    }
+    if what.contains('L') && info.line_defined.is_some() {
+        let lines = LuaTable::new();
+        for line in &info.active_lines {
+            lines.insert_int(i64::from(*line), boolean(true));
+        }
+        table.insert_ident("activelines", env.alloc_table(lines));
+    }
+    env.alloc_table(table)
+}
+
+// debug.getlocal(level, n) or debug.getlocal(f, n)
+// Returns the name and value of a local variable of the function running at
+// the level, or the name of a parameter of the function
+pub fn getlocal(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let n = int_arg(&args, 2, "getlocal")?;
+    if args.first().is_some_and(LuaValue::is_callable) {
+        let name = usize::try_from(n)
+            .ok()
+            .and_then(|n| debug::param_name(&args[0], n));
+        return Ok(vec![
+            name.map_or(LuaValue::new(LuaVal::LuaNil), |name| string(&name))
+        ]);
+    }
+    let level = level_arg(&args, "getlocal", env)?;
+    match level.and_then(|level| debug::local(env, level, n)) {
+        Some((name, val)) => Ok(vec![string(&name), val]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    }
+}
+
+// debug.setlocal(level, n, value)
+// Returns the name of the local variable assigned, or nil if there is none
+pub fn setlocal(mut args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let level = level_arg(&args, "setlocal", env)?;
+    let n = int_arg(&args, 2, "setlocal")?;
+    args.resize(3, LuaValue::new(LuaVal::LuaNil));
+    let val = args.swap_remove(2);
+    match level.and_then(|level| debug::set_local(env, level, n, val)) {
+        Some(name) => Ok(vec![string(&name)]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    }
+}
+
+// debug.getupvalue(f, n)
+// Returns the name and value of an upvalue of the function, or nil if there
+// is none
+pub fn getupvalue(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let func = function_arg(&args, "getupvalue")?;
+    let n = usize::try_from(int_arg(&args, 2, "getupvalue")?).unwrap_or(0);
+    match debug::upvalue(env, &func, n) {
+        Some((name, val)) => Ok(vec![string(&name), val]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    }
+}
+
+// debug.setupvalue(f, n, value)
+// Returns the name of the upvalue assigned, or nil if there is none
+pub fn setupvalue(mut args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let func = function_arg(&args, "setupvalue")?;
+    let n = usize::try_from(int_arg(&args, 2, "setupvalue")?).unwrap_or(0);
+    args.resize(3, LuaValue::new(LuaVal::LuaNil));
+    let val = args.swap_remove(2);
+    match debug::set_upvalue(env, &func, n, val) {
+        Some(name) => Ok(vec![string(&name)]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
+    }
+}
+
+fn string(s: &str) -> LuaValue {
+    LuaValue::new(LuaVal::LuaString(LuaString::from(s)))
+}
+
+fn int_arg(args: &[LuaValue], n: usize, fn_name: &str) -> Result<i64, ASTExecError> {
+    match args.get(n - 1) {
+        Some(arg) if arg.is_numeral() => arg.clone().into_int().map_err(|_| {
+            ASTExecError::from(format!(
+                "bad argument #{n} to '{fn_name}' (number has no integer representation)"
+            ))
+        }),
+        arg => Err(ASTExecError::from(format!(
+            "bad argument #{n} to '{fn_name}' (number expected, got {})",
+            arg.map_or("no value", |arg| arg.type_name())
+        ))),
+    }
+}
+
+fn function_arg(args: &[LuaValue], fn_name: &str) -> Result<LuaValue, ASTExecError> {
+    match args.first() {
+        Some(func) if func.is_callable() => Ok(func.clone()),
+        arg => Err(ASTExecError::from(format!(
+            "bad argument #1 to '{fn_name}' (function expected, got {})",
+            arg.map_or("no value", |arg| arg.type_name())
+        ))),
+    }
+}
+
+// Level of the first argument, as a level of the debug interface. None for
+// the level of the library function, which has no locals.
+fn level_arg(
+    args: &[LuaValue],
+    fn_name: &str,
+    env: &mut Env,
+) -> Result<Option<usize>, ASTExecError> {
+    let level = int_arg(args, 1, fn_name)?;
+    match level {
+        0 => Ok(None),
+        level if level > 0 && env.thread().frame(level as usize - 1).is_some() => {
+            Ok(Some(level as usize - 1))
+        }
+        _ => Err(ASTExecError::from(format!(
+            "bad argument #1 to '{fn_name}' (level out of range)"
+        ))),
+    }
+}
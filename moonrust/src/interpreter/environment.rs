@@ -1,211 +1,207 @@
-use crate::interpreter::{LuaVal, LuaValue};
+use crate::debug::{Hook, HookFn, HookMask, Hooks};
+use crate::gc::{self, Heap};
+use crate::interpreter::{coroutine, debug, package};
+use crate::interpreter::{LuaString, LuaTable, LuaVal, LuaValue, TableKey};
+use crate::limits::{InterruptHandle, Limits, Meter};
+use crate::vm::{Coroutine, Thread};
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
-// One scope of bindings
-#[derive(Debug, PartialEq, Clone)]
-pub struct EnvTable<'a>(Rc<RefCell<HashMap<String, LuaValue<'a>>>>);
-impl<'a> EnvTable<'a> {
-    pub fn new() -> Self {
-        EnvTable(Rc::new(RefCell::new(HashMap::new())))
-    }
-
-    pub fn get(&self, name: &str) -> Option<LuaValue<'a>> {
-        let hm = self.0.borrow();
-        let res = hm.get(name);
-        res.map(|res| res.clone_rc())
-    }
-
-    pub fn get_mut(&mut self, name: &str) -> Option<LuaValue<'a>> {
-        let mut hm = self.0.borrow_mut();
-        let res = hm.get_mut(name);
-        res.map(|res| res.clone_rc())
-    }
-
-    // Insert a new variable or update an existing one
-    pub fn insert(&mut self, name: String, var: LuaValue<'a>) -> Option<LuaValue<'a>> {
-        self.0.borrow_mut().insert(name, var)
-    }
-}
-
-impl<'a> Default for EnvTable<'a> {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Debug, PartialEq)]
+pub struct Env {
+    global: LuaValue,       // Always a table, shared with every function defined in it
+    varargs: Vec<LuaValue>, // Arguments of the chunks run in the environment, read by `...`
+    thread: Thread,         // Registers and call frames of the VM
+    heap: Heap,             // Objects created by the VM, for the garbage collector
+    coroutines: Vec<Rc<Coroutine>>, // The main thread and the coroutines it resumed, the running one last
+    meter: Meter,           // Execution limits and the resources used under them
+    hooks: Hooks,           // Hook called on the events of the scripts
 }
 
-// Insert None between each EnvTable to represent a new scope
-#[derive(Debug, PartialEq)]
-pub struct LocalEnv<'a>(Vec<Option<EnvTable<'a>>>);
-impl<'a> LocalEnv<'a> {
+impl Env {
     pub fn new() -> Self {
-        let mut env = LocalEnv(vec![]);
-        env.extend_env();
+        let mut env = Env {
+            global: LuaValue::new(LuaVal::LuaNil),
+            varargs: vec![],
+            thread: Thread::new(),
+            heap: Heap::new(),
+            coroutines: vec![Rc::new(Coroutine::main())],
+            meter: Meter::default(),
+            hooks: Hooks::default(),
+        };
+        env.global = env.alloc_table(LuaTable::new());
+        // Insert built-in functions
+        env.insert_global("print".to_string(), LuaValue::new(LuaVal::Print));
+        env.insert_global("read".to_string(), LuaValue::new(LuaVal::Read));
+        env.insert_global("random".to_string(), LuaValue::new(LuaVal::Random));
+        env.insert_global("load".to_string(), LuaValue::new(LuaVal::Load));
+        env.insert_global("loadfile".to_string(), LuaValue::new(LuaVal::LoadFile));
+        env.insert_global("dofile".to_string(), LuaValue::new(LuaVal::DoFile));
+        env.insert_global(
+            "require".to_string(),
+            LuaValue::new(LuaVal::Require(Rc::new(RefCell::new(vec![])))),
+        );
+        let package = package::new_package_table(&mut env);
+        env.insert_global("package".to_string(), package);
+        let coroutine = coroutine::new_coroutine_table(&mut env);
+        env.insert_global("coroutine".to_string(), coroutine);
+        let debug = debug::new_debug_table(&mut env);
+        env.insert_global("debug".to_string(), debug);
+        let base_functions = [
+            ("setmetatable", LuaVal::SetMetatable),
+            ("getmetatable", LuaVal::GetMetatable),
+            ("rawget", LuaVal::RawGet),
+            ("rawset", LuaVal::RawSet),
+            ("rawequal", LuaVal::RawEqual),
+            ("rawlen", LuaVal::RawLen),
+            ("next", LuaVal::Next),
+            ("pairs", LuaVal::Pairs),
+            ("ipairs", LuaVal::IPairs),
+            ("error", LuaVal::Error),
+            ("pcall", LuaVal::PCall),
+            ("type", LuaVal::Type),
+            ("collectgarbage", LuaVal::CollectGarbage),
+        ];
+        for (name, func) in base_functions {
+            env.insert_global(name.to_string(), LuaValue::new(func));
+        }
+        // The global environment is itself a global variable
+        env.insert_global("_G".to_string(), env.get_global_env());
         env
     }
 
-    pub fn get(&self, name: &str) -> Option<LuaValue<'a>> {
-        // Start from top of the stack
-        for table in self.0.iter().rev().flatten() {
-            if let Some(var) = table.get(name) {
-                return Some(var);
-            }
-        }
-        None
-    }
-
-    // pub fn get_mut(&mut self, name: &str) -> Option<&mut LuaValue<'a>> {
-    //     // Search in reversed order to check current scope first
-    //     for table in self.0.iter_mut().rev() {
-    //         for (var_name, var) in table.0.iter_mut() {
-    //             if var_name == name {
-    //                 return Some(var);
-    //             }
-    //         }
-    //     }
-    //     None
-    // }
-
-    pub fn extend_env(&mut self) {
-        self.0.push(None);
-        self.0.push(Some(EnvTable::new()));
-    }
-
-    pub fn extend_env_with_table(&mut self, table: &Option<EnvTable<'a>>) {
-        match table {
-            Some(table) => {
-                self.0.push(Some(EnvTable(Rc::clone(&table.0))));
-            }
-            None => self.0.push(None),
+    fn global_table(&self) -> &LuaTable {
+        match &self.global.0 {
+            LuaVal::LuaTable(table) => table,
+            _ => panic!("Global environment is not a table"),
         }
     }
 
-    // None is inserted to represent a new scope
-    // This function does not insert None
-    pub fn extend_env_without_scope(&mut self) {
-        self.0.push(Some(EnvTable::new()));
+    // The table holding global variables
+    pub fn get_global_env(&self) -> LuaValue {
+        self.global.clone()
     }
 
-    // Pop all tables in current scope
-    pub fn pop_env(&mut self) {
-        while let Some(Some(_)) = self.0.pop() {}
+    pub fn set_global_env(&mut self, table: LuaValue) {
+        self.global = table;
     }
 
-    // Always inserting into the current scope
-    pub fn insert(&mut self, name: String, var: LuaValue<'a>) -> Option<LuaValue<'a>> {
-        match self.0.last_mut() {
-            Some(table) => match table {
-                Some(table) => table.insert(name, var),
-                None => panic!("Environment is corrupted"),
-            },
-            None => panic!("Environment stack is empty"),
-        }
+    pub fn get_global(&self, name: &str) -> Option<LuaValue> {
+        self.global_table().get(TableKey::String(LuaString::from(name)))
+    }
+
+    pub fn insert_global(&mut self, name: String, var: LuaValue) {
+        self.global_table().insert_ident(&name, var);
     }
 
-    pub fn update(&mut self, name: String, var: LuaValue<'a>) -> Option<LuaValue<'a>> {
-        for table in self.0.iter_mut().rev().flatten() {
-            if table.get(&name).is_some() {
-                return table.insert(name, var);
+    /// Names of the global variables that are set
+    pub fn global_names(&self) -> Vec<String> {
+        let table = self.global_table();
+        let mut names = vec![];
+        let mut key = None;
+        while let Ok(Some((next, _))) = table.next(key.as_ref()) {
+            if let TableKey::String(name) = &next {
+                names.push(name.to_string());
             }
+            key = Some(next);
         }
-        None
+        names
     }
 
-    pub fn capture_env(&self) -> Self {
-        let mut env = LocalEnv::new();
-        for table in self.0.iter().rev() {
-            env.extend_env_with_table(table);
-        }
-        env
+    pub fn get_varargs(&self) -> Vec<LuaValue> {
+        self.varargs.to_vec()
     }
-}
 
-impl<'a> Default for LocalEnv<'a> {
-    fn default() -> Self {
-        Self::new()
+    pub fn set_varargs(&mut self, varargs: Vec<LuaValue>) {
+        self.varargs = varargs;
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Env<'a> {
-    global: EnvTable<'a>,
-    local: LocalEnv<'a>,
-}
+    /// Limit the depth of nested Lua calls. A call past it raises "stack overflow".
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.thread.set_max_depth(depth);
+    }
 
-impl<'a> Env<'a> {
-    pub fn new() -> Self {
-        let mut env = Env {
-            global: EnvTable::new(),
-            local: LocalEnv::new(),
-        };
-        // Insert built-in functions
-        env.insert_global("print".to_string(), LuaValue::new(LuaVal::Print));
-        env.insert_global("read".to_string(), LuaValue::new(LuaVal::Read));
-        env.insert_global("random".to_string(), LuaValue::new(LuaVal::Random));
-        env
+    /// Limit the instructions, memory and time of the scripts, counted from now on.
+    /// A script exceeding a limit stops with an error whose `limit` tells which one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.meter.set_limits(limits);
     }
 
-    // pub fn get_global_env(&self) -> &EnvTable<'a> {
-    //     &self.global.borrow()
-    // }
+    /// Handle interrupting the scripts running in the environment from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.meter.interrupt_handle()
+    }
 
-    pub fn set_global_env(&mut self, env: &Rc<RefCell<HashMap<String, LuaValue<'a>>>>) {
-        self.global = EnvTable(Rc::clone(env));
+    pub fn limits(&self) -> &Limits {
+        self.meter.limits()
     }
 
-    pub fn get_local(&self, name: &str) -> Option<LuaValue<'a>> {
-        self.local.get(name)
+    /// Call `hook` on the events of `mask` in the scripts running in the
+    /// environment, replacing the previous hook, e.g. one set by `debug.sethook`
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>, mask: HookMask) {
+        self.install_hook(Some(HookFn::Rust(hook)), mask);
     }
 
-    pub fn get_global(&self, name: &str) -> Option<LuaValue<'a>> {
-        self.global.get(name)
+    pub fn remove_hook(&mut self) {
+        self.install_hook(None, HookMask::default());
     }
 
-    pub fn get(&self, name: &str) -> Option<LuaValue<'a>> {
-        self.local.get(name).or_else(|| self.global.get(name))
+    pub(crate) fn install_hook(&mut self, hook: Option<HookFn>, mask: HookMask) {
+        // Line and count events need the VM to stop at every instruction
+        let every_instruction = hook.is_some() && (mask.line || mask.count.is_some());
+        self.hooks.set(hook, mask);
+        self.meter.set_hooked(every_instruction);
     }
 
-    pub fn insert_local(&mut self, name: String, var: LuaValue<'a>) {
-        self.local.insert(name, var);
+    pub(crate) fn hooks(&mut self) -> &mut Hooks {
+        &mut self.hooks
     }
 
-    pub fn update_local(&mut self, name: String, var: LuaValue<'a>) {
-        self.local.update(name, var);
+    pub(crate) fn thread(&mut self) -> &mut Thread {
+        &mut self.thread
     }
 
-    pub fn insert_global(&mut self, name: String, var: LuaValue<'a>) {
-        self.global.insert(name, var);
+    /// Make `table` a Lua value. Every table is allocated here, by the VM as
+    /// well as by the libraries and the host, so that the garbage collector
+    /// knows about it and can break the cycles that go through it.
+    pub fn alloc_table(&mut self, table: LuaTable) -> LuaValue {
+        let table = Rc::new(table);
+        self.heap.track_table(&table);
+        LuaValue::new(LuaVal::LuaTable(table))
     }
 
-    // Only used for local environment
-    pub fn extend_local_env(&mut self) {
-        self.local.extend_env();
+    pub(crate) fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
     }
 
-    pub fn extend_local_without_scope(&mut self) {
-        self.local.extend_env_without_scope();
+    #[inline]
+    pub(crate) fn meter(&mut self) -> &mut Meter {
+        &mut self.meter
     }
 
-    // Only used for local environment
-    pub fn pop_local_env(&mut self) {
-        self.local.pop_env();
+    pub(crate) fn coroutines(&mut self) -> &mut Vec<Rc<Coroutine>> {
+        &mut self.coroutines
     }
 
-    pub fn get_local_env(&self) -> &LocalEnv<'a> {
-        &self.local
+    pub(crate) fn running_coroutine(&self) -> Rc<Coroutine> {
+        Rc::clone(self.coroutines.last().expect("no running coroutine"))
     }
+}
 
-    // Use captured local environment with current global environment
-    pub fn create_with_captured_env(&self, local_env: &LocalEnv<'a>) -> Env<'a> {
-        let mut new_env = Env::new();
-        new_env.set_global_env(&self.global.0);
-        new_env.local = local_env.capture_env();
-        new_env
+// The global environment and the functions defined in it usually form cycles,
+// which are only freed by the collector. Every pending finalizer runs first,
+// while the globals are still available.
+impl Drop for Env {
+    fn drop(&mut self) {
+        gc::finalize_all(self);
+        self.thread = Thread::new();
+        self.varargs.clear();
+        self.global = LuaValue::new(LuaVal::LuaNil);
+        self.heap.full_collect();
     }
 }
 
-impl<'a> Default for Env<'a> {
+impl Default for Env {
     fn default() -> Self {
         Self::new()
     }
@@ -1,14 +1,22 @@
 use crate::ast::*;
+use crate::interpreter::base;
+use crate::interpreter::coroutine;
+use crate::interpreter::debug;
+use crate::interpreter::chunk_id;
+use crate::compiler;
 use crate::interpreter::environment::Env;
+use crate::interpreter::package;
 use crate::interpreter::ASTExecError;
-use crate::interpreter::LuaFunction;
-use crate::interpreter::LuaTable;
+use crate::interpreter::LuaString;
 use crate::interpreter::LuaVal;
 use crate::interpreter::LuaValue;
 use crate::interpreter::TableKey;
+use crate::parser;
+use crate::vm::{self, Closure};
 use rand::Rng;
 use std::cell::RefCell;
-use std::io::{self, BufRead};
+use std::fs;
+use std::io::{self, BufRead, Read};
 use std::rc::Rc;
 
 enum IntFloatBool {
@@ -17,66 +25,36 @@ enum IntFloatBool {
     Bool(bool),
 }
 
-impl Expression {
-    pub fn eval<'a, 'b>(&'a self, env: &'b mut Env<'a>) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
-        let val = match self {
-            Expression::Nil => vec![LuaValue::new(LuaVal::LuaNil)],
-            Expression::False => vec![LuaValue::new(LuaVal::LuaBool(false))],
-            Expression::True => vec![LuaValue::new(LuaVal::LuaBool(true))],
-            Expression::Numeral(n) => match n {
-                Numeral::Integer(i) => vec![LuaValue::new(LuaVal::LuaNum(i.to_be_bytes(), false))],
-                Numeral::Float(f) => vec![LuaValue::new(LuaVal::LuaNum(f.to_be_bytes(), true))],
-            },
-            Expression::LiteralString(s) => vec![LuaValue::new(LuaVal::LuaString(s.clone()))],
-            Expression::DotDotDot => unimplemented!(),
-            Expression::FunctionDef((par_list, block)) => {
-                let captured_env = env.get_local_env().capture_env();
-                vec![LuaValue::new(LuaVal::Function(LuaFunction {
-                    par_list,
-                    block,
-                    captured_env,
-                }))]
-            }
-            Expression::PrefixExp(prefixexp) => prefixexp.eval(env)?,
-            Expression::TableConstructor(fields) => {
-                let table = build_table(fields, env)?;
-                vec![LuaValue::new(LuaVal::LuaTable(table))]
-            }
-            Expression::BinaryOp((left, op, right)) => {
-                vec![Expression::eval_binary_exp(op, left, right, env)?]
-            }
-            Expression::UnaryOp((op, exp)) => vec![Expression::eval_unary_exp(op, exp, env)?],
-        };
-        Ok(val)
+// Whether an integer operation would divide by zero
+fn is_int_zero(left: &LuaValue, right: &LuaValue) -> bool {
+    matches!((&left.0, &right.0), (LuaVal::LuaInt(_), LuaVal::LuaInt(0)))
+}
+
+// Shift of the bits of an integer, to the right for a negative shift. Bits
+// shifted in are zeros, so shifting by 64 or more gives 0.
+fn shift_left(i: i64, shift: i64) -> i64 {
+    match shift {
+        0..=63 => ((i as u64) << shift) as i64,
+        -63..=-1 => ((i as u64) >> -shift) as i64,
+        _ => 0,
     }
+}
 
-    pub fn eval_unary_exp<'a>(
-        op: &UnOp,
-        exp: &'a Expression,
-        env: &mut Env<'a>,
-    ) -> Result<LuaValue<'a>, ASTExecError> {
+impl LuaValue {
+    /// Apply a unary operator to an evaluated operand
+    pub fn unary_op(op: &UnOp, val: LuaValue) -> Result<LuaValue, ASTExecError> {
         match op {
             UnOp::Negate => {
-                let val = LuaValue::extract_first_return_val(exp.eval(env)?);
-                match val.0.as_ref() {
-                    LuaVal::LuaNum(bytes, is_float) => {
-                        if !*is_float {
-                            // Integer
-                            let i = i64::from_be_bytes(*bytes);
-                            Ok(LuaValue::new(LuaVal::LuaNum((-i).to_be_bytes(), false)))
-                        } else {
-                            // Float
-                            let f = f64::from_be_bytes(*bytes);
-                            Ok(LuaValue::new(LuaVal::LuaNum((-f).to_be_bytes(), true)))
-                        }
-                    }
-                    _ => Err(ASTExecError(String::from(
+                match &val.0 {
+                    LuaVal::LuaInt(i) => Ok(LuaValue::new(LuaVal::LuaInt(i.wrapping_neg()))),
+                    LuaVal::LuaFloat(f) => Ok(LuaValue::new(LuaVal::LuaFloat(-f))),
+                    _ => Err(ASTExecError::from(String::from(
                         "Cannot negate values that are not numbers",
                     ))),
                 }
             }
             UnOp::LogicalNot => {
-                if LuaValue::extract_first_return_val(exp.eval(env)?).is_true() {
+                if val.is_true() {
                     // Negate the true
                     Ok(LuaValue::new(LuaVal::LuaBool(false)))
                 } else {
@@ -85,100 +63,71 @@ impl Expression {
                 }
             }
             UnOp::Length => {
-                match LuaValue::extract_first_return_val(exp.eval(env)?)
-                    .0
-                    .as_ref()
-                {
+                match &val.0 {
                     LuaVal::LuaString(s) => {
                         // length of a string is its number of bytes
-                        Ok(LuaValue::new(LuaVal::LuaNum(
-                            (s.len() as i64).to_be_bytes(),
-                            false,
-                        )))
+                        Ok(LuaValue::new(LuaVal::LuaInt(s.len() as i64)))
                     }
                     LuaVal::LuaTable(table) => {
                         let border = table.calculate_border();
-                        Ok(LuaValue::new(LuaVal::LuaNum(border.to_be_bytes(), false)))
+                        Ok(LuaValue::new(LuaVal::LuaInt(border as i64)))
                     }
-                    _ => Err(ASTExecError(String::from(
+                    _ => Err(ASTExecError::from(String::from(
                         "Cannot get length of value that is not a string or table",
                     ))),
                 }
             }
             UnOp::BitNot => {
                 // operate on all bits of those integers, and result in an integer.
-                let val = LuaValue::extract_first_return_val(exp.eval(env)?);
                 let val = val.into_int()?;
-                Ok(LuaValue::new(LuaVal::LuaNum((!val).to_be_bytes(), false)))
+                Ok(LuaValue::new(LuaVal::LuaInt(!val)))
             }
         }
     }
 
-    pub fn eval_binary_exp<'a>(
-        op: &BinOp,
-        left: &'a Expression,
-        right: &'a Expression,
-        env: &mut Env<'a>,
-    ) -> Result<LuaValue<'a>, ASTExecError> {
-        fn execute_arithmetic<'a, F1, F2>(
+    /// Apply a binary operator to evaluated operands. `and` and `or` only
+    /// select one of the operands, their short-circuit is up to the caller.
+    pub fn binary_op(op: &BinOp, left: LuaValue, right: LuaValue) -> Result<LuaValue, ASTExecError> {
+        fn execute_arithmetic<F1, F2>(
             exec_ints: F1,
             exec_floats: F2,
             left: LuaValue,
             right: LuaValue,
-        ) -> Result<LuaValue<'a>, ASTExecError>
+        ) -> Result<LuaValue, ASTExecError>
         where
             F1: FnOnce(i64, i64) -> IntFloatBool,
             F2: FnOnce(f64, f64) -> IntFloatBool,
         {
             // If both are integers, the operation is performed over integers and the result is an integer.
             // If both are numbers, then they are converted to floats
-            let result = match (left.0.as_ref(), right.0.as_ref()) {
-                (LuaVal::LuaNum(bytes1, is_float1), LuaVal::LuaNum(bytes2, is_float2)) => {
-                    if *is_float1 && *is_float2 {
-                        // Both are float
-                        let f1 = f64::from_be_bytes(*bytes1);
-                        let f2 = f64::from_be_bytes(*bytes2);
-                        exec_floats(f1, f2)
-                    } else if *is_float1 {
-                        // Left is float, right is integer
-                        let f1 = f64::from_be_bytes(*bytes1);
-                        let i2 = i64::from_be_bytes(*bytes2);
-                        exec_floats(f1, i2 as f64)
-                    } else if *is_float2 {
-                        // Right is float, left is integer
-                        let i1 = i64::from_be_bytes(*bytes1);
-                        let f2 = f64::from_be_bytes(*bytes2);
-                        exec_floats(i1 as f64, f2)
-                    } else {
-                        // Both are integers
-                        let i1 = i64::from_be_bytes(*bytes1);
-                        let i2 = i64::from_be_bytes(*bytes2);
-                        exec_ints(i1, i2)
-                    }
-                }
+            let result = match (&left.0, &right.0) {
+                (LuaVal::LuaInt(i1), LuaVal::LuaInt(i2)) => exec_ints(*i1, *i2),
+                (LuaVal::LuaFloat(f1), LuaVal::LuaFloat(f2)) => exec_floats(*f1, *f2),
+                (LuaVal::LuaFloat(f1), LuaVal::LuaInt(i2)) => exec_floats(*f1, *i2 as f64),
+                (LuaVal::LuaInt(i1), LuaVal::LuaFloat(f2)) => exec_floats(*i1 as f64, *f2),
                 // Skipping string coercion to numbers for now
                 _ => {
-                    return Err(ASTExecError(String::from(
+                    return Err(ASTExecError::from(String::from(
                         "Cannot execute opration on values that are not numbers",
                     )));
                 }
             };
 
             match result {
-                IntFloatBool::Int(i) => Ok(LuaValue::new(LuaVal::LuaNum(i.to_be_bytes(), false))),
-                IntFloatBool::Float(f) => Ok(LuaValue::new(LuaVal::LuaNum(f.to_be_bytes(), true))),
+                IntFloatBool::Int(i) => Ok(LuaValue::new(LuaVal::LuaInt(i))),
+                IntFloatBool::Float(f) => Ok(LuaValue::new(LuaVal::LuaFloat(f))),
                 IntFloatBool::Bool(bool) => Ok(LuaValue::new(LuaVal::LuaBool(bool))),
             }
         }
 
-        fn equal<'a>(
-            left: LuaValue<'a>,
-            right: LuaValue<'a>,
-        ) -> Result<LuaValue<'a>, ASTExecError> {
-            match (left.0.as_ref(), right.0.as_ref()) {
+        fn equal(
+            left: LuaValue,
+            right: LuaValue,
+        ) -> Result<LuaValue, ASTExecError> {
+            match (&left.0, &right.0) {
                 (LuaVal::LuaNil, LuaVal::LuaNil) => Ok(LuaValue::new(LuaVal::LuaBool(true))),
                 // If number, check if they are equal based on mathematical values
-                (LuaVal::LuaNum(_, _), LuaVal::LuaNum(_, _)) => execute_arithmetic(
+                _ if left.is_numeral() && right.is_numeral() => execute_arithmetic(
                     |i1, i2| IntFloatBool::Bool(i1 == i2),
                     |f1, f2| IntFloatBool::Bool(f1 == f2),
                     left,
@@ -192,26 +141,20 @@ impl Expression {
                 (LuaVal::LuaBool(b1), LuaVal::LuaBool(b2)) => {
                     Ok(LuaValue::new(LuaVal::LuaBool(b1 == b2)))
                 }
-                // If table, check if they are equal based on reference
-                (LuaVal::LuaTable(_), LuaVal::LuaTable(_)) => Ok(LuaValue::new(LuaVal::LuaBool(
-                    Rc::ptr_eq(&left.0, &right.0),
-                ))),
-                // If function, check if they are equal based on reference
-                (LuaVal::Function(_), LuaVal::Function(_)) => Ok(LuaValue::new(LuaVal::LuaBool(
-                    Rc::ptr_eq(&left.0, &right.0),
-                ))),
-                _ => Ok(LuaValue::new(LuaVal::LuaBool(false))),
+                // Tables, functions (built-in ones too) and threads are equal
+                // only to themselves
+                _ => Ok(LuaValue::new(LuaVal::LuaBool(left.ref_eq(&right)))),
             }
         }
 
-        fn less_or_greater_than<'a>(
-            left: LuaValue<'a>,
-            right: LuaValue<'a>,
+        fn less_or_greater_than(
+            left: LuaValue,
+            right: LuaValue,
             is_less_than: bool,
-        ) -> Result<LuaValue<'a>, ASTExecError> {
-            match (left.0.as_ref(), right.0.as_ref()) {
+        ) -> Result<LuaValue, ASTExecError> {
+            match (&left.0, &right.0) {
                 // If number, check if they are equal based on mathematical values
-                (LuaVal::LuaNum(_, _), LuaVal::LuaNum(_, _)) => execute_arithmetic(
+                _ if left.is_numeral() && right.is_numeral() => execute_arithmetic(
                     |i1, i2| {
                         if is_less_than {
                             IntFloatBool::Bool(i1 < i2)
@@ -237,46 +180,42 @@ impl Expression {
                         LuaValue::new(LuaVal::LuaBool(s1 > s2))
                     }
                 }),
-                _ => Err(ASTExecError(
+                _ => Err(ASTExecError::from(
                     "Cannot compare two values due to types".to_string(),
                 )),
             }
         }
 
-        let left = LuaValue::extract_first_return_val(left.eval(env)?);
         match op {
             BinOp::Add => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1 + i2);
+                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1.wrapping_add(i2));
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Float(f1 + f2);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::Sub => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1 - i2);
+                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1.wrapping_sub(i2));
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Float(f1 - f2);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::Mult => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1 * i2);
+                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1.wrapping_mul(i2));
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Float(f1 * f2);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::Div => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
                 let exec_ints = |i1: i64, i2: i64| IntFloatBool::Float(i1 as f64 / i2 as f64);
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Float(f1 / f2);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::IntegerDiv => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1 / i2);
+                if is_int_zero(&left, &right) {
+                    return Err(ASTExecError::from(String::from("attempt to perform 'n//0'")));
+                }
+                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1.wrapping_div(i2));
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Int((f1 / f2).floor() as i64);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::Pow => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
                 let exec_ints = |i1: i64, i2: i64| {
                     let i1 = i1 as f64;
                     let i2 = i2 as f64;
@@ -286,337 +225,88 @@ impl Expression {
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::Mod => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1 % i2);
+                if is_int_zero(&left, &right) {
+                    return Err(ASTExecError::from(String::from("attempt to perform 'n%0'")));
+                }
+                let exec_ints = |i1: i64, i2: i64| IntFloatBool::Int(i1.wrapping_rem(i2));
                 let exec_floats = |f1: f64, f2: f64| IntFloatBool::Float(f1 % f2);
                 execute_arithmetic(exec_ints, exec_floats, left, right)
             }
             BinOp::BitAnd => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaNum(
-                    (left.into_int()? & right.into_int()?).to_be_bytes(),
-                    false,
-                )))
+                Ok(LuaValue::new(LuaVal::LuaInt(left.into_int()? & right.into_int()?)))
             }
             BinOp::BitXor => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaNum(
-                    (left.into_int()? ^ right.into_int()?).to_be_bytes(),
-                    false,
-                )))
+                Ok(LuaValue::new(LuaVal::LuaInt(left.into_int()? ^ right.into_int()?)))
             }
             BinOp::BitOr => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaNum(
-                    (left.into_int()? | right.into_int()?).to_be_bytes(),
-                    false,
-                )))
+                Ok(LuaValue::new(LuaVal::LuaInt(left.into_int()? | right.into_int()?)))
             }
             BinOp::ShiftRight => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaNum(
-                    (left.into_int()? >> right.into_int()?).to_be_bytes(),
-                    false,
-                )))
+                let (i, shift) = (left.into_int()?, right.into_int()?);
+                Ok(LuaValue::new(LuaVal::LuaInt(shift_left(i, shift.wrapping_neg()))))
             }
             BinOp::ShiftLeft => {
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaNum(
-                    (left.into_int()? << right.into_int()?).to_be_bytes(),
-                    false,
-                )))
+                let (i, shift) = (left.into_int()?, right.into_int()?);
+                Ok(LuaValue::new(LuaVal::LuaInt(shift_left(i, shift))))
             }
             BinOp::Concat => {
                 // If both operands are strings or numbers, then the numbers are converted to strings in a non-specified format.
                 // Otherwise, the __concat metamethod is called (in our case, return error).
-                let right = LuaValue::extract_first_return_val(right.eval(env)?);
-                Ok(LuaValue::new(LuaVal::LuaString(format!(
+                Ok(LuaValue::new(LuaVal::LuaString(LuaString::from(format!(
                     "{}{}",
                     left.into_string()?,
                     right.into_string()?
-                ))))
+                )))))
             }
             BinOp::LessThan => less_or_greater_than(
                 left,
-                LuaValue::extract_first_return_val(right.eval(env)?),
+                right,
                 true,
             ),
             BinOp::LessEq => less_or_greater_than(
                 left,
-                LuaValue::extract_first_return_val(right.eval(env)?),
+                right,
                 false,
             )?
             .negate_bool(),
             BinOp::GreaterThan => less_or_greater_than(
                 left,
-                LuaValue::extract_first_return_val(right.eval(env)?),
+                right,
                 false,
             ),
             BinOp::GreaterEq => less_or_greater_than(
                 left,
-                LuaValue::extract_first_return_val(right.eval(env)?),
+                right,
                 true,
             )?
             .negate_bool(),
-            BinOp::Equal => equal(left, LuaValue::extract_first_return_val(right.eval(env)?)),
+            BinOp::Equal => equal(left, right),
             BinOp::NotEqual => {
-                equal(left, LuaValue::extract_first_return_val(right.eval(env)?))?.negate_bool()
+                equal(left, right)?.negate_bool()
             }
             BinOp::LogicalAnd => {
                 if left.is_false() {
                     Ok(left)
                 } else {
-                    // If left is true, return value on the right
-                    Ok(LuaValue::extract_first_return_val(right.eval(env)?))
+                    Ok(right)
                 }
             }
             BinOp::LogicalOr => {
                 if left.is_true() {
                     Ok(left)
                 } else {
-                    // If left is true, return value on the right
-                    Ok(LuaValue::extract_first_return_val(right.eval(env)?))
+                    Ok(right)
                 }
             }
         }
     }
 }
 
-impl PrefixExp {
-    pub fn eval<'a, 'b>(&'a self, env: &'b mut Env<'a>) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
-        match self {
-            PrefixExp::Var(var) => {
-                match var {
-                    Var::Name(name) => match env.get(name) {
-                        Some(val) => Ok(vec![val.clone_rc()]),
-                        None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
-                    },
-                    Var::Bracket((prefixexp, exp)) => {
-                        let prefixexp = LuaValue::extract_first_return_val(prefixexp.eval(env)?);
-                        match prefixexp.0.as_ref() {
-                            LuaVal::LuaTable(table) => {
-                                let key = LuaValue::extract_first_return_val(exp.eval(env)?);
-                                let key = match key.0.as_ref() {
-                                    LuaVal::LuaNum(num_bytes, is_float) => {
-                                        let num_bytes = if *is_float {
-                                            let num = f64::from_be_bytes(*num_bytes);
-                                            // Check if float has no significant decimal places
-                                            if num % 1.0 == 0.0 {
-                                                (num as i64).to_be_bytes()
-                                            } else {
-                                                *num_bytes
-                                            }
-                                        } else {
-                                            *num_bytes
-                                        };
-
-                                        TableKey::Number(num_bytes)
-                                    },
-                                    LuaVal::LuaString(name) => TableKey::String(name.clone()),
-                                    _ => return Err(ASTExecError(format!("Field key '{key}' does not evaluate to a string or numeral")))
-                                };
-                                match table.get(key) {
-                                    Some(val) => Ok(vec![val]),
-                                    None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
-                                }
-                            }
-                            _ => {
-                                return Err(ASTExecError(format!(
-                                    "attempt to index a non-table value '{prefixexp}'"
-                                )))
-                            }
-                        }
-                    }
-                    Var::Dot((prefixexp, field)) => {
-                        let prefixexp = LuaValue::extract_first_return_val(prefixexp.eval(env)?);
-                        match prefixexp.0.as_ref() {
-                            LuaVal::LuaTable(table) => {
-                                let key = TableKey::String(field.clone());
-                                match table.get(key) {
-                                    Some(val) => Ok(vec![val]),
-                                    None => Ok(vec![LuaValue::new(LuaVal::LuaNil)]),
-                                }
-                            }
-                            _ => {
-                                return Err(ASTExecError(format!(
-                                    "attempt to index a non-table value '{prefixexp}'"
-                                )))
-                            }
-                        }
-                    }
-                }
-            }
-            PrefixExp::FunctionCall(funcall) => {
-                // Call function and check if there is return value
-                let return_vals = funcall.exec(env)?;
-                Ok(return_vals)
-            }
-            PrefixExp::Exp(exp) => Ok(exp.eval(env)?),
-        }
-    }
-}
-
 impl FunctionCall {
-    pub fn exec<'a, 'b>(&'a self, env: &'b mut Env<'a>) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
-        match self {
-            FunctionCall::Standard((func, args)) => {
-                let func = LuaValue::extract_first_return_val((*func).eval(env)?);
-                let rc = func.0;
-                match rc.as_ref() {
-                    LuaVal::Function(LuaFunction {
-                        par_list,
-                        block,
-                        captured_env,
-                    }) => {
-                        // Evaluate arguments first
-                        let args = args.eval(env)?;
-
-                        // Create environment for function
-                        let mut func_env = env.create_with_captured_env(captured_env);
-
-                        // Extend function environment with function arguments
-                        func_env.extend_local_env();
-                        let par_length = par_list.0.len();
-                        let arg_length = args.len();
-                        let mut i = 0;
-                        while i < par_length {
-                            // Arguments are locally scoped
-                            if i >= arg_length {
-                                func_env.insert_local(
-                                    par_list.0[i].clone(),
-                                    LuaValue::new(LuaVal::LuaNil),
-                                );
-                            } else {
-                                func_env.insert_local(par_list.0[i].clone(), args[i].clone_rc());
-                            }
-                            i += 1;
-                        }
-
-                        let result = block.exec(&mut func_env)?;
-
-                        // Remove arguments from the environment
-                        func_env.pop_local_env();
-                        match result {
-                            Some(vals) => Ok(vals),
-                            None => Err(ASTExecError(String::from(
-                                "Break statement can be only used in while, repeat, or for loop",
-                            ))),
-                        }
-                    }
-                    LuaVal::Print => {
-                        let args = args.eval(env)?;
-                        let mut stdout = io::stdout().lock();
-                        FunctionCall::print_fn(args, &mut stdout)
-                    }
-                    LuaVal::TestPrint(buffer) => {
-                        let args = args.eval(env)?;
-                        FunctionCall::test_print_fn(args, buffer)
-                    }
-                    LuaVal::Read => {
-                        let mut args = args.eval(env)?;
-                        if args.is_empty() {
-                            args.push(LuaValue::new(LuaVal::LuaString(String::from("*line"))));
-                        }
-                        FunctionCall::read_fn(args, io::stdin().lock())
-                    }
-                    LuaVal::Random => {
-                        let args = args.eval(env)?;
-                        if args.is_empty() {
-                            return Err(ASTExecError(String::from(
-                                "random() requires at least one argument",
-                            )));
-                        }
-                        FunctionCall::random_fn(args.get(0).unwrap())
-                    }
-                    _ => {
-                        return Err(ASTExecError(format!(
-                            "Cannot call non-function value with arguments. RC: {:?}",
-                            rc
-                        )))
-                    }
-                }
-            }
-            // prefixexp is a table
-            FunctionCall::Method((prefixexp, method_name, args)) => {
-                // evaluate
-                let prefixexp = LuaValue::extract_first_return_val(prefixexp.eval(env)?);
-                // pattern match on table: if it doesn't match LuaTable, throw an error
-                match prefixexp.0.as_ref() {
-
-                    LuaVal::LuaTable(table) => {
-                        // check if function is in table
-                        match table.get(TableKey::String(method_name.clone())) {
-                            
-                            Some(lua_value) => {
-                                // check the type of the lua value
-                                match lua_value.0.as_ref() {
-                                    // break down lua funciton into component parts, block comes from AST
-                                    LuaVal::Function(LuaFunction{par_list, block, captured_env}) => {
-                                        // evaluate arguments
-                                        let args = args.eval(env)?;
-                                        // create environment
-                                        let mut func_env = env.create_with_captured_env(captured_env);
-                                        // Extend function environment with function arguments
-                                        func_env.extend_local_env();
-                                        let par_length = par_list.0.len();
-                                        let arg_length = args.len();
-                                        let mut i = 0;
-                                        // can pass more/less arguments than specified in function call
-                                        while i < par_length {
-                                            // Arguments are locally scoped
-                                            if i >= arg_length {
-                                                func_env.insert_local(
-                                                    par_list.0[i].clone(),
-                                                    LuaValue::new(LuaVal::LuaNil),
-                                                );
-                                            } else {
-                                                // update reference count, make arg part of function's local environment
-                                                func_env.insert_local(par_list.0[i].clone(), args[i].clone_rc());
-                                            }
-                                            i += 1;
-                                        }
-
-                                        // Option: if you break from loop then it's None, else it's Some
-                                        let result = block.exec(&mut func_env)?;
-
-                                        // Remove arguments from the environment
-                                        func_env.pop_local_env();
-                                        match result {
-                                            Some(vals) => Ok(vals),
-                                            None => Err(ASTExecError(String::from(
-                                                "Break statement can be only used in while, repeat, or for loop",
-                                            ))),
-                                        }
-                                    } 
-                                    // not a function, return an error
-                                    _ => {
-                                        return Err(ASTExecError(format!(
-                                            "the value '{method_name}' is not a function"
-                                        )))
-                                    }
-                                }
-                            } 
-                            None => {
-                                return Err(ASTExecError(format!(
-                                    "could not find value '{method_name}' in table"
-                                )))
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(ASTExecError(format!(
-                            "table '{prefixexp}' doesn't exist"
-                        )))
-                    }
-                }
-            }
-        }
-    }
-
-    fn test_print_fn<'a>(
+    fn test_print_fn(
         args: Vec<LuaValue>,
         buffer: &Rc<RefCell<Vec<String>>>,
-    ) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
+    ) -> Result<Vec<LuaValue>, ASTExecError> {
         let mut temp_buf = Vec::with_capacity(args.len());
         for arg in args.iter() {
             temp_buf.push(format!("{}", arg));
@@ -625,10 +315,10 @@ impl FunctionCall {
         Ok(vec![])
     }
 
-    fn print_fn<'a, W>(
+    fn print_fn<W>(
         args: Vec<LuaValue>,
         stdout: &mut W,
-    ) -> Result<Vec<LuaValue<'a>>, ASTExecError>
+    ) -> Result<Vec<LuaValue>, ASTExecError>
     where
         W: std::io::Write,
     {
@@ -640,7 +330,7 @@ impl FunctionCall {
             } {
                 Ok(_) => {}
                 Err(_) => {
-                    return Err(ASTExecError(format!(
+                    return Err(ASTExecError::from(format!(
                         "Cannot print value of type {:?}",
                         arg.0
                     )))
@@ -650,7 +340,7 @@ impl FunctionCall {
         Ok(vec![])
     }
 
-    fn read_fn<'a, R>(args: Vec<LuaValue>, mut reader: R) -> Result<Vec<LuaValue<'a>>, ASTExecError>
+    fn read_fn<R>(args: Vec<LuaValue>, mut reader: R) -> Result<Vec<LuaValue>, ASTExecError>
     where
         R: BufRead,
     {
@@ -659,16 +349,16 @@ impl FunctionCall {
         // but skipping them for now
         let mut result = Vec::with_capacity(args.len());
         for arg in args {
-            match arg.0.as_ref() {
+            match &arg.0 {
                 LuaVal::LuaString(s) => {
                     if s == "*line" {
                         // "*line" reads the next line (default)
                         let mut input = String::new();
                         match reader.read_line(&mut input) {
                             Ok(_) => result
-                                .push(LuaValue::new(LuaVal::LuaString(input.trim().to_string()))),
+                                .push(LuaValue::new(LuaVal::LuaString(LuaString::from(input.trim())))),
                             Err(_) => {
-                                return Err(ASTExecError(String::from(
+                                return Err(ASTExecError::from(String::from(
                                     "Cannot read line from stdin",
                                 )))
                             }
@@ -682,34 +372,28 @@ impl FunctionCall {
                                 let is_float = number % 1.0 != 0.0;
                                 if !is_float {
                                     let number = number as i64;
-                                    result.push(LuaValue::new(LuaVal::LuaNum(
-                                        number.to_be_bytes(),
-                                        false,
-                                    )));
+                                    result.push(LuaValue::new(LuaVal::LuaInt(number)));
                                 } else {
-                                    result.push(LuaValue::new(LuaVal::LuaNum(
-                                        number.to_be_bytes(),
-                                        true,
-                                    )));
+                                    result.push(LuaValue::new(LuaVal::LuaFloat(number)));
                                 }
                             }
                             Err(_) => {
-                                return Err(ASTExecError(String::from(
+                                return Err(ASTExecError::from(String::from(
                                     "Cannot read line from stdin",
                                 )))
                             }
                         }
                     } else {
-                        return Err(ASTExecError(format!(
+                        return Err(ASTExecError::from(format!(
                             "Cannot read from stdin with argument '{}'",
                             s
                         )));
                     }
                 }
                 _ => {
-                    return Err(ASTExecError(format!(
+                    return Err(ASTExecError::from(format!(
                         "Cannot read with argument of {:?}",
-                        arg.0.as_ref()
+                        &arg.0
                     )))
                 }
             }
@@ -717,20 +401,16 @@ impl FunctionCall {
         Ok(result)
     }
 
-    fn random_fn<'a>(arg: &LuaValue<'a>) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
-        match *arg.0 {
-            LuaVal::LuaNum(bytes, is_float) => {
-                if is_float {
-                    Err(ASTExecError(
-                        "Cannot generate random number with float".to_string(),
-                    ))
-                } else {
-                    let n = i64::from_be_bytes(bytes);
-                    let rng = rand::thread_rng().gen_range(0..=n);
-                    Ok(vec![LuaValue::new(LuaVal::LuaNum(rng.to_be_bytes(), true))])
-                }
+    fn random_fn(arg: &LuaValue) -> Result<Vec<LuaValue>, ASTExecError> {
+        match arg.0 {
+            LuaVal::LuaFloat(_) => Err(ASTExecError::from(
+                "Cannot generate random number with float".to_string(),
+            )),
+            LuaVal::LuaInt(n) => {
+                let rng = rand::thread_rng().gen_range(0..=n);
+                Ok(vec![LuaValue::new(LuaVal::LuaInt(rng))])
             }
-            _ => Err(ASTExecError(format!(
+            _ => Err(ASTExecError::from(format!(
                 "Cannot generate random number with argument of {:?}",
                 arg.0
             ))),
@@ -738,134 +418,492 @@ impl FunctionCall {
     }
 }
 
-impl Args {
-    fn eval<'a>(&'a self, env: &mut Env<'a>) -> Result<Vec<LuaValue<'a>>, ASTExecError> {
-        match self {
-            Args::ExpList(exps_list) => {
-                let mut args = Vec::with_capacity(exps_list.len());
-                for (i, exp) in exps_list.iter().enumerate() {
-                    // For each argument, only the first return value is used,
-                    // but last argument can use multiple return values
-                    if i == exps_list.len() - 1 {
-                        args.append(&mut exp.eval(env)?);
-                    } else {
-                        args.push(LuaValue::extract_first_return_val(exp.eval(env)?));
+impl FunctionCall {
+    // load(chunk [, chunkname [, mode [, env]]])
+    // Returns the compiled chunk as a function, or nil and an error message
+    fn load_fn(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        let mut args = args.into_iter();
+        let chunk = args.next().unwrap_or(LuaValue::new(LuaVal::LuaNil));
+        let chunkname = args.next().filter(|name| !name.is_nil());
+        let mode = args.next().filter(|mode| !mode.is_nil());
+        let global_env = FunctionCall::chunk_env(args.next(), "load", env)?;
+
+        let source = match &chunk.0 {
+            LuaVal::LuaString(source) => source.to_string(),
+            // A reader function returns pieces of the chunk until it returns nil or ""
+            _ if chunk.is_callable() => {
+                let mut source = String::new();
+                loop {
+                    let piece = match chunk.call(vec![], env) {
+                        Ok(vals) => LuaValue::extract_first_return_val(vals),
+                        Err(err) => return Ok(FunctionCall::load_error(err.to_string())),
+                    };
+                    match &piece.0 {
+                        LuaVal::LuaNil => break,
+                        LuaVal::LuaString(piece) if piece.is_empty() => break,
+                        LuaVal::LuaString(piece) => source.push_str(piece),
+                        _ => {
+                            return Ok(FunctionCall::load_error(String::from(
+                                "reader function must return a string",
+                            )))
+                        }
                     }
                 }
-                Ok(args)
+                source
+            }
+            _ => {
+                return Err(ASTExecError::from(format!(
+                    "bad argument #1 to 'load' (string expected, got {})",
+                    chunk.type_name()
+                )))
             }
-            Args::TableConstructor(fields) => {
-                let table = build_table(fields, env)?;
+        };
+        let chunkname = match chunkname {
+            Some(name) => name.into_string()?.to_string(),
+            None => source.clone(),
+        };
+        let mode = match mode {
+            Some(mode) => mode.into_string()?.to_string(),
+            None => String::from("bt"),
+        };
 
-                Ok(vec![LuaValue::new(LuaVal::LuaTable(table))])
+        Ok(FunctionCall::compile_chunk(
+            &source,
+            &chunkname,
+            &mode,
+            global_env,
+        ))
+    }
+
+    // loadfile([filename [, mode [, env]]])
+    // Like load, but the chunk is read from a file (or stdin without a file name)
+    fn loadfile_fn(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        let mut args = args.into_iter();
+        let filename = args.next().filter(|name| !name.is_nil());
+        let mode = args.next().filter(|mode| !mode.is_nil());
+        let global_env = FunctionCall::chunk_env(args.next(), "loadfile", env)?;
+
+        let (source, chunkname) = match FunctionCall::read_chunk_file(filename) {
+            Ok(file) => file,
+            Err(err) => return Ok(FunctionCall::load_error(err.to_string())),
+        };
+        let mode = match mode {
+            Some(mode) => mode.into_string()?.to_string(),
+            None => String::from("bt"),
+        };
+
+        Ok(FunctionCall::compile_chunk(
+            &source,
+            &chunkname,
+            &mode,
+            global_env,
+        ))
+    }
+
+    // dofile([filename])
+    // Runs the file as a chunk and returns its values, errors are propagated
+    fn dofile_fn(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        let filename = args.into_iter().next().filter(|name| !name.is_nil());
+        let (source, chunkname) = FunctionCall::read_chunk_file(filename)?;
+
+        let mut vals = FunctionCall::compile_chunk(
+            &source,
+            &chunkname,
+            "t",
+            env.get_global_env(),
+        );
+        if vals.len() > 1 {
+            return Err(ASTExecError::from(vals.remove(1).into_string()?.to_string()));
+        }
+        vals.remove(0).call(vec![], env)
+    }
+
+    // Environment of a loaded chunk: the given table, or the environment of the caller
+    fn chunk_env(
+        table: Option<LuaValue>,
+        fn_name: &str,
+        env: &Env,
+    ) -> Result<LuaValue, ASTExecError> {
+        match table {
+            None => Ok(env.get_global_env()),
+            Some(table) => match &table.0 {
+                LuaVal::LuaNil => Ok(env.get_global_env()),
+                LuaVal::LuaTable(_) => Ok(table),
+                _ => Err(ASTExecError::from(format!(
+                    "bad argument #4 to '{fn_name}' (table expected, got {})",
+                    table.type_name()
+                ))),
+            },
+        }
+    }
+
+    // Returns the source of the file and its chunk name
+    fn read_chunk_file(filename: Option<LuaValue>) -> Result<(String, String), ASTExecError> {
+        match filename {
+            Some(filename) => {
+                let filename = filename.into_string()?.to_string();
+                match fs::read_to_string(&filename) {
+                    Ok(source) => Ok((source, format!("@{filename}"))),
+                    Err(_) => Err(ASTExecError::from(format!("cannot open {filename}"))),
+                }
+            }
+            None => {
+                let mut source = String::new();
+                match io::stdin().read_to_string(&mut source) {
+                    Ok(_) => Ok((source, String::from("=stdin"))),
+                    Err(_) => Err(ASTExecError::from(String::from("cannot read stdin"))),
+                }
+            }
+        }
+    }
+
+    // Parse the source into a vararg function without parameters,
+    // or return nil and the error message
+    pub(crate) fn compile_chunk(
+        source: &str,
+        chunkname: &str,
+        mode: &str,
+        global_env: LuaValue,
+    ) -> Vec<LuaValue> {
+        // There are no binary chunks, every chunk is text
+        if !mode.contains('t') {
+            return FunctionCall::load_error(format!(
+                "attempt to load a text chunk (mode is '{mode}')"
+            ));
+        }
+        let ast = match parser::parse_chunk(source) {
+            Ok(ast) => ast,
+            Err(err) => {
+                return FunctionCall::load_error(format!("{}: {err}", chunk_id(chunkname)))
             }
-            Args::LiteralString(s) => Ok(vec![LuaValue::new(LuaVal::LuaString(s.clone()))]),
+        };
+        match compiler::compile(&ast, chunkname) {
+            Ok(proto) => vec![LuaValue::new(LuaVal::Function(Rc::new(Closure::main(
+                proto, global_env,
+            ))))],
+            Err(err) => FunctionCall::load_error(format!("{}: {err}", chunk_id(chunkname))),
         }
     }
+
+    fn load_error(msg: String) -> Vec<LuaValue> {
+        vec![
+            LuaValue::new(LuaVal::LuaNil),
+            LuaValue::new(LuaVal::LuaString(LuaString::from(msg))),
+        ]
+    }
 }
 
-fn build_table<'a>(
-    fields: &'a Vec<Field>,
-    env: &mut Env<'a>,
-) -> Result<LuaTable<'a>, ASTExecError> {
-    let table = LuaTable::new();
-    let mut numeric_index = 1;
-    let field_count = fields.len();
-    for (i, field) in fields.iter().enumerate() {
-        match field {
-            Field::Bracketed((exp1, exp2)) => {
-                // Fully evaluate key and value
-                let key = LuaValue::extract_first_return_val(exp1.eval(env)?);
-                let val = LuaValue::extract_first_return_val(exp2.eval(env)?);
-
-                // If there are multiple values, it's correct behavior to only use the first value
-                if !key.is_numeral() && !key.is_string() {
-                    return Err(ASTExecError(format!(
-                        "Field key '{exp1}' does not evaluate to a string or numeral"
-                    )));
+impl LuaValue {
+    /// Whether the value is a function, or a table with a `__call` metamethod
+    pub fn is_callable(&self) -> bool {
+        self.type_name() == "function" || self.call_metamethod().is_some()
+    }
+
+    /// The function of the `__call` metamethod of a table, called with the
+    /// table before the arguments when the table is called
+    pub(crate) fn call_metamethod(&self) -> Option<LuaValue> {
+        match &self.0 {
+            LuaVal::LuaTable(table) => table
+                .get_metamethod("__call")
+                .filter(|handler| handler.type_name() == "function"),
+            _ => None,
+        }
+    }
+
+    /// Call the value with already evaluated arguments. Both Lua functions
+    /// and built-in functions can be called.
+    pub fn call(
+        &self,
+        mut args: Vec<LuaValue>,
+        env: &mut Env,
+    ) -> Result<Vec<LuaValue>, ASTExecError> {
+        if let Some(handler) = self.call_metamethod() {
+            args.insert(0, self.clone());
+            return handler.call(args, env);
+        }
+        match &self.0 {
+            LuaVal::Function(func) => vm::call_closure(func, args, env),
+            LuaVal::Print => {
+                let mut stdout = io::stdout().lock();
+                FunctionCall::print_fn(args, &mut stdout)
+            }
+            LuaVal::TestPrint(buffer) => FunctionCall::test_print_fn(args, buffer),
+            LuaVal::Read => {
+                if args.is_empty() {
+                    args.push(LuaValue::new(LuaVal::LuaString(LuaString::from("*line"))));
                 }
-                table.insert(key, val)?;
+                FunctionCall::read_fn(args, io::stdin().lock())
             }
-            Field::Name((name, exp)) => {
-                let val = LuaValue::extract_first_return_val(exp.eval(env)?);
-                table.insert_ident(name.clone(), val);
+            LuaVal::Random => match args.first() {
+                Some(arg) => FunctionCall::random_fn(arg),
+                None => Err(ASTExecError::from(String::from(
+                    "random() requires at least one argument",
+                ))),
+            },
+            LuaVal::Load => FunctionCall::load_fn(args, env),
+            LuaVal::LoadFile => FunctionCall::loadfile_fn(args, env),
+            LuaVal::DoFile => FunctionCall::dofile_fn(args, env),
+            LuaVal::Require(loading) => package::require(args, env, loading),
+            LuaVal::PreloadSearcher => package::search_preload(args, env),
+            LuaVal::LuaSearcher => package::search_lua(args, env),
+            LuaVal::SearchPath => package::searchpath(args),
+            LuaVal::SetMetatable => base::setmetatable(args, env),
+            LuaVal::GetMetatable => base::getmetatable(args),
+            LuaVal::RawGet => base::rawget(args),
+            LuaVal::RawSet => base::rawset(args),
+            LuaVal::RawEqual => base::rawequal(args),
+            LuaVal::RawLen => base::rawlen(args),
+            LuaVal::Next => base::next(args),
+            LuaVal::Pairs => base::pairs(args, env),
+            LuaVal::IPairs => base::ipairs(args),
+            LuaVal::IPairsIter => base::ipairs_iter(args, env),
+            LuaVal::Error => base::error(args),
+            LuaVal::PCall => base::pcall(args, env),
+            LuaVal::Type => base::type_fn(args),
+            LuaVal::CollectGarbage => base::collectgarbage(args, env),
+            LuaVal::CoroutineCreate => coroutine::create(args, env),
+            LuaVal::CoroutineResume => coroutine::resume(args, env),
+            LuaVal::CoroutineYield => coroutine::yield_fn(env),
+            LuaVal::CoroutineStatus => coroutine::status(args),
+            LuaVal::CoroutineWrap => coroutine::wrap(args, env),
+            LuaVal::CoroutineWrapped(co) => coroutine::call_wrapped(co, args, env),
+            LuaVal::CoroutineIsYieldable => coroutine::isyieldable(env),
+            LuaVal::CoroutineRunning => coroutine::running(env),
+            LuaVal::CoroutineClose => coroutine::close(args, env),
+            LuaVal::DebugSetHook => debug::sethook(args, env),
+            LuaVal::DebugGetHook => debug::gethook(env),
+            LuaVal::DebugGetInfo => debug::getinfo(args, env),
+            LuaVal::DebugGetLocal => debug::getlocal(args, env),
+            LuaVal::DebugSetLocal => debug::setlocal(args, env),
+            LuaVal::DebugGetUpvalue => debug::getupvalue(args, env),
+            LuaVal::DebugSetUpvalue => debug::setupvalue(args, env),
+            LuaVal::RustFunction(func) => func.call(args, env),
+            _ => Err(ASTExecError::from(format!(
+                "Cannot call non-function value with arguments. RC: {:?}",
+                self.0
+            ))),
+        }
+    }
+}
+
+// Maximum length of a chain of __index or __newindex tables
+const MAX_META_CHAIN: usize = 2000;
+
+/// Where the chain of `__index` or `__newindex` metamethods of an access ends:
+/// at a value, or at a function to call with the table it belongs to
+pub(crate) enum Lookup {
+    Value(LuaValue),
+    Handler(LuaValue, LuaValue),
+}
+
+impl LuaValue {
+    /// Index the value like `self[key]`, following `__index` metamethods
+    pub fn index(&self, key: LuaValue, env: &mut Env) -> Result<LuaValue, ASTExecError> {
+        match self.lookup(&key)? {
+            Lookup::Value(val) => Ok(val),
+            Lookup::Handler(handler, table) => {
+                let vals = handler.call(vec![table, key], env)?;
+                Ok(LuaValue::extract_first_return_val(vals))
             }
-            Field::Unnamed(exp) => {
-                let vals = exp.eval(env)?;
-
-                // If this is the last field in the table constructor,
-                // and there are multiple values in the vector, spead
-                // them out into their own fields each indexed by an
-                // incrementing number
-                if vals.len() > 1 && i == field_count - 1 {
-                    vals.into_iter().for_each(|val| {
-                        table.insert_int(numeric_index, val);
-                        numeric_index += 1;
-                    });
-                } else {
-                    let val = LuaValue::extract_first_return_val(vals);
-                    table.insert_int(numeric_index, val);
-                    numeric_index += 1;
+        }
+    }
+
+    /// Follow the `__index` metamethods of `self[key]` up to the value or the
+    /// function to call for it
+    pub(crate) fn lookup(&self, key: &LuaValue) -> Result<Lookup, ASTExecError> {
+        let table_key = match TableKey::from_value(key) {
+            Some(table_key) => table_key,
+            None => {
+                return Err(ASTExecError::from(format!(
+                    "Field key '{key}' does not evaluate to a string or numeral"
+                )))
+            }
+        };
+
+        let mut current = self.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &current.0 {
+                LuaVal::LuaTable(table) => {
+                    match table.get(table_key.clone()).filter(|val| !val.is_nil()) {
+                        Some(val) => return Ok(Lookup::Value(val)),
+                        None => match table.get_metamethod("__index") {
+                            Some(handler) => handler,
+                            None => return Ok(Lookup::Value(LuaValue::new(LuaVal::LuaNil))),
+                        },
+                    }
+                }
+                _ => {
+                    return Err(ASTExecError::from(format!(
+                        "attempt to index a {} value",
+                        current.type_name()
+                    )))
                 }
+            };
+            if handler.is_callable() {
+                return Ok(Lookup::Handler(handler, current));
             }
+            current = handler;
         }
+        Err(ASTExecError::from(String::from(
+            "'__index' chain too long; possible loop",
+        )))
+    }
+
+    /// Assign like `self[key] = val`, following `__newindex` metamethods
+    pub fn set_index(
+        &self,
+        key: LuaValue,
+        val: LuaValue,
+        env: &mut Env,
+    ) -> Result<(), ASTExecError> {
+        if let Lookup::Handler(handler, table) = self.set_lookup(&key, &val)? {
+            handler.call(vec![table, key, val], env)?;
+        }
+        Ok(())
+    }
+
+    /// Follow the `__newindex` metamethods of `self[key] = val`: assign the
+    /// value to the table at the end of the chain, or return the function to
+    /// call for it
+    pub(crate) fn set_lookup(
+        &self,
+        key: &LuaValue,
+        val: &LuaValue,
+    ) -> Result<Lookup, ASTExecError> {
+        let mut current = self.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &current.0 {
+                LuaVal::LuaTable(table) => {
+                    // __newindex is only used for fields that are not present
+                    let present = TableKey::from_value(key)
+                        .and_then(|table_key| table.get(table_key))
+                        .is_some_and(|old| !old.is_nil());
+                    match table.get_metamethod("__newindex") {
+                        Some(handler) if !present => handler,
+                        _ => {
+                            table.insert(key.clone(), val.clone())?;
+                            return Ok(Lookup::Value(val.clone()));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(ASTExecError::from(format!(
+                        "attempt to index a {} value",
+                        current.type_name()
+                    )))
+                }
+            };
+            if handler.is_callable() {
+                return Ok(Lookup::Handler(handler, current));
+            }
+            current = handler;
+        }
+        Err(ASTExecError::from(String::from(
+            "'__newindex' chain too long; possible loop",
+        )))
     }
-
-    Ok(table)
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
-    use crate::interpreter::TableKey;
+    use crate::interpreter::{FloatKey, LuaTable, TableKey};
 
     use super::*;
     use std::{collections::HashMap, vec};
 
+    // Expressions are evaluated by compiling them into the return statement of a chunk
+    trait ToExpList {
+        fn to_exp_list(&self) -> Vec<Expression>;
+    }
+    impl ToExpList for Expression {
+        fn to_exp_list(&self) -> Vec<Expression> {
+            vec![self.clone()]
+        }
+    }
+    impl ToExpList for PrefixExp {
+        fn to_exp_list(&self) -> Vec<Expression> {
+            vec![Expression::PrefixExp(
+                Box::new(self.clone()),
+                Span::default(),
+            )]
+        }
+    }
+    impl ToExpList for FunctionCall {
+        fn to_exp_list(&self) -> Vec<Expression> {
+            PrefixExp::FunctionCall(self.clone()).to_exp_list()
+        }
+    }
+    impl ToExpList for Args {
+        fn to_exp_list(&self) -> Vec<Expression> {
+            match self {
+                Args::ExpList(exps) => exps.clone(),
+                Args::TableConstructor(fields) => vec![Expression::TableConstructor(
+                    fields.clone(),
+                    Span::default(),
+                )],
+                Args::LiteralString(s) => {
+                    vec![Expression::LiteralString(s.clone(), Span::default())]
+                }
+            }
+        }
+    }
+    fn eval(exp: &impl ToExpList, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        AST(Block {
+            statements: vec![],
+            return_stat: Some(exp.to_exp_list()),
+        })
+        .exec_with_return(env)
+    }
+    fn exec(stat: &Statement, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        AST(Block {
+            statements: vec![stat.clone()],
+            return_stat: None,
+        })
+        .exec_with_return(env)
+    }
+
     // Helper functions
     fn var_exp(name: &str) -> Expression {
-        Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(name.to_string()))))
+        Expression::PrefixExp(
+            Box::new(PrefixExp::Var(Var::Name(name.to_string()))),
+            Span::default(),
+        )
     }
-    fn lua_integer<'a>(n: i64) -> Vec<LuaValue<'a>> {
-        vec![LuaValue::new(LuaVal::LuaNum(n.to_be_bytes(), false))]
+    fn lua_integer(n: i64) -> Vec<LuaValue> {
+        vec![LuaValue::new(LuaVal::LuaInt(n))]
     }
-    fn lua_integers<'a>(nums: Vec<i64>) -> Vec<LuaValue<'a>> {
+    fn lua_integers(nums: Vec<i64>) -> Vec<LuaValue> {
         let mut v = Vec::with_capacity(nums.len());
         for n in nums {
-            v.push(LuaValue::new(LuaVal::LuaNum(n.to_be_bytes(), false)));
+            v.push(LuaValue::new(LuaVal::LuaInt(n)));
         }
         v
     }
-    fn lua_float<'a>(n: f64) -> Vec<LuaValue<'a>> {
-        vec![LuaValue::new(LuaVal::LuaNum(n.to_be_bytes(), true))]
+    fn lua_float(n: f64) -> Vec<LuaValue> {
+        vec![LuaValue::new(LuaVal::LuaFloat(n))]
     }
-    fn lua_nil<'a>() -> Vec<LuaValue<'a>> {
+    fn lua_nil() -> Vec<LuaValue> {
         vec![LuaValue::new(LuaVal::LuaNil)]
     }
-    fn lua_false<'a>() -> Vec<LuaValue<'a>> {
+    fn lua_false() -> Vec<LuaValue> {
         vec![LuaValue::new(LuaVal::LuaBool(false))]
     }
-    fn lua_true<'a>() -> Vec<LuaValue<'a>> {
+    fn lua_true() -> Vec<LuaValue> {
         vec![LuaValue::new(LuaVal::LuaBool(true))]
     }
-    fn lua_string<'a>(s: &str) -> Vec<LuaValue<'a>> {
-        vec![LuaValue::new(LuaVal::LuaString(s.to_string()))]
+    fn lua_string(s: &str) -> Vec<LuaValue> {
+        vec![LuaValue::new(LuaVal::LuaString(LuaString::from(s)))]
     }
-    fn lua_function<'a>(
-        par_list: &'a ParList,
-        block: &'a Block,
-        env: &Env<'a>,
-    ) -> Vec<LuaValue<'a>> {
-        let captured_env = env.get_local_env().capture_env();
-        vec![LuaValue::new(LuaVal::Function(LuaFunction {
-            par_list,
-            block,
-            captured_env,
-        }))]
+    fn lua_function(par_list: &ParList, block: &Block, env: &mut Env) -> Vec<LuaValue> {
+        let exp = Expression::FunctionDef(
+            FuncBody::new(par_list.clone(), block.clone(), Span::default()),
+            Span::default(),
+        );
+        eval(&exp, env).unwrap()
     }
-    fn lua_table<'a>(hmap: HashMap<TableKey, LuaValue<'a>>) -> Vec<LuaValue<'a>> {
-        vec![LuaValue::new(LuaVal::LuaTable(LuaTable(RefCell::new(
-            hmap,
-        ))))]
+    fn lua_table(hmap: HashMap<TableKey, LuaValue>) -> Vec<LuaValue> {
+        vec![LuaValue::new(LuaVal::LuaTable(Rc::new(LuaTable::from(hmap))))]
     }
 
     #[test]
@@ -873,8 +911,8 @@ mod tests {
         let mut env = Env::new();
 
         // Nil
-        let exp_nil = Expression::Nil;
-        assert_eq!(exp_nil.eval(&mut env), Ok(lua_nil()));
+        let exp_nil = Expression::Nil(Span::default());
+        assert_eq!(eval(&exp_nil, &mut env), Ok(lua_nil()));
     }
 
     #[test]
@@ -882,10 +920,10 @@ mod tests {
         let mut env = Env::new();
 
         // Boolean
-        let exp_false = Expression::False;
-        let exp_true = Expression::True;
-        assert_eq!(exp_false.eval(&mut env), Ok(lua_false()));
-        assert_eq!(exp_true.eval(&mut env), Ok(lua_true()));
+        let exp_false = Expression::False(Span::default());
+        let exp_true = Expression::True(Span::default());
+        assert_eq!(eval(&exp_false, &mut env), Ok(lua_false()));
+        assert_eq!(eval(&exp_true, &mut env), Ok(lua_true()));
     }
 
     #[test]
@@ -894,8 +932,8 @@ mod tests {
 
         // Integer
         let num: i64 = 10;
-        let exp_int = Expression::Numeral(Numeral::Integer(num));
-        assert_eq!(exp_int.eval(&mut env), Ok(lua_integer(num)));
+        let exp_int = Expression::Numeral(Numeral::Integer(num), Span::default());
+        assert_eq!(eval(&exp_int, &mut env), Ok(lua_integer(num)));
     }
 
     #[test]
@@ -904,8 +942,8 @@ mod tests {
 
         // Float
         let num: f64 = 10.04;
-        let exp_float = Expression::Numeral(Numeral::Float(num));
-        assert_eq!(exp_float.eval(&mut env), Ok(lua_float(num)));
+        let exp_float = Expression::Numeral(Numeral::Float(num), Span::default());
+        assert_eq!(eval(&exp_float, &mut env), Ok(lua_float(num)));
     }
 
     #[test]
@@ -913,8 +951,8 @@ mod tests {
         let mut env = Env::new();
 
         // String
-        let exp_str = Expression::LiteralString("Hello World!".to_string());
-        assert_eq!(exp_str.eval(&mut env), Ok(lua_string("Hello World!")));
+        let exp_str = Expression::LiteralString("Hello World!".to_string(), Span::default());
+        assert_eq!(eval(&exp_str, &mut env), Ok(lua_string("Hello World!")));
     }
 
     #[test]
@@ -928,9 +966,16 @@ mod tests {
             statements: vec![],
             return_stat: None,
         };
-        let expected_function = lua_function(&par_list, &block, &env);
-        let exp_func_def = Expression::FunctionDef((par_list.clone(), block.clone()));
-        assert_eq!(exp_func_def.eval(&mut env), Ok(expected_function));
+        let exp_func_def = Expression::FunctionDef(
+            FuncBody::new(par_list.clone(), block.clone(), Span::default()),
+            Span::default(),
+        );
+        let func = LuaValue::extract_first_return_val(eval(&exp_func_def, &mut env).unwrap());
+        assert_eq!(func.type_name(), "function");
+
+        // Every evaluation creates a new closure
+        let other = LuaValue::extract_first_return_val(eval(&exp_func_def, &mut env).unwrap());
+        assert_ne!(func, other);
     }
 
     #[test]
@@ -940,32 +985,36 @@ mod tests {
         // Set statements
         let varlist = vec![Var::Name("a".to_string()), Var::Name("b".to_string())];
         let explist = vec![
-            Expression::Numeral(Numeral::Integer(30)),
-            Expression::Numeral(Numeral::Integer(20)),
+            Expression::Numeral(Numeral::Integer(30), Span::default()),
+            Expression::Numeral(Numeral::Integer(20), Span::default()),
         ];
-        let stat = Statement::Assignment((varlist, explist, false));
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
         let return_stat = Some(vec![var_exp("test"), var_exp("a"), var_exp("b")]);
 
         let par_list = ParList(vec![String::from("test")], false);
         let block = Block {
             statements: vec![stat],
-            return_stat: return_stat,
+            return_stat,
         };
 
-        env.insert_global(
-            String::from("f"),
-            LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env)),
-        );
-        let args = Args::ExpList(vec![Expression::Numeral(Numeral::Integer(100))]);
+        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
+        env.insert_global(String::from("f"), f);
+        let args = Args::ExpList(vec![Expression::Numeral(
+            Numeral::Integer(100),
+            Span::default(),
+        )]);
         let func_call =
             FunctionCall::Standard((Box::new(PrefixExp::Var(Var::Name("f".to_string()))), args));
         let exp = PrefixExp::FunctionCall(func_call.clone());
 
         // f(100) executes a = 30, b = 20, return test
-        assert_eq!(exp.eval(&mut env), Ok(lua_integers(vec![100, 30, 20])));
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integers(vec![100, 30, 20])));
 
         // Function with return values of function call
-        let func_call_exp = Expression::PrefixExp(Box::new(PrefixExp::FunctionCall(func_call)));
+        let func_call_exp = Expression::PrefixExp(
+            Box::new(PrefixExp::FunctionCall(func_call)),
+            Span::default(),
+        );
         let par_list = ParList(vec![], false);
         let block = Block {
             statements: vec![],
@@ -975,17 +1024,15 @@ mod tests {
                 func_call_exp.clone(),
             ]),
         };
-        env.insert_global(
-            String::from("f2"),
-            LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env)),
-        );
+        let f2 = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
+        env.insert_global(String::from("f2"), f2);
         let func_call2 = PrefixExp::FunctionCall(FunctionCall::Standard((
             Box::new(PrefixExp::Var(Var::Name("f2".to_string()))),
             Args::ExpList(vec![]),
         )));
         // Each return value return one of the values, but last one return all
         assert_eq!(
-            func_call2.eval(&mut env),
+            eval(&func_call2, &mut env),
             Ok(lua_integers(vec![100, 100, 100, 30, 20]))
         );
 
@@ -998,10 +1045,8 @@ mod tests {
             statements: vec![],
             return_stat: Some(vec![var_exp("a"), var_exp("b"), var_exp("c")]),
         };
-        env.insert_global(
-            String::from("f3"),
-            LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env)),
-        );
+        let f3 = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
+        env.insert_global(String::from("f3"), f3);
         let args = Args::ExpList(vec![func_call_exp.clone(), func_call_exp.clone()]);
         let func_call3 = PrefixExp::FunctionCall(FunctionCall::Standard((
             Box::new(PrefixExp::Var(Var::Name("f3".to_string()))),
@@ -1009,7 +1054,7 @@ mod tests {
         )));
         // Each argument take one return value of each expression except last one
         assert_eq!(
-            func_call3.eval(&mut env),
+            eval(&func_call3, &mut env),
             Ok(lua_integers(vec![100, 100, 30]))
         );
     }
@@ -1024,32 +1069,35 @@ mod tests {
             statements: vec![],
             return_stat: None,
         };
-        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env));
+        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
         env.insert_global("f".to_string(), f);
         let args = Args::ExpList(vec![
-            Expression::Numeral(Numeral::Integer(10)),
-            Expression::Numeral(Numeral::Float(10.1)),
-            Expression::False,
-            Expression::LiteralString("Hello World!".to_string()),
-            Expression::Nil,
+            Expression::Numeral(Numeral::Integer(10), Span::default()),
+            Expression::Numeral(Numeral::Float(10.1), Span::default()),
+            Expression::False(Span::default()),
+            Expression::LiteralString("Hello World!".to_string(), Span::default()),
+            Expression::Nil(Span::default()),
             var_exp("f"),
         ]);
 
         // Capture the output of `print`
         let mut output = Vec::new();
         assert_eq!(
-            FunctionCall::print_fn(args.eval(&mut env).unwrap(), &mut output),
+            FunctionCall::print_fn(eval(&args, &mut env).unwrap(), &mut output),
             Ok(vec![])
         );
         let func_val = env.get_global("f").unwrap().0;
-        let func_reference = if let LuaVal::Function(f) = func_val.as_ref() {
+        let func_reference = if let LuaVal::Function(f) = &func_val {
             f
         } else {
             unreachable!("Expected function")
         };
         assert_eq!(
             String::from_utf8(output).unwrap(),
-            format!("10 10.1 false Hello World! nil {:p}\n", func_reference)
+            format!(
+                "10 10.1 false Hello World! nil {:p}\n",
+                Rc::as_ptr(func_reference)
+            )
         );
     }
 
@@ -1059,24 +1107,27 @@ mod tests {
 
         let input = b"I'm James\n100";
         let args = Args::ExpList(vec![
-            Expression::LiteralString("*line".to_string()),
-            Expression::LiteralString("*number".to_string()),
+            Expression::LiteralString("*line".to_string(), Span::default()),
+            Expression::LiteralString("*number".to_string(), Span::default()),
         ]);
-        let read_input = FunctionCall::read_fn(args.eval(&mut env).unwrap(), &input[..]);
+        let read_input = FunctionCall::read_fn(eval(&args, &mut env).unwrap(), &input[..]);
 
         assert_eq!(
             read_input,
             Ok(vec![
-                LuaValue::new(LuaVal::LuaString("I'm James".to_string())),
-                LuaValue::new(LuaVal::LuaNum(100i64.to_be_bytes(), false)),
+                LuaValue::new(LuaVal::LuaString(LuaString::from("I'm James"))),
+                LuaValue::new(LuaVal::LuaInt(100i64)),
             ])
         );
 
-        let args = Args::ExpList(vec![Expression::Numeral(Numeral::Float(100.01))]);
+        let args = Args::ExpList(vec![Expression::Numeral(
+            Numeral::Float(100.01),
+            Span::default(),
+        )]);
         assert_eq!(
-            FunctionCall::read_fn(args.eval(&mut env).unwrap(), &input[..]),
-            Err(ASTExecError(String::from(
-                "Cannot read with argument of LuaNum([64, 89, 0, 163, 215, 10, 61, 113], true)"
+            FunctionCall::read_fn(eval(&args, &mut env).unwrap(), &input[..]),
+            Err(ASTExecError::from(String::from(
+                "Cannot read with argument of LuaFloat(100.01)"
             )))
         );
     }
@@ -1085,37 +1136,43 @@ mod tests {
     fn test_eval_table_constructor() {
         let mut env = Env::new();
 
-        let exp = Expression::TableConstructor(vec![
-            Field::Name((
-                String::from("age"),
-                Expression::Numeral(Numeral::Integer(23)),
-            )),
-            Field::Unnamed(Expression::BinaryOp((
-                Box::new(Expression::Numeral(Numeral::Integer(2))),
-                BinOp::Add,
-                Box::new(Expression::Numeral(Numeral::Integer(3))),
-            ))),
-            Field::Bracketed((
-                Expression::Numeral(Numeral::Float(3.14)),
-                Expression::Numeral(Numeral::Integer(999)),
-            )),
-        ]);
+        let exp = Expression::TableConstructor(
+            vec![
+                Field::Name((
+                    String::from("age"),
+                    Expression::Numeral(Numeral::Integer(23), Span::default()),
+                )),
+                Field::Unnamed(Expression::BinaryOp(
+                    (
+                        Box::new(Expression::Numeral(Numeral::Integer(2), Span::default())),
+                        BinOp::Add,
+                        Box::new(Expression::Numeral(Numeral::Integer(3), Span::default())),
+                    ),
+                    Span::default(),
+                )),
+                Field::Bracketed((
+                    Expression::Numeral(Numeral::Float(3.14), Span::default()),
+                    Expression::Numeral(Numeral::Integer(999), Span::default()),
+                )),
+            ],
+            Span::default(),
+        );
 
         let expected = Ok(lua_table(HashMap::from([
             (
-                TableKey::String(String::from("age")),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::String(LuaString::from("age")),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(f64::to_be_bytes(3.14)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Float(FloatKey(3.14)),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
         ])));
-        let actual = exp.eval(&mut env);
+        let actual = eval(&exp, &mut env);
         assert_eq!(expected, actual);
     }
 
@@ -1123,28 +1180,31 @@ mod tests {
     fn test_eval_table_sequence() {
         let mut env = Env::new();
 
-        let exp = Expression::TableConstructor(vec![
-            Field::Unnamed(Expression::Numeral(Numeral::Integer(999))),
-            Field::Unnamed(Expression::Numeral(Numeral::Integer(888))),
-            Field::Unnamed(Expression::Numeral(Numeral::Integer(777))),
-        ]);
+        let exp = Expression::TableConstructor(
+            vec![
+                Field::Unnamed(Expression::Numeral(Numeral::Integer(999), Span::default())),
+                Field::Unnamed(Expression::Numeral(Numeral::Integer(888), Span::default())),
+                Field::Unnamed(Expression::Numeral(Numeral::Integer(777), Span::default())),
+            ],
+            Span::default(),
+        );
 
         let expected = Ok(lua_table(HashMap::from([
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(3)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(777), false)),
+                TableKey::Number(3),
+                LuaValue::new(LuaVal::LuaInt(777)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(2)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(888), false)),
+                TableKey::Number(2),
+                LuaValue::new(LuaVal::LuaInt(888)),
             ),
         ])));
 
-        let actual = exp.eval(&mut env);
+        let actual = eval(&exp, &mut env);
         assert_eq!(expected, actual)
     }
 
@@ -1155,45 +1215,49 @@ mod tests {
         let block = Block {
             statements: vec![],
             return_stat: Some(vec![
-                Expression::Numeral(Numeral::Integer(999)),
-                Expression::Numeral(Numeral::Integer(888)),
-                Expression::Numeral(Numeral::Integer(777)),
+                Expression::Numeral(Numeral::Integer(999), Span::default()),
+                Expression::Numeral(Numeral::Integer(888), Span::default()),
+                Expression::Numeral(Numeral::Integer(777), Span::default()),
             ]),
         };
 
-        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env));
+        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
         env.insert_global(String::from("f"), f);
 
-        let exp = Expression::TableConstructor(vec![
-            Field::Unnamed(Expression::Numeral(Numeral::Integer(111))),
-            Field::Unnamed(Expression::PrefixExp(Box::new(PrefixExp::FunctionCall(
-                FunctionCall::Standard((
-                    Box::new(PrefixExp::Var(Var::Name(String::from("f")))),
-                    Args::ExpList(Vec::new()),
+        let exp = Expression::TableConstructor(
+            vec![
+                Field::Unnamed(Expression::Numeral(Numeral::Integer(111), Span::default())),
+                Field::Unnamed(Expression::PrefixExp(
+                    Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+                        Box::new(PrefixExp::Var(Var::Name(String::from("f")))),
+                        Args::ExpList(Vec::new()),
+                    )))),
+                    Span::default(),
                 )),
-            )))),
-        ]);
+            ],
+            Span::default(),
+        );
 
         let expected = Ok(lua_table(HashMap::from([
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(111), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(111)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(2)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Number(2),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(3)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(888), false)),
+                TableKey::Number(3),
+                LuaValue::new(LuaVal::LuaInt(888)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(4)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(777), false)),
+                TableKey::Number(4),
+                LuaValue::new(LuaVal::LuaInt(777)),
             ),
         ])));
 
-        let actual = exp.eval(&mut env);
+        let actual = eval(&exp, &mut env);
         assert_eq!(expected, actual)
     }
 
@@ -1201,22 +1265,25 @@ mod tests {
     fn test_eval_table_capture() {
         let mut env = Env::new();
 
-        env.insert_global(
-            String::from("x"),
-            LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
-        );
+        env.insert_global(String::from("x"), LuaValue::new(LuaVal::LuaInt(999)));
 
-        let exp = Expression::TableConstructor(vec![Field::Name((
-            String::from("thing"),
-            Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(String::from("x"))))),
-        ))]);
+        let exp = Expression::TableConstructor(
+            vec![Field::Name((
+                String::from("thing"),
+                Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("x")))),
+                    Span::default(),
+                ),
+            ))],
+            Span::default(),
+        );
 
         let expected = Ok(lua_table(HashMap::from([(
-            TableKey::String(String::from("thing")),
-            LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+            TableKey::String(LuaString::from("thing")),
+            LuaValue::new(LuaVal::LuaInt(999)),
         )])));
 
-        let actual = exp.eval(&mut env);
+        let actual = eval(&exp, &mut env);
         assert_eq!(expected, actual)
     }
 
@@ -1224,15 +1291,18 @@ mod tests {
     fn test_eval_table_bad_key() {
         let mut env = Env::new();
 
-        let exp = Expression::TableConstructor(vec![Field::Bracketed((
-            Expression::True,
-            Expression::Numeral(Numeral::Integer(23)),
-        ))]);
+        let exp = Expression::TableConstructor(
+            vec![Field::Bracketed((
+                Expression::True(Span::default()),
+                Expression::Numeral(Numeral::Integer(23), Span::default()),
+            ))],
+            Span::default(),
+        );
 
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(String::from(
-                "Field key 'true' does not evaluate to a string or numeral"
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(String::from(
+                "Cannot add 'true' as key into a table"
             )))
         )
     }
@@ -1243,12 +1313,12 @@ mod tests {
 
         let table = LuaValue::extract_first_return_val(lua_table(HashMap::from([
             (
-                TableKey::Number(i64::to_be_bytes(86)),
-                LuaValue::new(LuaVal::LuaString(String::from("The first thing!"))),
+                TableKey::Number(86),
+                LuaValue::new(LuaVal::LuaString(LuaString::from("The first thing!"))),
             ),
             (
-                TableKey::String(String::from("launch_codes")),
-                LuaValue::new(LuaVal::LuaNum(f64::to_be_bytes(34.12456), false)),
+                TableKey::String(LuaString::from("launch_codes")),
+                LuaValue::new(LuaVal::LuaFloat(34.12456)),
             ),
         ])));
 
@@ -1256,25 +1326,22 @@ mod tests {
 
         let prefixexp = PrefixExp::Var(Var::Bracket((
             Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
-            Expression::Numeral(Numeral::Integer(86)),
+            Expression::Numeral(Numeral::Integer(86), Span::default()),
         )));
         assert_eq!(
-            prefixexp.eval(&mut env),
-            Ok(vec![LuaValue::new(LuaVal::LuaString(String::from(
+            eval(&prefixexp, &mut env),
+            Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(
                 "The first thing!"
             )))])
         );
 
         let prefixexp = PrefixExp::Var(Var::Bracket((
             Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
-            Expression::LiteralString(String::from("launch_codes")),
+            Expression::LiteralString(String::from("launch_codes"), Span::default()),
         )));
         assert_eq!(
-            prefixexp.eval(&mut env),
-            Ok(vec![LuaValue::new(LuaVal::LuaNum(
-                f64::to_be_bytes(34.12456),
-                false
-            ))])
+            eval(&prefixexp, &mut env),
+            Ok(vec![LuaValue::new(LuaVal::LuaFloat(34.12456))])
         );
     }
 
@@ -1284,32 +1351,32 @@ mod tests {
         let par_list = ParList(vec![String::from("that_table")], false);
         let block = Block {
             statements: vec![],
-            return_stat: Some(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                Var::Bracket((
+            return_stat: Some(vec![Expression::PrefixExp(
+                Box::new(PrefixExp::Var(Var::Bracket((
                     Box::new(PrefixExp::Var(Var::Name(String::from("that_table")))),
-                    Expression::LiteralString(String::from("a")),
-                )),
-            )))]),
+                    Expression::LiteralString(String::from("a"), Span::default()),
+                )))),
+                Span::default(),
+            )]),
         };
 
-        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &env));
+        let f = LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env));
         env.insert_global(String::from("f"), f);
 
-        let exp =
-            Expression::PrefixExp(Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+        let exp = Expression::PrefixExp(
+            Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
                 Box::new(PrefixExp::Var(Var::Name(String::from("f")))),
                 Args::TableConstructor(vec![Field::Name((
                     String::from("a"),
-                    Expression::Numeral(Numeral::Integer(86)),
+                    Expression::Numeral(Numeral::Integer(86), Span::default()),
                 ))]),
-            )))));
+            )))),
+            Span::default(),
+        );
 
         assert_eq!(
-            exp.eval(&mut env),
-            Ok(vec![LuaValue::new(LuaVal::LuaNum(
-                i64::to_be_bytes(86),
-                false
-            ))])
+            eval(&exp, &mut env),
+            Ok(vec![LuaValue::new(LuaVal::LuaInt(86))])
         )
     }
 
@@ -1317,32 +1384,47 @@ mod tests {
     fn test_eval_bin_add() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Add, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(30)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Add, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(30.1)));
-
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Add, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(30.1)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Add, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(11 as f64)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't add string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Add, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Add, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(30)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Add, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(30.1)));
+
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Add, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(30.1)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Add, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(11_f64)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::LiteralString("Can't add string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Add, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1352,32 +1434,48 @@ mod tests {
     fn test_eval_bin_sub() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(-10)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(-9.9)));
-
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(9.9)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(9.2)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't subtract with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(-10)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(-9.9)));
+
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(9.9)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(9.2)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right =
+            Expression::LiteralString("Can't subtract with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1387,32 +1485,47 @@ mod tests {
     fn test_eval_bin_mult() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mult, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(200)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mult, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(202.0)));
-
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(-10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mult, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(-202.0)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mult, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(9.09)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't multipy string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Sub, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mult, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(200)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mult, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(202.0)));
+
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(-10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mult, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(-202.0)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mult, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(9.09)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::LiteralString("Can't multipy string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Sub, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1422,32 +1535,50 @@ mod tests {
     fn test_eval_bin_div() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Div, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(2.0)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Div, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(1.01)));
-
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Div, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(20 as f64 / 10.1)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Div, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(10.1 / 0.9)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't float divide with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Div, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Div, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(2.0)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Div, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(1.01)));
+
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Div, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(20_f64 / 10.1)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Div, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(10.1 / 0.9)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::LiteralString(
+            "Can't float divide with string".to_string(),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Div, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1457,67 +1588,133 @@ mod tests {
     fn test_eval_bin_int_div() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::IntegerDiv, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(2)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::IntegerDiv, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(1)));
-
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::IntegerDiv, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(1)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::IntegerDiv, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(11)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't floor divide with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::IntegerDiv, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::IntegerDiv, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(2)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::IntegerDiv, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(1)));
+
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::IntegerDiv, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(1)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::IntegerDiv, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(11)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::LiteralString(
+            "Can't floor divide with string".to_string(),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::IntegerDiv, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
     }
 
+    #[test]
+    fn test_eval_bin_by_zero_and_overflow() {
+        let mut env = Env::new();
+        let int = |i| Box::new(Expression::Numeral(Numeral::Integer(i), Span::default()));
+
+        let exp = Expression::BinaryOp((int(1), BinOp::IntegerDiv, int(0)), Span::default());
+        assert_eq!(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from("attempt to perform 'n//0'".to_string()))
+        );
+        let exp = Expression::BinaryOp((int(1), BinOp::Mod, int(0)), Span::default());
+        assert_eq!(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from("attempt to perform 'n%0'".to_string()))
+        );
+
+        // Integers wrap around
+        let exp = Expression::BinaryOp((int(i64::MAX), BinOp::Add, int(1)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(i64::MIN)));
+        let exp =
+            Expression::BinaryOp((int(i64::MIN), BinOp::IntegerDiv, int(-1)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(i64::MIN)));
+
+        // Shifts fill with zeros
+        let exp = Expression::BinaryOp((int(1), BinOp::ShiftLeft, int(64)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(0)));
+        let exp = Expression::BinaryOp((int(-1), BinOp::ShiftRight, int(60)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(15)));
+        let exp = Expression::BinaryOp((int(1), BinOp::ShiftLeft, int(-1)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(0)));
+    }
+
     #[test]
     fn test_eval_bin_pow() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(2));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Pow, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(1024.0)));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(3));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Pow, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(1030.301)));
-
-        let left = Expression::Numeral(Numeral::Integer(2));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Pow, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(2.0_f64.powf(10.1))));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Pow, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(10.1_f64.powf(0.9))));
-
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't power with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Pow, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Pow, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(1024.0)));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(3), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Pow, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(1030.301)));
+
+        let left = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Pow, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(2.0_f64.powf(10.1))));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Pow, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(10.1_f64.powf(0.9))));
+
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right =
+            Expression::LiteralString("Can't power with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Pow, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1527,33 +1724,48 @@ mod tests {
     fn test_eval_bin_mod() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mod, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(0)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mod, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(0)));
 
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mod, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mod, Box::new(right)),
+            Span::default(),
+        );
         // In Rust, 10.1 % 10.0 = 0.09999999999999964
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(10.1 % 10.0)));
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(10.1 % 10.0)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mod, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(9.9)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mod, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(9.9)));
 
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::Numeral(Numeral::Float(0.9));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mod, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(10.1 % 0.9)));
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::Numeral(Numeral::Float(0.9), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mod, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(10.1 % 0.9)));
 
-        let left = Expression::Numeral(Numeral::Float(10.1));
-        let right = Expression::LiteralString("Can't mod with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Mod, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let right = Expression::LiteralString("Can't mod with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Mod, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot execute opration on values that are not numbers".to_string()
             ))
         );
@@ -1563,33 +1775,46 @@ mod tests {
     fn test_eval_bin_bitand() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(13));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(4)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(13), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(4)));
 
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(13.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(4)));
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(4)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(13.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitAnd, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitAnd, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
         );
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::LiteralString("Can't bitwise and with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitAnd, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right =
+            Expression::LiteralString("Can't bitwise and with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitAnd, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
@@ -1599,33 +1824,46 @@ mod tests {
     fn test_eval_bin_bitxor() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(13));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitXor, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(25)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(13), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitXor, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(25)));
 
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(13.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitXor, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(25)));
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitXor, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(25)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(13.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitXor, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitXor, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
         );
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::LiteralString("Can't bitwise and with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitXor, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right =
+            Expression::LiteralString("Can't bitwise and with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitXor, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
@@ -1635,33 +1873,46 @@ mod tests {
     fn test_eval_bin_bitor() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(13));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(29)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(13), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(29)));
 
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(13.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(29)));
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(29)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(13.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitOr, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitOr, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
         );
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::LiteralString("Can't bitwise and with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::BitOr, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right =
+            Expression::LiteralString("Can't bitwise and with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::BitOr, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
@@ -1671,33 +1922,46 @@ mod tests {
     fn test_eval_bin_bitsl() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(13));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftLeft, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(163840)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(13), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftLeft, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(163840)));
 
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(13.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftLeft, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(163840)));
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftLeft, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(163840)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(13.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftLeft, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(13.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftLeft, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
         );
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::LiteralString("Can't bitwise and with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftLeft, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right =
+            Expression::LiteralString("Can't bitwise and with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftLeft, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
@@ -1707,33 +1971,46 @@ mod tests {
     fn test_eval_bin_bitsr() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftRight, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(5)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftRight, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(5)));
 
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(2.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftRight, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(5)));
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftRight, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(5)));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Float(2.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftRight, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftRight, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
         );
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::LiteralString("Can't bitwise and with string".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::ShiftRight, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right =
+            Expression::LiteralString("Can't bitwise and with string".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::ShiftRight, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
@@ -1743,32 +2020,47 @@ mod tests {
     fn test_eval_bin_concat() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Concat, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_string("202")));
-
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::Numeral(Numeral::Float(2.0));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Concat, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_string("20.02.0")));
-
-        let left = Expression::Numeral(Numeral::Float(20.0));
-        let right = Expression::LiteralString("test".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Concat, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_string("20.0test")));
-
-        let left = Expression::LiteralString("Hello ".to_string());
-        let right = Expression::LiteralString("World!".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Concat, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_string("Hello World!")));
-
-        let left = Expression::Nil;
-        let right = Expression::Numeral(Numeral::Float(2.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Concat, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Concat, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_string("202")));
+
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Concat, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_string("20.02.0")));
+
+        let left = Expression::Numeral(Numeral::Float(20.0), Span::default());
+        let right = Expression::LiteralString("test".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Concat, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_string("20.0test")));
+
+        let left = Expression::LiteralString("Hello ".to_string(), Span::default());
+        let right = Expression::LiteralString("World!".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Concat, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_string("Hello World!")));
+
+        let left = Expression::Nil(Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Concat, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to String (types cannot be converted)".to_string()
             ))
         );
@@ -1778,285 +2070,415 @@ mod tests {
     fn test_eval_bin_equal() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let left = Expression::Nil;
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::Nil(Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let left = Expression::LiteralString("Same content".to_string());
-        let right = Expression::LiteralString("Same content".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::LiteralString("Same content".to_string(), Span::default());
+        let right = Expression::LiteralString("Same content".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
         // Function with same content but not same reference
-        let left = Expression::FunctionDef((
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        let right = Expression::FunctionDef((
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::FunctionDef(
+            FuncBody::new(
+                ParList(vec![], false),
+                Block {
+                    statements: vec![],
+                    return_stat: None,
+                },
+                Span::default(),
+            ),
+            Span::default(),
+        );
+        let right = Expression::FunctionDef(
+            FuncBody::new(
+                ParList(vec![], false),
+                Block {
+                    statements: vec![],
+                    return_stat: None,
+                },
+                Span::default(),
+            ),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
         // Function with same reference
-        let stat = Statement::FunctionDecl((
-            "f".to_string(),
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        stat.exec(&mut env).unwrap();
-        let exp =
-            Expression::BinaryOp((Box::new(var_exp("f")), BinOp::Equal, Box::new(var_exp("f"))));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let stat = Statement::FunctionDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(
+                    ParList(vec![], false),
+                    Block {
+                        statements: vec![],
+                        return_stat: None,
+                    },
+                    Span::default(),
+                ),
+            ),
+            Span::default(),
+        );
+        exec(&stat, &mut env).unwrap();
+        let exp = Expression::BinaryOp(
+            (Box::new(var_exp("f")), BinOp::Equal, Box::new(var_exp("f"))),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
         // Test table equality when two variables reference the same table (should be true)
         let table = LuaValue::extract_first_return_val(lua_table(HashMap::from([
             (
-                TableKey::String(String::from("age")),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::String(LuaString::from("age")),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(f64::to_be_bytes(3.14)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Float(FloatKey(3.14)),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
         ])));
-        env.insert_global(String::from("my_table"), table.clone_rc());
+        env.insert_global(String::from("my_table"), table.clone());
         env.insert_global(String::from("your_table"), table);
 
-        let exp = Expression::BinaryOp((
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("my_table"),
-            ))))),
-            BinOp::Equal,
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("your_table"),
-            ))))),
-        ));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let exp = Expression::BinaryOp(
+            (
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    Span::default(),
+                )),
+                BinOp::Equal,
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("your_table")))),
+                    Span::default(),
+                )),
+            ),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
         // Test table equality when two variables hold two separate tables that have the same
         // contents (should be false)
         let other_table = LuaValue::extract_first_return_val(lua_table(HashMap::from([
             (
-                TableKey::String(String::from("age")),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::String(LuaString::from("age")),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(f64::to_be_bytes(3.14)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Float(FloatKey(3.14)),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
         ])));
         env.insert_global(String::from("other_table"), other_table);
 
-        let exp = Expression::BinaryOp((
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("my_table"),
-            ))))),
-            BinOp::Equal,
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("other_table"),
-            ))))),
-        ));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let exp = Expression::BinaryOp(
+            (
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    Span::default(),
+                )),
+                BinOp::Equal,
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("other_table")))),
+                    Span::default(),
+                )),
+            ),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::LiteralString("Different types".to_string());
-        let right = Expression::Numeral(Numeral::Float(2.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::Equal, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::LiteralString("Different types".to_string(), Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::Equal, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        // Built-in functions and threads are equal only to themselves
+        "co = coroutine.create(print) other = coroutine.create(print)"
+            .parse::<crate::AST>()
+            .unwrap()
+            .exec(&mut env)
+            .unwrap();
+        for (left, right, equal) in [
+            ("print", "print", true),
+            ("print", "type", false),
+            ("co", "co", true),
+            ("co", "other", false),
+        ] {
+            let exp = Expression::BinaryOp(
+                (Box::new(var_exp(left)), BinOp::Equal, Box::new(var_exp(right))),
+                Span::default(),
+            );
+            let expected = if equal { lua_true() } else { lua_false() };
+            assert_eq!(eval(&exp, &mut env), Ok(expected), "{left} == {right}");
+        }
     }
 
     #[test]
     fn test_eval_bin_not_equal() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::Nil;
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::Nil(Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::LiteralString("Same content".to_string());
-        let right = Expression::LiteralString("Same content".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::LiteralString("Same content".to_string(), Span::default());
+        let right = Expression::LiteralString("Same content".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
         // Function with same content but not same reference
-        let left = Expression::FunctionDef((
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        let right = Expression::FunctionDef((
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::FunctionDef(
+            FuncBody::new(
+                ParList(vec![], false),
+                Block {
+                    statements: vec![],
+                    return_stat: None,
+                },
+                Span::default(),
+            ),
+            Span::default(),
+        );
+        let right = Expression::FunctionDef(
+            FuncBody::new(
+                ParList(vec![], false),
+                Block {
+                    statements: vec![],
+                    return_stat: None,
+                },
+                Span::default(),
+            ),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
         // Function with same reference
-        let stat = Statement::FunctionDecl((
-            "f".to_string(),
-            ParList(vec![], false),
-            Block {
-                statements: vec![],
-                return_stat: None,
-            },
-        ));
-        stat.exec(&mut env).unwrap();
-        let exp = Expression::BinaryOp((
-            Box::new(var_exp("f")),
-            BinOp::NotEqual,
-            Box::new(var_exp("f")),
-        ));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let stat = Statement::FunctionDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(
+                    ParList(vec![], false),
+                    Block {
+                        statements: vec![],
+                        return_stat: None,
+                    },
+                    Span::default(),
+                ),
+            ),
+            Span::default(),
+        );
+        exec(&stat, &mut env).unwrap();
+        let exp = Expression::BinaryOp(
+            (
+                Box::new(var_exp("f")),
+                BinOp::NotEqual,
+                Box::new(var_exp("f")),
+            ),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
         // Test table inequality when two variables reference the same table (should be false)
         let table = LuaValue::extract_first_return_val(lua_table(HashMap::from([
             (
-                TableKey::String(String::from("age")),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::String(LuaString::from("age")),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(f64::to_be_bytes(3.14)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Float(FloatKey(3.14)),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
         ])));
-        env.insert_global(String::from("my_table"), table.clone_rc());
+        env.insert_global(String::from("my_table"), table.clone());
         env.insert_global(String::from("your_table"), table);
 
-        let exp = Expression::BinaryOp((
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("my_table"),
-            ))))),
-            BinOp::NotEqual,
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("your_table"),
-            ))))),
-        ));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let exp = Expression::BinaryOp(
+            (
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    Span::default(),
+                )),
+                BinOp::NotEqual,
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("your_table")))),
+                    Span::default(),
+                )),
+            ),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
         // Test table equality when two variables hold two separate tables that have the same
         // contents (should be false)
         let other_table = LuaValue::extract_first_return_val(lua_table(HashMap::from([
             (
-                TableKey::String(String::from("age")),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(23), false)),
+                TableKey::String(LuaString::from("age")),
+                LuaValue::new(LuaVal::LuaInt(23)),
             ),
             (
-                TableKey::Number(i64::to_be_bytes(1)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(5), false)),
+                TableKey::Number(1),
+                LuaValue::new(LuaVal::LuaInt(5)),
             ),
             (
-                TableKey::Number(f64::to_be_bytes(3.14)),
-                LuaValue::new(LuaVal::LuaNum(i64::to_be_bytes(999), false)),
+                TableKey::Float(FloatKey(3.14)),
+                LuaValue::new(LuaVal::LuaInt(999)),
             ),
         ])));
         env.insert_global(String::from("other_table"), other_table);
 
-        let exp = Expression::BinaryOp((
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("my_table"),
-            ))))),
-            BinOp::NotEqual,
-            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                String::from("other_table"),
-            ))))),
-        ));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let exp = Expression::BinaryOp(
+            (
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    Span::default(),
+                )),
+                BinOp::NotEqual,
+                Box::new(Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("other_table")))),
+                    Span::default(),
+                )),
+            ),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let left = Expression::LiteralString("Different types".to_string());
-        let right = Expression::Numeral(Numeral::Float(2.1));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::NotEqual, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let left = Expression::LiteralString("Different types".to_string(), Span::default());
+        let right = Expression::Numeral(Numeral::Float(2.1), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::NotEqual, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
     }
 
     #[test]
     fn test_eval_bin_less_than() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(4));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::LiteralString("cba".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessThan, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(4), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::LiteralString("cba".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessThan, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot compare two values due to types".to_string()
             ))
         );
@@ -2066,32 +2488,47 @@ mod tests {
     fn test_eval_bin_less_equal() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(4));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::LiteralString("cba".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LessEq, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(4), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::LiteralString("cba".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LessEq, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot compare two values due to types".to_string()
             ))
         );
@@ -2101,32 +2538,47 @@ mod tests {
     fn test_eval_bin_greater_than() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(4));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::LiteralString("cba".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterThan, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterThan, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(4), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::LiteralString("cba".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterThan, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterThan, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot compare two values due to types".to_string()
             ))
         );
@@ -2136,32 +2588,47 @@ mod tests {
     fn test_eval_bin_greater_equal() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(20));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(2));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
-
-        let left = Expression::Numeral(Numeral::Float(2.0));
-        let right = Expression::Numeral(Numeral::Integer(4));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::LiteralString("cba".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterEq, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
-
-        let left = Expression::LiteralString("abc".to_string());
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::GreaterEq, Box::new(right)));
+        let left = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(2), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
+
+        let left = Expression::Numeral(Numeral::Float(2.0), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(4), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::LiteralString("cba".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterEq, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
+
+        let left = Expression::LiteralString("abc".to_string(), Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::GreaterEq, Box::new(right)),
+            Span::default(),
+        );
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot compare two values due to types".to_string()
             ))
         );
@@ -2171,79 +2638,116 @@ mod tests {
     fn test_eval_bin_logical_and() {
         let mut env = Env::new();
 
-        let left = Expression::Nil;
-        let right = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_nil()));
+        let left = Expression::Nil(Span::default());
+        let right = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_nil()));
 
-        let left = Expression::False;
+        let left = Expression::False(Span::default());
         // right should return error when evaluated
-        let right = Expression::BinaryOp((
-            Box::new(Expression::LiteralString("abc".to_string())),
-            BinOp::GreaterEq,
-            Box::new(Expression::Nil),
-        ));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let right = Expression::BinaryOp(
+            (
+                Box::new(Expression::LiteralString(
+                    "abc".to_string(),
+                    Span::default(),
+                )),
+                BinOp::GreaterEq,
+                Box::new(Expression::Nil(Span::default())),
+            ),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::False;
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let left = Expression::False(Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalAnd, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(20)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalAnd, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(20)));
     }
 
     #[test]
     fn test_eval_bin_logical_or() {
         let mut env = Env::new();
 
-        let left = Expression::Numeral(Numeral::Integer(10));
-        let right = Expression::Numeral(Numeral::Integer(20));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(10)));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let right = Expression::Numeral(Numeral::Integer(20), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(10)));
 
-        let left = Expression::Numeral(Numeral::Integer(10));
+        let left = Expression::Numeral(Numeral::Integer(10), Span::default());
         // right should return error when evaluated
-        let right = Expression::BinaryOp((
-            Box::new(Expression::LiteralString("abc".to_string())),
-            BinOp::GreaterEq,
-            Box::new(Expression::Nil),
-        ));
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(10)));
+        let right = Expression::BinaryOp(
+            (
+                Box::new(Expression::LiteralString(
+                    "abc".to_string(),
+                    Span::default(),
+                )),
+                BinOp::GreaterEq,
+                Box::new(Expression::Nil(Span::default())),
+            ),
+            Span::default(),
+        );
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(10)));
 
-        let left = Expression::Nil;
-        let right = Expression::LiteralString("a".to_string());
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_string("a")));
+        let left = Expression::Nil(Span::default());
+        let right = Expression::LiteralString("a".to_string(), Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_string("a")));
 
-        let left = Expression::False;
-        let right = Expression::Nil;
-        let exp = Expression::BinaryOp((Box::new(left), BinOp::LogicalOr, Box::new(right)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_nil()));
+        let left = Expression::False(Span::default());
+        let right = Expression::Nil(Span::default());
+        let exp = Expression::BinaryOp(
+            (Box::new(left), BinOp::LogicalOr, Box::new(right)),
+            Span::default(),
+        );
+        assert_eq!(eval(&exp, &mut env), Ok(lua_nil()));
     }
 
     #[test]
     fn test_eval_un_negate() {
         let mut env = Env::new();
 
-        let exp = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(-10)));
+        let exp = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(-10)));
 
-        let exp = Expression::Numeral(Numeral::Float(10.1));
-        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_float(-10.1)));
+        let exp = Expression::Numeral(Numeral::Float(10.1), Span::default());
+        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_float(-10.1)));
 
-        let exp = Expression::LiteralString("String cannot be negated".to_string());
-        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)));
+        let exp =
+            Expression::LiteralString("String cannot be negated".to_string(), Span::default());
+        let exp = Expression::UnaryOp((UnOp::Negate, Box::new(exp)), Span::default());
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot negate values that are not numbers".to_string()
             ))
         );
@@ -2253,40 +2757,42 @@ mod tests {
     fn test_eval_un_not() {
         let mut env = Env::new();
 
-        let exp = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let exp = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let exp =
-            Expression::LiteralString("Everything other than nil and false is true".to_string());
-        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_false()));
+        let exp = Expression::LiteralString(
+            "Everything other than nil and false is true".to_string(),
+            Span::default(),
+        );
+        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_false()));
 
-        let exp = Expression::False;
-        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let exp = Expression::False(Span::default());
+        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
 
-        let exp = Expression::Nil;
-        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_true()));
+        let exp = Expression::Nil(Span::default());
+        let exp = Expression::UnaryOp((UnOp::LogicalNot, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_true()));
     }
 
     #[test]
     fn test_eval_un_length() {
         let mut env = Env::new();
 
-        let exp = Expression::LiteralString("Let's get string length".to_string());
-        let exp = Expression::UnaryOp((UnOp::Length, Box::new(exp)));
+        let exp = Expression::LiteralString("Let's get string length".to_string(), Span::default());
+        let exp = Expression::UnaryOp((UnOp::Length, Box::new(exp)), Span::default());
         assert_eq!(
-            exp.eval(&mut env),
+            eval(&exp, &mut env),
             Ok(lua_integer("Let's get string length".len() as i64))
         );
 
-        let exp = Expression::Numeral(Numeral::Integer(10));
-        let exp = Expression::UnaryOp((UnOp::Length, Box::new(exp)));
+        let exp = Expression::Numeral(Numeral::Integer(10), Span::default());
+        let exp = Expression::UnaryOp((UnOp::Length, Box::new(exp)), Span::default());
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot get length of value that is not a string or table".to_string()
             ))
         );
@@ -2296,24 +2802,25 @@ mod tests {
     fn test_eval_un_bitnot() {
         let mut env = Env::new();
 
-        let exp = Expression::Numeral(Numeral::Integer(100));
-        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)));
-        assert_eq!(exp.eval(&mut env), Ok(lua_integer(-101)));
+        let exp = Expression::Numeral(Numeral::Integer(100), Span::default());
+        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)), Span::default());
+        assert_eq!(eval(&exp, &mut env), Ok(lua_integer(-101)));
 
-        let exp = Expression::LiteralString("Let's bitwise not string".to_string());
-        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)));
+        let exp =
+            Expression::LiteralString("Let's bitwise not string".to_string(), Span::default());
+        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)), Span::default());
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert value to integer (types cannot be converted)".to_string()
             ))
         );
 
-        let exp = Expression::Numeral(Numeral::Float(10.04));
-        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)));
+        let exp = Expression::Numeral(Numeral::Float(10.04), Span::default());
+        let exp = Expression::UnaryOp((UnOp::BitNot, Box::new(exp)), Span::default());
         assert_eq!(
-            exp.eval(&mut env),
-            Err(ASTExecError(
+            eval(&exp, &mut env),
+            Err(ASTExecError::from(
                 "Cannot convert float that does not have exact integer value to integer"
                     .to_string()
             ))
@@ -2321,85 +2828,30 @@ mod tests {
     }
 
     #[test]
-    fn test_capture_env() {
-        let mut env = Env::new();
-        env.insert_local(
-            "a".to_string(),
-            LuaValue::extract_first_return_val(lua_integer(10)),
-        );
-        env.insert_local(
-            "b".to_string(),
-            LuaValue::extract_first_return_val(lua_integer(20)),
-        );
-        env.insert_local(
-            "c".to_string(),
-            LuaValue::extract_first_return_val(lua_integer(30)),
-        );
-
-        let captured_env = env.get_local_env().capture_env();
-        env.pop_local_env();
-        let func_env = env.create_with_captured_env(&captured_env);
-
-        assert_eq!(
-            func_env.get("a"),
-            Some(LuaValue::extract_first_return_val(lua_integer(10)))
-        );
-        assert_eq!(
-            func_env.get("b"),
-            Some(LuaValue::extract_first_return_val(lua_integer(20)))
-        );
-        assert_eq!(
-            func_env.get("c"),
-            Some(LuaValue::extract_first_return_val(lua_integer(30)))
-        );
-        assert_eq!(func_env.get("d"), None);
-    }
-
-    #[test]
-    fn accepts_method_call(){
-
+    fn accepts_method_call() {
         // initialize new environment
         let mut env = Env::new();
-        let par_list = ParList(
-            vec![String::from("a")], false
-        );
-        let block = Block { 
-            statements: vec![], return_stat: Some(vec![Expression::True]) 
+        let par_list = ParList(vec![String::from("a")], false);
+        let block = Block {
+            statements: vec![],
+            return_stat: Some(vec![Expression::True(Span::default())]),
         };
 
         // add table to environment
-        let other_table = LuaValue::extract_first_return_val(
-            lua_table(
-                HashMap::from(
-                    [
-                        (
-                            TableKey::String(String::from("example_func")),
-                            LuaValue::new(
-                                LuaVal::Function(
-                                    LuaFunction { 
-                                        par_list: &par_list, 
-                                        block: &block, 
-                                        captured_env: env.get_local_env().capture_env() 
-                                    }
-                                )
-                            )
-                        ),
-            
-                    ]
-                )
-            )
-        );
+        let other_table = LuaValue::extract_first_return_val(lua_table(HashMap::from([(
+            TableKey::String(LuaString::from("example_func")),
+            LuaValue::extract_first_return_val(lua_function(&par_list, &block, &mut env)),
+        )])));
         // insert table into environment
         env.insert_global(String::from("other_table"), other_table);
 
         // method call expression
-        let method_call = FunctionCall::Method(
-            (Box::new
-                (PrefixExp::Var
-                    (Var::Name
-                        (String::from("other_table")))), 
-                        String::from("example_func"), Args::ExpList(vec![]) ));
-        
-        assert_eq!(method_call.exec(&mut env), Ok(lua_true()));
+        let method_call = FunctionCall::Method((
+            Box::new(PrefixExp::Var(Var::Name(String::from("other_table")))),
+            String::from("example_func"),
+            Args::ExpList(vec![]),
+        ));
+
+        assert_eq!(eval(&method_call, &mut env), Ok(lua_true()));
     }
 }
@@ -0,0 +1,280 @@
+// The module system: require and the package library
+use crate::ast::FunctionCall;
+use crate::interpreter::environment::Env;
+use crate::interpreter::{ASTExecError, LuaString, LuaTable, LuaVal, LuaValue, TableKey};
+use std::cell::RefCell;
+use std::fs::File;
+use std::rc::Rc;
+
+pub const DEFAULT_PATH: &str = "./?.lua;./?/init.lua";
+
+// Create the `package` table with its default searchers
+pub fn new_package_table(env: &mut Env) -> LuaValue {
+    let package = LuaTable::new();
+    package.insert_ident("loaded", env.alloc_table(LuaTable::new()));
+    package.insert_ident("preload", env.alloc_table(LuaTable::new()));
+    package.insert_ident(
+        "path",
+        LuaValue::new(LuaVal::LuaString(LuaString::from(DEFAULT_PATH))),
+    );
+    package.insert_ident(
+        "searchpath",
+        LuaValue::new(LuaVal::SearchPath),
+    );
+
+    let searchers = LuaTable::new();
+    searchers.insert_int(1, LuaValue::new(LuaVal::PreloadSearcher));
+    searchers.insert_int(2, LuaValue::new(LuaVal::LuaSearcher));
+    package.insert_ident("searchers", env.alloc_table(searchers));
+
+    env.alloc_table(package)
+}
+
+// Remove the access to the file system from the `package` table, so that
+// require only finds the modules of package.preload
+pub fn remove_file_searchers(package: &LuaValue, env: &mut Env) {
+    let nil = LuaValue::new(LuaVal::LuaNil);
+    set_field(package, "path", nil.clone());
+    set_field(package, "searchpath", nil);
+    let searchers = LuaTable::new();
+    searchers.insert_int(1, LuaValue::new(LuaVal::PreloadSearcher));
+    set_field(package, "searchers", env.alloc_table(searchers));
+}
+
+// Make the tables of the `package` table read-only for the scripts, so that
+// they can't replace the modules of package.preload or package.loaded
+pub fn set_read_only(package: &LuaValue) {
+    for field in ["loaded", "preload", "searchers"] {
+        if let Some(LuaVal::LuaTable(table)) = get_field(package, field).map(|val| val.0) {
+            table.set_read_only();
+        }
+    }
+}
+
+// require(modname)
+// Returns the cached module, or runs the loader found by the searchers and caches its result
+pub fn require(
+    args: Vec<LuaValue>,
+    env: &mut Env,
+    loading: &Rc<RefCell<Vec<String>>>,
+) -> Result<Vec<LuaValue>, ASTExecError> {
+    let name = string_arg(&args, 1, "require")?;
+    let package = package_table(env)?;
+    let loaded = package_field(&package, "loaded")?;
+
+    if let Some(module) = get_field(&loaded, &name) {
+        return Ok(vec![module]);
+    }
+
+    if loading.borrow().contains(&name) {
+        let mut chain = loading.borrow().clone();
+        chain.push(name.clone());
+        return Err(ASTExecError::from(format!(
+            "module '{name}' is required in a loop ({})",
+            chain.join(" -> ")
+        )));
+    }
+
+    let (loader, data) = find_loader(&name, &package, env)?;
+
+    loading.borrow_mut().push(name.clone());
+    let result = loader.call(
+        vec![
+            LuaValue::new(LuaVal::LuaString(LuaString::from(name.clone()))),
+            data.clone(),
+        ],
+        env,
+    );
+    loading.borrow_mut().pop();
+
+    let module = LuaValue::extract_first_return_val(result?);
+    if !module.is_nil() {
+        set_field(&loaded, &name, module);
+    }
+    // A module that returns nothing and doesn't set package.loaded[name] is still loaded
+    let module = match get_field(&loaded, &name) {
+        Some(module) => module,
+        None => {
+            let module = LuaValue::new(LuaVal::LuaBool(true));
+            set_field(&loaded, &name, module.clone());
+            module
+        }
+    };
+    Ok(vec![module, data])
+}
+
+// Ask each function in package.searchers for a loader of the module
+fn find_loader(
+    name: &str,
+    package: &LuaValue,
+    env: &mut Env,
+) -> Result<(LuaValue, LuaValue), ASTExecError> {
+    let searchers = package_field(package, "searchers")?;
+    let LuaVal::LuaTable(searchers) = &searchers.0 else {
+        unreachable!("package_field always returns a table")
+    };
+
+    let mut messages = String::new();
+    let mut i = 1;
+    while let Some(searcher) = searchers.get(TableKey::Number(i)) {
+        if searcher.is_nil() {
+            break;
+        }
+        let mut result = searcher
+            .call(
+                vec![LuaValue::new(LuaVal::LuaString(LuaString::from(name)))],
+                env,
+            )?
+            .into_iter();
+        let loader = result.next().unwrap_or(LuaValue::new(LuaVal::LuaNil));
+        if loader.is_callable() {
+            let data = result.next().unwrap_or(LuaValue::new(LuaVal::LuaNil));
+            return Ok((loader, data));
+        }
+        if let LuaVal::LuaString(msg) = &loader.0 {
+            messages.push_str("\n\t");
+            messages.push_str(msg);
+        }
+        i += 1;
+    }
+
+    Err(ASTExecError::from(format!(
+        "module '{name}' not found:{messages}"
+    )))
+}
+
+// Searcher looking for a loader in package.preload
+pub fn search_preload(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let name = string_arg(&args, 1, "searcher_preload")?;
+    let package = package_table(env)?;
+    let preload = package_field(&package, "preload")?;
+
+    match get_field(&preload, &name) {
+        Some(loader) => Ok(vec![
+            loader,
+            LuaValue::new(LuaVal::LuaString(LuaString::from(":preload:"))),
+        ]),
+        None => Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(format!(
+            "no field package.preload['{name}']"
+        ))))]),
+    }
+}
+
+// Searcher looking for a Lua file in package.path
+pub fn search_lua(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let name = string_arg(&args, 1, "searcher_Lua")?;
+    let package = package_table(env)?;
+    let path = match get_field(&package, "path") {
+        Some(path) if path.is_string() => path.into_string()?.to_string(),
+        _ => {
+            return Err(ASTExecError::from(String::from(
+                "'package.path' must be a string",
+            )))
+        }
+    };
+
+    let filename = match search_path(&name, &path) {
+        Ok(filename) => filename,
+        Err(msg) => return Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(msg)))]),
+    };
+    let source = match std::fs::read_to_string(&filename) {
+        Ok(source) => source,
+        Err(err) => {
+            return Err(ASTExecError::from(format!(
+                "error loading module '{name}' from file '{filename}':\n\t{err}"
+            )))
+        }
+    };
+
+    let mut chunk = FunctionCall::compile_chunk(
+        &source,
+        &format!("@{filename}"),
+        "t",
+        env.get_global_env(),
+    );
+    if chunk.len() > 1 {
+        return Err(ASTExecError::from(format!(
+            "error loading module '{name}' from file '{filename}':\n\t{}",
+            chunk.remove(1)
+        )));
+    }
+    Ok(vec![
+        chunk.remove(0),
+        LuaValue::new(LuaVal::LuaString(LuaString::from(filename))),
+    ])
+}
+
+// package.searchpath(name, path)
+// Returns the first file matching one of the templates, or nil and the files tried
+pub fn searchpath(args: Vec<LuaValue>) -> Result<Vec<LuaValue>, ASTExecError> {
+    let name = string_arg(&args, 1, "searchpath")?;
+    let path = string_arg(&args, 2, "searchpath")?;
+    match search_path(&name, &path) {
+        Ok(filename) => Ok(vec![LuaValue::new(LuaVal::LuaString(LuaString::from(filename)))]),
+        Err(msg) => Ok(vec![
+            LuaValue::new(LuaVal::LuaNil),
+            LuaValue::new(LuaVal::LuaString(LuaString::from(msg))),
+        ]),
+    }
+}
+
+// Replace each '?' in the ';' separated templates with the module name,
+// where dots in the name become directory separators
+fn search_path(name: &str, path: &str) -> Result<String, String> {
+    let name = name.replace('.', "/");
+    let mut tried = Vec::new();
+    for template in path.split(';').filter(|template| !template.is_empty()) {
+        let filename = template.replace('?', &name);
+        if File::open(&filename).is_ok_and(|file| file.metadata().is_ok_and(|m| m.is_file())) {
+            return Ok(filename);
+        }
+        tried.push(format!("no file '{filename}'"));
+    }
+    Err(tried.join("\n\t"))
+}
+
+fn string_arg(args: &[LuaValue], n: usize, fn_name: &str) -> Result<String, ASTExecError> {
+    match args.get(n - 1).map(|arg| &arg.0) {
+        Some(LuaVal::LuaString(s)) => Ok(s.to_string()),
+        Some(LuaVal::LuaInt(_) | LuaVal::LuaFloat(_)) => {
+            Ok(args[n - 1].clone().into_string()?.to_string())
+        }
+        arg => Err(ASTExecError::from(format!(
+            "bad argument #{n} to '{fn_name}' (string expected, got {})",
+            match arg {
+                Some(_) => args[n - 1].type_name(),
+                None => "no value",
+            }
+        ))),
+    }
+}
+
+fn package_table(env: &Env) -> Result<LuaValue, ASTExecError> {
+    match env.get_global("package") {
+        Some(package) if matches!(&package.0, LuaVal::LuaTable(_)) => Ok(package),
+        _ => Err(ASTExecError::from(String::from("'package' must be a table"))),
+    }
+}
+
+// One of the tables stored in `package`, e.g. package.loaded
+fn package_field(package: &LuaValue, field: &str) -> Result<LuaValue, ASTExecError> {
+    match get_field(package, field) {
+        Some(val) if matches!(&val.0, LuaVal::LuaTable(_)) => Ok(val),
+        _ => Err(ASTExecError::from(format!("'package.{field}' must be a table"))),
+    }
+}
+
+fn get_field(table: &LuaValue, field: &str) -> Option<LuaValue> {
+    match &table.0 {
+        LuaVal::LuaTable(table) => table
+            .get(TableKey::String(LuaString::from(field)))
+            .filter(|val| !val.is_nil()),
+        _ => None,
+    }
+}
+
+fn set_field(table: &LuaValue, field: &str, val: LuaValue) {
+    if let LuaVal::LuaTable(table) = &table.0 {
+        table.insert_ident(field, val);
+    }
+}
@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+// Strings up to this length are interned, as in the reference implementation
+const MAX_SHORT_LEN: usize = 40;
+
+struct StrData {
+    hash: u64, // Computed once, when the string is created
+    interned: bool,
+    s: Box<str>,
+}
+
+thread_local! {
+    // Interned strings by hash. The entries are weak, so a string is freed
+    // once the program stops using it.
+    static INTERNER: RefCell<HashMap<u64, Vec<Weak<StrData>>>> = RefCell::new(HashMap::new());
+}
+
+/// Immutable Lua string shared by reference counting.
+/// Short strings (identifiers, field names, most literals) are interned:
+/// equal short strings share the same allocation and compare by pointer.
+#[derive(Clone)]
+pub struct LuaString(Rc<StrData>);
+
+impl LuaString {
+    pub fn as_str(&self) -> &str {
+        &self.0.s
+    }
+
+    fn intern(s: &str) -> Self {
+        let hash = hash_str(s);
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            let bucket = interner.entry(hash).or_default();
+            if let Some(data) = bucket
+                .iter()
+                .filter_map(Weak::upgrade)
+                .find(|data| &*data.s == s)
+            {
+                return LuaString(data);
+            }
+            let data = Rc::new(StrData {
+                hash,
+                interned: true,
+                s: s.into(),
+            });
+            bucket.push(Rc::downgrade(&data));
+            LuaString(data)
+        })
+    }
+
+    fn long(s: Box<str>) -> Self {
+        LuaString(Rc::new(StrData {
+            hash: hash_str(&s),
+            interned: false,
+            s,
+        }))
+    }
+}
+
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Drop for StrData {
+    fn drop(&mut self) {
+        if !self.interned {
+            return;
+        }
+        // The interner may already be gone when the thread exits, or be in use
+        // if it was dropping this string itself. A dead entry is harmless.
+        let _ = INTERNER.try_with(|interner| {
+            if let Ok(mut interner) = interner.try_borrow_mut() {
+                if let Some(bucket) = interner.get_mut(&self.hash) {
+                    bucket.retain(|weak| weak.strong_count() > 0);
+                    if bucket.is_empty() {
+                        interner.remove(&self.hash);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl From<&str> for LuaString {
+    fn from(s: &str) -> Self {
+        if s.len() <= MAX_SHORT_LEN {
+            LuaString::intern(s)
+        } else {
+            LuaString::long(s.into())
+        }
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(s: String) -> Self {
+        if s.len() <= MAX_SHORT_LEN {
+            LuaString::intern(&s)
+        } else {
+            // Long strings keep the buffer they were built in
+            LuaString::long(s.into_boxed_str())
+        }
+    }
+}
+
+impl Deref for LuaString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0.s
+    }
+}
+
+impl PartialEq for LuaString {
+    fn eq(&self, other: &Self) -> bool {
+        if Rc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        // Different interned strings always have different contents
+        if self.0.interned && other.0.interned {
+            return false;
+        }
+        self.0.hash == other.0.hash && self.0.s == other.0.s
+    }
+}
+
+impl Eq for LuaString {}
+
+impl PartialEq<str> for LuaString {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0.s == other
+    }
+}
+
+impl PartialEq<&str> for LuaString {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0.s == *other
+    }
+}
+
+impl Hash for LuaString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl PartialOrd for LuaString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LuaString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.s.cmp(&other.0.s)
+    }
+}
+
+impl fmt::Display for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.s.fmt(f)
+    }
+}
+
+impl fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0.s, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_strings_are_interned() {
+        let a = LuaString::from("hello");
+        let b = LuaString::from(String::from("hello"));
+        assert!(a.0.interned);
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, LuaString::from("world"));
+    }
+
+    #[test]
+    fn test_long_strings_are_not_interned() {
+        let long = "a".repeat(MAX_SHORT_LEN + 1);
+        let a = LuaString::from(long.as_str());
+        let b = LuaString::from(long.clone());
+        assert!(!a.0.interned);
+        assert!(!Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_eq!(a.as_str(), long);
+    }
+
+    #[test]
+    fn test_unused_strings_are_freed() {
+        let s = "interned only by this test";
+        let hash = hash_str(s);
+        let a = LuaString::from(s);
+        assert!(INTERNER.with(|interner| interner.borrow().contains_key(&hash)));
+        drop(a);
+        assert!(!INTERNER.with(|interner| interner.borrow().contains_key(&hash)));
+    }
+}
@@ -1,4 +1,16 @@
 mod ast;
 pub use ast::AST;
+pub mod bytecode;
+pub mod compiler;
+pub mod debug;
+pub mod gc;
 pub mod interpreter;
+pub mod limits;
+pub mod lint;
+pub mod lua;
+pub use lua::Lua;
 pub mod parser;
+pub mod resolver;
+pub mod sandbox;
+pub mod stack;
+pub mod vm;
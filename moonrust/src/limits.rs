@@ -0,0 +1,404 @@
+// Execution limits for untrusted scripts: a budget of VM instructions, an
+// approximate limit on the memory of tables and strings, and a deadline.
+//
+// The VM counts every instruction it runs. The count is compared with the
+// budget, and the clock with the deadline, at the end of each batch of
+// instructions, so that the common case only decrements a counter. While a
+// hook sees every instruction (see `debug`), the batches are of one
+// instruction, and the end of each batch tells the VM to run the hook. Memory is
+// charged when the VM creates a table, grows one or concatenates strings. The
+// charges only add up: once they pass the limit, a full collection runs and
+// the estimate is replaced by the size of what is still alive, which raises
+// the error if it is still over the limit.
+//
+// When the limits are catchable, a script catching the error of an exceeded
+// budget or deadline fails again at the next check, while the memory is
+// measured again at the next charge and may be under the limit once the
+// garbage is collected.
+//
+// The host can also interrupt a script from another thread with an
+// `InterruptHandle`. The request is seen at the end of the current batch and
+// consumed by the error it raises, which `pcall` never catches, so that the
+// environment runs the next scripts normally. A request arriving after the
+// script finished is dropped when the host starts the next one.
+use crate::gc;
+use crate::interpreter::environment::Env;
+use crate::interpreter::ASTExecError;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+// Instructions run between two readings of the clock
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Execution limit that stopped a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Memory,
+    Time,
+    /// The host interrupted the script with an `InterruptHandle`
+    Interrupted,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Limit::Instructions => write!(f, "instruction limit exceeded"),
+            Limit::Memory => write!(f, "not enough memory"),
+            Limit::Time => write!(f, "time limit exceeded"),
+            Limit::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// Limits of the scripts running in an environment, see `Env::set_limits`.
+/// Every limit is off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Number of VM instructions the scripts can run
+    pub instructions: Option<u64>,
+    /// Approximate number of bytes of tables and strings the scripts can keep alive
+    pub memory: Option<usize>,
+    /// Time at which the running script stops
+    pub deadline: Option<Instant>,
+    /// Whether `pcall` and `coroutine.resume` catch the errors of the limits,
+    /// which otherwise go up to the host
+    pub catchable: bool,
+}
+
+/// Handle stopping the script running in an environment, from any thread.
+/// It is obtained with `Env::interrupt_handle` and can be cloned.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Stop the running script at its next check with an "interrupted" error.
+    /// Without a running script, the request has no effect: the next script
+    /// the host starts runs normally.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Resources used since the limits were set
+#[derive(Debug)]
+pub(crate) struct Meter {
+    limits: Limits,
+    interrupt: InterruptHandle,
+    instructions: u64, // Run before the current batch
+    batch: u64,        // Instructions of the current batch
+    countdown: u64,    // Instructions left in the current batch
+    memory: usize,     // Estimate of the memory in use
+    hooked: bool,      // A hook sees every instruction
+}
+
+impl Meter {
+    pub fn new(limits: Limits) -> Self {
+        let mut meter = Meter {
+            limits,
+            interrupt: InterruptHandle::default(),
+            instructions: 0,
+            batch: 0,
+            countdown: 0,
+            memory: 0,
+            hooked: false,
+        };
+        meter.next_batch(false);
+        meter
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Replace the limits, counting the resources from zero
+    pub fn set_limits(&mut self, limits: Limits) {
+        let interrupt = self.interrupt.clone();
+        let hooked = self.hooked;
+        *self = Meter {
+            interrupt,
+            hooked,
+            ..Meter::new(limits)
+        };
+        self.next_batch(false);
+    }
+
+    /// Make every instruction the end of a batch while a hook sees them
+    pub fn set_hooked(&mut self, hooked: bool) {
+        // The instructions of the current batch that already ran are counted
+        self.instructions += self.batch - self.countdown;
+        self.hooked = hooked;
+        self.next_batch(false);
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Drop an interruption meant for a script that already finished
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Count an instruction about to run. Error if it is past the instruction
+    /// budget, or if the deadline passed. True if the hooks must see the
+    /// instruction.
+    #[inline]
+    pub fn step(&mut self) -> Result<bool, ASTExecError> {
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.check()
+        } else {
+            Ok(false)
+        }
+    }
+
+    // End of a batch
+    fn check(&mut self) -> Result<bool, ASTExecError> {
+        self.instructions += self.batch;
+        let exceeded = if self.interrupt.0.swap(false, Ordering::Relaxed) {
+            Some(Limit::Interrupted)
+        } else if self
+            .limits
+            .instructions
+            .is_some_and(|max| self.instructions > max)
+        {
+            Some(Limit::Instructions)
+        } else if self
+            .limits
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(Limit::Time)
+        } else {
+            None
+        };
+        self.next_batch(exceeded.is_some());
+        match exceeded {
+            Some(limit) => Err(ASTExecError::limit_exceeded(limit)),
+            None => Ok(self.hooked),
+        }
+    }
+
+    // The batch ends at the first instruction past the budget, and after an
+    // exceeded limit every instruction fails
+    fn next_batch(&mut self, exceeded: bool) {
+        self.batch = match self.limits.instructions {
+            _ if exceeded || self.hooked => 1,
+            Some(max) if self.instructions <= max => (max - self.instructions)
+                .saturating_add(1)
+                .min(CHECK_INTERVAL),
+            Some(_) => 1,
+            None => CHECK_INTERVAL,
+        };
+        self.countdown = self.batch;
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter::new(Limits::default())
+    }
+}
+
+// Environments are compared by their values, not by what they ran
+impl PartialEq for Meter {
+    fn eq(&self, other: &Self) -> bool {
+        self.limits == other.limits
+    }
+}
+
+/// Charge `bytes` of new memory. Error if the memory limit is exceeded by
+/// the objects that are still alive.
+pub fn charge(env: &mut Env, bytes: usize) -> Result<(), ASTExecError> {
+    let meter = env.meter();
+    let Some(max) = meter.limits.memory else {
+        return Ok(());
+    };
+    meter.memory = meter.memory.saturating_add(bytes);
+    if meter.memory <= max {
+        return Ok(());
+    }
+    env.heap().full_collect();
+    gc::run_finalizers(env);
+    let registers = env.thread().stack().to_vec();
+    let live = env.heap().count_with_strings(&registers);
+    drop(registers);
+    env.meter().memory = live;
+    if live > max {
+        Err(ASTExecError::limit_exceeded(Limit::Memory))
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether `pcall` and `coroutine.resume` catch the error
+pub fn catchable(env: &Env, err: &ASTExecError) -> bool {
+    match err.limit() {
+        None => true,
+        Some(Limit::Interrupted) => false,
+        Some(_) => env.limits().catchable,
+    }
+}
+
+/// Whether the memory is limited, to skip measuring what the VM allocates
+pub fn limits_memory(env: &mut Env) -> bool {
+    env.meter().limits.memory.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::LuaValue;
+    use crate::AST;
+    use std::time::Duration;
+
+    fn run(src: &str, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        src.parse::<AST>().unwrap().exec_with_return(env)
+    }
+
+    fn limit_of(src: &str, limits: Limits) -> Option<Limit> {
+        let mut env = Env::new();
+        env.set_limits(limits);
+        run(src, &mut env).err().and_then(|err| err.limit())
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let limits = Limits {
+            instructions: Some(10_000),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limit_of("while true do end", limits),
+            Some(Limit::Instructions)
+        );
+        assert_eq!(
+            limit_of("local function f() return f() end f()", limits),
+            Some(Limit::Instructions)
+        );
+        assert_eq!(limit_of("for i = 1, 100 do end", limits), None);
+
+        // The budget is exact
+        let mut env = Env::new();
+        env.set_limits(Limits {
+            instructions: Some(2),
+            ..Limits::default()
+        });
+        assert!(run("return 1", &mut env).is_ok());
+        env.set_limits(Limits {
+            instructions: Some(2),
+            ..Limits::default()
+        });
+        assert!(run("local x = 1 return x", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let limits = Limits {
+            memory: Some(1 << 20),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limit_of("local t = {} while true do t[#t + 1] = t end", limits),
+            Some(Limit::Memory)
+        );
+        assert_eq!(
+            limit_of("local s = \"x\" while true do s = s .. s end", limits),
+            Some(Limit::Memory)
+        );
+        // Garbage is not counted
+        assert_eq!(
+            limit_of(
+                "for i = 1, 100000 do local t = {} t[1] = i .. \"\" end",
+                limits
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        assert_eq!(limit_of("while true do end", limits), Some(Limit::Time));
+    }
+
+    #[test]
+    fn test_pcall_and_limits() {
+        let src = "local ok, err = pcall(function() while true do end end)
+        return ok, err";
+        let mut env = Env::new();
+        env.set_limits(Limits {
+            instructions: Some(10_000),
+            ..Limits::default()
+        });
+        let err = run(src, &mut env).unwrap_err();
+        assert_eq!(err.limit(), Some(Limit::Instructions));
+        let src = "local co = coroutine.create(function() while true do end end)
+        return coroutine.resume(co)";
+        assert!(run(src, &mut env).is_err());
+
+        // A script can recover from a caught memory error once its garbage
+        // is collected, but not from an exceeded instruction budget
+        env.set_limits(Limits {
+            instructions: Some(100_000),
+            memory: Some(1 << 20),
+            catchable: true,
+            ..Limits::default()
+        });
+        let src =
+            "local ok, err = pcall(function() local s = \"x\" while true do s = s .. s end end)
+        return ok, err, #{1, 2}";
+        let vals: Vec<String> = run(src, &mut env)
+            .unwrap()
+            .iter()
+            .map(LuaValue::to_string)
+            .collect();
+        assert_eq!(vals, ["false", "not enough memory", "2"]);
+        let src = "pcall(function() while true do end end) return 1";
+        assert_eq!(
+            run(src, &mut env).unwrap_err().limit(),
+            Some(Limit::Instructions)
+        );
+
+        // Setting the limits again starts from zero
+        env.set_limits(Limits::default());
+        assert!(run("return 1", &mut env).is_ok());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut env = Env::new();
+        let handle = env.interrupt_handle();
+        assert!(run("return 1", &mut env).is_ok());
+        // A cancel arriving after the script finished doesn't stop the next one
+        handle.interrupt();
+        assert!(run("for i = 1, 10000 do end", &mut env).is_ok());
+
+        // Neither pcall nor coroutine.resume catch it, and it survives new limits
+        env.set_limits(Limits {
+            catchable: true,
+            ..Limits::default()
+        });
+        let handle = env.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.interrupt();
+        });
+        let src = "local co = coroutine.create(function()
+            while true do pcall(function() while true do end end) end
+        end)
+        coroutine.resume(co)";
+        let err = run(src, &mut env).unwrap_err();
+        interrupter.join().unwrap();
+        assert_eq!(err.limit(), Some(Limit::Interrupted));
+        // The interruption is consumed
+        assert!(run("for i = 1, 10000 do end", &mut env).is_ok());
+    }
+}
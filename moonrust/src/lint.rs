@@ -0,0 +1,820 @@
+// Static checks for common bugs in scripts, run on the AST without executing
+// it. The AST keeps no positions for names, so the checker walks it in source
+// order alongside the tokens of the source, moving to the token of each name,
+// keyword or literal it visits to know where to report a warning.
+use crate::ast::*;
+use crate::parser::lexer::{self, Token, TokenKind};
+use crate::parser::{self, ASTParseError};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub line: usize,
+    pub col: usize,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {} {}",
+            self.line, self.col, self.code, self.message
+        )
+    }
+}
+
+/// Check a chunk, given the names of the globals it can use without setting
+/// them. Warnings come in the order of the source. A comment
+/// `-- moonrust: disable=rule,rule` anywhere in the chunk turns rules off.
+pub fn check(src: &str, globals: &[String]) -> Result<Vec<Warning>, ASTParseError> {
+    let ast = parser::parse_chunk(src)?;
+    let (comments, tokens): (Vec<Token>, Vec<Token>) = lexer::tokenize(src)
+        .into_iter()
+        .partition(|token| token.kind == TokenKind::Comment);
+    let disabled: HashSet<&str> = comments
+        .iter()
+        .flat_map(|c| disabled_rules(c.text))
+        .collect();
+
+    let mut checker = Checker::new(tokens, globals);
+    checker.visit_chunk(&ast);
+    let mut warnings = checker.warnings;
+    warnings.retain(|warning| !disabled.contains(warning.code));
+    warnings.sort_by_key(|warning| (warning.line, warning.col));
+    Ok(warnings)
+}
+
+// Rules turned off by a comment `-- moonrust: disable=rule,rule`
+fn disabled_rules(comment: &str) -> Vec<&str> {
+    let directive = comment
+        .trim_start_matches('-')
+        .trim()
+        .strip_prefix("moonrust:")
+        .and_then(|directive| directive.trim().strip_prefix("disable="));
+    match directive {
+        Some(rules) => rules.split(',').map(str::trim).collect(),
+        None => vec![],
+    }
+}
+
+type Pos = (usize, usize);
+
+#[derive(Clone, Copy, PartialEq)]
+enum LocalKind {
+    Variable,
+    LoopVariable,
+    Function,
+    Parameter,
+    // `self` of a method
+    Implicit,
+}
+
+// Number of parameters of a function, and whether it takes varargs
+#[derive(Clone, Copy)]
+struct Arity(usize, bool);
+
+struct Local {
+    name: String,
+    pos: Pos,
+    kind: LocalKind,
+    used: bool,
+    // Set for a local holding a function it was declared with, until it is
+    // assigned again
+    arity: Option<Arity>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Str(String),
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Int(i) => write!(f, "[{i}]"),
+            Key::Str(s) => write!(f, "'{s}'"),
+        }
+    }
+}
+
+struct Checker<'a, 'g> {
+    tokens: Vec<Token<'a>>,
+    // Index of the next token to visit
+    next: usize,
+    // Position of the first token visited by the current statement or field
+    start: Option<Pos>,
+    builtins: HashSet<&'g str>,
+    // Locals of the blocks around the current point, the innermost last
+    scopes: Vec<Vec<Local>>,
+    // Number of loops around the current point in each function, the
+    // innermost function last
+    loops: Vec<usize>,
+    global_reads: Vec<(String, Pos)>,
+    global_writes: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a, 'g> Checker<'a, 'g> {
+    fn new(tokens: Vec<Token<'a>>, globals: &'g [String]) -> Self {
+        Checker {
+            tokens,
+            next: 0,
+            start: None,
+            builtins: globals.iter().map(String::as_str).collect(),
+            scopes: vec![],
+            loops: vec![],
+            global_reads: vec![],
+            global_writes: HashSet::new(),
+            warnings: vec![],
+        }
+    }
+
+    fn warn(&mut self, pos: Pos, code: &'static str, message: String) {
+        self.warnings.push(Warning {
+            line: pos.0,
+            col: pos.1,
+            code,
+            message,
+        });
+    }
+
+    // Position of the last visited token
+    fn here(&self) -> Pos {
+        self.next
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map_or((1, 1), |token| (token.line, token.col))
+    }
+
+    // Move past the next token matching, returning its position. If there is
+    // none, the tokens and the AST disagree, and the position stays.
+    fn seek(&mut self, matches: impl Fn(&Token) -> bool) -> Pos {
+        if let Some(i) = self.tokens[self.next..].iter().position(matches) {
+            self.next += i + 1;
+        }
+        let pos = self.here();
+        self.start.get_or_insert(pos);
+        pos
+    }
+
+    fn seek_name(&mut self, name: &str) -> Pos {
+        self.seek(|token| token.kind == TokenKind::Name && token.text == name)
+    }
+
+    fn seek_keyword(&mut self, keyword: &str) -> Pos {
+        self.seek(|token| token.kind == TokenKind::Keyword && token.text == keyword)
+    }
+
+    fn seek_kind(&mut self, kind: TokenKind) -> Pos {
+        self.seek(|token| token.kind == kind)
+    }
+
+    // Whether the next token is this symbol
+    fn at_symbol(&self, symbol: &str) -> bool {
+        self.tokens
+            .get(self.next)
+            .is_some_and(|token| token.kind == TokenKind::Symbol && token.text == symbol)
+    }
+
+    // Run a visit, returning the position of the first token it visited
+    fn start_of(&mut self, visit: impl FnOnce(&mut Self)) -> Option<Pos> {
+        let outer = self.start.take();
+        visit(self);
+        let start = self.start;
+        self.start = outer.or(start);
+        start
+    }
+
+    fn visit_chunk(&mut self, ast: &AST) {
+        self.loops.push(0);
+        self.scopes.push(vec![]);
+        self.visit_statements(&ast.0);
+        self.close_scope();
+        self.loops.pop();
+
+        let reads = std::mem::take(&mut self.global_reads);
+        for (name, pos) in reads {
+            if !self.global_writes.contains(&name) {
+                let message = format!("accessing undefined global {name}");
+                self.warn(pos, "undefined-global", message);
+            }
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.scopes.push(vec![]);
+        self.visit_statements(block);
+        self.close_scope();
+    }
+
+    // Statements of a block, in the current scope
+    fn visit_statements(&mut self, block: &Block) {
+        // Whether the statements visited so far always leave the block
+        let mut left = false;
+        let mut reported = false;
+        for statement in &block.statements {
+            let start = self.start_of(|checker| checker.visit_statement(statement));
+            if let (true, false, Some(pos)) = (left, reported, start) {
+                self.warn(pos, "unreachable-code", String::from("unreachable code"));
+                reported = true;
+            }
+            left = left || statement.leaves_block();
+        }
+        if let Some(exps) = &block.return_stat {
+            let pos = self.seek_keyword("return");
+            if left && !reported {
+                self.warn(pos, "unreachable-code", String::from("unreachable code"));
+            }
+            self.visit_exps(exps);
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment((vars, exps, true), _) => {
+                self.seek_keyword("local");
+                let names: Vec<(&String, Pos)> = vars
+                    .iter()
+                    .filter_map(|var| match var {
+                        Var::Name(name) => Some(name),
+                        _ => None,
+                    })
+                    .map(|name| (name, self.seek_name(name)))
+                    .collect();
+                self.visit_local_exps(exps);
+                for (i, (name, pos)) in names.into_iter().enumerate() {
+                    let arity = exps.get(i).and_then(function_arity);
+                    self.declare(name, pos, LocalKind::Variable, arity);
+                }
+            }
+            Statement::Assignment((vars, exps, false), _) => {
+                for var in vars {
+                    self.visit_target(var);
+                }
+                self.visit_exps(exps);
+            }
+            Statement::FunctionCall(call, _) => self.visit_call(call),
+            Statement::Break(_) => {
+                let pos = self.seek_keyword("break");
+                if self.loops.last() == Some(&0) {
+                    let message = String::from("break outside a loop");
+                    self.warn(pos, "break-outside-loop", message);
+                }
+            }
+            Statement::DoBlock(block, _) => {
+                self.seek_keyword("do");
+                self.visit_block(block);
+            }
+            Statement::While((exp, block), _) => {
+                self.seek_keyword("while");
+                self.visit_exp(exp);
+                self.seek_keyword("do");
+                self.visit_loop(|checker| checker.visit_block(block));
+            }
+            Statement::Repeat((block, exp), _) => {
+                self.seek_keyword("repeat");
+                // The condition sees the locals of the block
+                self.scopes.push(vec![]);
+                self.visit_loop(|checker| checker.visit_statements(block));
+                self.seek_keyword("until");
+                self.visit_exp(exp);
+                self.close_scope();
+            }
+            Statement::If((cond, then_block, elseifs, else_block), _) => {
+                self.seek_keyword("if");
+                self.visit_exp(cond);
+                self.visit_block(then_block);
+                for (cond, block) in elseifs {
+                    self.seek_keyword("elseif");
+                    self.visit_exp(cond);
+                    self.visit_block(block);
+                }
+                if let Some(block) = else_block {
+                    self.seek_keyword("else");
+                    self.visit_block(block);
+                }
+            }
+            Statement::ForNum((name, start, end, step, block), _) => {
+                self.seek_keyword("for");
+                let pos = self.seek_name(name);
+                self.visit_exp(start);
+                self.visit_exp(end);
+                if let Some(step) = step {
+                    self.visit_exp(step);
+                }
+                self.visit_for_body(&[(name, pos)], block);
+            }
+            Statement::ForGeneric((names, exps, block), _) => {
+                self.seek_keyword("for");
+                let names: Vec<(&String, Pos)> = names
+                    .iter()
+                    .map(|name| (name, self.seek_name(name)))
+                    .collect();
+                self.visit_exps(exps);
+                self.visit_for_body(&names, block);
+            }
+            Statement::FunctionDecl((_, body), _) => {
+                self.seek_keyword("function");
+                let (path, method) = self.function_path();
+                match &path[..] {
+                    [(name, pos)] => self.assign(name, *pos, function_arity_of(body)),
+                    [(name, pos), ..] => self.read(name, *pos),
+                    [] => (),
+                }
+                self.visit_function(body, method);
+            }
+            Statement::LocalFuncDecl((name, body), _) => {
+                self.seek_keyword("local");
+                self.seek_keyword("function");
+                let pos = self.seek_name(name);
+                let arity = function_arity_of(body);
+                self.declare(name, pos, LocalKind::Function, arity);
+                self.visit_function(body, false);
+            }
+            Statement::LocalAttribs((names, exps), _) => {
+                self.seek_keyword("local");
+                let names: Vec<(&String, Pos)> = names
+                    .iter()
+                    .map(|(name, attrib)| {
+                        let pos = self.seek_name(name);
+                        if attrib.is_some() {
+                            self.next = (self.next + 3).min(self.tokens.len());
+                        }
+                        (name, pos)
+                    })
+                    .collect();
+                self.visit_local_exps(exps);
+                for (i, (name, pos)) in names.into_iter().enumerate() {
+                    let arity = exps.get(i).and_then(function_arity);
+                    self.declare(name, pos, LocalKind::Variable, arity);
+                }
+            }
+            Statement::Semicolon(_) => (),
+        }
+    }
+
+    // Expressions of a local declaration, which are only in the source if it
+    // has an `=` (`local x` is parsed as `local x = nil`)
+    fn visit_local_exps(&mut self, exps: &[Expression]) {
+        if self.at_symbol("=") {
+            self.visit_exps(exps);
+        }
+    }
+
+    fn visit_loop(&mut self, visit: impl FnOnce(&mut Self)) {
+        *self.loops.last_mut().unwrap() += 1;
+        visit(self);
+        *self.loops.last_mut().unwrap() -= 1;
+    }
+
+    fn visit_for_body(&mut self, names: &[(&String, Pos)], block: &Block) {
+        self.seek_keyword("do");
+        self.scopes.push(vec![]);
+        for (name, pos) in names {
+            self.declare(name, *pos, LocalKind::LoopVariable, None);
+        }
+        self.visit_loop(|checker| checker.visit_block(block));
+        self.close_scope();
+    }
+
+    // Names of `function a.b.c:m`, read from the tokens since the AST joins
+    // them, and whether it declares a method
+    fn function_path(&mut self) -> (Vec<(&'a str, Pos)>, bool) {
+        let mut path = vec![];
+        let mut method = false;
+        while let Some(token) = self.tokens.get(self.next) {
+            if token.kind != TokenKind::Name {
+                break;
+            }
+            path.push((token.text, (token.line, token.col)));
+            self.next += 1;
+            match self.tokens.get(self.next).map(|token| token.text) {
+                Some(".") => self.next += 1,
+                Some(":") if !method => {
+                    self.next += 1;
+                    method = true;
+                }
+                _ => break,
+            }
+        }
+        (path, method)
+    }
+
+    fn visit_function(&mut self, body: &FuncBody, method: bool) {
+        self.loops.push(0);
+        self.scopes.push(vec![]);
+        if method {
+            let pos = self.here();
+            self.declare("self", pos, LocalKind::Implicit, None);
+        }
+        for name in &body.par_list.0 {
+            let pos = self.seek_name(name);
+            self.declare(name, pos, LocalKind::Parameter, None);
+        }
+        self.visit_statements(&body.block);
+        self.seek_keyword("end");
+        self.close_scope();
+        self.loops.pop();
+    }
+
+    fn visit_exps(&mut self, exps: &[Expression]) {
+        for exp in exps {
+            self.visit_exp(exp);
+        }
+    }
+
+    fn visit_exp(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Nil(_) => {
+                self.seek_keyword("nil");
+            }
+            Expression::False(_) => {
+                self.seek_keyword("false");
+            }
+            Expression::True(_) => {
+                self.seek_keyword("true");
+            }
+            Expression::Numeral(_, _) => {
+                self.seek_kind(TokenKind::Number);
+            }
+            Expression::LiteralString(_, _) => {
+                self.seek_kind(TokenKind::String);
+            }
+            Expression::DotDotDot(_) => {
+                self.seek(|token| token.kind == TokenKind::Symbol && token.text == "...");
+            }
+            Expression::FunctionDef(body, _) => {
+                self.seek_keyword("function");
+                self.visit_function(body, false);
+            }
+            Expression::PrefixExp(prefix, _) => self.visit_prefix(prefix),
+            Expression::TableConstructor(fields, _) => self.visit_table(fields),
+            Expression::BinaryOp((left, _, right), _) => {
+                self.visit_exp(left);
+                self.visit_exp(right);
+            }
+            Expression::UnaryOp((_, exp), _) => self.visit_exp(exp),
+        }
+    }
+
+    fn visit_prefix(&mut self, prefix: &PrefixExp) {
+        match prefix {
+            PrefixExp::Var(Var::Name(name)) => {
+                let pos = self.seek_name(name);
+                self.read(name, pos);
+            }
+            PrefixExp::Var(Var::Bracket((prefix, exp))) => {
+                self.visit_prefix(prefix);
+                self.visit_exp(exp);
+            }
+            PrefixExp::Var(Var::Dot((prefix, name))) => {
+                self.visit_prefix(prefix);
+                self.seek_name(name);
+            }
+            PrefixExp::FunctionCall(call) => self.visit_call(call),
+            PrefixExp::Exp(exp) => self.visit_exp(exp),
+        }
+    }
+
+    // Variable assigned to by an assignment statement
+    fn visit_target(&mut self, var: &Var) {
+        match var {
+            Var::Name(name) => {
+                let pos = self.seek_name(name);
+                self.assign(name, pos, None);
+            }
+            Var::Bracket((prefix, exp)) => {
+                self.visit_prefix(prefix);
+                self.visit_exp(exp);
+            }
+            Var::Dot((prefix, name)) => {
+                self.visit_prefix(prefix);
+                self.seek_name(name);
+            }
+        }
+    }
+
+    fn visit_call(&mut self, call: &FunctionCall) {
+        match call {
+            FunctionCall::Standard((prefix, args)) => {
+                let callee = match &**prefix {
+                    PrefixExp::Var(Var::Name(name)) => {
+                        let pos = self.seek_name(name);
+                        self.read(name, pos);
+                        let arity = self.resolve(name).and_then(|local| local.arity);
+                        arity.map(|arity| (name, pos, arity))
+                    }
+                    prefix => {
+                        self.visit_prefix(prefix);
+                        None
+                    }
+                };
+                if let Some((name, pos, arity)) = callee {
+                    self.check_arity(name, pos, arity, args);
+                }
+                self.visit_args(args);
+            }
+            FunctionCall::Method((prefix, name, args)) => {
+                self.visit_prefix(prefix);
+                self.seek_name(name);
+                self.visit_args(args);
+            }
+        }
+    }
+
+    fn check_arity(&mut self, name: &str, pos: Pos, Arity(params, vararg): Arity, args: &Args) {
+        // The last expression of a list can give any number of values
+        let (count, open) = match args {
+            Args::ExpList(exps) => match exps.last() {
+                Some(Expression::DotDotDot(_)) => (exps.len() - 1, true),
+                Some(Expression::PrefixExp(prefix, _))
+                    if matches!(**prefix, PrefixExp::FunctionCall(_)) =>
+                {
+                    (exps.len() - 1, true)
+                }
+                _ => (exps.len(), false),
+            },
+            _ => (1, false),
+        };
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        if (count > params && !vararg) || (count < params && !open) {
+            let message = format!(
+                "{name} takes {params} {}, called with {count} {}",
+                plural(params),
+                plural(count)
+            );
+            self.warn(pos, "wrong-arity", message);
+        }
+    }
+
+    fn visit_args(&mut self, args: &Args) {
+        match args {
+            Args::ExpList(exps) => self.visit_exps(exps),
+            Args::TableConstructor(fields) => self.visit_table(fields),
+            Args::LiteralString(_) => {
+                self.seek_kind(TokenKind::String);
+            }
+        }
+    }
+
+    fn visit_table(&mut self, fields: &[Field]) {
+        let mut keys: HashMap<Key, usize> = HashMap::new();
+        let mut index = 0;
+        for field in fields {
+            let (key, start) = match field {
+                Field::Bracketed((key, val)) => {
+                    let start = self.start_of(|checker| checker.visit_exp(key));
+                    self.visit_exp(val);
+                    (constant_key(key), start)
+                }
+                Field::Name((name, val)) => {
+                    let start = self.seek_name(name);
+                    self.visit_exp(val);
+                    (Some(Key::Str(name.clone())), Some(start))
+                }
+                Field::Unnamed(val) => {
+                    index += 1;
+                    let start = self.start_of(|checker| checker.visit_exp(val));
+                    (Some(Key::Int(index)), start)
+                }
+            };
+            let Some(key) = key else { continue };
+            let pos = start.unwrap_or_else(|| self.here());
+            match keys.get(&key) {
+                Some(line) => {
+                    let message = format!("duplicate key {key} in table, first set on line {line}");
+                    self.warn(pos, "duplicate-key", message);
+                }
+                None => {
+                    keys.insert(key, pos.0);
+                }
+            }
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Option<&mut Local> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|local| local.name == name)
+    }
+
+    fn read(&mut self, name: &str, pos: Pos) {
+        if let Some(local) = self.resolve(name) {
+            local.used = true;
+        } else if !(name == "_ENV" || self.builtins.contains(name) || self.local_env()) {
+            self.global_reads.push((name.to_string(), pos));
+        }
+    }
+
+    // Whether globals are fields of a local _ENV, whose contents are unknown
+    fn local_env(&mut self) -> bool {
+        match self.resolve("_ENV") {
+            Some(local) => {
+                local.used = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Assignment of a name, with the arity of the function assigned if known
+    fn assign(&mut self, name: &str, pos: Pos, arity: Option<Arity>) {
+        if let Some(local) = self.resolve(name) {
+            local.arity = arity;
+            return;
+        }
+        if self.local_env() {
+            return;
+        }
+        self.global_writes.insert(name.to_string());
+        if self.builtins.contains(name) {
+            let message = format!("setting built-in global {name}");
+            self.warn(pos, "global-write", message);
+        } else if self.loops.len() > 1 {
+            let message = format!("setting global {name} inside a function");
+            self.warn(pos, "global-write", message);
+        }
+    }
+
+    fn declare(&mut self, name: &str, pos: Pos, kind: LocalKind, arity: Option<Arity>) {
+        let shadowed = match self.resolve(name) {
+            Some(local) if local.kind != LocalKind::Implicit => Some(local.pos.0),
+            _ => None,
+        };
+        if let (Some(line), false) = (shadowed, is_ignored(name) || kind == LocalKind::Implicit) {
+            let message = format!("{name} shadows a local declared on line {line}");
+            self.warn(pos, "shadowed-local", message);
+        }
+        self.scopes.last_mut().unwrap().push(Local {
+            name: name.to_string(),
+            pos,
+            kind,
+            used: false,
+            arity,
+        });
+    }
+
+    fn close_scope(&mut self) {
+        for local in self.scopes.pop().unwrap_or_default() {
+            if local.used || is_ignored(&local.name) {
+                continue;
+            }
+            let (code, what) = match local.kind {
+                LocalKind::Variable => ("unused-local", "local variable"),
+                LocalKind::LoopVariable => ("unused-local", "loop variable"),
+                LocalKind::Function => ("unused-local", "local function"),
+                LocalKind::Parameter => ("unused-parameter", "parameter"),
+                LocalKind::Implicit => continue,
+            };
+            let message = format!("unused {what} {}", local.name);
+            self.warn(local.pos, code, message);
+        }
+    }
+}
+
+// Names starting with _ are meant to be unused
+fn is_ignored(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+fn function_arity(exp: &Expression) -> Option<Arity> {
+    match exp {
+        Expression::FunctionDef(body, _) => function_arity_of(body),
+        _ => None,
+    }
+}
+
+fn function_arity_of(body: &FuncBody) -> Option<Arity> {
+    Some(Arity(body.par_list.0.len(), body.par_list.1))
+}
+
+// Key of a table field given by a constant
+fn constant_key(exp: &Expression) -> Option<Key> {
+    match exp {
+        Expression::LiteralString(s, _) => Some(Key::Str(s.clone())),
+        Expression::Numeral(Numeral::Integer(i), _) => Some(Key::Int(*i)),
+        Expression::Numeral(Numeral::Float(f), _)
+            if f.fract() == 0.0 && f.abs() < 2f64.powi(63) =>
+        {
+            Some(Key::Int(*f as i64))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(src: &str) -> Vec<String> {
+        let globals = [String::from("print"), String::from("pairs")];
+        check(src, &globals)
+            .unwrap()
+            .iter()
+            .map(Warning::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_globals() {
+        let src = "x = 1
+print(x, y)
+function f()
+    z = x
+    print = nil
+end
+function t.m() end
+local _ENV = {}
+w = v";
+        assert_eq!(
+            warnings(src),
+            [
+                "2:10: undefined-global accessing undefined global y",
+                "4:5: global-write setting global z inside a function",
+                "5:5: global-write setting built-in global print",
+                "7:10: undefined-global accessing undefined global t",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_and_shadowed_locals() {
+        let src = "local a, _b = 1, 2
+local function f(x, y)
+    local a = y
+    for i, v in pairs({}) do print(v) end
+    return a
+end
+local c
+local o = {}
+function o:m(n) return self end";
+        assert_eq!(
+            warnings(src),
+            [
+                "1:7: unused-local unused local variable a",
+                "2:16: unused-local unused local function f",
+                "2:18: unused-parameter unused parameter x",
+                "3:11: shadowed-local a shadows a local declared on line 1",
+                "4:9: unused-local unused loop variable i",
+                "7:7: unused-local unused local variable c",
+                "9:14: unused-parameter unused parameter n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let src = "while true do
+    break
+    print(1)
+end
+do return end
+print(2)
+if x then break else return end";
+        assert_eq!(
+            warnings(src),
+            [
+                "3:5: unreachable-code unreachable code",
+                "6:1: unreachable-code unreachable code",
+                "7:4: undefined-global accessing undefined global x",
+                "7:11: break-outside-loop break outside a loop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tables_and_calls() {
+        let src = "local t = {1, 2, a = 1, [\"a\"] = 2, [2] = 3, [2.0] = 4}
+local function f(a, b) return a, b end
+local g = function(a, ...) return a end
+print(t, f(1), f(1, 2, 3), f(f(1, 2)), g(1, 2, 3), g())
+f = print
+f(1)";
+        assert_eq!(
+            warnings(src),
+            [
+                "1:26: duplicate-key duplicate key 'a' in table, first set on line 1",
+                "1:37: duplicate-key duplicate key [2] in table, first set on line 1",
+                "1:46: duplicate-key duplicate key [2] in table, first set on line 1",
+                "4:10: wrong-arity f takes 2 arguments, called with 1 argument",
+                "4:16: wrong-arity f takes 2 arguments, called with 3 arguments",
+                "4:52: wrong-arity g takes 1 argument, called with 0 arguments",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disable_rules() {
+        let src = "-- moonrust: disable=unused-local, undefined-global
+local x = y --[[ comment ]]
+do return end
+x = 1";
+        assert_eq!(warnings(src), ["4:1: unreachable-code unreachable code"]);
+    }
+}
@@ -0,0 +1,594 @@
+use crate::debug::{Hook, HookMask};
+use crate::interpreter::environment::Env;
+use crate::interpreter::{
+    chunk_id, ASTExecError, LuaString, LuaTable, LuaVal, LuaValue, RustFunction, TableKey,
+};
+use crate::limits::{InterruptHandle, Limit, Limits};
+use crate::parser::{self, ASTParseError};
+use crate::sandbox::Sandbox;
+use crate::AST;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// A Lua state for embedding MoonRust in a Rust program.
+///
+/// The state keeps a single global environment, so globals defined by one
+/// chunk are visible to the next.
+///
+/// ```
+/// use moonrust::Lua;
+///
+/// let mut lua = Lua::new();
+/// lua.load("function add(a, b) return a + b end").exec().unwrap();
+/// let sum: i64 = lua.call_function("add", (1, 2)).unwrap();
+/// assert_eq!(sum, 3);
+/// ```
+pub struct Lua {
+    env: Env,
+}
+
+impl Lua {
+    pub fn new() -> Self {
+        Lua { env: Env::new() }
+    }
+
+    /// A state whose scripts only see the functions exposed by `sandbox`.
+    /// Modules can only be registered with `register_module` if the sandbox
+    /// exposes the package library.
+    pub fn sandboxed(sandbox: &Sandbox) -> Self {
+        Lua {
+            env: sandbox.build(),
+        }
+    }
+
+    /// Prepare a chunk of Lua source for execution
+    pub fn load(&mut self, source: &str) -> Chunk<'_> {
+        Chunk {
+            lua: self,
+            source: source.to_string(),
+            name: None,
+        }
+    }
+
+    /// Access the global environment
+    pub fn globals(&mut self) -> Globals<'_> {
+        Globals { lua: self }
+    }
+
+    /// Call the global function `name` with the given arguments
+    pub fn call_function<A: IntoLuaMulti, R: FromLuaMulti>(
+        &mut self,
+        name: &str,
+        args: A,
+    ) -> Result<R, LuaError> {
+        let func = match self.env.get_global(name) {
+            Some(func) if func.is_callable() => func,
+            Some(val) => {
+                return Err(LuaError::Runtime(format!(
+                    "attempt to call a {} value (global '{name}')",
+                    val.type_name()
+                )))
+            }
+            None => {
+                return Err(LuaError::Runtime(format!(
+                    "attempt to call a nil value (global '{name}')"
+                )))
+            }
+        };
+        let args = args.into_lua_multi(&mut self.env);
+        let vals = func
+            .call(args, &mut self.env)
+            .map_err(|err| match err.limit() {
+                Some(limit) => LuaError::Limit(limit),
+                None => LuaError::Runtime(err.to_string()),
+            })?;
+        R::from_lua_multi(vals)
+    }
+
+    /// Limit the instructions, memory and time of the scripts run from now on,
+    /// see `Limits`. A script exceeding a limit fails with `LuaError::Limit`.
+    ///
+    /// ```
+    /// use moonrust::limits::{Limit, Limits};
+    /// use moonrust::lua::LuaError;
+    /// use moonrust::Lua;
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.set_limits(Limits {
+    ///     instructions: Some(100_000),
+    ///     ..Limits::default()
+    /// });
+    /// let err = lua.load("while true do end").exec().unwrap_err();
+    /// assert_eq!(err, LuaError::Limit(Limit::Instructions));
+    /// ```
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.env.set_limits(limits);
+    }
+
+    /// Handle stopping the running script from another thread, which then
+    /// fails with `LuaError::Limit(Limit::Interrupted)`. The state can run
+    /// other chunks afterwards.
+    ///
+    /// ```
+    /// use moonrust::limits::Limit;
+    /// use moonrust::lua::LuaError;
+    /// use moonrust::Lua;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let mut lua = Lua::new();
+    /// let handle = lua.interrupt_handle();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     handle.interrupt();
+    /// });
+    /// let err = lua.load("while true do end").exec().unwrap_err();
+    /// assert_eq!(err, LuaError::Limit(Limit::Interrupted));
+    /// assert_eq!(lua.load("1 + 1").eval::<i64>(), Ok(2));
+    /// ```
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.env.interrupt_handle()
+    }
+
+    /// Register a module implemented in Rust. `loader` fills the module table
+    /// the first time a script calls `require(name)`, after which the table is
+    /// cached in `package.loaded` like any other module. Fails if the state
+    /// has no `package.preload` table, as in a sandbox without the package
+    /// library, since scripts could not require the module.
+    ///
+    /// ```
+    /// use moonrust::Lua;
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.register_module("greet", |module| {
+    ///     module.set("greeting", "hello");
+    ///     module.set_function("shout", |name: String| Ok(name.to_uppercase()));
+    /// })
+    /// .unwrap();
+    /// let msg: String = lua.load("require(\"greet\").shout(\"moon\")").eval().unwrap();
+    /// assert_eq!(msg, "MOON");
+    /// ```
+    pub fn register_module<F>(&mut self, name: &str, loader: F) -> Result<(), LuaError>
+    where
+        F: Fn(&mut Module<'_>) + 'static,
+    {
+        let preload = self
+            .env
+            .get_global("package")
+            .and_then(|package| match &package.0 {
+                LuaVal::LuaTable(package) => {
+                    package.get(TableKey::String(LuaString::from("preload")))
+                }
+                _ => None,
+            });
+        let preload = match preload.map(|preload| preload.0) {
+            Some(LuaVal::LuaTable(preload)) => preload,
+            _ => {
+                return Err(LuaError::Runtime(format!(
+                    "cannot register module '{name}': 'package.preload' is not a table"
+                )))
+            }
+        };
+        let loader = RustFunction::new(move |_, env| {
+            let mut module = Module {
+                table: LuaTable::new(),
+                env,
+            };
+            loader(&mut module);
+            Ok(vec![module.env.alloc_table(module.table)])
+        });
+        preload.insert_ident(name, LuaValue::new(LuaVal::RustFunction(loader)));
+        Ok(())
+    }
+
+    /// Set a hook called on the events selected by `mask`, replacing any
+    /// hook set before, from Rust or by `debug.sethook`. The hook can look
+    /// at the running functions with the functions of `moonrust::debug`.
+    ///
+    /// ```
+    /// use moonrust::debug::{HookEvent, HookMask};
+    /// use moonrust::interpreter::environment::Env;
+    /// use moonrust::Lua;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// let lines = Rc::new(RefCell::new(Vec::new()));
+    /// let seen = lines.clone();
+    /// let mut lua = Lua::new();
+    /// lua.set_hook(
+    ///     Box::new(move |_: &mut Env, event: HookEvent| {
+    ///         if let HookEvent::Line(line) = event {
+    ///             seen.borrow_mut().push(line);
+    ///         }
+    ///         Ok(())
+    ///     }),
+    ///     HookMask::parse("l", 0),
+    /// );
+    /// lua.load("local x = 1\nx = x + 1").exec().unwrap();
+    /// assert_eq!(*lines.borrow(), vec![1, 2]);
+    /// ```
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>, mask: HookMask) {
+        self.env.set_hook(hook, mask);
+    }
+
+    /// Remove the hook, whether set by `set_hook` or by `debug.sethook`
+    pub fn remove_hook(&mut self) {
+        self.env.remove_hook();
+    }
+
+    fn run(&mut self, ast: AST, source: &str) -> Result<Vec<LuaValue>, ASTExecError> {
+        // Functions defined by the chunk share its prototypes, so the AST
+        // itself can be dropped once it has run
+        ast.exec_chunk(&mut self.env, source)
+    }
+}
+
+impl Default for Lua {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A chunk of Lua source loaded by `Lua::load` but not executed yet
+pub struct Chunk<'lua> {
+    lua: &'lua mut Lua,
+    source: String,
+    name: Option<String>,
+}
+
+impl<'lua> Chunk<'lua> {
+    /// Set the name used in error messages (e.g. the file name of the chunk)
+    pub fn set_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Execute the chunk, discarding any returned values
+    pub fn exec(self) -> Result<(), LuaError> {
+        let name = self.chunk_name();
+        let ast = parser::parse_chunk(&self.source).map_err(|err| LuaError::syntax(&name, err))?;
+        let source = self.source_name();
+        self.lua
+            .run(ast, &source)
+            .map_err(|err| LuaError::runtime(&name, err))?;
+        Ok(())
+    }
+
+    /// Evaluate the chunk as an expression (or, failing that, as a list of
+    /// statements) and convert the returned values
+    pub fn eval<R: FromLuaMulti>(self) -> Result<R, LuaError> {
+        let name = self.chunk_name();
+        let expr = format!("return {}", self.source);
+        let ast = match parser::parse_chunk(&expr) {
+            Ok(ast) => ast,
+            Err(_) => {
+                parser::parse_chunk(&self.source).map_err(|err| LuaError::syntax(&name, err))?
+            }
+        };
+        let source = self.source_name();
+        let vals = self
+            .lua
+            .run(ast, &source)
+            .map_err(|err| LuaError::runtime(&name, err))?;
+        R::from_lua_multi(vals)
+    }
+
+    // Name of the chunk for the debug interface, which like Lua's starts
+    // with '=' for a chosen name and is otherwise the text of the chunk
+    fn source_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("={name}"),
+            None => self.source.clone(),
+        }
+    }
+
+    fn chunk_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => chunk_id(&self.source),
+        }
+    }
+}
+
+/// Table of a module implemented in Rust, see `Lua::register_module`
+pub struct Module<'env> {
+    table: LuaTable,
+    env: &'env mut Env,
+}
+
+impl Module<'_> {
+    pub fn set<T: IntoLua>(&mut self, name: &str, val: T) {
+        let val = val.into_lua(self.env);
+        self.table.insert_ident(name, val);
+    }
+
+    /// Add a function to the module. Arguments and return values are
+    /// converted like those of `Lua::call_function`.
+    pub fn set_function<A, R, F>(&mut self, name: &str, func: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(A) -> Result<R, LuaError> + 'static,
+    {
+        let func = RustFunction::new(move |args, env| {
+            let args = A::from_lua_multi(args).map_err(LuaError::into_exec_error)?;
+            let vals = func(args).map_err(LuaError::into_exec_error)?;
+            Ok(vals.into_lua_multi(env))
+        });
+        self.table
+            .insert_ident(name, LuaValue::new(LuaVal::RustFunction(func)));
+    }
+}
+
+/// Handle to the global environment of a `Lua` state
+pub struct Globals<'lua> {
+    lua: &'lua mut Lua,
+}
+
+impl<'lua> Globals<'lua> {
+    pub fn get<T: FromLua>(&self, name: &str) -> Result<T, LuaError> {
+        match self.lua.env.get_global(name) {
+            Some(val) => T::from_lua(val),
+            None => T::from_lua(LuaValue::new(LuaVal::LuaNil)),
+        }
+    }
+
+    pub fn set<T: IntoLua>(&mut self, name: &str, val: T) {
+        let val = val.into_lua(&mut self.lua.env);
+        self.lua.env.insert_global(name.to_string(), val);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        match self.lua.env.get_global(name) {
+            Some(val) => !val.is_nil(),
+            None => false,
+        }
+    }
+}
+
+/// Conversion from a Lua value into a Rust value
+pub trait FromLua: Sized {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError>;
+}
+
+/// Conversion from a Rust value into a Lua value, whose tables are allocated in `env`
+pub trait IntoLua {
+    fn into_lua(self, env: &mut Env) -> LuaValue;
+}
+
+/// Conversion from the list of values returned by a chunk or function
+pub trait FromLuaMulti: Sized {
+    fn from_lua_multi(vals: Vec<LuaValue>) -> Result<Self, LuaError>;
+}
+
+/// Conversion into a list of arguments
+pub trait IntoLuaMulti {
+    fn into_lua_multi(self, env: &mut Env) -> Vec<LuaValue>;
+}
+
+fn conversion_error(val: &LuaValue, to: &str) -> LuaError {
+    LuaError::Conversion(format!(
+        "cannot convert a {} value to {to}",
+        val.type_name()
+    ))
+}
+
+impl FromLua for bool {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        Ok(val.is_true())
+    }
+}
+
+impl FromLua for i64 {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        if !val.is_numeral() {
+            return Err(conversion_error(&val, "i64"));
+        }
+        val.into_int()
+            .map_err(|err| LuaError::Conversion(err.to_string()))
+    }
+}
+
+impl FromLua for f64 {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        match &val.0 {
+            LuaVal::LuaFloat(n) => Ok(*n),
+            LuaVal::LuaInt(n) => Ok(*n as f64),
+            _ => Err(conversion_error(&val, "f64")),
+        }
+    }
+}
+
+impl FromLua for String {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        if !val.is_string() && !val.is_numeral() {
+            return Err(conversion_error(&val, "String"));
+        }
+        val.into_string()
+            .map(|s| s.to_string())
+            .map_err(|err| LuaError::Conversion(err.to_string()))
+    }
+}
+
+impl<T: FromLua> FromLua for Option<T> {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        if val.is_nil() {
+            Ok(None)
+        } else {
+            T::from_lua(val).map(Some)
+        }
+    }
+}
+
+impl<T: FromLua> FromLua for Vec<T> {
+    fn from_lua(val: LuaValue) -> Result<Self, LuaError> {
+        match &val.0 {
+            LuaVal::LuaTable(table) => {
+                let len = table.calculate_border() as i64;
+                let mut vec = Vec::with_capacity(len as usize);
+                for i in 1..=len {
+                    let elem = table
+                        .get(TableKey::Number(i))
+                        .unwrap_or_else(|| LuaValue::new(LuaVal::LuaNil));
+                    vec.push(T::from_lua(elem)?);
+                }
+                Ok(vec)
+            }
+            _ => Err(conversion_error(&val, "Vec")),
+        }
+    }
+}
+
+impl IntoLua for bool {
+    fn into_lua(self, _env: &mut Env) -> LuaValue {
+        LuaValue::new(LuaVal::LuaBool(self))
+    }
+}
+
+impl IntoLua for i64 {
+    fn into_lua(self, _env: &mut Env) -> LuaValue {
+        LuaValue::new(LuaVal::LuaInt(self))
+    }
+}
+
+impl IntoLua for f64 {
+    fn into_lua(self, _env: &mut Env) -> LuaValue {
+        LuaValue::new(LuaVal::LuaFloat(self))
+    }
+}
+
+impl IntoLua for String {
+    fn into_lua(self, _env: &mut Env) -> LuaValue {
+        LuaValue::new(LuaVal::LuaString(LuaString::from(self)))
+    }
+}
+
+impl IntoLua for &str {
+    fn into_lua(self, _env: &mut Env) -> LuaValue {
+        LuaValue::new(LuaVal::LuaString(LuaString::from(self)))
+    }
+}
+
+impl<T: IntoLua> IntoLua for Option<T> {
+    fn into_lua(self, env: &mut Env) -> LuaValue {
+        match self {
+            Some(val) => val.into_lua(env),
+            None => LuaValue::new(LuaVal::LuaNil),
+        }
+    }
+}
+
+impl<T: IntoLua> IntoLua for Vec<T> {
+    fn into_lua(self, env: &mut Env) -> LuaValue {
+        let table = LuaTable::new();
+        for (i, val) in self.into_iter().enumerate() {
+            table.insert_int(i as i64 + 1, val.into_lua(env));
+        }
+        env.alloc_table(table)
+    }
+}
+
+impl FromLuaMulti for () {
+    fn from_lua_multi(_vals: Vec<LuaValue>) -> Result<Self, LuaError> {
+        Ok(())
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for T {
+    fn from_lua_multi(vals: Vec<LuaValue>) -> Result<Self, LuaError> {
+        T::from_lua(LuaValue::extract_first_return_val(vals))
+    }
+}
+
+impl IntoLuaMulti for () {
+    fn into_lua_multi(self, _env: &mut Env) -> Vec<LuaValue> {
+        vec![]
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for T {
+    fn into_lua_multi(self, env: &mut Env) -> Vec<LuaValue> {
+        vec![self.into_lua(env)]
+    }
+}
+
+macro_rules! impl_tuple_multi {
+    ($($name:ident),+) => {
+        impl<$($name: FromLua),+> FromLuaMulti for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn from_lua_multi(vals: Vec<LuaValue>) -> Result<Self, LuaError> {
+                let mut vals = vals.into_iter();
+                $(
+                    let $name = $name::from_lua(
+                        vals.next().unwrap_or_else(|| LuaValue::new(LuaVal::LuaNil)),
+                    )?;
+                )+
+                Ok(($($name,)+))
+            }
+        }
+
+        impl<$($name: IntoLua),+> IntoLuaMulti for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_lua_multi(self, env: &mut Env) -> Vec<LuaValue> {
+                let ($($name,)+) = self;
+                vec![$($name.into_lua(env)),+]
+            }
+        }
+    };
+}
+
+impl_tuple_multi!(A);
+impl_tuple_multi!(A, B);
+impl_tuple_multi!(A, B, C);
+impl_tuple_multi!(A, B, C, D);
+impl_tuple_multi!(A, B, C, D, E);
+
+/// Errors reported by the embedding API
+#[derive(Debug, PartialEq)]
+pub enum LuaError {
+    /// The chunk could not be parsed
+    Syntax(String),
+    /// An error was raised while running Lua code
+    Runtime(String),
+    /// A value could not be converted between Lua and Rust
+    Conversion(String),
+    /// The script exceeded one of the limits set by `Lua::set_limits`,
+    /// or was interrupted by the host
+    Limit(Limit),
+}
+
+impl LuaError {
+    fn syntax(chunk_name: &str, err: ASTParseError) -> Self {
+        LuaError::Syntax(format!("{chunk_name}: {err}"))
+    }
+
+    fn runtime(chunk_name: &str, err: ASTExecError) -> Self {
+        match err.limit() {
+            Some(limit) => LuaError::Limit(limit),
+            None => LuaError::Runtime(format!("{chunk_name}: {err}")),
+        }
+    }
+
+    // Error raised in Lua code by a Rust function
+    fn into_exec_error(self) -> ASTExecError {
+        match self {
+            LuaError::Syntax(msg) | LuaError::Runtime(msg) | LuaError::Conversion(msg) => {
+                ASTExecError::new(&msg)
+            }
+            LuaError::Limit(limit) => ASTExecError::limit_exceeded(limit),
+        }
+    }
+}
+
+impl Display for LuaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            LuaError::Syntax(msg) => write!(f, "syntax error: {msg}"),
+            LuaError::Runtime(msg) => write!(f, "runtime error: {msg}"),
+            LuaError::Conversion(msg) => write!(f, "conversion error: {msg}"),
+            LuaError::Limit(limit) => write!(f, "runtime error: {limit}"),
+        }
+    }
+}
+
+impl std::error::Error for LuaError {}
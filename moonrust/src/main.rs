@@ -1,37 +1,364 @@
-use clap::Parser;
-use moonrust::interpreter::environment;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use moonrust::interpreter::environment::Env;
+use moonrust::interpreter::{LuaString, LuaTable, LuaVal, LuaValue};
+use moonrust::{lint, parser};
 use std::fs;
+use std::io::{self, IsTerminal, Read};
 use std::process;
 use std::time::Instant;
 
+mod repl;
+
 #[derive(Parser, Debug)]
-#[command(about, long_about = None)]
+#[command(
+    about,
+    long_about = None,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true
+)]
 struct Args {
-    /// Path of the file to run
-    #[clap(value_name = "FILE.lua")]
-    file: String,
-    /// AST print flag
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Script to run (- for the standard input) followed by its arguments;
+    /// without it, start the interactive mode
+    #[clap(
+        value_name = "FILE.lua",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    script: Vec<String>,
+    /// Execute the string STAT
+    #[arg(short, value_name = "STAT")]
+    execute: Vec<String>,
+    /// Require the module MOD into the global MOD, or into the global G with G=MOD
+    #[arg(short = 'l', value_name = "MOD")]
+    library: Vec<String>,
+    /// Enter the interactive mode after running the file
+    #[arg(short, long)]
+    interactive: bool,
+    /// Show version information
+    #[arg(short, long)]
+    version: bool,
+    /// Ignore the LUA_INIT environment variable
+    #[arg(short = 'E')]
+    ignore_env: bool,
+    /// Print the AST, in the Debug form or as JSON (which does not run the script)
+    #[arg(
+        short,
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "debug"
+    )]
+    ast: Option<AstFormat>,
+    /// Read the script as an AST in JSON, as printed by --ast=json
+    #[arg(long)]
+    from_json: bool,
+    /// Fold constant expressions and remove dead code before running the script
+    #[arg(long)]
+    opt: bool,
+    /// Bytecode print flag
     #[arg(short, long)]
-    ast: bool,
+    bytecode: bool,
     /// Report time statistics
     #[clap(short, long)]
     stats: bool,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum AstFormat {
+    Debug,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Format Lua files, printing them unless --write or --check is given
+    Fmt {
+        /// Files to format, or - for the standard input
+        #[clap(value_name = "FILE.lua", required = true)]
+        files: Vec<String>,
+        /// Write the formatted files back in place
+        #[arg(short, long)]
+        write: bool,
+        /// Fail if a file is not formatted, without changing it
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+    },
+    /// Report common bugs in Lua files without running them
+    Check {
+        /// Files to check, or - for the standard input
+        #[clap(value_name = "FILE.lua", required = true)]
+        files: Vec<String>,
+    },
+}
+
+impl Args {
+    fn file(&self) -> Option<&String> {
+        self.script.first()
+    }
+
+    fn script_args(&self) -> &[String] {
+        self.script.get(1..).unwrap_or_default()
+    }
+}
+
+/// Option of the command line running Lua code before the script
+enum Action<'a> {
+    Execute(&'a str),
+    Require(&'a str),
+}
+
 fn main() {
-    let args = Args::parse();
-    let file = &args.file;
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    match &args.command {
+        Some(Command::Fmt {
+            files,
+            write,
+            check,
+        }) => {
+            format_files(files, *write, *check);
+            return;
+        }
+        Some(Command::Check { files }) => {
+            check_files(files);
+            return;
+        }
+        None => (),
+    }
+
+    let mut env = Env::new();
+    let arg = arg_table(std::env::args().collect(), &args, &mut env);
+    env.insert_global(String::from("arg"), arg);
+    if args.version || args.interactive {
+        print_version();
+    }
+    if !args.ignore_env {
+        run_lua_init(&mut env);
+    }
+    for action in actions(&args, &matches) {
+        match action {
+            Action::Execute(stat) => run_string(stat, &mut env),
+            Action::Require(module) => require(module, &mut env),
+        }
+    }
+
+    match args.file() {
+        Some(file) => run_script(file, &args, &mut env),
+        None if args.interactive || !args.execute.is_empty() || args.version => (),
+        None if io::stdin().is_terminal() => {
+            print_version();
+            repl::run(env);
+            return;
+        }
+        None => run_script("-", &args, &mut env),
+    }
+
+    if args.interactive {
+        repl::run(env);
+    }
+}
+
+fn print_version() {
+    println!("MoonRust {}", env!("CARGO_PKG_VERSION"));
+}
+
+// -e and -l options, in the order of the command line
+fn actions<'a>(args: &'a Args, matches: &ArgMatches) -> Vec<Action<'a>> {
+    let indices = |id| matches.indices_of(id).into_iter().flatten();
+    let mut actions: Vec<(usize, Action)> = indices("execute")
+        .zip(&args.execute)
+        .map(|(i, stat)| (i, Action::Execute(stat)))
+        .chain(
+            indices("library")
+                .zip(&args.library)
+                .map(|(i, module)| (i, Action::Require(module))),
+        )
+        .collect();
+    actions.sort_by_key(|(i, _)| *i);
+    actions.into_iter().map(|(_, action)| action).collect()
+}
+
+// Table of the command line arguments: the script at index 0, its arguments
+// at positive indices, and the interpreter and its options at negative ones.
+// Without a script, the interpreter is at index 0.
+fn arg_table(argv: Vec<String>, args: &Args, env: &mut Env) -> LuaValue {
+    let script = match args.file() {
+        Some(_) => argv.len() - args.script.len(),
+        None => 0,
+    };
+    let table = LuaTable::new();
+    for (i, arg) in argv.into_iter().enumerate() {
+        table.insert_int(
+            i as i64 - script as i64,
+            LuaValue::new(LuaVal::LuaString(LuaString::from(arg))),
+        );
+    }
+    env.alloc_table(table)
+}
+
+// Run the code in LUA_INIT_5_4 or LUA_INIT, or the file it names after an @
+fn run_lua_init(env: &mut Env) {
+    let init = ["LUA_INIT_5_4", "LUA_INIT"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok());
+    match init.as_deref().map(|init| (init, init.strip_prefix('@'))) {
+        Some((_, Some(file))) => {
+            let src = read_source(file);
+            run_string(&src, env);
+        }
+        Some((init, None)) => run_string(init, env),
+        None => (),
+    }
+}
+
+// require(module), stored in the global of the same name or the one before =
+fn require(module: &str, env: &mut Env) {
+    let (global, module) = module.split_once('=').unwrap_or((module, module));
+    let require = env
+        .get_global("require")
+        .unwrap_or_else(|| LuaValue::new(LuaVal::LuaNil));
+    let name = LuaValue::new(LuaVal::LuaString(LuaString::from(module.to_string())));
+    match require.call(vec![name], env) {
+        Ok(vals) => {
+            let val = vals
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| LuaValue::new(LuaVal::LuaNil));
+            env.insert_global(global.to_string(), val);
+        }
+        Err(err) => {
+            eprintln!("Runtime error [{err}]");
+            process::exit(1);
+        }
+    }
+}
+
+fn run_string(src: &str, env: &mut Env) {
+    let ast = match parser::parse_chunk(src) {
+        Ok(ast) => ast,
+        Err(ast_parse_error) => {
+            eprintln!("Parse error [{ast_parse_error}]");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = ast.exec_chunk(env, "=(command line)") {
+        eprintln!("Runtime error [{err}]");
+        process::exit(1);
+    }
+}
 
-    // Read file
-    let src: String = match fs::read_to_string(file) {
+// Read a file, or the standard input for -
+fn read_file(file: &str) -> String {
+    let src = if file == "-" {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src).map(|_| src)
+    } else {
+        fs::read_to_string(file)
+    };
+    match src {
         Ok(src) => src,
         Err(err) => {
             eprintln!("File read error [{file}; {err}]");
             process::exit(1);
         }
-    };
+    }
+}
+
+// Split the first line of a source off if it starts with # (e.g.
+// #!/usr/bin/env moonrust), so that it isn't parsed as Lua
+fn split_shebang(src: &str) -> (&str, &str) {
+    if src.starts_with('#') {
+        src.split_at(src.find('\n').unwrap_or(src.len()))
+    } else {
+        ("", src)
+    }
+}
+
+// Read the source of a chunk. The line of a shebang stays, empty, to keep the
+// line numbers.
+fn read_source(file: &str) -> String {
+    let src = read_file(file);
+    split_shebang(&src).1.to_string()
+}
+
+// Print each file formatted, write it back, or only check that it is formatted
+fn format_files(files: &[String], write: bool, check: bool) {
+    let mut unformatted = false;
+    for file in files {
+        let src = read_file(file);
+        let (shebang, chunk) = split_shebang(&src);
+        let formatted = match parser::format(chunk) {
+            Ok(formatted) if shebang.is_empty() => formatted,
+            Ok(formatted) => format!("{shebang}\n{formatted}"),
+            Err(parser::FormatError::Parse(ast_parse_error)) => {
+                eprintln!("Parse error [{file}; {ast_parse_error}]");
+                process::exit(1);
+            }
+            Err(format_error) => {
+                eprintln!("Format error [{file}; {format_error}]");
+                process::exit(1);
+            }
+        };
+        if check {
+            if formatted != src {
+                eprintln!("{file} is not formatted");
+                unformatted = true;
+            }
+        } else if write && file != "-" {
+            if formatted != src {
+                if let Err(err) = fs::write(file, formatted) {
+                    eprintln!("File write error [{file}; {err}]");
+                    process::exit(1);
+                }
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+// Print the warnings of the linter for each file, as file:line:col: code
+// message. Fails if there is any.
+fn check_files(files: &[String]) {
+    let globals = Env::new().global_names();
+    let mut failed = false;
+    for file in files {
+        let src = read_source(file);
+        match lint::check(&src, &globals) {
+            Ok(warnings) => {
+                for warning in &warnings {
+                    println!("{file}:{warning}");
+                }
+                failed |= !warnings.is_empty();
+            }
+            Err(ast_parse_error) => {
+                eprintln!("Parse error [{file}; {ast_parse_error}]");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn run_script(file: &str, args: &Args, env: &mut Env) {
+    let src = read_source(file);
 
-    let ast = match src.parse::<moonrust::AST>() {
+    let ast = if args.from_json {
+        moonrust::AST::from_json(&src)
+    } else {
+        parser::parse_chunk(&src)
+    };
+    let ast = match ast {
+        Ok(ast) if args.opt => ast.optimize(),
         Ok(ast) => ast,
         Err(ast_parse_error) => {
             eprintln!("Parse error [{ast_parse_error}]");
@@ -39,14 +366,42 @@ fn main() {
         }
     };
 
-    if args.ast {
-        println!("AST: {:#?}", ast);
+    match args.ast {
+        Some(AstFormat::Debug) => println!("AST: {:#?}", ast),
+        Some(AstFormat::Json) => {
+            // The dump is meant to be fed back with --from-json, so it is all
+            // that is printed
+            println!("{}", ast.to_json());
+            return;
+        }
+        None => (),
     }
 
-    // Execute the program
+    let chunk_name = if file == "-" {
+        String::from("=stdin")
+    } else {
+        format!("@{file}")
+    };
+
+    if args.bytecode {
+        match moonrust::compiler::compile(&ast, &chunk_name) {
+            Ok(proto) => print!("{proto}"),
+            Err(err) => {
+                eprintln!("Compile error [{err}]");
+                process::exit(1);
+            }
+        }
+    }
+
+    // Execute the program, with the arguments of the script as `...`
     let exec_start = Instant::now();
-    let mut env = environment::Env::new();
-    match ast.exec(&mut env) {
+    env.set_varargs(
+        args.script_args()
+            .iter()
+            .map(|arg| LuaValue::new(LuaVal::LuaString(LuaString::from(arg.clone()))))
+            .collect(),
+    );
+    match ast.exec_chunk(env, &chunk_name) {
         Ok(_) => (),
         Err(err) => {
             eprintln!("Runtime error [{err}]");
@@ -64,3 +419,44 @@ fn main() {
         println!("exec time   : {exec_time:>13.10} seconds");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> (Args, ArgMatches) {
+        let matches = Args::command().get_matches_from(argv);
+        (Args::from_arg_matches(&matches).unwrap(), matches)
+    }
+
+    #[test]
+    fn test_options_stop_at_the_script() {
+        let (args, matches) = parse(&[
+            "moonrust", "-l", "m", "-e", "x = 1", "-s", "f.lua", "-a", "--", "b",
+        ]);
+        assert!(args.stats && args.ast.is_none());
+        assert_eq!(args.file().map(String::as_str), Some("f.lua"));
+        assert_eq!(args.script_args(), ["-a", "--", "b"]);
+        assert!(matches!(
+            actions(&args, &matches)[..],
+            [Action::Require("m"), Action::Execute("x = 1")]
+        ));
+    }
+
+    #[test]
+    fn test_arg_table() {
+        let argv = ["moonrust", "-e", "x = 1", "--", "f.lua", "a"];
+        let (args, _) = parse(&argv);
+        let mut env = Env::new();
+        let arg = arg_table(argv.map(String::from).to_vec(), &args, &mut env);
+        env.insert_global(String::from("arg"), arg);
+        let ast = parser::parse_chunk("return arg[-4], arg[-1], arg[0], arg[1], arg[2]").unwrap();
+        let vals: Vec<String> = ast
+            .exec_with_return(&mut env)
+            .unwrap()
+            .iter()
+            .map(LuaValue::to_string)
+            .collect();
+        assert_eq!(vals, ["moonrust", "--", "f.lua", "a", "nil"]);
+    }
+}
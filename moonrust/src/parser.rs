@@ -1,20 +1,22 @@
 pub mod common;
 pub mod expression;
+pub mod lexer;
 pub mod statement;
 pub mod util;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
-use nom::{combinator::map, IResult};
+use nom::{combinator::map, error::ErrorKind, IResult};
 
 use crate::ast::*;
 use std::str::FromStr;
 
 use self::common::parse_block;
-use self::util::ws;
+use self::lexer::TokenKind;
+use self::util::{ws, Input};
 
 /// Just to simplify the return type of each parse function
-type ParseResult<'a, T> = IResult<&'a str, T>;
+type ParseResult<'a, T> = IResult<Input<'a>, T>;
 
 impl FromStr for AST {
     type Err = ASTParseError;
@@ -22,21 +24,126 @@ impl FromStr for AST {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match parse(s) {
             Ok(ast) => Ok(ast.1),
-            Err(e) => {
-                let msg = e.to_string();
-                Err(ASTParseError(format!("Could not parse file: {msg}")))
-            }
+            Err(e) => Err(ASTParseError(format!("Could not parse file: {}", error_message(e)))),
+        }
+    }
+}
+
+/// Parse the input program file into an AST, recording the spans of its
+/// statements and expressions.
+pub fn parse(input: &str) -> IResult<&str, AST> {
+    let tokens = util::token_spans(input);
+    let result = map(ws(parse_block), AST)(Input::new(input, &tokens));
+    // What is left, as a slice of the input rather than of the parser input
+    result
+        .map(|(rest, ast)| (&input[rest.offset()..], ast))
+        .map_err(|err| err.map_input(|rest| &input[rest.offset()..]))
+}
+
+/// Parse a whole chunk into an AST. Unlike `str::parse`, any input left over
+/// after the last statement is reported as an error.
+pub fn parse_chunk(input: &str) -> Result<AST, ASTParseError> {
+    match parse(input) {
+        Ok(("", ast)) => Ok(ast),
+        Ok((rest, _)) => {
+            let near: String = rest.chars().take(20).collect();
+            Err(ASTParseError(format!(
+                "Could not parse file: unexpected symbol near '{near}'"
+            )))
+        }
+        Err(e) => Err(ASTParseError(format!("Could not parse file: {}", error_message(e)))),
+    }
+}
+
+/// Format a chunk: parse it and print it back with consistent indentation,
+/// keeping its comments. The formatted chunk is checked to parse to the same
+/// AST and to have as many comments.
+pub fn format(input: &str) -> Result<String, FormatError> {
+    let ast = parse_chunk(input).map_err(FormatError::Parse)?;
+    let layout = layout_of(input);
+    let comments = layout.comments.len();
+    let formatted = ast.format(layout);
+    // The code moves around, so only the nodes are compared, not their spans
+    let same_code = parse_chunk(&formatted)
+        .is_ok_and(|reparsed| reparsed.without_spans() == ast.without_spans());
+    if same_code && layout_of(&formatted).comments.len() == comments {
+        Ok(formatted)
+    } else {
+        Err(FormatError::Changed)
+    }
+}
+
+// Comments and keyword positions of a source, for the formatter
+fn layout_of(input: &str) -> Layout {
+    let mut layout = Layout::default();
+    for token in lexer::tokenize(input) {
+        let pos = (token.line, token.col);
+        match (token.kind, token.text) {
+            (TokenKind::Comment, text) => layout.comments.push((pos, text.to_string())),
+            (TokenKind::Keyword, "else") => layout.elses.push(pos),
+            (TokenKind::Keyword, "return") => layout.returns.push(pos),
+            _ => (),
+        }
+    }
+    layout
+}
+
+/// Why a chunk could not be formatted
+#[derive(Debug, PartialEq)]
+pub enum FormatError {
+    Parse(ASTParseError),
+    /// The formatted chunk would not parse to the same AST or would lose a
+    /// comment, which is a bug of the formatter
+    Changed,
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            FormatError::Parse(err) => err.fmt(f),
+            FormatError::Changed => write!(
+                f,
+                "Could not format file: the formatted chunk doesn't parse to the same AST and comments"
+            ),
         }
     }
 }
 
-/// Parse the input program file into an AST.
-pub fn parse(input: &str) -> ParseResult<AST> {
-    map(ws(parse_block), AST)(input)
+/// Whether the input ends inside a string, or inside a block, parenthesis,
+/// brace or bracket that is not closed yet, so that more input could turn it
+/// into a valid chunk. The interactive mode uses it to ask for more lines.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    for token in lexer::tokenize(input) {
+        match (token.kind, token.text) {
+            (TokenKind::Unfinished, _) => return true,
+            (TokenKind::Keyword, "function" | "if" | "do" | "repeat") => depth += 1,
+            (TokenKind::Keyword, "end" | "until") => depth -= 1,
+            (TokenKind::Symbol, "(" | "{" | "[") => depth += 1,
+            (TokenKind::Symbol, ")" | "}" | "]") => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+fn error_message(e: nom::Err<nom::error::Error<&str>>) -> String {
+    match e {
+        nom::Err::Failure(e) if e.code == ErrorKind::TooLarge => {
+            String::from("chunk has too many syntax levels")
+        }
+        e => e.to_string(),
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ASTParseError(String);
+
+impl ASTParseError {
+    pub(crate) fn new(msg: &str) -> Self {
+        ASTParseError(msg.to_string())
+    }
+}
 impl Display for ASTParseError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -49,6 +156,203 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn rejects_deep_nesting() {
+        let input = format!("x = {}1{}", "(".repeat(1000), ")".repeat(1000));
+
+        assert_eq!(
+            input.parse::<AST>(),
+            Err(ASTParseError(String::from(
+                "Could not parse file: chunk has too many syntax levels"
+            )))
+        );
+    }
+
+    #[test]
+    fn records_spans() {
+        let src =
+            "local x = 1 -- one\n\nfunction f(a)\n    return a +\n        x\nend\nprint( f(2) )";
+        let ast = parse_chunk(src).unwrap();
+        let position = |span: Span| (span.start, span.end);
+        let statements = &ast.0.statements;
+        assert_eq!(position(statements[0].span()), ((1, 1), (1, 11)));
+        assert_eq!(position(statements[1].span()), ((3, 1), (6, 3)));
+        let Statement::FunctionDecl((_, body), _) = &statements[1] else {
+            panic!("expected a function declaration");
+        };
+        assert_eq!(position(body.span), ((3, 1), (6, 3)));
+        let returned = &body.block.return_stat.as_ref().unwrap()[0];
+        assert_eq!(position(returned.span()), ((4, 12), (5, 9)));
+        assert_eq!(position(statements[2].span()), ((7, 1), (7, 13)));
+        // A line comment at the end of the chunk leaves a slice of the source
+        let ast = parse_chunk("x = 1 -- last").unwrap();
+        assert_eq!(position(ast.0.statements[0].span()), ((1, 1), (1, 5)));
+        // Without a source, as when parsing a part of a chunk, spans are unknown
+        let (_, exp) = expression::parse_exp("1 + 2".into()).unwrap();
+        assert_eq!(exp.span().line(), 0);
+    }
+
+    #[test]
+    fn formats_assets_idempotently() {
+        for entry in std::fs::read_dir("assets").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "lua") {
+                continue;
+            }
+            let src = std::fs::read_to_string(path).unwrap();
+            // Some assets use syntax the parser doesn't support
+            if parse_chunk(&src).is_err() {
+                continue;
+            }
+            let formatted = format(&src).unwrap();
+            assert_eq!(format(&formatted), Ok(formatted));
+        }
+    }
+
+    #[test]
+    fn formats_chunks() {
+        let input = "local a,b=1 ,\"q\\\"\\n\" if a then while b do f{1} end elseif b then return else end
+        return (a+b)*-c,2^-1 ,- - a,not a==b,1.0,{x=1,[2]=function(...) end}";
+        let expected = "local a, b = 1, \"q\\\"\\n\"
+if a then
+    while b do
+        f{1}
+    end
+elseif b then
+    return
+else
+end
+return (a + b) * -c, 2 ^ -1, - -a, not a == b, 1.0, {
+    x = 1,
+    [2] = function(...)
+    end
+}
+";
+        assert_eq!(format(input), Ok(String::from(expected)));
+    }
+
+    #[test]
+    fn formats_semicolons_and_table_functions() {
+        let input = "local f = g ; (f or g)() ; x = 1 ;
+t = {n = 0, inc = function(self) -- increment
+self.n = self.n + 1 end, {function() return { function() end } end}}
+;(t.inc)(t)";
+        let expected = "local f = g
+;(f or g)();
+x = 1;
+t = {
+    n = 0,
+    inc = function(self)
+        -- increment
+        self.n = self.n + 1
+    end,
+    {
+        function()
+            return {
+                function()
+                end
+            }
+        end
+    }
+}
+;(t.inc)(t)
+";
+        let formatted = format(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), Ok(formatted));
+    }
+
+    #[test]
+    fn formats_operators_with_needed_parentheses() {
+        let int = |i| Box::new(Expression::Numeral(Numeral::Integer(i), Span::default()));
+        let binop = |a, op, b| Box::new(Expression::BinaryOp((a, op, b), Span::default()));
+        let exp = Expression::BinaryOp(
+            (
+                binop(int(1), BinOp::Add, int(2)),
+                BinOp::Mult,
+                binop(int(3), BinOp::Mult, int(4)),
+            ),
+            Span::default(),
+        );
+        assert_eq!(exp.to_string(), "(1 + 2) * (3 * 4)");
+        let exp = Expression::BinaryOp(
+            (
+                binop(int(1), BinOp::Pow, int(2)),
+                BinOp::Pow,
+                Box::new(Expression::UnaryOp(
+                    (UnOp::Negate, int(-3)),
+                    Span::default(),
+                )),
+            ),
+            Span::default(),
+        );
+        assert_eq!(exp.to_string(), "(1 ^ 2) ^ (- -3)");
+    }
+
+    #[test]
+    fn detects_incomplete_chunks() {
+        assert!(is_incomplete("function f()"));
+        assert!(is_incomplete("for i = 1, 3 do\n  if i > 1 then"));
+        assert!(is_incomplete("repeat x = x + 1"));
+        assert!(is_incomplete("t = {1, 2,"));
+        assert!(is_incomplete("s = \"unclosed"));
+        assert!(is_incomplete("s = \"escaped quote \\\""));
+        assert!(!is_incomplete("while x do x = false end"));
+        assert!(!is_incomplete("s = \"end do\""));
+        assert!(!is_incomplete("x = = 1"));
+        assert!(!is_incomplete("x = 1 end"));
+        assert!(is_incomplete("--[[ unclosed"));
+        assert!(!is_incomplete("x = 1 -- do function"));
+    }
+
+    #[test]
+    fn skips_comments() {
+        let input = "-- first\nx = 1 --[[ between\n]] + 2 --[==[ ]] ]==]\nreturn x--last";
+        let code = |src| parse_chunk(src).map(AST::without_spans);
+        assert_eq!(code(input), code("x = 1 + 2 return x"));
+        assert_eq!(code("x = 1 - -2"), code("x = 1- - 2"));
+        assert_eq!(code("x = 1 --2"), code("x = 1"));
+        assert!(parse_chunk("x = 1 --[[ unclosed").is_err());
+        assert_eq!(format("x = 1 -- note"), Ok(String::from("x = 1 -- note\n")));
+    }
+
+    #[test]
+    fn formats_comments() {
+        let input = "-- header
+local t = { -- fields
+  a = 1 }
+  --[[ long
+       comment ]]
+function f(a) -- start
+  if a then return -- early
+  -- before else
+  else
+    x = 1 ; -- after semicolon
+  end
+  -- end of body
+end -- f
+-- last";
+        let expected = "-- header
+local t = {a = 1} -- fields
+--[[ long
+     comment ]]
+function f(a)
+    -- start
+    if a then
+        return -- early
+        -- before else
+    else
+        x = 1; -- after semicolon
+    end
+    -- end of body
+end -- f
+-- last
+";
+        let formatted = format(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), Ok(formatted));
+    }
+
     #[test]
     fn accepts_ast() {
         let input = "
@@ -57,7 +361,7 @@ mod tests {
         a = 3 + 5 + 10.0
         ";
 
-        let result = parse(input);
+        let result = parse(input).map(|(rest, ast)| (rest, ast.without_spans()));
 
         assert_eq!(
             result,
@@ -65,45 +369,99 @@ mod tests {
                 "",
                 AST(Block {
                     statements: vec![
-                        Statement::Assignment((
-                            vec![Var::Name(String::from("a"))],
-                            vec![Expression::BinaryOp((
-                                Box::new(Expression::BinaryOp((
-                                    Box::new(Expression::Numeral(Numeral::Integer(3))),
-                                    BinOp::Add,
-                                    Box::new(Expression::Numeral(Numeral::Integer(5)))
-                                ))),
-                                BinOp::Add,
-                                Box::new(Expression::Numeral(Numeral::Float(10.0)))
-                            ))],
-                            false
-                        )),
-                        Statement::Assignment((
-                            vec![Var::Name(String::from("a"))],
-                            vec![Expression::BinaryOp((
-                                Box::new(Expression::BinaryOp((
-                                    Box::new(Expression::Numeral(Numeral::Integer(3))),
-                                    BinOp::Add,
-                                    Box::new(Expression::Numeral(Numeral::Integer(5)))
-                                ))),
-                                BinOp::Add,
-                                Box::new(Expression::Numeral(Numeral::Float(10.0)))
-                            ))],
-                            false
-                        )),
-                        Statement::Assignment((
-                            vec![Var::Name(String::from("a"))],
-                            vec![Expression::BinaryOp((
-                                Box::new(Expression::BinaryOp((
-                                    Box::new(Expression::Numeral(Numeral::Integer(3))),
-                                    BinOp::Add,
-                                    Box::new(Expression::Numeral(Numeral::Integer(5)))
-                                ))),
-                                BinOp::Add,
-                                Box::new(Expression::Numeral(Numeral::Float(10.0)))
-                            ))],
-                            false
-                        ))
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("a"))],
+                                vec![Expression::BinaryOp(
+                                    (
+                                        Box::new(Expression::BinaryOp(
+                                            (
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(3),
+                                                    Span::default()
+                                                )),
+                                                BinOp::Add,
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(5),
+                                                    Span::default()
+                                                ))
+                                            ),
+                                            Span::default()
+                                        )),
+                                        BinOp::Add,
+                                        Box::new(Expression::Numeral(
+                                            Numeral::Float(10.0),
+                                            Span::default()
+                                        ))
+                                    ),
+                                    Span::default()
+                                )],
+                                false
+                            ),
+                            Span::default()
+                        ),
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("a"))],
+                                vec![Expression::BinaryOp(
+                                    (
+                                        Box::new(Expression::BinaryOp(
+                                            (
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(3),
+                                                    Span::default()
+                                                )),
+                                                BinOp::Add,
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(5),
+                                                    Span::default()
+                                                ))
+                                            ),
+                                            Span::default()
+                                        )),
+                                        BinOp::Add,
+                                        Box::new(Expression::Numeral(
+                                            Numeral::Float(10.0),
+                                            Span::default()
+                                        ))
+                                    ),
+                                    Span::default()
+                                )],
+                                false
+                            ),
+                            Span::default()
+                        ),
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("a"))],
+                                vec![Expression::BinaryOp(
+                                    (
+                                        Box::new(Expression::BinaryOp(
+                                            (
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(3),
+                                                    Span::default()
+                                                )),
+                                                BinOp::Add,
+                                                Box::new(Expression::Numeral(
+                                                    Numeral::Integer(5),
+                                                    Span::default()
+                                                ))
+                                            ),
+                                            Span::default()
+                                        )),
+                                        BinOp::Add,
+                                        Box::new(Expression::Numeral(
+                                            Numeral::Float(10.0),
+                                            Span::default()
+                                        ))
+                                    ),
+                                    Span::default()
+                                )],
+                                false
+                            ),
+                            Span::default()
+                        )
                     ],
                     return_stat: None
                 })
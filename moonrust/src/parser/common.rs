@@ -12,30 +12,33 @@ use crate::ast::{Args, Block, Expression, Field, FunctionCall, ParList, PrefixEx
 use super::{
     expression::parse_exp,
     statement::{parse_return, parse_stmt},
-    util::{identifier, parse_string, ws},
+    util::{identifier, nested, parse_string, spanned, ws, Input},
     ParseResult,
 };
 
 /// Parse a block. A block is zero or more statements followed by an
 /// optional return statement.
-pub fn parse_block(input: &str) -> ParseResult<Block> {
-    map(
-        pair(many0(parse_stmt), opt(parse_return)),
-        |(statements, return_stat)| Block {
-            statements,
-            return_stat,
-        },
-    )(input)
+pub fn parse_block(input: Input<'_>) -> ParseResult<'_, Block> {
+    nested(
+        input,
+        map(
+            pair(many0(parse_stmt), opt(parse_return)),
+            |(statements, return_stat)| Block {
+                statements,
+                return_stat,
+            },
+        ),
+    )
 }
 
 // use for explist!
-fn parse_namelist(input: &str) -> ParseResult<Vec<String>> {
+fn parse_namelist(input: Input<'_>) -> ParseResult<'_, Vec<String>> {
     map(separated_list1(ws(tag(",")), identifier), |result| {
         result.into_iter().map(String::from).collect()
     })(input)
 }
 
-pub fn parse_parlist(input: &str) -> ParseResult<ParList> {
+pub fn parse_parlist(input: Input<'_>) -> ParseResult<'_, ParList> {
     alt((
         map(
             pair(
@@ -48,7 +51,7 @@ pub fn parse_parlist(input: &str) -> ParseResult<ParList> {
     ))(input)
 }
 
-pub fn parse_var(input: &str) -> ParseResult<Var> {
+pub fn parse_var(input: Input<'_>) -> ParseResult<'_, Var> {
     alt((
         map(identifier, |result| Var::Name(String::from(result))),
         map(
@@ -65,21 +68,18 @@ pub fn parse_var(input: &str) -> ParseResult<Var> {
     ))(input)
 }
 
-pub fn parse_table_constructor(input: &str) -> ParseResult<Vec<Field>> {
+pub fn parse_table_constructor(input: Input<'_>) -> ParseResult<'_, Vec<Field>> {
     map(
         delimited(ws(char('{')), opt(parse_fieldlist), ws(char('}'))),
-        |result| match result {
-            Some(fields) => fields,
-            None => Vec::new(),
-        },
+        |result| result.unwrap_or_default(),
     )(input)
 }
 
-fn parse_fieldlist(input: &str) -> ParseResult<Vec<Field>> {
+fn parse_fieldlist(input: Input<'_>) -> ParseResult<'_, Vec<Field>> {
     separated_list1(ws(alt((char(','), char(';')))), parse_field)(input)
 }
 
-fn parse_field(input: &str) -> ParseResult<Field> {
+fn parse_field(input: Input<'_>) -> ParseResult<'_, Field> {
     alt((
         map(
             separated_pair(
@@ -113,7 +113,7 @@ enum Tail {
     PossibleMethod((Option<String>, Args)),
 }
 
-fn parse_tail(input: &str) -> ParseResult<Tail> {
+fn parse_tail(input: Input<'_>) -> ParseResult<'_, Tail> {
     alt((
         map(
             delimited(ws(char('[')), parse_exp, ws(char(']'))),
@@ -129,7 +129,7 @@ fn parse_tail(input: &str) -> ParseResult<Tail> {
     ))(input)
 }
 
-fn parse_prefix_part(input: &str) -> ParseResult<PrefixPart> {
+fn parse_prefix_part(input: Input<'_>) -> ParseResult<'_, PrefixPart> {
     alt((
         map(pair(ws(identifier), many0(parse_tail)), |result| {
             PrefixPart::NamePart((String::from(result.0), result.1))
@@ -141,7 +141,7 @@ fn parse_prefix_part(input: &str) -> ParseResult<PrefixPart> {
     ))(input)
 }
 
-fn parse_prefix_temp(input: &str) -> ParseResult<PrefixTemp> {
+fn parse_prefix_temp(input: Input<'_>) -> ParseResult<'_, PrefixTemp> {
     map(pair(parse_prefix_part, many0(parse_args)), |result| {
         PrefixTemp(result.0, result.1)
     })(input)
@@ -202,11 +202,11 @@ fn convert_to_prefixexp(prefix_temp: PrefixTemp) -> PrefixExp {
 }
 
 /// prefixexp ::= (Name {'[' exp ']' | `.` Name | [`:` Name] args} | `(` exp `)`) {args}
-pub fn parse_prefixexp(input: &str) -> ParseResult<PrefixExp> {
+pub fn parse_prefixexp(input: Input<'_>) -> ParseResult<'_, PrefixExp> {
     map(parse_prefix_temp, convert_to_prefixexp)(input)
 }
 
-pub fn parse_args(input: &str) -> ParseResult<Args> {
+pub fn parse_args(input: Input<'_>) -> ParseResult<'_, Args> {
     alt((
         map(
             delimited(
@@ -223,7 +223,8 @@ pub fn parse_args(input: &str) -> ParseResult<Args> {
     ))(input)
 }
 
-pub fn parse_funcbody(input: &str) -> ParseResult<(ParList, Block)> {
+/// Parse the parameters and the block of a function, up to its `end`
+pub fn parse_funcbody(input: Input<'_>) -> ParseResult<'_, (ParList, Block)> {
     terminated(
         pair(
             delimited(
@@ -240,12 +241,16 @@ pub fn parse_funcbody(input: &str) -> ParseResult<(ParList, Block)> {
     )(input)
 }
 
-pub fn parse_dot_dot_dot(input: &str) -> ParseResult<Expression> {
+pub fn parse_dot_dot_dot(input: Input<'_>) -> ParseResult<'_, Expression> {
     // DotDotDot, // Used for a variable number of arguments in things like functions
-    map(ws(tag("...")), |_| Expression::DotDotDot)(input)
+    map(spanned(ws(tag("..."))), |(_, span)| {
+        Expression::DotDotDot(span)
+    })(input)
 }
 
-pub fn parse_literal_string(input: &str) -> ParseResult<Expression> {
+pub fn parse_literal_string(input: Input<'_>) -> ParseResult<'_, Expression> {
     // Skipping string literals that aren't in double quotes for now
-    map(ws(parse_string), Expression::LiteralString)(input)
+    map(spanned(ws(parse_string)), |(string, span)| {
+        Expression::LiteralString(string, span)
+    })(input)
 }
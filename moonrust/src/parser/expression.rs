@@ -17,13 +17,13 @@ use super::{
     util::*,
     ParseResult,
 };
-use crate::ast::{BinOp, Expression, Numeral, UnOp};
+use crate::ast::{BinOp, Expression, FuncBody, Numeral, UnOp};
 
-pub fn parse_exp(input: &str) -> ParseResult<Expression> {
-    parse_or_exp(input)
+pub fn parse_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
+    nested(input, parse_or_exp)
 }
 
-fn parse_or_exp(input: &str) -> ParseResult<Expression> {
+fn parse_or_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
     map(
         pair(
             parse_and_exp,
@@ -36,7 +36,7 @@ fn parse_or_exp(input: &str) -> ParseResult<Expression> {
     )(input)
 }
 
-fn parse_and_exp(input: &str) -> ParseResult<Expression> {
+fn parse_and_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
     map(
         pair(
             parse_rel_exp,
@@ -49,8 +49,8 @@ fn parse_and_exp(input: &str) -> ParseResult<Expression> {
     )(input)
 }
 
-fn parse_rel_exp(input: &str) -> ParseResult<Expression> {
-    fn parse_rel_op(input: &str) -> ParseResult<BinOp> {
+fn parse_rel_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
+    fn parse_rel_op(input: Input<'_>) -> ParseResult<'_, BinOp> {
         ws(alt((
             map(tag("<="), |_| BinOp::LessEq),
             map(tag(">="), |_| BinOp::GreaterEq),
@@ -70,15 +70,15 @@ fn parse_rel_exp(input: &str) -> ParseResult<Expression> {
     )(input)
 }
 
-fn parse_concat_expr(input: &str) -> ParseResult<Expression> {
+fn parse_concat_expr(input: Input<'_>) -> ParseResult<'_, Expression> {
     map(
         pair(parse_add_exp, many0(preceded(ws(tag("..")), parse_add_exp))),
         |result| foldr_op_exp(result.0, BinOp::Concat, result.1),
     )(input)
 }
 
-fn parse_add_exp(input: &str) -> ParseResult<Expression> {
-    fn parse_add_op(input: &str) -> ParseResult<BinOp> {
+fn parse_add_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
+    fn parse_add_op(input: Input<'_>) -> ParseResult<'_, BinOp> {
         ws(alt((
             map(char('+'), |_| BinOp::Add),
             map(char('-'), |_| BinOp::Sub),
@@ -91,8 +91,8 @@ fn parse_add_exp(input: &str) -> ParseResult<Expression> {
     )(input)
 }
 
-fn parse_mult_exp(input: &str) -> ParseResult<Expression> {
-    fn parse_mult_op(input: &str) -> ParseResult<BinOp> {
+fn parse_mult_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
+    fn parse_mult_op(input: Input<'_>) -> ParseResult<'_, BinOp> {
         ws(alt((
             map(char('*'), |_| BinOp::Mult),
             map(tag("//"), |_| BinOp::IntegerDiv),
@@ -107,32 +107,41 @@ fn parse_mult_exp(input: &str) -> ParseResult<Expression> {
     )(input)
 }
 
-fn parse_unary_exp(input: &str) -> ParseResult<Expression> {
+fn parse_unary_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
     alt((
-        map(preceded(ws(char('-')), parse_unary_exp), |result| {
-            Expression::UnaryOp((UnOp::Negate, Box::new(result)))
-        }),
-        map(preceded(ws(tag("not")), parse_unary_exp), |result| {
-            Expression::UnaryOp((UnOp::LogicalNot, Box::new(result)))
-        }),
-        map(preceded(ws(char('#')), parse_unary_exp), |result| {
-            Expression::UnaryOp((UnOp::Length, Box::new(result)))
-        }),
-        map(preceded(ws(char('~')), parse_unary_exp), |result| {
-            Expression::UnaryOp((UnOp::BitNot, Box::new(result)))
-        }),
+        map(
+            spanned(preceded(ws(char('-')), parse_operand)),
+            |(result, span)| Expression::UnaryOp((UnOp::Negate, Box::new(result)), span),
+        ),
+        map(
+            spanned(preceded(ws(tag("not")), parse_operand)),
+            |(result, span)| Expression::UnaryOp((UnOp::LogicalNot, Box::new(result)), span),
+        ),
+        map(
+            spanned(preceded(ws(char('#')), parse_operand)),
+            |(result, span)| Expression::UnaryOp((UnOp::Length, Box::new(result)), span),
+        ),
+        map(
+            spanned(preceded(ws(char('~')), parse_operand)),
+            |(result, span)| Expression::UnaryOp((UnOp::BitNot, Box::new(result)), span),
+        ),
         parse_pow_exp,
     ))(input)
 }
 
-fn parse_pow_exp(input: &str) -> ParseResult<Expression> {
+// The operand of a unary operator, one level deeper
+fn parse_operand(input: Input<'_>) -> ParseResult<'_, Expression> {
+    nested(input, parse_unary_exp)
+}
+
+fn parse_pow_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
     map(
         pair(parse_atom, many0(preceded(ws(char('^')), parse_atom))),
         |result| foldr_op_exp(result.0, BinOp::Pow, result.1),
     )(input)
 }
 
-fn parse_atom(input: &str) -> ParseResult<Expression> {
+fn parse_atom(input: Input<'_>) -> ParseResult<'_, Expression> {
     alt((
         parse_nil,
         parse_true,
@@ -142,49 +151,54 @@ fn parse_atom(input: &str) -> ParseResult<Expression> {
         parse_dot_dot_dot,
         parse_fn_def,
         parse_table_constructor_exp,
-        map(parse_prefixexp, |result| {
-            Expression::PrefixExp(Box::new(result))
+        map(spanned(parse_prefixexp), |(result, span)| {
+            Expression::PrefixExp(Box::new(result), span)
         }),
     ))(input)
 }
 
-fn parse_nil(input: &str) -> ParseResult<Expression> {
-    map(ws(tag("nil")), |_| Expression::Nil)(input)
+fn parse_nil(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(ws(tag("nil"))), |(_, span)| Expression::Nil(span))(input)
 }
 
-fn parse_false(input: &str) -> ParseResult<Expression> {
-    map(ws(tag("false")), |_| Expression::False)(input)
+fn parse_false(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(ws(tag("false"))), |(_, span)| {
+        Expression::False(span)
+    })(input)
 }
 
-fn parse_true(input: &str) -> ParseResult<Expression> {
-    map(ws(tag("true")), |_| Expression::True)(input)
+fn parse_true(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(ws(tag("true"))), |(_, span)| Expression::True(span))(input)
 }
 
-fn parse_numeral(input: &str) -> ParseResult<Expression> {
+fn parse_numeral(input: Input<'_>) -> ParseResult<'_, Expression> {
     alt((parse_float, parse_integer))(input)
 }
 
-fn parse_integer(input: &str) -> ParseResult<Expression> {
-    map(ws(i64), |numeral: i64| {
-        Expression::Numeral(Numeral::Integer(numeral))
+fn parse_integer(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(ws(i64)), |(numeral, span)| {
+        Expression::Numeral(Numeral::Integer(numeral), span)
     })(input)
 }
 
-fn parse_float(input: &str) -> ParseResult<Expression> {
-    map(ws(float), |result| {
-        Expression::Numeral(Numeral::Float(result.parse().unwrap()))
+fn parse_float(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(ws(float)), |(result, span)| {
+        Expression::Numeral(Numeral::Float(result.parse().unwrap()), span)
     })(input)
 }
 
-fn parse_fn_def(input: &str) -> ParseResult<Expression> {
-    map(preceded(ws(tag("function")), parse_funcbody), |result| {
-        Expression::FunctionDef(result)
-    })(input)
+fn parse_fn_def(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(
+        spanned(preceded(ws(tag("function")), parse_funcbody)),
+        |((par_list, block), span)| {
+            Expression::FunctionDef(FuncBody::new(par_list, block, span), span)
+        },
+    )(input)
 }
 
-fn parse_table_constructor_exp(input: &str) -> ParseResult<Expression> {
-    map(parse_table_constructor, |result| {
-        Expression::TableConstructor(result)
+fn parse_table_constructor_exp(input: Input<'_>) -> ParseResult<'_, Expression> {
+    map(spanned(parse_table_constructor), |(result, span)| {
+        Expression::TableConstructor(result, span)
     })(input)
 }
 
@@ -192,60 +206,89 @@ fn parse_table_constructor_exp(input: &str) -> ParseResult<Expression> {
 /// An initial expression must be given to start the fold.
 fn foldl_exp(init: Expression, op_and_exps: Vec<(BinOp, Expression)>) -> Expression {
     op_and_exps.into_iter().fold(init, |acc, op_and_exp| {
-        Expression::BinaryOp((Box::new(acc), op_and_exp.0, Box::new(op_and_exp.1)))
+        let span = acc.span().to(op_and_exp.1.span());
+        Expression::BinaryOp((Box::new(acc), op_and_exp.0, Box::new(op_and_exp.1)), span)
     })
 }
 
 /// Fold (in a right-associative manner) a list of expressions given an initial expression and a binary operator.
 fn foldr_op_exp(init: Expression, op: BinOp, exps: Vec<Expression>) -> Expression {
     iter::once(init)
-        .chain(exps.into_iter())
+        .chain(exps)
         .rfold(None, |acc, exp| match acc {
             None => Some(exp),
-            Some(acc_exp) => Some(Expression::BinaryOp((Box::new(exp), op, Box::new(acc_exp)))),
+            Some(acc_exp) => {
+                let span = exp.span().to(acc_exp.span());
+                Some(Expression::BinaryOp(
+                    (Box::new(exp), op, Box::new(acc_exp)),
+                    span,
+                ))
+            }
         })
         .unwrap() // We'll definitely have at least one element, so None is impossible
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
-    use crate::ast::{Args, Field, FunctionCall, PrefixExp, Var};
+    use nom::IResult;
+
+    use crate::ast::{Args, Field, FunctionCall, PrefixExp, Span, Var};
 
     use super::*;
 
+    fn parse_exp(input: &str) -> IResult<&str, Expression> {
+        parse_str(super::parse_exp, input)
+    }
+
     #[test]
     fn accepts_nil() {
         let result = parse_exp("nil");
-        assert_eq!(result, Ok(("", Expression::Nil)));
+        assert_eq!(result, Ok(("", Expression::Nil(Span::default()))));
 
         let result = parse_exp("    nil  ");
-        assert_eq!(result, Ok(("", Expression::Nil)));
+        assert_eq!(result, Ok(("", Expression::Nil(Span::default()))));
     }
 
     #[test]
     fn accepts_bools() {
         let result = parse_exp("true");
-        assert_eq!(result, Ok(("", Expression::True)));
+        assert_eq!(result, Ok(("", Expression::True(Span::default()))));
 
         let result = parse_exp("false");
-        assert_eq!(result, Ok(("", Expression::False)));
+        assert_eq!(result, Ok(("", Expression::False(Span::default()))));
 
         let result = parse_exp("    true  ");
-        assert_eq!(result, Ok(("", Expression::True)));
+        assert_eq!(result, Ok(("", Expression::True(Span::default()))));
     }
 
     #[test]
     fn accepts_numerals() {
         let result = parse_exp("123");
-        assert_eq!(result, Ok(("", Expression::Numeral(Numeral::Integer(123)))));
+        assert_eq!(
+            result,
+            Ok((
+                "",
+                Expression::Numeral(Numeral::Integer(123), Span::default())
+            ))
+        );
 
         let result = parse_exp("    1.23  ");
-        assert_eq!(result, Ok(("", Expression::Numeral(Numeral::Float(1.23)))));
+        assert_eq!(
+            result,
+            Ok((
+                "",
+                Expression::Numeral(Numeral::Float(1.23), Span::default())
+            ))
+        );
 
         let result = parse_exp("    6.02E23  ");
         assert_eq!(
             result,
-            Ok(("", Expression::Numeral(Numeral::Float(6.02E23))))
+            Ok((
+                "",
+                Expression::Numeral(Numeral::Float(6.02E23), Span::default())
+            ))
         );
     }
 
@@ -254,7 +297,10 @@ mod tests {
         let result = parse_exp("    \"Foo bar baz!\"     ");
         assert_eq!(
             result,
-            Ok(("", Expression::LiteralString(String::from("Foo bar baz!"))))
+            Ok((
+                "",
+                Expression::LiteralString(String::from("Foo bar baz!"), Span::default())
+            ))
         );
 
         let data = "\"tab:\\tafter tab, newline:\\nnew line, quote: \\\", emoji: \\u{1F602}, newline:\\nescaped whitespace: \\    abc\"";
@@ -262,14 +308,17 @@ mod tests {
         let result = parse_exp(data);
         assert_eq!(
             result,
-            Ok(("", Expression::LiteralString(String::from(expected))))
+            Ok((
+                "",
+                Expression::LiteralString(String::from(expected), Span::default())
+            ))
         );
     }
 
     #[test]
     fn accepts_dotdotdot() {
         let result = parse_exp("  ...  ");
-        assert_eq!(result, Ok(("", Expression::DotDotDot)));
+        assert_eq!(result, Ok(("", Expression::DotDotDot(Span::default()))));
     }
 
     #[test]
@@ -278,17 +327,20 @@ mod tests {
 
         let expected = Ok((
             "",
-            Expression::TableConstructor(vec![
-                Field::Name((
-                    String::from("red"),
-                    Expression::LiteralString(String::from("#ff0000")),
-                )),
-                Field::Bracketed((
-                    Expression::Numeral(Numeral::Integer(1)),
-                    Expression::Numeral(Numeral::Float(3.14159265)),
-                )),
-                Field::Unnamed(Expression::True),
-            ]),
+            Expression::TableConstructor(
+                vec![
+                    Field::Name((
+                        String::from("red"),
+                        Expression::LiteralString(String::from("#ff0000"), Span::default()),
+                    )),
+                    Field::Bracketed((
+                        Expression::Numeral(Numeral::Integer(1), Span::default()),
+                        Expression::Numeral(Numeral::Float(3.14159265), Span::default()),
+                    )),
+                    Field::Unnamed(Expression::True(Span::default())),
+                ],
+                Span::default(),
+            ),
         ));
 
         let actual = parse_exp(input);
@@ -322,11 +374,14 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::BinaryOp((
-                    Box::new(Expression::Numeral(Numeral::Integer(1))),
-                    BinOp::Add,
-                    Box::new(Expression::Numeral(Numeral::Integer(2)))
-                ))
+                Expression::BinaryOp(
+                    (
+                        Box::new(Expression::Numeral(Numeral::Integer(1), Span::default())),
+                        BinOp::Add,
+                        Box::new(Expression::Numeral(Numeral::Integer(2), Span::default()))
+                    ),
+                    Span::default()
+                )
             ))
         );
 
@@ -338,15 +393,21 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::BinaryOp((
-                    Box::new(Expression::Numeral(Numeral::Integer(2))),
-                    BinOp::Pow,
-                    Box::new(Expression::BinaryOp((
-                        Box::new(Expression::Numeral(Numeral::Integer(3))),
+                Expression::BinaryOp(
+                    (
+                        Box::new(Expression::Numeral(Numeral::Integer(2), Span::default())),
                         BinOp::Pow,
-                        Box::new(Expression::Numeral(Numeral::Integer(4)))
-                    )))
-                ))
+                        Box::new(Expression::BinaryOp(
+                            (
+                                Box::new(Expression::Numeral(Numeral::Integer(3), Span::default())),
+                                BinOp::Pow,
+                                Box::new(Expression::Numeral(Numeral::Integer(4), Span::default()))
+                            ),
+                            Span::default()
+                        ))
+                    ),
+                    Span::default()
+                )
             ))
         );
 
@@ -357,11 +418,17 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::PrefixExp(Box::new(PrefixExp::Exp(Expression::BinaryOp((
-                    Box::new(Expression::Numeral(Numeral::Integer(1))),
-                    BinOp::Add,
-                    Box::new(Expression::Numeral(Numeral::Integer(2))),
-                )))))
+                Expression::PrefixExp(
+                    Box::new(PrefixExp::Exp(Expression::BinaryOp(
+                        (
+                            Box::new(Expression::Numeral(Numeral::Integer(1), Span::default())),
+                            BinOp::Add,
+                            Box::new(Expression::Numeral(Numeral::Integer(2), Span::default())),
+                        ),
+                        Span::default()
+                    ))),
+                    Span::default()
+                )
             ))
         )
     }
@@ -375,15 +442,20 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::BinaryOp((
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(
-                        Var::Name(String::from("i"))
-                    )))),
-                    BinOp::LessEq,
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(
-                        Var::Name(String::from("j"))
-                    )))),
-                ))
+                Expression::BinaryOp(
+                    (
+                        Box::new(Expression::PrefixExp(
+                            Box::new(PrefixExp::Var(Var::Name(String::from("i")))),
+                            Span::default()
+                        )),
+                        BinOp::LessEq,
+                        Box::new(Expression::PrefixExp(
+                            Box::new(PrefixExp::Var(Var::Name(String::from("j")))),
+                            Span::default()
+                        )),
+                    ),
+                    Span::default()
+                )
             ))
         )
     }
@@ -398,13 +470,19 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::UnaryOp((
-                    UnOp::LogicalNot,
-                    Box::new(Expression::UnaryOp((
+                Expression::UnaryOp(
+                    (
                         UnOp::LogicalNot,
-                        Box::new(Expression::True)
-                    )))
-                ))
+                        Box::new(Expression::UnaryOp(
+                            (
+                                UnOp::LogicalNot,
+                                Box::new(Expression::True(Span::default()))
+                            ),
+                            Span::default()
+                        ))
+                    ),
+                    Span::default()
+                )
             ))
         );
 
@@ -415,10 +493,16 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::UnaryOp((
-                    UnOp::Length,
-                    Box::new(Expression::LiteralString(String::from("wow!")))
-                ))
+                Expression::UnaryOp(
+                    (
+                        UnOp::Length,
+                        Box::new(Expression::LiteralString(
+                            String::from("wow!"),
+                            Span::default()
+                        ))
+                    ),
+                    Span::default()
+                )
             ))
         );
     }
@@ -432,9 +516,10 @@ mod tests {
             result,
             Ok((
                 ";",
-                Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(String::from(
-                    "my_variable"
-                )))))
+                Expression::PrefixExp(
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_variable")))),
+                    Span::default()
+                )
             ))
         );
     }
@@ -448,17 +533,19 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::PrefixExp(Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
-                    Box::new(PrefixExp::Var(Var::Name(String::from(
-                        "launch_missiles"
+                Expression::PrefixExp(
+                    Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+                        Box::new(PrefixExp::Var(Var::Name(String::from("launch_missiles")))),
+                        Args::ExpList(vec![
+                            Expression::PrefixExp(
+                                Box::new(PrefixExp::Var(Var::Name(String::from("launch_code")))),
+                                Span::default()
+                            ),
+                            Expression::Numeral(Numeral::Integer(23), Span::default())
+                        ])
                     )))),
-                    Args::ExpList(vec![
-                        Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                            String::from("launch_code")
-                        )))),
-                        Expression::Numeral(Numeral::Integer(23))
-                    ])
-                )))))
+                    Span::default()
+                )
             ))
         );
     }
@@ -472,11 +559,17 @@ mod tests {
             result,
             Ok((
                 "",
-                Expression::PrefixExp(Box::new(PrefixExp::FunctionCall(FunctionCall::Method((
-                    Box::new(PrefixExp::Var(Var::Name(String::from("government")))),
-                    String::from("launch_missiles"),
-                    Args::ExpList(vec![Expression::Nil, Expression::DotDotDot])
-                )))))
+                Expression::PrefixExp(
+                    Box::new(PrefixExp::FunctionCall(FunctionCall::Method((
+                        Box::new(PrefixExp::Var(Var::Name(String::from("government")))),
+                        String::from("launch_missiles"),
+                        Args::ExpList(vec![
+                            Expression::Nil(Span::default()),
+                            Expression::DotDotDot(Span::default())
+                        ])
+                    )))),
+                    Span::default()
+                )
             ))
         )
     }
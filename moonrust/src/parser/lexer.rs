@@ -0,0 +1,192 @@
+// Splits a source into tokens with their line and column. The parser works on
+// the text directly and finds the spans of the nodes from these tokens. The
+// tools that point at places in a file (the linter) or need to see comments
+// (the formatter, the interactive mode) use them too.
+use super::util::long_bracket;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Name,
+    Keyword,
+    Number,
+    String,
+    Symbol,
+    Comment,
+    /// String or long comment that is not closed before the end of the input
+    Unfinished,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Line and column (in characters) of the first character, from 1
+    pub line: usize,
+    pub col: usize,
+}
+
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+const SYMBOLS: [&str; 10] = ["...", "..", "==", "~=", "<=", ">=", "//", "::", "<<", ">>"];
+
+/// Tokens of the input, comments included. Characters that can't start a token
+/// become one-character symbols, so that any input can be split.
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let (mut line, mut col) = (1, 1);
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            (line, col) = advance(line, col, &rest[..c.len_utf8()]);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let (kind, len) = token_at(rest);
+        let text = &rest[..len];
+        tokens.push(Token {
+            kind,
+            text,
+            line,
+            col,
+        });
+        (line, col) = advance(line, col, text);
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+// Kind and length in bytes of the token at the start of the input
+fn token_at(input: &str) -> (TokenKind, usize) {
+    let mut chars = input.chars();
+    let c = chars.next().unwrap_or_default();
+    let next = chars.next().unwrap_or_default();
+    if let Some(comment) = input.strip_prefix("--") {
+        return match long_bracket(comment) {
+            Some(level) => match long_end(&comment[level + 2..], level) {
+                Some(len) => (TokenKind::Comment, level + 4 + len),
+                None => (TokenKind::Unfinished, input.len()),
+            },
+            None => (
+                TokenKind::Comment,
+                comment.find('\n').map_or(input.len(), |i| i + 2),
+            ),
+        };
+    }
+    if let Some(level) = long_bracket(input) {
+        return match long_end(&input[level + 2..], level) {
+            Some(len) => (TokenKind::String, level + 2 + len),
+            None => (TokenKind::Unfinished, input.len()),
+        };
+    }
+    if c == '"' || c == '\'' {
+        let mut escaped = false;
+        for (i, ch) in input.char_indices().skip(1) {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                ch if ch == c => return (TokenKind::String, i + 1),
+                _ => (),
+            }
+        }
+        return (TokenKind::Unfinished, input.len());
+    }
+    if c.is_ascii_digit() || (c == '.' && next.is_ascii_digit()) {
+        let mut len = 0;
+        let mut prev = ' ';
+        for ch in input.chars() {
+            let exponent_sign = (ch == '+' || ch == '-') && "eEpP".contains(prev);
+            if !(ch.is_ascii_alphanumeric() || ch == '.' || exponent_sign) {
+                break;
+            }
+            len += 1;
+            prev = ch;
+        }
+        return (TokenKind::Number, len);
+    }
+    if c.is_alphabetic() || c == '_' {
+        let len = input
+            .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+            .unwrap_or(input.len());
+        let kind = if KEYWORDS.contains(&&input[..len]) {
+            TokenKind::Keyword
+        } else {
+            TokenKind::Name
+        };
+        return (kind, len);
+    }
+    match SYMBOLS.iter().find(|symbol| input.starts_with(*symbol)) {
+        Some(symbol) => (TokenKind::Symbol, symbol.len()),
+        None => (TokenKind::Symbol, c.len_utf8()),
+    }
+}
+
+// Length of a long string or comment body up to and including its closing
+// bracket of the given level
+fn long_end(body: &str, level: usize) -> Option<usize> {
+    let close = format!("]{}]", "=".repeat(level));
+    body.find(&close).map(|end| end + close.len())
+}
+
+pub(crate) fn advance(mut line: usize, mut col: usize, text: &str) -> (usize, usize) {
+    for c in text.chars() {
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_with_positions() {
+        let tokens = tokenize("local s = \"a\\\"b\" -- note\nx = t[1]..--[==[ long\n]==]s");
+        let summary: Vec<(TokenKind, &str, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.kind, token.text, token.line, token.col))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (TokenKind::Keyword, "local", 1, 1),
+                (TokenKind::Name, "s", 1, 7),
+                (TokenKind::Symbol, "=", 1, 9),
+                (TokenKind::String, "\"a\\\"b\"", 1, 11),
+                (TokenKind::Comment, "-- note", 1, 18),
+                (TokenKind::Name, "x", 2, 1),
+                (TokenKind::Symbol, "=", 2, 3),
+                (TokenKind::Name, "t", 2, 5),
+                (TokenKind::Symbol, "[", 2, 6),
+                (TokenKind::Number, "1", 2, 7),
+                (TokenKind::Symbol, "]", 2, 8),
+                (TokenKind::Symbol, "..", 2, 9),
+                (TokenKind::Comment, "--[==[ long\n]==]", 2, 11),
+                (TokenKind::Name, "s", 3, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_numbers_and_unfinished_tokens() {
+        let kinds =
+            |input| -> Vec<TokenKind> { tokenize(input).iter().map(|token| token.kind).collect() };
+        assert_eq!(kinds("1e-3 .5 0x1F"), [TokenKind::Number; 3]);
+        assert_eq!(
+            kinds("1 - 3"),
+            [TokenKind::Number, TokenKind::Symbol, TokenKind::Number]
+        );
+        assert_eq!(
+            kinds("x = \"open"),
+            [TokenKind::Name, TokenKind::Symbol, TokenKind::Unfinished]
+        );
+        assert_eq!(kinds("--[[ open"), [TokenKind::Unfinished]);
+    }
+}
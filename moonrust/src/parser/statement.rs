@@ -13,13 +13,14 @@ use super::common::{parse_args, parse_funcbody, parse_prefixexp};
 use super::expression::parse_exp;
 use super::{util::*, ParseResult};
 
-use crate::ast::{Expression, FunctionCall, PrefixExp, Statement};
+use crate::ast::{Attrib, Expression, FuncBody, FunctionCall, PrefixExp, Span, Statement};
 use crate::parser::common::parse_block;
 use crate::parser::expression;
 
-pub fn parse_stmt(input: &str) -> ParseResult<Statement> {
+pub fn parse_stmt(input: Input<'_>) -> ParseResult<'_, Statement> {
     complete(alt((
         parse_semicolon,
+        parse_local_attribs,
         parse_stmt_prefixexp,
         parse_break,
         parse_while,
@@ -34,11 +35,13 @@ pub fn parse_stmt(input: &str) -> ParseResult<Statement> {
 }
 /// Parse a single semicolon. Toss the result since it provides no
 /// semantic information.
-fn parse_semicolon(input: &str) -> ParseResult<Statement> {
-    map(ws(tag(";")), |_| Statement::Semicolon)(input)
+fn parse_semicolon(input: Input<'_>) -> ParseResult<'_, Statement> {
+    map(spanned(ws(tag(";"))), |(_, span)| {
+        Statement::Semicolon(span)
+    })(input)
 }
 
-pub fn parse_functioncall(input: &str) -> ParseResult<FunctionCall> {
+pub fn parse_functioncall(input: Input<'_>) -> ParseResult<'_, FunctionCall> {
     // FunctionCall((PrefixExp, Option<String>))
 
     alt((
@@ -57,52 +60,55 @@ pub fn parse_functioncall(input: &str) -> ParseResult<FunctionCall> {
     ))(input)
 }
 
-pub fn parse_functioncall_statement(input: &str) -> ParseResult<Statement> {
+pub fn parse_functioncall_statement(input: Input<'_>) -> ParseResult<'_, Statement> {
     // FunctionCall((PrefixExp, Option<String>))
-    map(tuple((parse_functioncall, opt(parse_string))), |result| {
-        Statement::FunctionCall(result.0)
-    })(input)
+    map(
+        spanned(tuple((parse_functioncall, opt(parse_string)))),
+        |(result, span)| Statement::FunctionCall(result.0, span),
+    )(input)
 }
 
-fn parse_break(input: &str) -> ParseResult<Statement> {
-    map(ws(tag("break")), |_| Statement::Break)(input)
+fn parse_break(input: Input<'_>) -> ParseResult<'_, Statement> {
+    map(spanned(ws(tag("break"))), |(_, span)| {
+        Statement::Break(span)
+    })(input)
 }
 
-fn parse_do_block(input: &str) -> ParseResult<Statement> {
+fn parse_do_block(input: Input<'_>) -> ParseResult<'_, Statement> {
     // DoBlock(Block)
     map(
-        delimited(ws(tag("do")), parse_block, ws(tag("end"))),
-        Statement::DoBlock,
+        spanned(delimited(ws(tag("do")), parse_block, ws(tag("end")))),
+        |(block, span)| Statement::DoBlock(block, span),
     )(input)
 }
 
-fn parse_while(input: &str) -> ParseResult<Statement> {
+fn parse_while(input: Input<'_>) -> ParseResult<'_, Statement> {
     // While((Expression, Block))
     map(
-        tuple((
+        spanned(tuple((
             ws(tag("while")),
             expression::parse_exp,
             delimited(ws(tag("do")), parse_block, ws(tag("end"))),
-        )),
-        |result| Statement::While((result.1, result.2)),
+        ))),
+        |(result, span)| Statement::While((result.1, result.2), span),
     )(input)
 }
 
-fn parse_repeat(input: &str) -> ParseResult<Statement> {
+fn parse_repeat(input: Input<'_>) -> ParseResult<'_, Statement> {
     // Repeat((Block, Expression))
     map(
-        pair(
+        spanned(pair(
             preceded(ws(tag("repeat")), parse_block),
             preceded(ws(tag("until")), expression::parse_exp),
-        ),
-        Statement::Repeat,
+        )),
+        |(result, span)| Statement::Repeat(result, span),
     )(input)
 }
 
-fn parse_if(input: &str) -> ParseResult<Statement> {
+fn parse_if(input: Input<'_>) -> ParseResult<'_, Statement> {
     // If((Expression, Block, Vec<(Expression, Block)>, Option<Block>))
     map(
-        tuple((
+        spanned(tuple((
             preceded(ws(tag("if")), parse_exp),
             preceded(ws(tag("then")), parse_block),
             many0(pair(
@@ -110,46 +116,46 @@ fn parse_if(input: &str) -> ParseResult<Statement> {
                 preceded(ws(tag("then")), parse_block),
             )),
             terminated(opt(preceded(ws(tag("else")), parse_block)), ws(tag("end"))),
-        )),
-        Statement::If,
+        ))),
+        |(result, span)| Statement::If(result, span),
     )(input)
 }
 
-fn parse_for_num(input: &str) -> ParseResult<Statement> {
+fn parse_for_num(input: Input<'_>) -> ParseResult<'_, Statement> {
     // ForNum((String, Expression, Expression, Option<Expression>, Block))
 
     map(
-        tuple((
+        spanned(tuple((
             map(preceded(ws(tag("for")), identifier), String::from),
             preceded(ws(tag("=")), parse_exp),
             preceded(ws(tag(",")), parse_exp),
             opt(preceded(ws(tag(",")), parse_exp)),
             delimited(ws(tag("do")), parse_block, ws(tag("end"))),
-        )),
-        Statement::ForNum,
+        ))),
+        |(result, span)| Statement::ForNum(result, span),
     )(input)
 }
 
 // redo
-fn parse_for_generic(input: &str) -> ParseResult<Statement> {
+fn parse_for_generic(input: Input<'_>) -> ParseResult<'_, Statement> {
     // ForGeneric((Vec<String>, Vec<Expression>, Block))
     map(
-        tuple((
+        spanned(tuple((
             preceded(
                 ws(tag("for")),
                 separated_list1(ws(tag(",")), map(identifier, String::from)),
             ),
             preceded(ws(tag("in")), separated_list1(ws(tag(",")), parse_exp)),
             delimited(ws(tag("do")), parse_block, ws(tag("end"))),
-        )),
-        Statement::ForGeneric,
+        ))),
+        |(result, span)| Statement::ForGeneric(result, span),
     )(input)
 }
 
-fn parse_function_decl(input: &str) -> ParseResult<Statement> {
-    // FunctionDecl((String, ParList, Block)) where String = name of function being declared
+fn parse_function_decl(input: Input<'_>) -> ParseResult<'_, Statement> {
+    // FunctionDecl((String, Rc<FuncBody>)) where String = name of function being declared
     map(
-        pair(
+        spanned(pair(
             preceded(
                 ws(tag("function")),
                 map(
@@ -168,71 +174,114 @@ fn parse_function_decl(input: &str) -> ParseResult<Statement> {
                 ),
             ),
             parse_funcbody,
-        ),
-        |result| Statement::FunctionDecl((result.0, result.1 .0, result.1 .1)),
+        )),
+        |((name, (par_list, block)), span)| {
+            Statement::FunctionDecl((name, FuncBody::new(par_list, block, span)), span)
+        },
     )(input)
 }
 
-fn parse_local_func_decl(input: &str) -> ParseResult<Statement> {
-    // LocalFuncDecl((String, ParList, Block))
+fn parse_local_func_decl(input: Input<'_>) -> ParseResult<'_, Statement> {
+    // LocalFuncDecl((String, Rc<FuncBody>))
     map(
-        preceded(
+        spanned(preceded(
             pair(ws(tag("local")), ws(tag("function"))),
             pair(map(identifier, String::from), parse_funcbody),
-        ),
-        |result| Statement::LocalFuncDecl((result.0, result.1 .0, result.1 .1)),
+        )),
+        |((name, (par_list, block)), span)| {
+            Statement::LocalFuncDecl((name, FuncBody::new(par_list, block, span)), span)
+        },
+    )(input)
+}
+
+fn parse_local_attribs(input: Input<'_>) -> ParseResult<'_, Statement> {
+    // local attnamelist [‘=’ explist], where at least one name has an attribute.
+    // Locals without attributes are parsed as local assignments.
+    map(
+        spanned(preceded(
+            ws(tag("local")),
+            pair(
+                verify(
+                    separated_list1(
+                        ws(char(',')),
+                        pair(map(ws(identifier), String::from), opt(parse_attrib)),
+                    ),
+                    |names: &Vec<_>| names.iter().any(|(_, attrib)| attrib.is_some()),
+                ),
+                opt(preceded(
+                    ws(char('=')),
+                    separated_list1(ws(char(',')), parse_exp),
+                )),
+            ),
+        )),
+        |((names, exps), span)| {
+            let exps = exps.unwrap_or_else(|| vec![Expression::Nil(Span::default()); names.len()]);
+            Statement::LocalAttribs((names, exps), span)
+        },
+    )(input)
+}
+
+fn parse_attrib(input: Input<'_>) -> ParseResult<'_, Attrib> {
+    delimited(
+        ws(char('<')),
+        alt((
+            map(tag("const"), |_| Attrib::Const),
+            map(tag("close"), |_| Attrib::Close),
+        )),
+        ws(char('>')),
     )(input)
 }
 
-fn parse_stmt_prefixexp(input: &str) -> ParseResult<Statement> {
+fn parse_stmt_prefixexp(input: Input<'_>) -> ParseResult<'_, Statement> {
     let (input_after_local, is_local) =
         map(opt(ws(tag("local"))), |result| result.is_some())(input)?;
     let (rest_input, pexp) = parse_prefixexp(input_after_local)?;
 
     if let PrefixExp::FunctionCall(fncall) = pexp {
         if is_local {
-            fail("Function calls cannot be local")
+            fail(input)
         } else {
-            Ok((rest_input, Statement::FunctionCall(fncall)))
+            let span = span_of(input, rest_input);
+            Ok((rest_input, Statement::FunctionCall(fncall, span)))
         }
     } else {
-        map(
-            verify(
-                pair(
-                    separated_list1(
-                        ws(char(',')),
-                        map(
-                            verify(parse_prefixexp, |pexp| matches!(pexp, PrefixExp::Var(_))),
-                            |result| match result {
-                                PrefixExp::Var(var) => var,
-                                _ => unreachable!(),
-                            },
-                        ),
+        let (rest_input, result) = verify(
+            pair(
+                separated_list1(
+                    ws(char(',')),
+                    map(
+                        verify(parse_prefixexp, |pexp| matches!(pexp, PrefixExp::Var(_))),
+                        |result| match result {
+                            PrefixExp::Var(var) => var,
+                            _ => unreachable!(),
+                        },
                     ),
-                    opt(preceded(
-                        ws(char('=')),
-                        separated_list1(ws(char(',')), expression::parse_exp),
-                    )),
                 ),
-                |result| result.1.is_some() || is_local,
+                opt(preceded(
+                    ws(char('=')),
+                    separated_list1(ws(char(',')), expression::parse_exp),
+                )),
             ),
-            |result| match result.1 {
-                Some(exps) => Statement::Assignment((result.0, exps, is_local)),
-                None => {
-                    let vars_len = result.0.len();
-                    let mut exp_vec = Vec::new();
-                    for _ in 0..vars_len {
-                        exp_vec.push(Expression::Nil);
-                    }
-                    Statement::Assignment((result.0, exp_vec, is_local))
+            |result| result.1.is_some() || is_local,
+        )(input_after_local)?;
+        let span = span_of(input, rest_input);
+        let statement = match result.1 {
+            Some(exps) => Statement::Assignment((result.0, exps, is_local), span),
+            None => {
+                let vars_len = result.0.len();
+                let mut exp_vec = Vec::new();
+                for _ in 0..vars_len {
+                    exp_vec.push(Expression::Nil(Span::default()));
                 }
-            },
-        )(input_after_local)
+                Statement::Assignment((result.0, exp_vec, is_local), span)
+            }
+        };
+        Ok((rest_input, statement))
     }
 }
 
 // used in parse_block, not considered a Lua statement
-pub fn parse_return(input: &str) -> ParseResult<Vec<Expression>> {
+pub fn parse_return(input: Input<'_>) -> ParseResult<'_, Vec<Expression>> {
     // retstat ::= return [explist] [‘;’]
     // explist and ; are optional
     preceded(
@@ -246,18 +295,23 @@ pub fn parse_return(input: &str) -> ParseResult<Vec<Expression>> {
 
 #[cfg(test)]
 mod tests {
+    use nom::IResult;
 
     use crate::ast::{Args, BinOp, Block, Numeral, ParList, PrefixExp, UnOp, Var};
 
     use super::*;
 
+    fn parse_stmt(input: &str) -> IResult<&str, Statement> {
+        parse_str(super::parse_stmt, input)
+    }
+
     #[test]
     fn accepts_semicolon() {
-        let expected = parse_semicolon(";");
-        assert_eq!(expected, Ok(("", Statement::Semicolon)));
+        let expected = parse_str(parse_semicolon, ";");
+        assert_eq!(expected, Ok(("", Statement::Semicolon(Span::default()))));
 
         let expected = parse_stmt("     ;     ");
-        assert_eq!(expected, Ok(("", Statement::Semicolon)));
+        assert_eq!(expected, Ok(("", Statement::Semicolon(Span::default()))));
     }
 
     #[test]
@@ -267,11 +321,17 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::Assignment((
-                vec![Var::Name(String::from("r")), Var::Name(String::from("v"))],
-                vec![Expression::Nil, Expression::Nil],
-                true,
-            )),
+            Statement::Assignment(
+                (
+                    vec![Var::Name(String::from("r")), Var::Name(String::from("v"))],
+                    vec![
+                        Expression::Nil(Span::default()),
+                        Expression::Nil(Span::default()),
+                    ],
+                    true,
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -286,10 +346,16 @@ mod tests {
         let input = "do_thing(true, false)";
         let expected = Ok((
             "",
-            Statement::FunctionCall(FunctionCall::Standard((
-                Box::new(PrefixExp::Var(Var::Name(String::from("do_thing")))),
-                Args::ExpList(vec![Expression::True, Expression::False]),
-            ))),
+            Statement::FunctionCall(
+                FunctionCall::Standard((
+                    Box::new(PrefixExp::Var(Var::Name(String::from("do_thing")))),
+                    Args::ExpList(vec![
+                        Expression::True(Span::default()),
+                        Expression::False(Span::default()),
+                    ]),
+                )),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -299,10 +365,10 @@ mod tests {
     #[test]
     fn accepts_break() {
         let expected = parse_stmt("break");
-        assert_eq!(expected, Ok(("", Statement::Break)));
+        assert_eq!(expected, Ok(("", Statement::Break(Span::default()))));
 
         let expected = parse_stmt("     break     ");
-        assert_eq!(expected, Ok(("", Statement::Break)));
+        assert_eq!(expected, Ok(("", Statement::Break(Span::default()))));
     }
 
     #[test]
@@ -315,27 +381,43 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::DoBlock(Block {
-                statements: vec![
-                    Statement::Assignment((
-                        vec![Var::Name(String::from("a"))],
-                        vec![Expression::Numeral(Numeral::Integer(1))],
-                        true,
-                    )),
-                    Statement::Assignment((
-                        vec![Var::Name(String::from("b"))],
-                        vec![Expression::BinaryOp((
-                            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                                String::from("a"),
-                            ))))),
-                            BinOp::Add,
-                            Box::new(Expression::Numeral(Numeral::Integer(3))),
-                        ))],
-                        false,
-                    )),
-                ],
-                return_stat: None,
-            }),
+            Statement::DoBlock(
+                Block {
+                    statements: vec![
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("a"))],
+                                vec![Expression::Numeral(Numeral::Integer(1), Span::default())],
+                                true,
+                            ),
+                            Span::default(),
+                        ),
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("b"))],
+                                vec![Expression::BinaryOp(
+                                    (
+                                        Box::new(Expression::PrefixExp(
+                                            Box::new(PrefixExp::Var(Var::Name(String::from("a")))),
+                                            Span::default(),
+                                        )),
+                                        BinOp::Add,
+                                        Box::new(Expression::Numeral(
+                                            Numeral::Integer(3),
+                                            Span::default(),
+                                        )),
+                                    ),
+                                    Span::default(),
+                                )],
+                                false,
+                            ),
+                            Span::default(),
+                        ),
+                    ],
+                    return_stat: None,
+                },
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -355,50 +437,86 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::While((
-                Expression::BinaryOp((
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                        String::from("i"),
-                    ))))),
-                    BinOp::LessEq,
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                        String::from("x"),
-                    ))))),
-                )),
-                Block {
-                    statements: vec![
-                        Statement::Assignment((
-                            vec![Var::Name(String::from("x"))],
-                            vec![Expression::BinaryOp((
-                                Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(
-                                    Var::Name(String::from("i")),
-                                )))),
-                                BinOp::Mult,
-                                Box::new(Expression::Numeral(Numeral::Integer(2))),
-                            ))],
-                            true,
-                        )),
-                        Statement::FunctionCall(FunctionCall::Standard((
-                            Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
-                            Args::ExpList(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                                Var::Name(String::from("x")),
-                            )))]),
-                        ))),
-                        Statement::Assignment((
-                            vec![Var::Name(String::from("i"))],
-                            vec![Expression::BinaryOp((
-                                Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(
-                                    Var::Name(String::from("i")),
-                                )))),
-                                BinOp::Add,
-                                Box::new(Expression::Numeral(Numeral::Integer(1))),
-                            ))],
-                            false,
-                        )),
-                    ],
-                    return_stat: None,
-                },
-            )),
+            Statement::While(
+                (
+                    Expression::BinaryOp(
+                        (
+                            Box::new(Expression::PrefixExp(
+                                Box::new(PrefixExp::Var(Var::Name(String::from("i")))),
+                                Span::default(),
+                            )),
+                            BinOp::LessEq,
+                            Box::new(Expression::PrefixExp(
+                                Box::new(PrefixExp::Var(Var::Name(String::from("x")))),
+                                Span::default(),
+                            )),
+                        ),
+                        Span::default(),
+                    ),
+                    Block {
+                        statements: vec![
+                            Statement::Assignment(
+                                (
+                                    vec![Var::Name(String::from("x"))],
+                                    vec![Expression::BinaryOp(
+                                        (
+                                            Box::new(Expression::PrefixExp(
+                                                Box::new(PrefixExp::Var(Var::Name(String::from(
+                                                    "i",
+                                                )))),
+                                                Span::default(),
+                                            )),
+                                            BinOp::Mult,
+                                            Box::new(Expression::Numeral(
+                                                Numeral::Integer(2),
+                                                Span::default(),
+                                            )),
+                                        ),
+                                        Span::default(),
+                                    )],
+                                    true,
+                                ),
+                                Span::default(),
+                            ),
+                            Statement::FunctionCall(
+                                FunctionCall::Standard((
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
+                                    Args::ExpList(vec![Expression::PrefixExp(
+                                        Box::new(PrefixExp::Var(Var::Name(String::from("x")))),
+                                        Span::default(),
+                                    )]),
+                                )),
+                                Span::default(),
+                            ),
+                            Statement::Assignment(
+                                (
+                                    vec![Var::Name(String::from("i"))],
+                                    vec![Expression::BinaryOp(
+                                        (
+                                            Box::new(Expression::PrefixExp(
+                                                Box::new(PrefixExp::Var(Var::Name(String::from(
+                                                    "i",
+                                                )))),
+                                                Span::default(),
+                                            )),
+                                            BinOp::Add,
+                                            Box::new(Expression::Numeral(
+                                                Numeral::Integer(1),
+                                                Span::default(),
+                                            )),
+                                        ),
+                                        Span::default(),
+                                    )],
+                                    false,
+                                ),
+                                Span::default(),
+                            ),
+                        ],
+                        return_stat: None,
+                    },
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -418,29 +536,52 @@ mod tests {
 
         let expected = Ok((
             "", // uncomsumed data
-            Statement::Repeat((
-                Block {
-                    statements: vec![Statement::Assignment((
-                        vec![Var::Name(String::from("a"))],
-                        vec![Expression::BinaryOp((
-                            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                                String::from("a"),
-                            ))))),
-                            BinOp::Add,
-                            Box::new(Expression::Numeral(Numeral::Integer(1))),
-                        ))],
-                        false,
-                    ))],
-                    return_stat: None,
-                },
-                Expression::PrefixExp(Box::new(PrefixExp::Exp(Expression::BinaryOp((
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                        String::from("a"),
-                    ))))),
-                    BinOp::GreaterThan,
-                    Box::new(Expression::Numeral(Numeral::Integer(15))),
-                ))))),
-            )),
+            Statement::Repeat(
+                (
+                    Block {
+                        statements: vec![Statement::Assignment(
+                            (
+                                vec![Var::Name(String::from("a"))],
+                                vec![Expression::BinaryOp(
+                                    (
+                                        Box::new(Expression::PrefixExp(
+                                            Box::new(PrefixExp::Var(Var::Name(String::from("a")))),
+                                            Span::default(),
+                                        )),
+                                        BinOp::Add,
+                                        Box::new(Expression::Numeral(
+                                            Numeral::Integer(1),
+                                            Span::default(),
+                                        )),
+                                    ),
+                                    Span::default(),
+                                )],
+                                false,
+                            ),
+                            Span::default(),
+                        )],
+                        return_stat: None,
+                    },
+                    Expression::PrefixExp(
+                        Box::new(PrefixExp::Exp(Expression::BinaryOp(
+                            (
+                                Box::new(Expression::PrefixExp(
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("a")))),
+                                    Span::default(),
+                                )),
+                                BinOp::GreaterThan,
+                                Box::new(Expression::Numeral(
+                                    Numeral::Integer(15),
+                                    Span::default(),
+                                )),
+                            ),
+                            Span::default(),
+                        ))),
+                        Span::default(),
+                    ),
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -466,36 +607,68 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::If((
-                Expression::PrefixExp(Box::new(PrefixExp::Exp(Expression::BinaryOp((
-                    Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                        String::from("c"),
-                    ))))),
-                    BinOp::LessThan,
-                    Box::new(Expression::Numeral(Numeral::Integer(43))),
-                ))))),
-                Block {
-                    statements: vec![],
-                    return_stat: Some(vec![Expression::LiteralString(String::from("yes"))]),
-                },
-                vec![(
-                    Expression::PrefixExp(Box::new(PrefixExp::Exp(Expression::BinaryOp((
-                        Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                            String::from("c"),
-                        ))))),
-                        BinOp::GreaterThan,
-                        Box::new(Expression::Numeral(Numeral::Integer(43))),
-                    ))))),
+            Statement::If(
+                (
+                    Expression::PrefixExp(
+                        Box::new(PrefixExp::Exp(Expression::BinaryOp(
+                            (
+                                Box::new(Expression::PrefixExp(
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("c")))),
+                                    Span::default(),
+                                )),
+                                BinOp::LessThan,
+                                Box::new(Expression::Numeral(
+                                    Numeral::Integer(43),
+                                    Span::default(),
+                                )),
+                            ),
+                            Span::default(),
+                        ))),
+                        Span::default(),
+                    ),
                     Block {
                         statements: vec![],
-                        return_stat: Some(vec![Expression::LiteralString(String::from("no"))]),
+                        return_stat: Some(vec![Expression::LiteralString(
+                            String::from("yes"),
+                            Span::default(),
+                        )]),
                     },
-                )],
-                Some(Block {
-                    statements: vec![],
-                    return_stat: Some(vec![Expression::LiteralString(String::from("maybe"))]),
-                }),
-            )),
+                    vec![(
+                        Expression::PrefixExp(
+                            Box::new(PrefixExp::Exp(Expression::BinaryOp(
+                                (
+                                    Box::new(Expression::PrefixExp(
+                                        Box::new(PrefixExp::Var(Var::Name(String::from("c")))),
+                                        Span::default(),
+                                    )),
+                                    BinOp::GreaterThan,
+                                    Box::new(Expression::Numeral(
+                                        Numeral::Integer(43),
+                                        Span::default(),
+                                    )),
+                                ),
+                                Span::default(),
+                            ))),
+                            Span::default(),
+                        ),
+                        Block {
+                            statements: vec![],
+                            return_stat: Some(vec![Expression::LiteralString(
+                                String::from("no"),
+                                Span::default(),
+                            )]),
+                        },
+                    )],
+                    Some(Block {
+                        statements: vec![],
+                        return_stat: Some(vec![Expression::LiteralString(
+                            String::from("maybe"),
+                            Span::default(),
+                        )]),
+                    }),
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -514,24 +687,34 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::ForNum((
-                String::from("i"),
-                Expression::Numeral(Numeral::Integer(10)),
-                Expression::Numeral(Numeral::Integer(1)),
-                Some(Expression::UnaryOp((
-                    UnOp::Negate,
-                    Box::new(Expression::Numeral(Numeral::Integer(1))),
-                ))),
-                Block {
-                    statements: vec![Statement::FunctionCall(FunctionCall::Standard((
-                        Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
-                        Args::ExpList(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                            Var::Name(String::from("i")),
-                        )))]),
-                    )))],
-                    return_stat: None,
-                },
-            )),
+            Statement::ForNum(
+                (
+                    String::from("i"),
+                    Expression::Numeral(Numeral::Integer(10), Span::default()),
+                    Expression::Numeral(Numeral::Integer(1), Span::default()),
+                    Some(Expression::UnaryOp(
+                        (
+                            UnOp::Negate,
+                            Box::new(Expression::Numeral(Numeral::Integer(1), Span::default())),
+                        ),
+                        Span::default(),
+                    )),
+                    Block {
+                        statements: vec![Statement::FunctionCall(
+                            FunctionCall::Standard((
+                                Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
+                                Args::ExpList(vec![Expression::PrefixExp(
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("i")))),
+                                    Span::default(),
+                                )]),
+                            )),
+                            Span::default(),
+                        )],
+                        return_stat: None,
+                    },
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -551,29 +734,41 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::ForGeneric((
-                vec![String::from("index"), String::from("name")],
-                vec![Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                    String::from("names"),
-                ))))],
-                Block {
-                    statements: vec![
-                        Statement::FunctionCall(FunctionCall::Standard((
-                            Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
-                            Args::ExpList(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                                Var::Name(String::from("name")),
-                            )))]),
-                        ))),
-                        Statement::FunctionCall(FunctionCall::Standard((
-                            Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
-                            Args::ExpList(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                                Var::Name(String::from("index")),
-                            )))]),
-                        ))),
-                    ],
-                    return_stat: None,
-                },
-            )),
+            Statement::ForGeneric(
+                (
+                    vec![String::from("index"), String::from("name")],
+                    vec![Expression::PrefixExp(
+                        Box::new(PrefixExp::Var(Var::Name(String::from("names")))),
+                        Span::default(),
+                    )],
+                    Block {
+                        statements: vec![
+                            Statement::FunctionCall(
+                                FunctionCall::Standard((
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
+                                    Args::ExpList(vec![Expression::PrefixExp(
+                                        Box::new(PrefixExp::Var(Var::Name(String::from("name")))),
+                                        Span::default(),
+                                    )]),
+                                )),
+                                Span::default(),
+                            ),
+                            Statement::FunctionCall(
+                                FunctionCall::Standard((
+                                    Box::new(PrefixExp::Var(Var::Name(String::from("print")))),
+                                    Args::ExpList(vec![Expression::PrefixExp(
+                                        Box::new(PrefixExp::Var(Var::Name(String::from("index")))),
+                                        Span::default(),
+                                    )]),
+                                )),
+                                Span::default(),
+                            ),
+                        ],
+                        return_stat: None,
+                    },
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -582,7 +777,7 @@ mod tests {
 
     #[test]
     fn accepts_function_decl() {
-        // FunctionDecl((String, ParList, Block))
+        // FunctionDecl((String, Rc<FuncBody>))
 
         let input = "
             function num(num1)
@@ -593,16 +788,23 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::FunctionDecl((
-                String::from("num"),
-                ParList(vec![String::from("num1")], false),
-                Block {
-                    statements: vec![],
-                    return_stat: Some(vec![Expression::PrefixExp(Box::new(PrefixExp::Var(
-                        Var::Name(String::from("num1")),
-                    )))]),
-                },
-            )),
+            Statement::FunctionDecl(
+                (
+                    String::from("num"),
+                    FuncBody::new(
+                        ParList(vec![String::from("num1")], false),
+                        Block {
+                            statements: vec![],
+                            return_stat: Some(vec![Expression::PrefixExp(
+                                Box::new(PrefixExp::Var(Var::Name(String::from("num1")))),
+                                Span::default(),
+                            )]),
+                        },
+                        Span::default(),
+                    ),
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
@@ -611,7 +813,7 @@ mod tests {
 
     #[test]
     fn accepts_local_func_def() {
-        // LocalFuncDecl((String, ParList, Block))
+        // LocalFuncDecl((String, Rc<FuncBody>))
 
         let input = "
             local function fact(n)
@@ -622,31 +824,86 @@ mod tests {
 
         let expected = Ok((
             "",
-            Statement::LocalFuncDecl((
-                String::from("fact"),
-                ParList(vec![String::from("n")], false),
-                Block {
-                    statements: vec![Statement::If((
-                        Expression::BinaryOp((
-                            Box::new(Expression::PrefixExp(Box::new(PrefixExp::Var(Var::Name(
-                                String::from("n"),
-                            ))))),
-                            BinOp::Equal,
-                            Box::new(Expression::Numeral(Numeral::Integer(0))),
-                        )),
+            Statement::LocalFuncDecl(
+                (
+                    String::from("fact"),
+                    FuncBody::new(
+                        ParList(vec![String::from("n")], false),
                         Block {
-                            statements: vec![],
-                            return_stat: Some(vec![Expression::Numeral(Numeral::Integer(1))]),
+                            statements: vec![Statement::If(
+                                (
+                                    Expression::BinaryOp(
+                                        (
+                                            Box::new(Expression::PrefixExp(
+                                                Box::new(PrefixExp::Var(Var::Name(String::from(
+                                                    "n",
+                                                )))),
+                                                Span::default(),
+                                            )),
+                                            BinOp::Equal,
+                                            Box::new(Expression::Numeral(
+                                                Numeral::Integer(0),
+                                                Span::default(),
+                                            )),
+                                        ),
+                                        Span::default(),
+                                    ),
+                                    Block {
+                                        statements: vec![],
+                                        return_stat: Some(vec![Expression::Numeral(
+                                            Numeral::Integer(1),
+                                            Span::default(),
+                                        )]),
+                                    },
+                                    vec![],
+                                    None,
+                                ),
+                                Span::default(),
+                            )],
+                            return_stat: None,
                         },
-                        vec![],
-                        None,
-                    ))],
-                    return_stat: None,
-                },
-            )),
+                        Span::default(),
+                    ),
+                ),
+                Span::default(),
+            ),
         ));
 
         let actual = parse_stmt(input);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn accepts_local_attribs() {
+        let input = "local x <const>, y, z <close> = 1, f()";
+
+        let expected = Ok((
+            "",
+            Statement::LocalAttribs(
+                (
+                    vec![
+                        (String::from("x"), Some(Attrib::Const)),
+                        (String::from("y"), None),
+                        (String::from("z"), Some(Attrib::Close)),
+                    ],
+                    vec![
+                        Expression::Numeral(Numeral::Integer(1), Span::default()),
+                        Expression::PrefixExp(
+                            Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+                                Box::new(PrefixExp::Var(Var::Name(String::from("f")))),
+                                Args::ExpList(vec![]),
+                            )))),
+                            Span::default(),
+                        ),
+                    ],
+                ),
+                Span::default(),
+            ),
+        ));
+        assert_eq!(parse_stmt(input), expected);
+
+        // Without attributes, it is a local assignment
+        let actual = parse_stmt("local x = 1");
+        assert!(matches!(actual, Ok(("", Statement::Assignment(_, _)))));
+    }
 }
@@ -6,23 +6,348 @@ use nom::{
     },
     character::complete::{alpha1, alphanumeric1, char, multispace0, multispace1, one_of},
     combinator::{complete, map, map_opt, map_res, opt, recognize, value, verify},
-    error::ParseError,
+    error::{ErrorKind, ParseError},
     multi::{fold_many0, many0, many0_count, many1},
     sequence::{delimited, pair, preceded, terminated, tuple},
-    IResult,
+    Compare, CompareResult, IResult, InputIter, InputLength, InputTake, InputTakeAtPosition,
+    Needed, Offset, Slice,
 };
 
+use std::fmt;
+use std::ops::{Range, RangeFrom, RangeFull, RangeTo};
+use std::str::{CharIndices, Chars};
+
+use super::lexer::{self, TokenKind};
+use crate::ast::Span;
+use crate::stack::Level;
+
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading and
-/// trailing whitespace, returning the output of `inner`.
+/// trailing whitespace and comments, returning the output of `inner`.
 ///
 /// Credit: https://github.com/rust-bakery/nom/blob/main/doc/nom_recipes.md#whitespace
-pub fn ws<'a, F, O, E: ParseError<&'a str>>(
+pub fn ws<'a, F, O, E: ParseError<Input<'a>>>(
     inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: FnMut(Input<'a>) -> IResult<Input<'a>, O, E>,
 {
-    delimited(multispace0, inner, multispace0)
+    delimited(blank, inner, blank)
+}
+
+/// Consume whitespace and comments: `--` up to the end of the line, or a long
+/// comment such as `--[[ ... ]]` or `--[==[ ... ]==]`. A long comment that is
+/// never closed is a failure.
+pub fn blank<'a, E: ParseError<Input<'a>>>(input: Input<'a>) -> IResult<Input<'a>, (), E> {
+    let mut rest = input;
+    loop {
+        let (after, _) = multispace0(rest)?;
+        let Some(comment) = after.fragment().strip_prefix("--") else {
+            return Ok((after, ()));
+        };
+        let len = match long_bracket(comment) {
+            Some(level) => {
+                let close = format!("]{}]", "=".repeat(level));
+                let body = &comment[level + 2..];
+                match body.find(&close) {
+                    Some(end) => level + 4 + end + close.len(),
+                    None => {
+                        return Err(nom::Err::Failure(E::from_error_kind(after, ErrorKind::Eof)))
+                    }
+                }
+            }
+            None => 2 + comment.find('\n').unwrap_or(comment.len()),
+        };
+        rest = after.slice(len..);
+    }
+}
+
+/// Level of the opening long bracket at the start of the input, the number of
+/// `=` in `[==[`
+pub fn long_bracket(input: &str) -> Option<usize> {
+    let level = input.strip_prefix('[')?.chars().take_while(|c| *c == '=').count();
+    input[level + 1..].starts_with('[').then_some(level)
+}
+
+/// Run the parser `inner` one level of nesting deeper. Once the nesting is too
+/// deep, parsing stops with a failure of kind `TooLarge`.
+pub fn nested<'a, O>(
+    input: Input<'a>,
+    inner: impl FnOnce(Input<'a>) -> IResult<Input<'a>, O>,
+) -> IResult<Input<'a>, O> {
+    match Level::enter() {
+        Some(_level) => inner(input),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            ErrorKind::TooLarge,
+        ))),
+    }
+}
+
+// Offsets of the start and the end of a token of the chunk, and its span
+type TokenSpan = (usize, usize, Span);
+
+/// Spans of the tokens of `source`, without its comments, to find the spans of
+/// the nodes parsed from it
+pub fn token_spans(source: &str) -> Vec<TokenSpan> {
+    lexer::tokenize(source)
+        .into_iter()
+        .filter(|token| token.kind != TokenKind::Comment)
+        .map(|token| {
+            let start = token.text.as_ptr() as usize - source.as_ptr() as usize;
+            let last = token.text.char_indices().last().map_or(0, |(i, _)| i);
+            let span = Span {
+                start: (token.line, token.col),
+                end: lexer::advance(token.line, token.col, &token.text[..last]),
+            };
+            (start, start + token.text.len(), span)
+        })
+        .collect()
+}
+
+/// Part of the chunk being parsed: the text left to parse, with its offset in
+/// the chunk and the spans of the tokens of the chunk. Input made from a bare
+/// string has no tokens, and the nodes parsed from it get the default span.
+#[derive(Clone, Copy)]
+pub struct Input<'a> {
+    fragment: &'a str,
+    offset: usize,
+    tokens: &'a [TokenSpan],
+}
+
+impl<'a> Input<'a> {
+    /// The whole chunk `source`, with the spans given by `token_spans`
+    pub fn new(source: &'a str, tokens: &'a [TokenSpan]) -> Self {
+        Input {
+            fragment: source,
+            offset: 0,
+            tokens,
+        }
+    }
+
+    /// The text left to parse
+    pub fn fragment(&self) -> &'a str {
+        self.fragment
+    }
+
+    /// Offset of the text left to parse in the chunk
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // The input from `start` to `end`, offsets in this input
+    fn range(&self, start: usize, end: usize) -> Self {
+        Input {
+            fragment: &self.fragment[start..end],
+            offset: self.offset + start,
+            tokens: self.tokens,
+        }
+    }
+}
+
+// Inputs are the same part of the same chunk
+impl PartialEq for Input<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset && self.fragment == other.fragment
+    }
+}
+
+impl<'a> From<&'a str> for Input<'a> {
+    fn from(fragment: &'a str) -> Self {
+        Input::new(fragment, &[])
+    }
+}
+
+impl fmt::Debug for Input<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fragment.fmt(f)
+    }
+}
+
+impl fmt::Display for Input<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fragment.fmt(f)
+    }
+}
+
+// The traits the nom parsers need from their input, done on the fragment
+
+impl InputLength for Input<'_> {
+    fn input_len(&self) -> usize {
+        self.fragment.len()
+    }
+}
+
+impl InputTake for Input<'_> {
+    fn take(&self, count: usize) -> Self {
+        self.range(0, count)
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        (self.range(count, self.fragment.len()), self.range(0, count))
+    }
+}
+
+impl<'a> InputIter for Input<'a> {
+    type Item = char;
+    type Iter = CharIndices<'a>;
+    type IterElem = Chars<'a>;
+
+    fn iter_indices(&self) -> Self::Iter {
+        self.fragment.char_indices()
+    }
+
+    fn iter_elements(&self) -> Self::IterElem {
+        self.fragment.chars()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.fragment.position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        self.fragment.slice_index(count)
+    }
+}
+
+impl InputTakeAtPosition for Input<'_> {
+    type Item = char;
+
+    fn split_at_position<P, E: ParseError<Self>>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        match self.fragment.find(predicate) {
+            Some(i) => Ok(self.take_split(i)),
+            None => Err(nom::Err::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1<P, E: ParseError<Self>>(
+        &self,
+        predicate: P,
+        e: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        match self.fragment.find(predicate) {
+            Some(0) => Err(nom::Err::Error(E::from_error_kind(*self, e))),
+            Some(i) => Ok(self.take_split(i)),
+            None => Err(nom::Err::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position_complete<P, E: ParseError<Self>>(
+        &self,
+        predicate: P,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        let i = self.fragment.find(predicate).unwrap_or(self.fragment.len());
+        Ok(self.take_split(i))
+    }
+
+    fn split_at_position1_complete<P, E: ParseError<Self>>(
+        &self,
+        predicate: P,
+        e: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        match self.fragment.find(predicate).unwrap_or(self.fragment.len()) {
+            0 => Err(nom::Err::Error(E::from_error_kind(*self, e))),
+            i => Ok(self.take_split(i)),
+        }
+    }
+}
+
+impl<'a, T> Compare<T> for Input<'a>
+where
+    &'a str: Compare<T>,
+{
+    fn compare(&self, t: T) -> CompareResult {
+        self.fragment.compare(t)
+    }
+
+    fn compare_no_case(&self, t: T) -> CompareResult {
+        self.fragment.compare_no_case(t)
+    }
+}
+
+impl Offset for Input<'_> {
+    fn offset(&self, second: &Self) -> usize {
+        second.offset - self.offset
+    }
+}
+
+impl Slice<Range<usize>> for Input<'_> {
+    fn slice(&self, range: Range<usize>) -> Self {
+        self.range(range.start, range.end)
+    }
+}
+
+impl Slice<RangeTo<usize>> for Input<'_> {
+    fn slice(&self, range: RangeTo<usize>) -> Self {
+        self.range(0, range.end)
+    }
+}
+
+impl Slice<RangeFrom<usize>> for Input<'_> {
+    fn slice(&self, range: RangeFrom<usize>) -> Self {
+        self.range(range.start, self.fragment.len())
+    }
+}
+
+impl Slice<RangeFull> for Input<'_> {
+    fn slice(&self, _: RangeFull) -> Self {
+        *self
+    }
+}
+
+/// Run `parser` on a bare string, so that the nodes get the default span, and
+/// give what is left as a string. For the tests of the parsers.
+#[cfg(test)]
+pub fn parse_str<'a, O>(
+    mut parser: impl FnMut(Input<'a>) -> IResult<Input<'a>, O>,
+    input: &'a str,
+) -> IResult<&'a str, O> {
+    parser(input.into())
+        .map(|(rest, output)| (rest.fragment(), output))
+        .map_err(|err| err.map_input(|rest| rest.fragment()))
+}
+
+/// Span of what a parser consumed from `input`, leaving `rest`: from its first
+/// token to its last one
+pub fn span_of(input: Input, rest: Input) -> Span {
+    let tokens = input.tokens;
+    let first = tokens.partition_point(|token| token.0 < input.offset);
+    let end = tokens.partition_point(|token| token.1 <= rest.offset);
+    if first < end {
+        Span {
+            start: tokens[first].2.start,
+            end: tokens[end - 1].2.end,
+        }
+    } else {
+        Span::default()
+    }
+}
+
+/// A combinator that takes a parser `inner` and produces a parser that also
+/// returns the span of what `inner` consumed, without the whitespace and
+/// comments around it.
+pub fn spanned<'a, O>(
+    mut inner: impl FnMut(Input<'a>) -> IResult<Input<'a>, O>,
+) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, (O, Span)> {
+    move |input| {
+        let (rest, output) = inner(input)?;
+        let span = span_of(input, rest);
+        Ok((rest, (output, span)))
+    }
 }
 
 // parser combinators are constructed from the bottom up:
@@ -32,7 +357,7 @@ where
 /// Parse a unicode sequence, of the form u{XXXX}, where XXXX is 1 to 6
 /// hexadecimal numerals. We will combine this later with parse_escaped_char
 /// to parse sequences like \u{00AC}.
-fn parse_unicode(input: &str) -> IResult<&str, char> {
+fn parse_unicode(input: Input<'_>) -> IResult<Input<'_>, char> {
     // `take_while_m_n` parses between `m` and `n` bytes (inclusive) that match
     // a predicate. `parse_hex` here parses between 1 and 6 hexadecimal numerals.
     let parse_hex = take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit());
@@ -50,7 +375,9 @@ fn parse_unicode(input: &str) -> IResult<&str, char> {
     // `map_res` takes the result of a parser and applies a function that returns
     // a Result. In this case we take the hex bytes from parse_hex and attempt to
     // convert them to a u32.
-    let parse_u32 = map_res(parse_delimited_hex, move |hex| u32::from_str_radix(hex, 16));
+    let parse_u32 = map_res(parse_delimited_hex, move |hex: Input| {
+        u32::from_str_radix(hex.fragment(), 16)
+    });
 
     // map_opt is like map_res, but it takes an Option instead of a Result. If
     // the function returns None, map_opt returns an error. In this case, because
@@ -60,7 +387,7 @@ fn parse_unicode(input: &str) -> IResult<&str, char> {
 }
 
 /// Parse an escaped character: \n, \t, \r, \u{00AC}, etc.
-fn parse_escaped_char(input: &str) -> IResult<&str, char> {
+fn parse_escaped_char(input: Input<'_>) -> IResult<Input<'_>, char> {
     preceded(
         char('\\'),
         // `alt` tries each parser in sequence, returning the result of
@@ -85,12 +412,12 @@ fn parse_escaped_char(input: &str) -> IResult<&str, char> {
 
 /// Parse a backslash, followed by any amount of whitespace. This is used later
 /// to discard any escaped whitespace.
-fn parse_escaped_whitespace(input: &str) -> IResult<&str, &str> {
+fn parse_escaped_whitespace(input: Input<'_>) -> IResult<Input<'_>, Input<'_>> {
     preceded(char('\\'), multispace1)(input)
 }
 
 /// Parse a non-empty block of text that doesn't include \ or "
-fn parse_literal(input: &str) -> IResult<&str, &str> {
+fn parse_literal(input: Input<'_>) -> IResult<Input<'_>, &str> {
     // `is_not` parses a string of 0 or more characters that aren't one of the
     // given characters.
     let not_quote_slash = is_not("\"\\");
@@ -99,7 +426,10 @@ fn parse_literal(input: &str) -> IResult<&str, &str> {
     // the parser. The verification function accepts out output only if it
     // returns true. In this case, we want to ensure that the output of is_not
     // is non-empty.
-    verify(not_quote_slash, |s: &str| !s.is_empty())(input)
+    map(
+        verify(not_quote_slash, |s: &Input| !s.fragment().is_empty()),
+        |s: Input| s.fragment(),
+    )(input)
 }
 
 /// A string fragment contains a fragment of a string being parsed: either
@@ -114,7 +444,7 @@ enum StringFragment<'a> {
 
 /// Combine parse_literal, parse_escaped_whitespace, and parse_escaped_char
 /// into a StringFragment.
-fn parse_fragment(input: &str) -> IResult<&str, StringFragment> {
+fn parse_fragment(input: Input<'_>) -> IResult<Input<'_>, StringFragment<'_>> {
     alt((
         // The `map` combinator runs a parser, then applies a function to the output
         // of that parser.
@@ -126,7 +456,7 @@ fn parse_fragment(input: &str) -> IResult<&str, StringFragment> {
 
 /// Parse a string. Use a loop of parse_fragment and push all of the fragments
 /// into an output string.
-pub fn parse_string(input: &str) -> IResult<&str, String> {
+pub fn parse_string(input: Input<'_>) -> IResult<Input<'_>, String> {
     // fold is the equivalent of iterator::fold. It runs a parser in a loop,
     // and for each output value, calls a folding function on each output value.
     let build_string = fold_many0(
@@ -154,23 +484,26 @@ pub fn parse_string(input: &str) -> IResult<&str, String> {
     delimited(char('"'), build_string, char('"'))(input)
 }
 
-pub fn identifier(input: &str) -> IResult<&str, &str> {
+pub fn identifier(input: Input<'_>) -> IResult<Input<'_>, &str> {
     const KEYWORDS: [&str; 22] = [
         "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
         "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
     ];
 
     verify(
-        recognize(pair(
-            alt((alpha1, tag("_"))),
-            many0_count(complete(alt((alphanumeric1, tag("_"))))),
-        )),
+        map(
+            recognize(pair(
+                alt((alpha1, tag("_"))),
+                many0_count(complete(alt((alphanumeric1, tag("_"))))),
+            )),
+            |result: Input| result.fragment(),
+        ),
         |result: &str| result != "_" && !KEYWORDS.contains(&result),
     )(input)
 }
 
-pub fn float(input: &str) -> IResult<&str, &str> {
-    alt((
+pub fn float(input: Input<'_>) -> IResult<Input<'_>, &str> {
+    let float = alt((
         // Case one: .42
         recognize(tuple((
             char('.'),
@@ -185,10 +518,11 @@ pub fn float(input: &str) -> IResult<&str, &str> {
             decimal,
         ))), // Case three: 42. and 42.42
         recognize(tuple((decimal, char('.'), opt(decimal)))),
-    ))(input)
+    ));
+    map(float, |result: Input| result.fragment())(input)
 }
 
-fn decimal(input: &str) -> IResult<&str, &str> {
+fn decimal(input: Input<'_>) -> IResult<Input<'_>, Input<'_>> {
     recognize(many1(terminated(one_of("0123456789"), many0(char('_')))))(input)
 }
 
@@ -199,8 +533,8 @@ mod tests {
     #[test]
     fn foo1() {
         let input = "1.45678";
-        let result = float(input);
+        let (rest, result) = float(input.into()).unwrap();
 
-        assert_eq!(result, Ok(("", "1.45678")));
+        assert_eq!((rest.fragment(), result), ("", "1.45678"));
     }
 }
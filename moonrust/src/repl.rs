@@ -0,0 +1,160 @@
+// Interactive mode of the interpreter: reads chunks line by line and runs them
+// in a single environment, so globals persist from one line to the next
+use moonrust::interpreter::environment::Env;
+use moonrust::interpreter::LuaValue;
+use moonrust::parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::mem;
+
+/// Outcome of a line of input
+#[derive(Debug)]
+pub enum Input {
+    /// The chunk is not finished, more lines are needed
+    Incomplete,
+    /// The chunk ran and returned these values
+    Values(Vec<LuaValue>),
+    /// The chunk could not be parsed or raised an error
+    Error(String),
+}
+
+pub struct Repl {
+    env: Env,
+    // Lines of the chunk read so far
+    pending: String,
+}
+
+impl Repl {
+    pub fn new(env: Env) -> Self {
+        Repl {
+            env,
+            pending: String::new(),
+        }
+    }
+
+    /// Whether the previous lines wait for the rest of their chunk
+    pub fn is_continuation(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Drop the lines of an unfinished chunk
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Add a line to the current chunk and run the chunk once it is complete.
+    /// A line starting with `=` and a chunk that is an expression list return
+    /// the values of the expressions.
+    pub fn feed(&mut self, line: &str) -> Input {
+        if self.pending.is_empty() {
+            self.pending = match line.strip_prefix('=') {
+                Some(exps) => format!("return {exps}"),
+                None => line.to_string(),
+            };
+        } else {
+            self.pending.push('\n');
+            self.pending.push_str(line);
+        }
+        let source = mem::take(&mut self.pending);
+
+        let expr = format!("return {source}");
+        let ast = match parser::parse_chunk(&expr) {
+            Ok(ast) => ast,
+            Err(_) => match parser::parse_chunk(&source) {
+                Ok(ast) => ast,
+                Err(_) if parser::is_incomplete(&source) => {
+                    self.pending = source;
+                    return Input::Incomplete;
+                }
+                Err(err) => return Input::Error(format!("Parse error [{err}]")),
+            },
+        };
+        match ast.exec_chunk(&mut self.env, "=stdin") {
+            Ok(vals) => Input::Values(vals),
+            Err(err) => Input::Error(format!("Runtime error [{err}]")),
+        }
+    }
+}
+
+/// Read, run and print chunks until the end of the input
+pub fn run(env: Env) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Terminal error [{err}]");
+            return;
+        }
+    };
+    let mut repl = Repl::new(env);
+    loop {
+        let prompt = if repl.is_continuation() { ">> " } else { "> " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                match repl.feed(&line) {
+                    Input::Incomplete => (),
+                    Input::Values(vals) if vals.is_empty() => (),
+                    Input::Values(vals) => {
+                        let vals: Vec<String> = vals.iter().map(LuaValue::to_string).collect();
+                        println!("{}", vals.join(" "));
+                    }
+                    Input::Error(err) => eprintln!("{err}"),
+                }
+            }
+            // Ctrl-C abandons the current chunk, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => repl.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Terminal error [{err}]");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: Input) -> String {
+        match input {
+            Input::Values(vals) => {
+                let vals: Vec<String> = vals.iter().map(LuaValue::to_string).collect();
+                vals.join(" ")
+            }
+            input => panic!("expected values, got {input:?}"),
+        }
+    }
+
+    #[test]
+    fn test_globals_persist() {
+        let mut repl = Repl::new(Env::new());
+        assert_eq!(values(repl.feed("x = 20")), "");
+        assert_eq!(values(repl.feed("x + 1, \"two\"")), "21 two");
+        assert_eq!(values(repl.feed("=x * 2")), "40");
+    }
+
+    #[test]
+    fn test_multi_line_chunks() {
+        let mut repl = Repl::new(Env::new());
+        assert!(matches!(repl.feed("function add(a, b)"), Input::Incomplete));
+        assert!(repl.is_continuation());
+        assert!(matches!(repl.feed("  return a + b"), Input::Incomplete));
+        assert_eq!(values(repl.feed("end")), "");
+        assert!(!repl.is_continuation());
+        assert!(matches!(repl.feed("s = \"first"), Input::Incomplete));
+        assert_eq!(values(repl.feed("second\"")), "");
+        assert_eq!(values(repl.feed("add(1, 2), s")), "3 first\nsecond");
+    }
+
+    #[test]
+    fn test_errors_reset_the_chunk() {
+        let mut repl = Repl::new(Env::new());
+        assert!(matches!(repl.feed("x = = 1"), Input::Error(_)));
+        assert!(!repl.is_continuation());
+        assert!(matches!(repl.feed("error(\"boom\")"), Input::Error(err) if err.contains("boom")));
+        assert!(matches!(repl.feed("do"), Input::Incomplete));
+        repl.clear();
+        assert_eq!(values(repl.feed("1")), "1");
+    }
+}
@@ -0,0 +1,572 @@
+// Static resolution of names, run before the compiler: every local variable
+// gets a register of the function declaring it, and every use of a name is
+// classified as a local, an upvalue or a global (a field of _ENV)
+use crate::ast::*;
+use crate::bytecode::{Reg, UpvalDesc, MAX_REGISTERS};
+use crate::interpreter::ASTExecError;
+use crate::stack::Level;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Where the value of a name lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarKind {
+    Local(Reg),
+    Upvalue(u8),
+    /// Field of _ENV, which is itself a local or an upvalue
+    Global(EnvVar),
+}
+
+/// The variable holding _ENV for a global name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvVar {
+    Local(Reg),
+    Upvalue(u8),
+}
+
+/// Declaration of a local variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalInfo {
+    pub reg: Reg,
+    /// Used by a nested function, so it must be closed when it goes out of scope
+    pub captured: bool,
+}
+
+// AST node identified by its address, so that two uses of the same name
+// are told apart
+struct NodeRef<'a, T>(&'a T);
+
+impl<T> PartialEq for NodeRef<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl<T> Eq for NodeRef<'_, T> {}
+
+impl<T> Hash for NodeRef<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+/// Result of the resolution of a chunk, borrowing its AST
+pub struct Resolution<'a> {
+    names: HashMap<NodeRef<'a, String>, VarKind>,
+    locals: HashMap<NodeRef<'a, String>, LocalInfo>,
+    functions: HashMap<NodeRef<'a, FuncBody>, Vec<UpvalDesc>>,
+    main_upvalues: Vec<UpvalDesc>,
+}
+
+impl<'a> Resolution<'a> {
+    /// Variable referred to by a use of a name in the chunk
+    pub fn name(&self, name: &'a String) -> VarKind {
+        *self
+            .names
+            .get(&NodeRef(name))
+            .expect("name was not resolved")
+    }
+
+    /// Local variable declared by a name of the chunk (local statement,
+    /// parameter, for loop variable or local function)
+    pub fn local(&self, name: &'a String) -> LocalInfo {
+        *self
+            .locals
+            .get(&NodeRef(name))
+            .expect("local was not declared")
+    }
+
+    /// Upvalues of a function of the chunk, in the order of their indices
+    pub fn upvalues(&self, body: &'a FuncBody) -> &[UpvalDesc] {
+        self.functions
+            .get(&NodeRef(body))
+            .expect("function was not resolved")
+    }
+
+    /// Upvalues of the main function: only _ENV
+    pub fn main_upvalues(&self) -> &[UpvalDesc] {
+        &self.main_upvalues
+    }
+}
+
+/// Resolve every name of the chunk
+pub fn resolve(ast: &AST) -> Result<Resolution<'_>, ASTExecError> {
+    let mut resolver = Resolver {
+        funcs: vec![],
+        resolution: Resolution {
+            names: HashMap::new(),
+            locals: HashMap::new(),
+            functions: HashMap::new(),
+            main_upvalues: vec![],
+        },
+    };
+    resolver.funcs.push(FuncScope::default());
+    resolver.funcs[0].upvalues.push(UpvalDesc {
+        name: String::from("_ENV"),
+        in_stack: true,
+        index: 0,
+    });
+    resolver.block(&ast.0)?;
+    let main = resolver.funcs.pop().expect("no function being resolved");
+    resolver.resolution.main_upvalues = main.upvalues;
+    Ok(resolver.resolution)
+}
+
+struct ActiveLocal<'a> {
+    name: &'a str,
+    reg: Reg,
+    decl: Option<&'a String>, // None for the hidden state of for loops
+    attrib: Option<Attrib>,
+}
+
+#[derive(Default)]
+struct FuncScope<'a> {
+    actives: Vec<ActiveLocal<'a>>,
+    blocks: Vec<usize>, // Active locals when each block was entered
+    upvalues: Vec<UpvalDesc>,
+}
+
+struct Resolver<'a> {
+    funcs: Vec<FuncScope<'a>>, // Enclosing functions, the innermost last
+    resolution: Resolution<'a>,
+}
+
+impl<'a> Resolver<'a> {
+    fn scope(&mut self) -> &mut FuncScope<'a> {
+        self.funcs.last_mut().expect("no function being resolved")
+    }
+
+    fn enter_block(&mut self) {
+        let scope = self.scope();
+        let num_locals = scope.actives.len();
+        scope.blocks.push(num_locals);
+    }
+
+    fn leave_block(&mut self) {
+        let scope = self.scope();
+        let num_locals = scope.blocks.pop().expect("no block to leave");
+        scope.actives.truncate(num_locals);
+    }
+
+    // Locals take the registers after the ones of the active locals
+    fn declare(&mut self, name: &'a str, decl: Option<&'a String>) -> Result<(), ASTExecError> {
+        let scope = self.scope();
+        let reg = scope.actives.len();
+        if reg >= MAX_REGISTERS {
+            return Err(ASTExecError::new(
+                "function or expression needs too many registers",
+            ));
+        }
+        scope.actives.push(ActiveLocal {
+            name,
+            reg: reg as Reg,
+            decl,
+            attrib: None,
+        });
+        if let Some(decl) = decl {
+            self.resolution.locals.insert(
+                NodeRef(decl),
+                LocalInfo {
+                    reg: reg as Reg,
+                    captured: false,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn declare_local(&mut self, name: &'a String) -> Result<(), ASTExecError> {
+        self.declare(name, Some(name))
+    }
+
+    fn declare_hidden(&mut self, count: usize) -> Result<(), ASTExecError> {
+        for _ in 0..count {
+            self.declare("(for state)", None)?;
+        }
+        Ok(())
+    }
+
+    // Find a name in the function at `level` of the stack of functions
+    fn find(&mut self, level: usize, name: &str) -> Result<Option<VarKind>, ASTExecError> {
+        let scope = &self.funcs[level];
+        if let Some(local) = scope.actives.iter().rev().find(|local| local.name == name) {
+            return Ok(Some(VarKind::Local(local.reg)));
+        }
+        if let Some(i) = scope.upvalues.iter().position(|up| up.name == name) {
+            return Ok(Some(VarKind::Upvalue(i as u8)));
+        }
+        if level == 0 {
+            return Ok(None);
+        }
+        let (in_stack, index) = match self.find(level - 1, name)? {
+            Some(VarKind::Local(reg)) => {
+                self.capture(level - 1, reg);
+                (true, reg)
+            }
+            Some(VarKind::Upvalue(index)) => (false, index),
+            _ => return Ok(None),
+        };
+        let upvalues = &mut self.funcs[level].upvalues;
+        if upvalues.len() > u8::MAX as usize {
+            return Err(ASTExecError::new("too many upvalues in function"));
+        }
+        upvalues.push(UpvalDesc {
+            name: name.to_string(),
+            in_stack,
+            index,
+        });
+        Ok(Some(VarKind::Upvalue((upvalues.len() - 1) as u8)))
+    }
+
+    // The local in `reg` of the function at `level` is used by a nested function
+    fn capture(&mut self, level: usize, reg: Reg) {
+        let scope = &self.funcs[level];
+        let decl = scope
+            .actives
+            .iter()
+            .rev()
+            .find(|local| local.reg == reg)
+            .and_then(|local| local.decl);
+        if let Some(decl) = decl {
+            if let Some(local) = self.resolution.locals.get_mut(&NodeRef(decl)) {
+                local.captured = true;
+            }
+        }
+    }
+
+    // Resolve a name being assigned to, which can't be a const or to-be-closed local
+    fn assigned_name(&mut self, name: &'a String) -> Result<(), ASTExecError> {
+        let local = self
+            .funcs
+            .iter()
+            .rev()
+            .find_map(|scope| scope.actives.iter().rev().find(|local| local.name == name));
+        if local.is_some_and(|local| local.attrib.is_some()) {
+            return Err(ASTExecError::new(&format!(
+                "attempt to assign to const variable '{name}'"
+            )));
+        }
+        self.name(name)
+    }
+
+    // Resolve a use of a name, free names are fields of _ENV
+    fn name(&mut self, name: &'a String) -> Result<(), ASTExecError> {
+        let level = self.funcs.len() - 1;
+        let kind = match self.find(level, name)? {
+            Some(kind) => kind,
+            None => match self.find(level, "_ENV")? {
+                Some(VarKind::Local(reg)) => VarKind::Global(EnvVar::Local(reg)),
+                Some(VarKind::Upvalue(index)) => VarKind::Global(EnvVar::Upvalue(index)),
+                _ => return Err(ASTExecError::new("_ENV is not in scope")),
+            },
+        };
+        self.resolution.names.insert(NodeRef(name), kind);
+        Ok(())
+    }
+
+    fn function(&mut self, body: &'a FuncBody) -> Result<(), ASTExecError> {
+        self.funcs.push(FuncScope::default());
+        for name in &body.par_list.0 {
+            self.declare_local(name)?;
+        }
+        self.block(&body.block)?;
+        let scope = self.funcs.pop().expect("no function being resolved");
+        self.resolution
+            .functions
+            .insert(NodeRef(body), scope.upvalues);
+        Ok(())
+    }
+
+    // Statements of a block, in the scope opened by the caller
+    fn block(&mut self, block: &'a Block) -> Result<(), ASTExecError> {
+        for statement in &block.statements {
+            self.statement(statement)?;
+        }
+        if let Some(explist) = &block.return_stat {
+            self.explist(explist)?;
+        }
+        Ok(())
+    }
+
+    fn scoped_block(&mut self, block: &'a Block) -> Result<(), ASTExecError> {
+        self.enter_block();
+        self.block(block)?;
+        self.leave_block();
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &'a Statement) -> Result<(), ASTExecError> {
+        match statement {
+            Statement::Semicolon(_) | Statement::Break(_) => {}
+            Statement::Assignment((varlist, explist, true), _) => {
+                // The new locals are not visible in their own initializers
+                self.explist(explist)?;
+                for var in varlist {
+                    match var {
+                        Var::Name(name) => self.declare_local(name)?,
+                        _ => return Err(ASTExecError::new("cannot declare a field as local")),
+                    }
+                }
+            }
+            Statement::Assignment((varlist, explist, false), _) => {
+                for var in varlist {
+                    match var {
+                        Var::Name(name) => self.assigned_name(name)?,
+                        var => self.var(var)?,
+                    }
+                }
+                self.explist(explist)?;
+            }
+            Statement::LocalAttribs((names, explist), _) => {
+                self.explist(explist)?;
+                for (name, attrib) in names {
+                    self.declare_local(name)?;
+                    self.scope().actives.last_mut().unwrap().attrib = *attrib;
+                }
+            }
+            Statement::FunctionCall(funcall, _) => self.funcall(funcall)?,
+            Statement::DoBlock(block, _) => self.scoped_block(block)?,
+            Statement::While((exp, block), _) => {
+                self.exp(exp)?;
+                self.scoped_block(block)?;
+            }
+            Statement::Repeat((block, exp), _) => {
+                // The condition can refer to the locals of the block
+                self.enter_block();
+                self.block(block)?;
+                self.exp(exp)?;
+                self.leave_block();
+            }
+            Statement::If((exp, block, elseifs, elseblock), _) => {
+                self.exp(exp)?;
+                self.scoped_block(block)?;
+                for (exp, block) in elseifs {
+                    self.exp(exp)?;
+                    self.scoped_block(block)?;
+                }
+                if let Some(elseblock) = elseblock {
+                    self.scoped_block(elseblock)?;
+                }
+            }
+            Statement::ForNum((name, initial, limit, step, block), _) => {
+                self.exp(initial)?;
+                self.exp(limit)?;
+                if let Some(step) = step {
+                    self.exp(step)?;
+                }
+                // Initial value, limit and step
+                self.enter_block();
+                self.declare_hidden(3)?;
+                self.enter_block();
+                self.declare_local(name)?;
+                self.block(block)?;
+                self.leave_block();
+                self.leave_block();
+            }
+            Statement::ForGeneric((names, explist, block), _) => {
+                self.explist(explist)?;
+                // Iterator function, state and control variable
+                self.enter_block();
+                self.declare_hidden(3)?;
+                self.enter_block();
+                for name in names {
+                    self.declare_local(name)?;
+                }
+                self.block(block)?;
+                self.leave_block();
+                self.leave_block();
+            }
+            Statement::FunctionDecl((name, body), _) => {
+                self.assigned_name(name)?;
+                self.function(body)?;
+            }
+            Statement::LocalFuncDecl((name, body), _) => {
+                // The function can refer to itself
+                self.declare_local(name)?;
+                self.function(body)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn var(&mut self, var: &'a Var) -> Result<(), ASTExecError> {
+        match var {
+            Var::Name(name) => self.name(name),
+            Var::Bracket((prefixexp, exp)) => {
+                self.prefixexp(prefixexp)?;
+                self.exp(exp)
+            }
+            Var::Dot((prefixexp, _)) => self.prefixexp(prefixexp),
+        }
+    }
+
+    fn explist(&mut self, explist: &'a [Expression]) -> Result<(), ASTExecError> {
+        for exp in explist {
+            self.exp(exp)?;
+        }
+        Ok(())
+    }
+
+    fn exp(&mut self, exp: &'a Expression) -> Result<(), ASTExecError> {
+        let Some(_level) = Level::enter() else {
+            return Err(ASTExecError::new("chunk has too many syntax levels"));
+        };
+        match exp {
+            Expression::Nil(_)
+            | Expression::False(_)
+            | Expression::True(_)
+            | Expression::Numeral(_, _)
+            | Expression::LiteralString(_, _)
+            | Expression::DotDotDot(_) => Ok(()),
+            Expression::FunctionDef(body, _) => self.function(body),
+            Expression::PrefixExp(prefixexp, _) => self.prefixexp(prefixexp),
+            Expression::TableConstructor(fields, _) => self.fields(fields),
+            Expression::BinaryOp(_, _) => {
+                // A chain of binary operators is as long as the source makes
+                // it, so its operands are visited in a loop, one level deeper
+                let mut pending = vec![exp];
+                while let Some(exp) = pending.pop() {
+                    match exp {
+                        Expression::BinaryOp((left, _, right), _) => {
+                            pending.push(right);
+                            pending.push(left);
+                        }
+                        operand => self.exp(operand)?,
+                    }
+                }
+                Ok(())
+            }
+            Expression::UnaryOp((_, exp), _) => self.exp(exp),
+        }
+    }
+
+    fn prefixexp(&mut self, prefixexp: &'a PrefixExp) -> Result<(), ASTExecError> {
+        match prefixexp {
+            PrefixExp::Var(var) => self.var(var),
+            PrefixExp::FunctionCall(funcall) => self.funcall(funcall),
+            PrefixExp::Exp(exp) => self.exp(exp),
+        }
+    }
+
+    fn funcall(&mut self, funcall: &'a FunctionCall) -> Result<(), ASTExecError> {
+        let args = match funcall {
+            FunctionCall::Standard((func, args)) => {
+                self.prefixexp(func)?;
+                args
+            }
+            FunctionCall::Method((object, _, args)) => {
+                self.prefixexp(object)?;
+                args
+            }
+        };
+        match args {
+            Args::ExpList(explist) => self.explist(explist),
+            Args::TableConstructor(fields) => self.fields(fields),
+            Args::LiteralString(_) => Ok(()),
+        }
+    }
+
+    fn fields(&mut self, fields: &'a [Field]) -> Result<(), ASTExecError> {
+        for field in fields {
+            match field {
+                Field::Bracketed((key, val)) => {
+                    self.exp(key)?;
+                    self.exp(val)?;
+                }
+                Field::Name((_, val)) => self.exp(val)?,
+                Field::Unnamed(exp) => self.exp(exp)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Name of a variable, or of the variable read by an expression
+    fn var_name(var: &Var) -> &String {
+        match var {
+            Var::Name(name) => name,
+            _ => panic!("not a name"),
+        }
+    }
+    fn exp_name(exp: &Expression) -> &String {
+        match exp {
+            Expression::PrefixExp(prefixexp, _) => match prefixexp.as_ref() {
+                PrefixExp::Var(var) => var_name(var),
+                _ => panic!("not a name"),
+            },
+            _ => panic!("not a name"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_locals_and_globals() {
+        let ast = "local a = 1 local b = a c = b".parse::<AST>().unwrap();
+        let resolution = resolve(&ast).unwrap();
+        let (a, (b, b_exps), (c, c_exps)) = match &ast.0.statements[..] {
+            [Statement::Assignment((a, _, true), _), Statement::Assignment((b, b_exps, true), _), Statement::Assignment((c, c_exps, false), _)] => {
+                (a, (b, b_exps), (c, c_exps))
+            }
+            _ => panic!("unexpected statements"),
+        };
+
+        assert_eq!(
+            resolution.local(var_name(&a[0])),
+            LocalInfo {
+                reg: 0,
+                captured: false
+            }
+        );
+        assert_eq!(resolution.local(var_name(&b[0])).reg, 1);
+        assert_eq!(resolution.name(exp_name(&b_exps[0])), VarKind::Local(0));
+        assert_eq!(
+            resolution.name(var_name(&c[0])),
+            VarKind::Global(EnvVar::Upvalue(0))
+        );
+        assert_eq!(resolution.name(exp_name(&c_exps[0])), VarKind::Local(1));
+    }
+
+    #[test]
+    fn test_resolve_captured_locals() {
+        let ast = "local a, b = 1, 2 local function f() return b end"
+            .parse::<AST>()
+            .unwrap();
+        let resolution = resolve(&ast).unwrap();
+        let (names, body) = match (&ast.0.statements[0], &ast.0.statements[1]) {
+            (
+                Statement::Assignment((names, _, true), _),
+                Statement::LocalFuncDecl((_, body), _),
+            ) => (names, body),
+            _ => panic!("unexpected statements"),
+        };
+        let captured: Vec<bool> = names
+            .iter()
+            .map(|var| resolution.local(var_name(var)).captured)
+            .collect();
+        assert_eq!(captured, vec![false, true]);
+        assert_eq!(
+            resolution.upvalues(body),
+            [UpvalDesc {
+                name: String::from("b"),
+                in_stack: true,
+                index: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resolve_local_env() {
+        let ast = "local _ENV = {} x = 1".parse::<AST>().unwrap();
+        let resolution = resolve(&ast).unwrap();
+        match &ast.0.statements[1] {
+            Statement::Assignment((varlist, _, false), _) => assert_eq!(
+                resolution.name(var_name(&varlist[0])),
+                VarKind::Global(EnvVar::Local(0))
+            ),
+            _ => panic!("unexpected statement"),
+        }
+    }
+}
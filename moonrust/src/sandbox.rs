@@ -0,0 +1,278 @@
+// Environments for untrusted scripts, exposing only the built-in functions
+// chosen by the host.
+//
+// A sandbox starts from the globals of `Env::new` and removes every global it
+// doesn't expose. `loadfile` and `dofile` are never exposed, and the `package`
+// library can only require the modules of `package.preload`, which the host
+// registers: a sandboxed script can't read files. `load` only loads text
+// chunks, there are no binary chunks to load.
+use crate::interpreter::environment::Env;
+use crate::interpreter::package;
+use crate::interpreter::{LuaVal, LuaValue};
+use std::fmt::{self, Display, Formatter};
+
+/// Group of built-in functions a sandbox can expose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Library {
+    /// Functions working on values only: `pairs`, `ipairs`, `next`, `type`,
+    /// `error`, `pcall`, the raw accessors, the metatable functions, `load`,
+    /// `collectgarbage`, and `_G`
+    Base,
+    /// The `coroutine` table
+    Coroutine,
+    /// `require` and the `package` table, without the file searchers
+    Package,
+    /// `random`
+    Math,
+    /// `print` and `read`, on the standard streams of the host
+    Io,
+}
+
+const LIBRARIES: [(Library, &[&str]); 5] = [
+    (
+        Library::Base,
+        &[
+            "_G",
+            "setmetatable",
+            "getmetatable",
+            "rawget",
+            "rawset",
+            "rawequal",
+            "rawlen",
+            "next",
+            "pairs",
+            "ipairs",
+            "error",
+            "pcall",
+            "type",
+            "load",
+            "collectgarbage",
+        ],
+    ),
+    (Library::Coroutine, &["coroutine"]),
+    (Library::Package, &["require", "package"]),
+    (Library::Math, &["random"]),
+    (Library::Io, &["print", "read"]),
+];
+
+/// Error of a sandbox asked to expose a function that isn't a built-in
+#[derive(Debug, PartialEq)]
+pub struct SandboxError(String);
+impl Display for SandboxError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "'{}' is not a function a sandbox can expose", self.0)
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+/// Profile of a sandboxed environment, exposing nothing until libraries or
+/// functions are added.
+///
+/// ```
+/// use moonrust::sandbox::{Library, Sandbox};
+/// use moonrust::Lua;
+///
+/// let sandbox = Sandbox::new()
+///     .library(Library::Base)
+///     .library(Library::Coroutine)
+///     .function("print")
+///     .unwrap()
+///     .without("load")
+///     .read_only_globals();
+/// let mut lua = Lua::sandboxed(&sandbox);
+/// assert!(lua.globals().contains("pairs"));
+/// assert!(!lua.globals().contains("read"));
+/// assert!(lua.load("x = 1").exec().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    functions: Vec<&'static str>, // Names of the exposed globals
+    read_only: bool,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Sandbox::default()
+    }
+
+    /// Expose every function of the library
+    pub fn library(mut self, library: Library) -> Self {
+        for (lib, names) in LIBRARIES {
+            if lib == library {
+                self.functions.extend(names);
+            }
+        }
+        self
+    }
+
+    /// Expose one function of a library, by its global name. Fails if
+    /// `name` isn't a function of one of the libraries.
+    pub fn function(mut self, name: &str) -> Result<Self, SandboxError> {
+        let found = LIBRARIES
+            .iter()
+            .flat_map(|(_, names)| names.iter())
+            .find(|function| **function == name);
+        match found {
+            Some(function) => self.functions.push(function),
+            None => return Err(SandboxError(name.to_string())),
+        }
+        Ok(self)
+    }
+
+    /// Hide a function exposed by one of the libraries
+    pub fn without(mut self, name: &str) -> Self {
+        self.functions.retain(|function| *function != name);
+        self
+    }
+
+    /// Make the global table, and the library tables in it, read-only for
+    /// the scripts: they can't assign globals, even with `rawset`, nor change
+    /// the metatable of `_G`, nor replace the modules of `package.preload`
+    /// and `package.loaded`. The host can still set globals and register
+    /// modules.
+    pub fn read_only_globals(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// A new environment with the globals of the profile
+    pub fn build(&self) -> Env {
+        let mut env = Env::new();
+        for name in env.global_names() {
+            if !self.functions.contains(&name.as_str()) {
+                env.insert_global(name, LuaValue::new(LuaVal::LuaNil));
+            }
+        }
+        if let Some(package) = env.get_global("package") {
+            package::remove_file_searchers(&package, &mut env);
+        }
+        if self.read_only {
+            let libraries = self
+                .functions
+                .iter()
+                .filter_map(|name| env.get_global(name));
+            for val in libraries.chain([env.get_global_env()]) {
+                if let LuaVal::LuaTable(table) = &val.0 {
+                    table.set_read_only();
+                }
+            }
+            if let Some(package) = env.get_global("package") {
+                package::set_read_only(&package);
+            }
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::ASTExecError;
+    use crate::AST;
+    use std::fs;
+
+    fn run(src: &str, env: &mut Env) -> Result<Vec<String>, ASTExecError> {
+        let vals = src.parse::<AST>().unwrap().exec_with_return(env)?;
+        Ok(vals.iter().map(LuaValue::to_string).collect())
+    }
+
+    #[test]
+    fn test_exposed_functions() {
+        let mut env = Sandbox::new()
+            .library(Library::Base)
+            .function("random")
+            .unwrap()
+            .without("collectgarbage")
+            .build();
+        let src = "return type(pairs), type(random), collectgarbage, read, print,
+            dofile, loadfile, require, package, coroutine, _G == _ENV";
+        assert_eq!(
+            run(src, &mut env).unwrap(),
+            [
+                "function", "function", "nil", "nil", "nil", "nil", "nil", "nil", "nil", "nil",
+                "true"
+            ]
+        );
+        // Chunks loaded by the script see the same globals
+        assert_eq!(
+            run("return load(\"return dofile, read\")()", &mut env).unwrap(),
+            ["nil", "nil"]
+        );
+        assert_eq!(
+            run("return load(\"return 1\", \"chunk\", \"b\")", &mut env).unwrap(),
+            ["nil", "attempt to load a text chunk (mode is 'b')"]
+        );
+        assert_eq!(Sandbox::new().build().global_names(), Vec::<String>::new());
+        assert_eq!(
+            Sandbox::new().function("loadfile").unwrap_err().to_string(),
+            "'loadfile' is not a function a sandbox can expose"
+        );
+    }
+
+    #[test]
+    fn test_require_without_files() {
+        let dir = std::env::temp_dir().join("moonrust_sandbox_require");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret.lua"), "return \"secret\"").unwrap();
+        let src = format!(
+            "package.path = \"{}/?.lua\"
+            return pcall(require, \"secret\")",
+            dir.display()
+        );
+        // The module is found outside a sandbox
+        assert_eq!(run(&src, &mut Env::new()).unwrap()[..2], ["true", "secret"]);
+
+        let mut env = Sandbox::new()
+            .library(Library::Base)
+            .library(Library::Package)
+            .build();
+        let vals = run(&src, &mut env).unwrap();
+        assert_eq!(vals[0], "false");
+        assert!(!vals[1].contains("secret.lua"), "{}", vals[1]);
+        assert_eq!(
+            run("return package.searchpath, #package.searchers", &mut env).unwrap(),
+            ["nil", "1"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_globals() {
+        let mut env = Sandbox::new()
+            .library(Library::Base)
+            .library(Library::Coroutine)
+            .library(Library::Package)
+            .read_only_globals()
+            .build();
+        let attempts = [
+            "x = 1",
+            "pairs = nil",
+            "_G.x = 1",
+            "rawset(_G, \"x\", 1)",
+            "setmetatable(_G, {__index = function() return 1 end})",
+            "coroutine.create = nil",
+            "local env = _ENV env.x = 1",
+            "package.preload.auth = function() return {allow = true} end",
+            "package.loaded.auth = {allow = true}",
+            "package.searchers[1] = function() end",
+            "setmetatable(package.loaded, {})",
+        ];
+        for attempt in attempts {
+            let src = format!("return pcall(function() {attempt} end)");
+            let vals = run(&src, &mut env).unwrap();
+            assert_eq!(vals[0], "false", "{attempt}");
+        }
+        assert_eq!(
+            run(
+                "local x = 1 local t = {} t.x = x return x, t.x, rawget(_G, \"x\")",
+                &mut env
+            )
+            .unwrap(),
+            ["1", "1", "nil"]
+        );
+        // The host can still set globals
+        env.insert_global(String::from("x"), LuaValue::new(LuaVal::LuaInt(2)));
+        assert_eq!(run("return x", &mut env).unwrap(), ["2"]);
+    }
+}
@@ -0,0 +1,57 @@
+// Guard against overflows of the native stack. The parser and the compiler
+// recurse on nested expressions and blocks, and every call from Rust code into
+// Lua code (metamethods, functions called by built-ins, ...) runs the VM again.
+// Each of them enters a level, which fails once the nesting gets too deep.
+use std::cell::Cell;
+
+/// Levels of nesting allowed, as in the reference implementation
+pub const MAX_LEVELS: usize = 200;
+
+/// Native stack that the nested levels may use, in bytes. Threads usually have
+/// at least 2 MB of stack, and debug builds need much more of it per level.
+pub const STACK_BUDGET: usize = 1 << 20;
+
+thread_local! {
+    static LEVELS: Cell<usize> = const { Cell::new(0) };
+    // Stack address of the outermost level
+    static BASE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A level of nesting, left when dropped
+pub struct Level(());
+
+impl Level {
+    /// Enter a new level, or None if the nesting is too deep
+    pub fn enter() -> Option<Level> {
+        let marker = 0u8;
+        let address = std::ptr::addr_of!(marker) as usize;
+        let levels = LEVELS.with(Cell::get);
+        if levels == 0 {
+            BASE.with(|base| base.set(address));
+        } else if levels >= MAX_LEVELS || BASE.with(Cell::get).abs_diff(address) > STACK_BUDGET {
+            return None;
+        }
+        LEVELS.with(|l| l.set(levels + 1));
+        Some(Level(()))
+    }
+}
+
+impl Drop for Level {
+    fn drop(&mut self) {
+        LEVELS.with(|levels| levels.set(levels.get() - 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_are_limited() {
+        let levels: Vec<Level> = (0..MAX_LEVELS).map_while(|_| Level::enter()).collect();
+        assert_eq!(levels.len(), MAX_LEVELS);
+        assert!(Level::enter().is_none());
+        drop(levels);
+        assert!(Level::enter().is_some());
+    }
+}
@@ -0,0 +1,2220 @@
+// Register-based virtual machine running the bytecode of compiled functions
+use crate::ast::BinOp;
+use crate::bytecode::*;
+use crate::debug::{self, HookEvent};
+use crate::gc;
+use crate::interpreter::environment::Env;
+use crate::gc::Child;
+use crate::interpreter::expression::Lookup;
+use crate::interpreter::{ASTExecError, LuaString, LuaTable, LuaVal, LuaValue};
+use crate::limits;
+use crate::stack::Level;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fmt::Formatter;
+use std::mem;
+use std::rc::Rc;
+
+/// Variable captured by a closure. It stays in the register of the function
+/// declaring it while that function runs, and moves into the upvalue once the
+/// variable goes out of scope.
+#[derive(Debug, PartialEq)]
+pub enum Upvalue {
+    Open(usize), // Index in the stack
+    Closed(LuaValue),
+}
+
+pub type UpvalRef = Rc<RefCell<Upvalue>>;
+
+/// Compiled Lua function with its upvalues
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub upvalues: Vec<UpvalRef>,
+}
+
+impl Closure {
+    /// Main function of a chunk, running in the environment `env`
+    pub fn main(proto: Rc<Proto>, env: LuaValue) -> Self {
+        Closure {
+            proto,
+            upvalues: vec![Rc::new(RefCell::new(Upvalue::Closed(env)))],
+        }
+    }
+}
+
+// Upvalues usually refer to tables containing the closure itself,
+// so closures are compared and printed by reference
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("proto", &Rc::as_ptr(&self.proto))
+            .field("upvalues", &self.upvalues.len())
+            .finish()
+    }
+}
+
+/// How a frame was called, for the debug interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CallKind {
+    Call,     // By an instruction of the frame below
+    TailCall, // By the function it replaced
+    Rust,     // By Rust code, pcall or a metamethod
+}
+
+/// What the VM does with the results of a frame when it returns
+#[derive(Debug, Clone, PartialEq)]
+enum Continuation {
+    Results,   // Stores them for the caller
+    Protected, // Stores true before them, or false and the error that stopped the frame (pcall)
+    Method(LuaValue), // Stores the method of this name found by __index, which must be callable
+}
+
+pub(crate) struct CallFrame {
+    pub(crate) closure: Rc<Closure>,
+    pub(crate) base: usize, // Stack index of register 0
+    pub(crate) pc: usize,   // Saved when the frame calls a function or a hook
+    pub(crate) varargs: Vec<LuaValue>,
+    ret: usize,              // Stack index of the first result in the caller
+    nresults: Option<usize>, // Results expected by the caller, None for all of them
+    pub(crate) kind: CallKind,
+    continuation: Continuation,
+}
+
+impl CallFrame {
+    /// Instruction running in the frame
+    pub(crate) fn current_pc(&self) -> usize {
+        self.pc.saturating_sub(1)
+    }
+}
+
+/// Default limit of nested Lua calls, past which a call raises "stack overflow"
+pub const MAX_CALL_DEPTH: usize = 200_000;
+
+/// Stack of values and call frames of the running Lua functions
+#[derive(Default)]
+pub struct Thread {
+    stack: Vec<LuaValue>,
+    frames: Vec<CallFrame>,
+    open_upvalues: Vec<UpvalRef>, // Sorted by stack index
+    detached: Vec<usize>,         // Stack indices of the open upvalues while another thread runs
+    tbc: Vec<usize>,              // Stack indices of the to-be-closed variables
+    max_depth: usize,  // Limit of frames
+    coroutine: bool,   // Runs a coroutine, which can yield
+    nested: usize,     // Calls from Rust code running on this thread
+    resume_at: Option<(usize, Option<usize>, Continuation)>, // Where the call to yield expects its results
+}
+
+impl Thread {
+    pub fn new() -> Self {
+        Thread {
+            max_depth: MAX_CALL_DEPTH,
+            ..Thread::default()
+        }
+    }
+
+    pub(crate) fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    pub(crate) fn stack(&self) -> &[LuaValue] {
+        &self.stack
+    }
+
+    pub(crate) fn stack_mut(&mut self) -> &mut [LuaValue] {
+        &mut self.stack
+    }
+
+    /// Frame of the Lua function running at `level`, 0 being the innermost
+    pub(crate) fn frame(&self, level: usize) -> Option<&CallFrame> {
+        let index = self.frames.len().checked_sub(level + 1)?;
+        self.frames.get(index)
+    }
+
+    pub(crate) fn frame_mut(&mut self, level: usize) -> Option<&mut CallFrame> {
+        let index = self.frames.len().checked_sub(level + 1)?;
+        self.frames.get_mut(index)
+    }
+
+    /// Error unless the running function can yield: it must run in a
+    /// coroutine, and not be called from Rust code (a function called by a
+    /// built-in other than pcall, a hook, a `__close` metamethod, ...)
+    pub(crate) fn check_yield(&self) -> Result<(), ASTExecError> {
+        if !self.coroutine {
+            Err(ASTExecError::new("attempt to yield from outside a coroutine"))
+        } else if self.nested > 0 {
+            Err(ASTExecError::new("attempt to yield across a C-call boundary"))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Whether a call to the value runs in the VM loop: a Lua function, yield
+    // if the thread can yield, or a table whose `__call` metamethod is one
+    // of them
+    fn runs_in_loop(&self, func: &LuaValue) -> bool {
+        let handler = func.call_metamethod();
+        match handler.as_ref().unwrap_or(func).0 {
+            LuaVal::Function(_) => true,
+            LuaVal::CoroutineYield => self.check_yield().is_ok(),
+            _ => false,
+        }
+    }
+
+    // Suspend the coroutine at the call to yield in `func_index`, returning
+    // the arguments of the call. The values passed to the next resume are
+    // the results of the call, stored at `ret` like the results of a frame
+    // with the continuation.
+    fn yield_call(
+        &mut self,
+        func_index: usize,
+        nargs: usize,
+        ret: usize,
+        nresults: Option<usize>,
+        continuation: Continuation,
+    ) -> Result<Vec<LuaValue>, ASTExecError> {
+        self.check_yield()?;
+        let values = self.stack[func_index + 1..func_index + 1 + nargs].to_vec();
+        self.stack[ret + 1..].fill(nil());
+        self.resume_at = Some((ret, nresults, continuation));
+        Ok(values)
+    }
+
+    // Error if another frame would exceed the call depth limit
+    fn check_depth(&self) -> Result<(), ASTExecError> {
+        if self.frames.len() >= self.max_depth {
+            Err(ASTExecError::new("stack overflow"))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Start running `closure` with its `nargs` arguments at `base`
+    fn push_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        base: usize,
+        nargs: usize,
+        ret: usize,
+        nresults: Option<usize>,
+    ) {
+        let proto = &closure.proto;
+        let num_params = proto.num_params as usize;
+        let varargs = if proto.is_vararg && nargs > num_params {
+            self.stack[base + num_params..base + nargs].to_vec()
+        } else {
+            vec![]
+        };
+        // Missing parameters and the other registers start as nil
+        let frame_top = base + proto.max_stack as usize;
+        self.stack.truncate(base + nargs.min(num_params));
+        self.stack.resize(frame_top, nil());
+        self.frames.push(CallFrame {
+            closure,
+            base,
+            pc: 0,
+            varargs,
+            ret,
+            nresults,
+            kind: CallKind::Call,
+            continuation: Continuation::Results,
+        });
+    }
+
+    // Store the results of a call at `ret` as its continuation requires,
+    // returning the index after the last one
+    fn finish_call(
+        &mut self,
+        ret: usize,
+        mut results: Vec<LuaValue>,
+        nresults: Option<usize>,
+        continuation: Continuation,
+    ) -> Result<usize, ASTExecError> {
+        match continuation {
+            Continuation::Results => (),
+            Continuation::Protected => results.insert(0, LuaValue::new(LuaVal::LuaBool(true))),
+            Continuation::Method(name) => {
+                let method = results.into_iter().next().unwrap_or_else(nil);
+                check_method(&method, &name)?;
+                results = vec![method];
+            }
+        }
+        Ok(self.place_results(ret, results, nresults))
+    }
+
+    // Store the results of a call at `dest`, returning the index after the last one
+    fn place_results(
+        &mut self,
+        dest: usize,
+        results: Vec<LuaValue>,
+        nresults: Option<usize>,
+    ) -> usize {
+        let count = nresults.unwrap_or(results.len());
+        if self.stack.len() < dest + count {
+            self.stack.resize(dest + count, nil());
+        }
+        let mut results = results.into_iter();
+        for i in 0..count {
+            let val = results.next().unwrap_or_else(nil);
+            self.stack[dest + i] = val;
+        }
+        dest + count
+    }
+
+    pub(crate) fn get_upvalue(&self, upval: &UpvalRef) -> LuaValue {
+        match &*upval.borrow() {
+            Upvalue::Open(index) => self.stack[*index].clone(),
+            Upvalue::Closed(val) => val.clone(),
+        }
+    }
+
+    pub(crate) fn set_upvalue(&mut self, upval: &UpvalRef, val: LuaValue) {
+        match &mut *upval.borrow_mut() {
+            Upvalue::Open(index) => self.stack[*index] = val,
+            Upvalue::Closed(closed) => *closed = val,
+        }
+    }
+
+    // The open upvalue of a stack slot, shared by every closure capturing it
+    fn find_upvalue(&mut self, index: usize) -> UpvalRef {
+        let mut pos = self.open_upvalues.len();
+        for (i, upval) in self.open_upvalues.iter().enumerate().rev() {
+            match *upval.borrow() {
+                Upvalue::Open(open) if open == index => return Rc::clone(upval),
+                Upvalue::Open(open) if open < index => break,
+                _ => pos = i,
+            }
+        }
+        let upval = Rc::new(RefCell::new(Upvalue::Open(index)));
+        self.open_upvalues.insert(pos, Rc::clone(&upval));
+        upval
+    }
+
+    // Close the upvalues of the stack slots at `level` and above
+    fn close_upvalues(&mut self, level: usize) {
+        while let Some(upval) = self.open_upvalues.last() {
+            let index = match *upval.borrow() {
+                Upvalue::Open(index) => index,
+                Upvalue::Closed(_) => unreachable!("closed upvalue in the open list"),
+            };
+            if index < level {
+                break;
+            }
+            let val = self.stack[index].clone();
+            *upval.borrow_mut() = Upvalue::Closed(val);
+            self.open_upvalues.pop();
+        }
+    }
+}
+
+// Open upvalues refer to the stack of their thread. While another thread
+// runs, they hold the value of their variable like closed upvalues.
+impl Thread {
+    fn detach_upvalues(&mut self) {
+        for upval in &self.open_upvalues {
+            let index = match *upval.borrow() {
+                Upvalue::Open(index) => index,
+                Upvalue::Closed(_) => unreachable!("closed upvalue in the open list"),
+            };
+            self.detached.push(index);
+            *upval.borrow_mut() = Upvalue::Closed(self.stack[index].clone());
+        }
+    }
+
+    fn attach_upvalues(&mut self) {
+        for (upval, index) in self.open_upvalues.iter().zip(self.detached.drain(..)) {
+            if let Upvalue::Closed(val) = upval.replace(Upvalue::Open(index)) {
+                self.stack[index] = val;
+            }
+        }
+    }
+}
+
+// Only the values matter when comparing environments
+impl PartialEq for Thread {
+    fn eq(&self, other: &Self) -> bool {
+        self.stack == other.stack
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("stack", &self.stack.len())
+            .field("frames", &self.frames.len())
+            .finish()
+    }
+}
+
+/// State of a coroutine, as reported by `coroutine.status`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoStatus {
+    Suspended, // Not started yet, or stopped in a call to yield
+    Running,
+    Normal, // Resumed another coroutine
+    Dead,   // Its function returned or raised an error
+}
+
+impl CoStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoStatus::Suspended => "suspended",
+            CoStatus::Running => "running",
+            CoStatus::Normal => "normal",
+            CoStatus::Dead => "dead",
+        }
+    }
+}
+
+/// Lua thread: a function running on its own stack, which can suspend itself
+/// (yield) and be resumed later. The running thread is the thread of the
+/// environment, a coroutine holds its thread while it doesn't run.
+pub struct Coroutine {
+    thread: RefCell<Thread>,
+    status: Cell<CoStatus>,
+    error: RefCell<Option<String>>, // Error that stopped the coroutine
+}
+
+impl Coroutine {
+    /// Coroutine calling `func` when it is first resumed
+    pub fn new(func: LuaValue, env: &mut Env) -> Self {
+        // The function waits in the first slot of the stack
+        let thread = Thread {
+            stack: vec![func],
+            max_depth: env.thread().max_depth,
+            coroutine: true,
+            ..Thread::new()
+        };
+        Coroutine {
+            thread: RefCell::new(thread),
+            status: Cell::new(CoStatus::Suspended),
+            error: RefCell::new(None),
+        }
+    }
+
+    /// The main thread, whose stack is the thread of the environment
+    pub fn main() -> Self {
+        Coroutine {
+            thread: RefCell::new(Thread::new()),
+            status: Cell::new(CoStatus::Running),
+            error: RefCell::new(None),
+        }
+    }
+
+    pub fn status(&self) -> CoStatus {
+        self.status.get()
+    }
+
+    // Call `f` with every object referenced by the stack of the coroutine.
+    // False if the thread is being modified and can't be traversed.
+    pub(crate) fn trace(&self, f: &mut dyn FnMut(Child)) -> bool {
+        let Ok(thread) = self.thread.try_borrow() else {
+            return false;
+        };
+        thread.stack.iter().for_each(|val| f(Child::Value(val)));
+        for frame in &thread.frames {
+            f(Child::Closure(&frame.closure));
+            frame.varargs.iter().for_each(|val| f(Child::Value(val)));
+        }
+        thread.open_upvalues.iter().for_each(|upval| f(Child::Upvalue(upval)));
+        true
+    }
+
+    // Free the stack of an unreachable coroutine
+    pub(crate) fn take_thread(&self) -> Thread {
+        self.thread.take()
+    }
+}
+
+// Coroutines are compared and printed by reference
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Coroutine")
+            .field("status", &self.status.get())
+            .finish()
+    }
+}
+
+/// Call a compiled function from Rust and return its results
+pub fn call_closure(
+    closure: &Rc<Closure>,
+    args: Vec<LuaValue>,
+    env: &mut Env,
+) -> Result<Vec<LuaValue>, ASTExecError> {
+    // A call from the host, with no script running, starts a new script
+    if env.thread().frames.is_empty() && env.coroutines().len() == 1 {
+        env.meter().clear_interrupt();
+    }
+    let thread = env.thread();
+    thread.check_depth()?;
+    // Unlike the calls between Lua functions, this call runs the VM again
+    // on the native stack
+    let Some(_level) = Level::enter() else {
+        return Err(ASTExecError::new("C stack overflow"));
+    };
+    let base = thread.stack.len();
+    let depth = thread.frames.len();
+    let nargs = args.len();
+    thread.stack.extend(args);
+    thread.push_frame(Rc::clone(closure), base, nargs, base, None);
+    thread.frames.last_mut().unwrap().kind = CallKind::Rust;
+
+    thread.nested += 1;
+    let result = execute(env, depth, 0);
+    env.thread().nested -= 1;
+    result.map_err(|err| unwind(env, base, depth, err))
+}
+
+/// Resume the coroutine with `args`, returning the values it passes to yield,
+/// or the results of its function once it returns
+pub fn resume(
+    co: &Rc<Coroutine>,
+    args: Vec<LuaValue>,
+    env: &mut Env,
+) -> Result<Vec<LuaValue>, ASTExecError> {
+    match co.status.get() {
+        CoStatus::Suspended => (),
+        CoStatus::Dead => return Err(ASTExecError::new("cannot resume dead coroutine")),
+        _ => return Err(ASTExecError::new("cannot resume non-suspended coroutine")),
+    }
+    // The coroutine runs the VM again on the native stack
+    let Some(_level) = Level::enter() else {
+        return Err(ASTExecError::new("C stack overflow"));
+    };
+    let resumer = env.running_coroutine();
+    resumer.status.set(CoStatus::Normal);
+    co.status.set(CoStatus::Running);
+    env.coroutines().push(Rc::clone(co));
+
+    let mut thread = co.thread.take();
+    switch_thread(env, &mut thread);
+    let result = continue_coroutine(args, env);
+    let suspended = result.is_ok() && !env.thread().frames.is_empty();
+    if !suspended {
+        env.thread().stack.clear();
+    }
+    switch_thread(env, &mut thread);
+    co.thread.replace(thread);
+
+    env.coroutines().pop();
+    resumer.status.set(CoStatus::Running);
+    co.status.set(match suspended {
+        true => CoStatus::Suspended,
+        false => CoStatus::Dead,
+    });
+    if let Err(err) = &result {
+        co.error.replace(Some(err.to_string()));
+    }
+    result
+}
+
+// Start the function of the running coroutine, or continue it after the call
+// to yield that suspended it
+fn continue_coroutine(args: Vec<LuaValue>, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+    let thread = env.thread();
+    let top = match thread.resume_at.take() {
+        Some((ret, nresults, continuation)) => {
+            thread.finish_call(ret, args, nresults, continuation)
+        }
+        None => {
+            let func = thread.stack[0].clone();
+            let LuaVal::Function(closure) = &func.0 else {
+                // A built-in function can't yield, it runs at once
+                return func.call(args, env);
+            };
+            let nargs = args.len();
+            thread.stack.extend(args);
+            thread.push_frame(Rc::clone(closure), 1, nargs, 1, None);
+            thread.frames.last_mut().unwrap().kind = CallKind::Rust;
+            Ok(0)
+        }
+    };
+    top.and_then(|top| execute(env, 0, top))
+        .map_err(|err| unwind(env, 0, 0, err))
+}
+
+/// Kill a suspended coroutine, closing its pending to-be-closed variables.
+/// Returns the error of a `__close` metamethod, or the error that stopped
+/// the coroutine if it is already dead.
+pub fn close(co: &Rc<Coroutine>, env: &mut Env) -> Result<(), ASTExecError> {
+    match co.status.get() {
+        CoStatus::Suspended => (),
+        CoStatus::Dead => {
+            return match co.error.take() {
+                Some(err) => Err(ASTExecError::new(&err)),
+                None => Ok(()),
+            }
+        }
+        status => {
+            return Err(ASTExecError::new(&format!(
+                "cannot close a {} coroutine",
+                status.name()
+            )))
+        }
+    }
+    co.status.set(CoStatus::Dead);
+    let mut thread = co.thread.take();
+    switch_thread(env, &mut thread);
+    let result = close_variables(env, 0, None);
+    let closed = env.thread();
+    closed.close_upvalues(0);
+    closed.stack.clear();
+    closed.frames.clear();
+    switch_thread(env, &mut thread);
+    co.thread.replace(thread);
+    result
+}
+
+// Make `thread` the running thread of the environment, and store the previous one in it
+fn switch_thread(env: &mut Env, thread: &mut Thread) {
+    env.thread().detach_upvalues();
+    mem::swap(env.thread(), thread);
+    env.thread().attach_upvalues();
+}
+
+// Unwind the frames above `depth` after an error, whose values start at `base`.
+// Returns the error, or the error of a `__close` metamethod that replaced it.
+fn unwind(env: &mut Env, base: usize, depth: usize, err: ASTExecError) -> ASTExecError {
+    let err = match close_variables(env, base, Some(err)) {
+        Err(err) => err,
+        Ok(()) => unreachable!("closing variables after an error is an error"),
+    };
+    let thread = env.thread();
+    thread.close_upvalues(base);
+    thread.stack.truncate(base);
+    thread.frames.truncate(depth);
+    err
+}
+
+fn close_metamethod(val: &LuaValue) -> Option<LuaValue> {
+    match &val.0 {
+        LuaVal::LuaTable(table) => table.get_metamethod("__close"),
+        _ => None,
+    }
+}
+
+// Call the `__close` metamethods of the to-be-closed variables at `level` and
+// above, the last declared first, with the error that is closing them if any.
+// The error of a metamethod replaces the previous error, unless that one
+// comes from an execution limit.
+fn close_variables(
+    env: &mut Env,
+    level: usize,
+    mut error: Option<ASTExecError>,
+) -> Result<(), ASTExecError> {
+    loop {
+        let thread = env.thread();
+        let Some(index) = thread.tbc.pop_if(|index| *index >= level) else {
+            break;
+        };
+        let val = thread.stack[index].clone();
+        let err = match &error {
+            Some(err) => LuaValue::new(LuaVal::LuaString(LuaString::from(err.to_string()))),
+            None => nil(),
+        };
+        if let Some(close) = close_metamethod(&val) {
+            if let Err(err) = close.call(vec![val, err], env) {
+                if error.as_ref().is_none_or(|error| error.limit().is_none()) {
+                    error = Some(err);
+                }
+            }
+        }
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn nil() -> LuaValue {
+    LuaValue::new(LuaVal::LuaNil)
+}
+
+fn jump(pc: usize, offset: i32) -> usize {
+    (pc as isize + offset as isize) as usize
+}
+
+fn int_value(i: i64) -> LuaValue {
+    LuaValue::new(LuaVal::LuaInt(i))
+}
+
+// Integer of a numeric for loop register
+fn for_int(val: &LuaValue) -> Result<i64, ASTExecError> {
+    match &val.0 {
+        LuaVal::LuaInt(n) => Ok(*n),
+        _ => Err(ASTExecError::new("'for' state is not an integer")),
+    }
+}
+
+// Whether the numeric for loop continues with the value `i`
+fn for_continues(limit: &LuaValue, step: i64, i: i64) -> Result<bool, ASTExecError> {
+    if step > 0 {
+        limit.is_greater_or_equal(i)
+    } else {
+        limit.is_less_or_equal(i)
+    }
+}
+
+// Error unless `method`, found in a table under `name`, can be called
+fn check_method(method: &LuaValue, name: &LuaValue) -> Result<(), ASTExecError> {
+    if method.is_nil() {
+        Err(ASTExecError::new(&format!(
+            "could not find value '{name}' in table"
+        )))
+    } else if !method.is_callable() {
+        Err(ASTExecError::new(&format!(
+            "the value '{name}' is not a function"
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+// Call the function of an `__index` or `__newindex` metamethod for the
+// instruction at `pc`, storing its first result in `dest` if any. A Lua
+// function gets a frame, and true is returned so that the loop runs it; a
+// built-in function runs at once.
+fn call_metamethod(
+    env: &mut Env,
+    pc: usize,
+    handler: LuaValue,
+    args: Vec<LuaValue>,
+    dest: Option<usize>,
+    continuation: Continuation,
+) -> Result<bool, ASTExecError> {
+    let LuaVal::Function(closure) = &handler.0 else {
+        let results = handler.call(args, env)?;
+        if let Some(dest) = dest {
+            env.thread()
+                .finish_call(dest, results, Some(1), continuation)?;
+        }
+        return Ok(false);
+    };
+    let thread = env.thread();
+    thread.check_depth()?;
+    thread.frames.last_mut().unwrap().pc = pc;
+    let base = thread.stack.len();
+    let (ret, nresults) = match dest {
+        Some(dest) => (dest, Some(1)),
+        None => (base, Some(0)),
+    };
+    let nargs = args.len();
+    thread.stack.extend(args);
+    thread.push_frame(Rc::clone(closure), base, nargs, ret, nresults);
+    let frame = thread.frames.last_mut().unwrap();
+    frame.kind = CallKind::Rust;
+    frame.continuation = continuation;
+    Ok(true)
+}
+
+// Index a table for the instruction at `pc`, storing the value in `dest` as
+// the continuation requires. Returns true if a frame was pushed for the
+// `__index` metamethod, see call_metamethod.
+fn get_index(
+    env: &mut Env,
+    pc: usize,
+    table: &LuaValue,
+    key: LuaValue,
+    dest: usize,
+    continuation: Continuation,
+) -> Result<bool, ASTExecError> {
+    match table.lookup(&key)? {
+        Lookup::Value(val) => {
+            env.thread()
+                .finish_call(dest, vec![val], Some(1), continuation)?;
+            Ok(false)
+        }
+        Lookup::Handler(handler, table) => {
+            call_metamethod(env, pc, handler, vec![table, key], Some(dest), continuation)
+        }
+    }
+}
+
+// Assign to a field of a table for the instruction at `pc`, charging the
+// memory limit with its growth. Returns true if a frame was pushed for the
+// `__newindex` metamethod, see call_metamethod.
+fn set_index(
+    env: &mut Env,
+    pc: usize,
+    table: &LuaValue,
+    key: LuaValue,
+    val: LuaValue,
+) -> Result<bool, ASTExecError> {
+    let size = match &table.0 {
+        LuaVal::LuaTable(t) if limits::limits_memory(env) => Some(t.size_estimate()),
+        _ => None,
+    };
+    match table.set_lookup(&key, &val)? {
+        Lookup::Value(_) => {
+            if let (Some(size), LuaVal::LuaTable(t)) = (size, &table.0) {
+                limits::charge(env, t.size_estimate().saturating_sub(size))?;
+            }
+            Ok(false)
+        }
+        Lookup::Handler(handler, table) => {
+            let args = vec![table, key, val];
+            call_metamethod(env, pc, handler, args, None, Continuation::Results)
+        }
+    }
+}
+
+// Prepare the call of the value in `func_index` with the `nargs` values above
+// it. A call to pcall of a function that can run in this loop calls that
+// function instead, protected; a table with a `__call` metamethod is replaced
+// by the function, and becomes its first argument. Returns where the function
+// to call is, its number of arguments and the continuation of the call.
+fn prepare_call(
+    thread: &mut Thread,
+    mut func_index: usize,
+    mut nargs: usize,
+) -> (usize, usize, Continuation) {
+    let mut continuation = Continuation::Results;
+    if let LuaVal::PCall = thread.stack[func_index].0 {
+        if nargs > 0 && thread.runs_in_loop(&thread.stack[func_index + 1]) {
+            func_index += 1;
+            nargs -= 1;
+            continuation = Continuation::Protected;
+        }
+    }
+    if let Some(handler) = thread.stack[func_index].call_metamethod() {
+        thread.stack.insert(func_index, handler);
+        nargs += 1;
+    }
+    (func_index, nargs, continuation)
+}
+
+// Call a function that doesn't run in the VM loop (a built-in or Rust function)
+// with the arguments above it, returning the end of its results
+fn call_value(
+    env: &mut Env,
+    func: LuaValue,
+    func_index: usize,
+    nargs: usize,
+    nresults: Option<usize>,
+) -> Result<usize, ASTExecError> {
+    let thread = env.thread();
+    let args = thread.stack[func_index + 1..func_index + 1 + nargs].to_vec();
+    // The registers above the function are free. Clearing them leaves
+    // no stale values that the collector would take for roots.
+    thread.stack[func_index + 1..].fill(nil());
+    let results = func.call(args, env)?;
+    Ok(env.thread().place_results(func_index, results, nresults))
+}
+
+// Run the frames above `entry_depth` until the first of them returns, or until
+// the coroutine yields if they run a coroutine. `top` is the end of the values
+// produced by the last call or `...` with a variable number of results. An
+// error is caught by the innermost protected call above `entry_depth`, if any,
+// and the frames below it keep running.
+fn execute(
+    env: &mut Env,
+    entry_depth: usize,
+    mut top: usize,
+) -> Result<Vec<LuaValue>, ASTExecError> {
+    loop {
+        match run(env, entry_depth, top) {
+            Err(err) => top = catch(env, entry_depth, err)?,
+            result => return result,
+        }
+    }
+}
+
+// Unwind the frames down to the innermost protected frame above `entry_depth`
+// after an error, and store false and the error as the results of its call,
+// for the frame below it. Returns the end of the results, or the error if no
+// frame catches it.
+fn catch(env: &mut Env, entry_depth: usize, err: ASTExecError) -> Result<usize, ASTExecError> {
+    let frames = &env.thread().frames;
+    let Some(depth) = frames
+        .iter()
+        .rposition(|frame| frame.continuation == Continuation::Protected)
+        .filter(|depth| *depth > entry_depth)
+    else {
+        return Err(err);
+    };
+    let frame = &frames[depth];
+    let (base, ret, nresults) = (frame.base, frame.ret, frame.nresults);
+    let err = unwind(env, base, depth, err);
+    if !limits::catchable(env, &err) {
+        return Err(err);
+    }
+    let thread = env.thread();
+    let caller = thread.frames.last().unwrap();
+    let caller_top = caller.base + caller.closure.proto.max_stack as usize;
+    thread.stack.resize(caller_top, nil());
+    let results = vec![
+        LuaValue::new(LuaVal::LuaBool(false)),
+        LuaValue::new(LuaVal::LuaString(LuaString::from(err.to_string()))),
+    ];
+    Ok(thread.place_results(ret, results, nresults))
+}
+
+// The loop of execute, which returns at the first error
+fn run(env: &mut Env, entry_depth: usize, mut top: usize) -> Result<Vec<LuaValue>, ASTExecError> {
+    'frames: loop {
+        let frame = env.thread().frames.last().expect("no frame to run");
+        let closure = Rc::clone(&frame.closure);
+        let base = frame.base;
+        let mut pc = frame.pc;
+        let kind = frame.kind;
+        let proto = closure.proto.as_ref();
+        let constants = &proto.constants;
+        // Previous instruction run in the frame, for the line hook
+        let mut old_pc = pc.checked_sub(1);
+        if pc == 0 && env.hooks().mask.call {
+            let event = match kind {
+                CallKind::TailCall => HookEvent::TailCall,
+                _ => HookEvent::Call,
+            };
+            debug::fire(env, event)?;
+        }
+
+        // Value of a register or constant operand
+        let rk = |env: &mut Env, operand: RK| -> LuaValue {
+            if operand < RK_CONST {
+                env.thread().stack[base + operand as usize].clone()
+            } else {
+                constants[(operand - RK_CONST) as usize].clone()
+            }
+        };
+
+        loop {
+            let instr = proto.code[pc];
+            pc += 1;
+            if env.meter().step()? {
+                env.thread().frames.last_mut().unwrap().pc = pc;
+                debug::instruction(env, proto, pc - 1, old_pc)?;
+                old_pc = Some(pc - 1);
+            }
+            match instr {
+                Instr::Move(a, b) => {
+                    let stack = &mut env.thread().stack;
+                    stack[base + a as usize] = stack[base + b as usize].clone();
+                }
+                Instr::LoadK(a, b) => {
+                    env.thread().stack[base + a as usize] = constants[b as usize].clone();
+                }
+                Instr::LoadNil(a, b) => {
+                    let start = base + a as usize;
+                    env.thread().stack[start..start + b as usize].fill(nil());
+                }
+                Instr::LoadBool(a, b) => {
+                    env.thread().stack[base + a as usize] = LuaValue::new(LuaVal::LuaBool(b));
+                }
+                Instr::GetUpval(a, b) => {
+                    let thread = env.thread();
+                    let val = thread.get_upvalue(&closure.upvalues[b as usize]);
+                    thread.stack[base + a as usize] = val;
+                }
+                Instr::SetUpval(a, b) => {
+                    let thread = env.thread();
+                    let val = thread.stack[base + a as usize].clone();
+                    thread.set_upvalue(&closure.upvalues[b as usize], val);
+                }
+                Instr::GetTabUp(a, b, c) => {
+                    let table = env.thread().get_upvalue(&closure.upvalues[b as usize]);
+                    if !matches!(&table.0, LuaVal::LuaTable(_)) {
+                        return Err(ASTExecError::new(&format!(
+                            "attempt to index a {} value (upvalue '{}')",
+                            table.type_name(),
+                            proto.upvalues[b as usize].name
+                        )));
+                    }
+                    let key = rk(env, c);
+                    let dest = base + a as usize;
+                    if get_index(env, pc, &table, key, dest, Continuation::Results)? {
+                        continue 'frames;
+                    }
+                }
+                Instr::SetTabUp(a, b, c) => {
+                    let table = env.thread().get_upvalue(&closure.upvalues[a as usize]);
+                    if !matches!(&table.0, LuaVal::LuaTable(_)) {
+                        return Err(ASTExecError::new(&format!(
+                            "attempt to index a {} value (upvalue '{}')",
+                            table.type_name(),
+                            proto.upvalues[a as usize].name
+                        )));
+                    }
+                    let key = rk(env, b);
+                    let val = rk(env, c);
+                    if set_index(env, pc, &table, key, val)? {
+                        continue 'frames;
+                    }
+                }
+                Instr::GetTable(a, b, c) => {
+                    let table = env.thread().stack[base + b as usize].clone();
+                    if !matches!(&table.0, LuaVal::LuaTable(_)) {
+                        return Err(ASTExecError::new(&format!(
+                            "attempt to index a non-table value '{table}'"
+                        )));
+                    }
+                    let key = rk(env, c);
+                    let dest = base + a as usize;
+                    if get_index(env, pc, &table, key, dest, Continuation::Results)? {
+                        continue 'frames;
+                    }
+                }
+                Instr::SetTable(a, b, c) => {
+                    let table = env.thread().stack[base + a as usize].clone();
+                    if !matches!(&table.0, LuaVal::LuaTable(_)) {
+                        return Err(ASTExecError::new(&format!(
+                            "attempt to index a non-table value '{table}'"
+                        )));
+                    }
+                    let key = rk(env, b);
+                    let val = rk(env, c);
+                    if set_index(env, pc, &table, key, val)? {
+                        continue 'frames;
+                    }
+                }
+                Instr::GetMethod(a, b, c) => {
+                    let object = env.thread().stack[base + b as usize].clone();
+                    if !matches!(&object.0, LuaVal::LuaTable(_)) {
+                        return Err(ASTExecError::new(&format!(
+                            "table '{object}' doesn't exist"
+                        )));
+                    }
+                    let name = rk(env, c);
+                    let continuation = Continuation::Method(name.clone());
+                    if get_index(env, pc, &object, name, base + a as usize, continuation)? {
+                        continue 'frames;
+                    }
+                }
+                Instr::NewTable(a) => {
+                    let table = env.alloc_table(LuaTable::new());
+                    env.thread().stack[base + a as usize] = table;
+                    gc::check(env);
+                    limits::charge(env, mem::size_of::<LuaTable>())?;
+                }
+                Instr::SetList(a, b, c) => {
+                    let thread = env.thread();
+                    let first = base + a as usize + 1;
+                    let count = match b {
+                        0 => top - first,
+                        b => b as usize - 1,
+                    };
+                    let mut growth = 0;
+                    if let LuaVal::LuaTable(table) = &thread.stack[base + a as usize].0 {
+                        let size = table.size_estimate();
+                        for i in 0..count {
+                            table.insert_int(
+                                c as i64 + i as i64,
+                                thread.stack[first + i].clone(),
+                            );
+                        }
+                        growth = table.size_estimate().saturating_sub(size);
+                    }
+                    limits::charge(env, growth)?;
+                }
+                Instr::Unary(op, a, b) => {
+                    let val = env.thread().stack[base + b as usize].clone();
+                    env.thread().stack[base + a as usize] = LuaValue::unary_op(&op, val)?;
+                }
+                Instr::Binary(op, a, b, c) => {
+                    let left = rk(env, b);
+                    let right = rk(env, c);
+                    env.thread().stack[base + a as usize] = LuaValue::binary_op(&op, left, right)?;
+                    if let BinOp::Concat = op {
+                        let len = match &env.thread().stack[base + a as usize].0 {
+                            LuaVal::LuaString(s) => s.as_str().len(),
+                            _ => 0,
+                        };
+                        limits::charge(env, len)?;
+                    }
+                }
+                Instr::Jmp(offset) => pc = jump(pc, offset),
+                Instr::JmpIf(a, cond, offset) => {
+                    if env.thread().stack[base + a as usize].is_true() == cond {
+                        pc = jump(pc, offset);
+                    }
+                }
+                Instr::Call(a, b, c) => {
+                    let thread = env.thread();
+                    let ret = base + a as usize;
+                    let nargs = match b {
+                        0 => top - ret - 1,
+                        b => b as usize - 1,
+                    };
+                    let nresults = match c {
+                        0 => None,
+                        c => Some(c as usize - 1),
+                    };
+                    thread.frames.last_mut().unwrap().pc = pc;
+                    let (func_index, nargs, continuation) = prepare_call(thread, ret, nargs);
+                    let func = thread.stack[func_index].clone();
+                    if let LuaVal::Function(callee) = &func.0 {
+                        // Lua functions run in this loop, without growing the Rust stack
+                        thread.check_depth()?;
+                        thread.push_frame(Rc::clone(callee), func_index + 1, nargs, ret, nresults);
+                        let frame = thread.frames.last_mut().unwrap();
+                        if continuation == Continuation::Protected {
+                            frame.kind = CallKind::Rust;
+                        }
+                        frame.continuation = continuation;
+                        continue 'frames;
+                    }
+                    if let LuaVal::CoroutineYield = func.0 {
+                        return thread.yield_call(func_index, nargs, ret, nresults, continuation);
+                    }
+                    top = call_value(env, func, func_index, nargs, nresults)?;
+                }
+                Instr::TailCall(a, b) => {
+                    let thread = env.thread();
+                    let ret = base + a as usize;
+                    let nargs = match b {
+                        0 => top - ret - 1,
+                        b => b as usize - 1,
+                    };
+                    let (func_index, nargs, continuation) = prepare_call(thread, ret, nargs);
+                    let func = thread.stack[func_index].clone();
+                    match &func.0 {
+                        LuaVal::Function(callee) if continuation == Continuation::Results => {
+                            // The callee replaces this frame: the arguments move down to its
+                            // base, and the results go where this frame returns them
+                            thread.close_upvalues(base);
+                            let frame = thread.frames.pop().expect("no frame to return from");
+                            thread.stack.drain(base..func_index + 1);
+                            thread.push_frame(
+                                Rc::clone(callee),
+                                base,
+                                nargs,
+                                frame.ret,
+                                frame.nresults,
+                            );
+                            let callee = thread.frames.last_mut().unwrap();
+                            callee.kind = CallKind::TailCall;
+                            callee.continuation = frame.continuation;
+                            continue 'frames;
+                        }
+                        // A protected call runs in a frame above this one
+                        LuaVal::Function(callee) => {
+                            thread.frames.last_mut().unwrap().pc = pc;
+                            thread.check_depth()?;
+                            thread.push_frame(Rc::clone(callee), func_index + 1, nargs, ret, None);
+                            let frame = thread.frames.last_mut().unwrap();
+                            frame.kind = CallKind::Rust;
+                            frame.continuation = continuation;
+                            continue 'frames;
+                        }
+                        _ => (),
+                    }
+                    // The following return passes on the results
+                    thread.frames.last_mut().unwrap().pc = pc;
+                    if let LuaVal::CoroutineYield = func.0 {
+                        return thread.yield_call(func_index, nargs, ret, None, continuation);
+                    }
+                    top = call_value(env, func, func_index, nargs, None)?;
+                }
+                Instr::Return(a, b) => {
+                    if env.hooks().mask.ret {
+                        env.thread().frames.last_mut().unwrap().pc = pc;
+                        debug::fire(env, HookEvent::Return)?;
+                    }
+                    let thread = env.thread();
+                    let first = base + a as usize;
+                    let count = match b {
+                        0 => top - first,
+                        b => b as usize - 1,
+                    };
+                    let results = thread.stack[first..first + count].to_vec();
+                    if thread.tbc.last().is_some_and(|index| *index >= base) {
+                        close_variables(env, base, None)?;
+                    }
+                    let thread = env.thread();
+                    thread.close_upvalues(base);
+                    let frame = thread.frames.pop().expect("no frame to return from");
+                    if thread.frames.len() == entry_depth {
+                        thread.stack.truncate(base);
+                        return Ok(results);
+                    }
+                    // Back to the caller, with its registers
+                    let caller = thread.frames.last().unwrap();
+                    let caller_top = caller.base + caller.closure.proto.max_stack as usize;
+                    thread.stack.resize(caller_top, nil());
+                    top = thread.finish_call(
+                        frame.ret,
+                        results,
+                        frame.nresults,
+                        frame.continuation,
+                    )?;
+                    continue 'frames;
+                }
+                Instr::ForPrep(a, offset) => {
+                    let thread = env.thread();
+                    let a = base + a as usize;
+                    let initial = match &thread.stack[a].0 {
+                        LuaVal::LuaInt(n) => *n,
+                        _ => {
+                            return Err(ASTExecError::new(
+                                "Initial value in for loop must be an integer",
+                            ))
+                        }
+                    };
+                    let step = match &thread.stack[a + 2].0 {
+                        LuaVal::LuaInt(n) => *n,
+                        _ => {
+                            return Err(ASTExecError::new(
+                                "Step value in for loop must be an integer",
+                            ))
+                        }
+                    };
+                    if step == 0 {
+                        return Err(ASTExecError::new("Step value in for loop cannot be 0"));
+                    }
+                    if for_continues(&thread.stack[a + 1], step, initial)? {
+                        thread.stack[a + 3] = thread.stack[a].clone();
+                    } else {
+                        pc = jump(pc, offset);
+                    }
+                }
+                Instr::ForLoop(a, offset) => {
+                    let thread = env.thread();
+                    let a = base + a as usize;
+                    let step = for_int(&thread.stack[a + 2])?;
+                    // The loop also ends if the counter would overflow
+                    if let Some(i) = for_int(&thread.stack[a])?.checked_add(step) {
+                        if for_continues(&thread.stack[a + 1], step, i)? {
+                            let i = int_value(i);
+                            thread.stack[a] = i.clone();
+                            thread.stack[a + 3] = i;
+                            pc = jump(pc, offset);
+                        }
+                    }
+                }
+                Instr::TForPrep(a, offset) => {
+                    let iterator = &env.thread().stack[base + a as usize];
+                    if !iterator.is_callable() {
+                        return Err(ASTExecError::new(&format!(
+                            "attempt to call a {} value (for iterator)",
+                            iterator.type_name()
+                        )));
+                    }
+                    pc = jump(pc, offset);
+                }
+                Instr::TForCall(a, c) => {
+                    let thread = env.thread();
+                    let a = base + a as usize;
+                    let iterator = thread.stack[a].clone();
+                    let nresults = Some(c as usize);
+                    thread.frames.last_mut().unwrap().pc = pc;
+                    if let LuaVal::Function(callee) = &iterator.0 {
+                        // Call a copy of the iterator and its arguments placed
+                        // above the loop variables
+                        let func_index = a + 3 + c as usize;
+                        if thread.stack.len() < func_index + 3 {
+                            thread.stack.resize(func_index + 3, nil());
+                        }
+                        for i in 0..3 {
+                            thread.stack[func_index + i] = thread.stack[a + i].clone();
+                        }
+                        thread.push_frame(Rc::clone(callee), func_index + 1, 2, a + 3, nresults);
+                        continue 'frames;
+                    }
+                    let args = vec![
+                        thread.stack[a + 1].clone(),
+                        thread.stack[a + 2].clone(),
+                    ];
+                    let results = iterator.call(args, env)?;
+                    env.thread().place_results(a + 3, results, nresults);
+                }
+                Instr::TForLoop(a, offset) => {
+                    let thread = env.thread();
+                    let a = base + a as usize;
+                    if !thread.stack[a + 3].is_nil() {
+                        thread.stack[a + 2] = thread.stack[a + 3].clone();
+                        pc = jump(pc, offset);
+                    }
+                }
+                Instr::Closure(a, b) => {
+                    let thread = env.thread();
+                    let proto = Rc::clone(&proto.protos[b as usize]);
+                    let upvalues = proto
+                        .upvalues
+                        .iter()
+                        .map(|desc| match desc.in_stack {
+                            true => thread.find_upvalue(base + desc.index as usize),
+                            false => Rc::clone(&closure.upvalues[desc.index as usize]),
+                        })
+                        .collect();
+                    let closure = Rc::new(Closure { proto, upvalues });
+                    env.heap().track_closure(&closure);
+                    env.thread().stack[base + a as usize] =
+                        LuaValue::new(LuaVal::Function(closure));
+                    gc::check(env);
+                }
+                Instr::VarArg(a, b) => {
+                    let thread = env.thread();
+                    let varargs = thread.frames.last().unwrap().varargs.clone();
+                    let nresults = match b {
+                        0 => None,
+                        b => Some(b as usize - 1),
+                    };
+                    top = thread.place_results(base + a as usize, varargs, nresults);
+                }
+                Instr::Close(a) => {
+                    let level = base + a as usize;
+                    if env.thread().tbc.last().is_some_and(|index| *index >= level) {
+                        close_variables(env, level, None)?;
+                    }
+                    env.thread().close_upvalues(level);
+                }
+                Instr::Tbc(a, b) => {
+                    let thread = env.thread();
+                    let index = base + a as usize;
+                    // nil and false are ignored
+                    let val = &thread.stack[index];
+                    if val.is_true() {
+                        if close_metamethod(val).is_none() {
+                            return Err(ASTExecError::new(&format!(
+                                "variable '{}' got a non-closable value",
+                                constants[b as usize]
+                            )));
+                        }
+                        thread.tbc.push(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::*;
+    use crate::interpreter::{LuaString, LuaTable, RustFunction, TableKey};
+    use std::collections::HashMap;
+
+    // Helper functions
+    fn run(src: &str, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        src.parse::<AST>().unwrap().exec_with_return(env)
+    }
+    // Run the statement as a chunk and return the values of its return statement
+    fn exec(stat: &Statement, env: &mut Env) -> Result<Vec<LuaValue>, ASTExecError> {
+        let chunk = AST(Block {
+            statements: vec![stat.clone()],
+            return_stat: None,
+        });
+        chunk.exec_with_return(env)
+    }
+    fn var_exp(name: &str) -> Expression {
+        Expression::PrefixExp(
+            Box::new(PrefixExp::Var(Var::Name(name.to_string()))),
+            Span::default(),
+        )
+    }
+    fn integer_exp(n: i64) -> Expression {
+        Expression::Numeral(Numeral::Integer(n), Span::default())
+    }
+    fn lua_integer(n: i64) -> LuaValue {
+        int_value(n)
+    }
+    fn lua_integers(nums: &[i64]) -> Vec<LuaValue> {
+        nums.iter().map(|n| int_value(*n)).collect()
+    }
+    fn lua_float(n: f64) -> LuaValue {
+        LuaValue::new(LuaVal::LuaFloat(n))
+    }
+    fn lua_table(hmap: HashMap<TableKey, LuaValue>) -> Vec<LuaValue> {
+        vec![LuaValue::new(LuaVal::LuaTable(Rc::new(LuaTable::from(hmap))))]
+    }
+
+    #[test]
+    fn test_vm_loop_closures_capture_each_iteration() {
+        let src = "local fs = {}
+            for i = 1, 3 do
+                fs[i] = function() return i end
+            end
+            return fs[1](), fs[2](), fs[3]()";
+        assert_eq!(run(src, &mut Env::new()), Ok(lua_integers(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn test_vm_shared_upvalue() {
+        let src = "local function counter()
+                local n = 0
+                return function() n = n + 1 return n end, function() return n end
+            end
+            local inc, get = counter()
+            inc()
+            inc()
+            local n = get()
+            return n, inc(), get()";
+        assert_eq!(run(src, &mut Env::new()), Ok(lua_integers(&[2, 3, 3])));
+    }
+
+    #[test]
+    fn test_vm_varargs_and_multiple_results() {
+        let src = "local function pack(...) return {...} end
+            local function three() return 1, 2, 3 end
+            local t = pack(three())
+            local a, b = three()
+            return #t, a, b, three()";
+        assert_eq!(
+            run(src, &mut Env::new()),
+            Ok(lua_integers(&[3, 1, 2, 1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn test_vm_reentrant_calls() {
+        // The __index function runs on the VM while the VM calls a built-in function
+        let src = "local t = setmetatable({}, {__index = function(t, k) return k * 2 end})
+            local function get(k) return rawget(t, k) or t[k] end
+            return get(21)";
+        assert_eq!(run(src, &mut Env::new()), Ok(lua_integers(&[42])));
+    }
+
+    #[test]
+    fn test_vm_error_unwinds_stack() {
+        let mut env = Env::new();
+        let src = "local function f(n) if n == 0 then return 1 + {} end return f(n - 1) end
+            f(10)";
+        assert_eq!(
+            run(src, &mut env),
+            Err(ASTExecError::new(
+                "Cannot execute opration on values that are not numbers"
+            ))
+        );
+        assert!(env.thread().stack.is_empty());
+        assert!(env.thread().frames.is_empty());
+        assert!(env.thread().open_upvalues.is_empty());
+
+        // The environment can still be used
+        assert_eq!(run("return 1", &mut env), Ok(lua_integers(&[1])));
+    }
+
+    #[test]
+    fn test_vm_tail_calls_reuse_frame() {
+        let mut env = Env::new();
+        let depth = RustFunction::new(|_, env| {
+            let depth = env.thread().frames.len() as i64;
+            Ok(vec![lua_integer(depth)])
+        });
+        env.insert_global("depth".to_string(), LuaValue::new(LuaVal::RustFunction(depth)));
+        let src = "local function f(n)
+                if n == 0 then
+                    return depth()
+                end
+                return f(n - 1)
+            end
+            local function g(n)
+                if n == 0 then
+                    return depth()
+                end
+                local d = g(n - 1)
+                return d
+            end
+            return f(0), f(100), g(0), g(100)";
+        assert_eq!(run(src, &mut env), Ok(lua_integers(&[2, 2, 2, 102])));
+    }
+
+    #[test]
+    fn test_exec_stat_assign() {
+        // Test Statement exec method
+        let mut env = Env::new();
+
+        // varlist.len > explist.len
+        let a: i64 = 10;
+        let b: i64 = 20;
+        let varlist = vec![
+            Var::Name("a".to_string()),
+            Var::Name("b".to_string()),
+            Var::Name("a".to_string()),
+        ];
+        let explist = vec![
+            Expression::Numeral(Numeral::Integer(30), Span::default()),
+            Expression::Numeral(Numeral::Integer(20), Span::default()),
+            Expression::Numeral(Numeral::Integer(10), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+        assert_eq!(env.get_global("b").unwrap().0, LuaVal::LuaInt(b));
+
+        // varlist.len > explist.len
+        let a: i64 = 30;
+        let b: i64 = 20;
+        let varlist = vec![
+            Var::Name("a".to_string()),
+            Var::Name("b".to_string()),
+            Var::Name("c".to_string()),
+        ];
+        let explist = vec![
+            Expression::Numeral(Numeral::Integer(30), Span::default()),
+            Expression::Numeral(Numeral::Integer(20), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+        assert_eq!(env.get_global("b").unwrap().0, LuaVal::LuaInt(b));
+        assert_eq!(env.get_global("c").unwrap().0, LuaVal::LuaNil);
+
+        // varlist.len < explist.len
+        let a: i64 = 30;
+        let b: i64 = 20;
+        let varlist = vec![Var::Name("a".to_string()), Var::Name("b".to_string())];
+        let explist = vec![
+            Expression::Numeral(Numeral::Integer(30), Span::default()),
+            Expression::Numeral(Numeral::Integer(20), Span::default()),
+            Expression::Numeral(Numeral::Integer(10), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+        assert_eq!(env.get_global("b").unwrap().0, LuaVal::LuaInt(b));
+
+        // Local assignment
+        env = Env::new();
+        let a: i64 = 10;
+        let b: i64 = 20;
+        let varlist = vec![Var::Name("a".to_string()), Var::Name("b".to_string())];
+        let explist = vec![
+            Expression::Numeral(Numeral::Integer(10), Span::default()),
+            Expression::Numeral(Numeral::Integer(20), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, true), Span::default());
+        let chunk = AST(Block {
+            statements: vec![stat],
+            return_stat: Some(vec![var_exp("a"), var_exp("b")]),
+        });
+
+        // Local variables are read from their registers
+        assert_eq!(
+            chunk.exec_with_return(&mut env),
+            Ok(vec![lua_integer(a), lua_integer(b)])
+        );
+        assert_eq!(env.get_global("a"), None);
+    }
+
+    #[test]
+    fn test_exec_stat_reassign() {
+        // Test Statement exec method
+        let mut env = Env::new();
+
+        // reassignment in one line (should not know value of "a" in same line)
+        let a: i64 = 10;
+        let varlist = vec![
+            Var::Name("a".to_string()),
+            Var::Name("b".to_string()),
+            Var::Name("a".to_string()),
+        ];
+        let explist = vec![
+            Expression::Numeral(Numeral::Integer(30), Span::default()),
+            Expression::PrefixExp(
+                Box::new(PrefixExp::Var(Var::Name("a".to_string()))),
+                Span::default(),
+            ),
+            Expression::Numeral(Numeral::Integer(10), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+        assert_eq!(env.get_global("b").unwrap().0, LuaVal::LuaNil);
+
+        // string reassignment (in assignment for b, should know value of a as 10)
+        let a = "testA";
+        let b = "testB";
+        let varlist = vec![
+            Var::Name("a".to_string()),
+            Var::Name("b".to_string()),
+            Var::Name("a".to_string()),
+        ];
+        let explist = vec![
+            Expression::LiteralString(b.to_string(), Span::default()),
+            Expression::PrefixExp(
+                Box::new(PrefixExp::Var(Var::Name("a".to_string()))),
+                Span::default(),
+            ),
+            Expression::LiteralString(a.to_string(), Span::default()),
+        ];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(
+            env.get_global("a").unwrap().0,
+            LuaVal::LuaString(LuaString::from(a))
+        );
+        assert_eq!(env.get_global("b").unwrap().0, LuaVal::LuaInt(10_i64));
+    }
+
+    #[test]
+    fn test_exec_stat_table_assign() {
+        let mut env = Env::new();
+
+        let table = LuaValue::extract_first_return_val(lua_table(HashMap::new()));
+
+        env.insert_global(String::from("my_table"), table);
+
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Dot((
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    String::from("x"),
+                ))],
+                vec![Expression::LiteralString(
+                    String::from("just added!"),
+                    Span::default(),
+                )],
+                false,
+            ),
+            Span::default(),
+        );
+
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+
+        let expected_table = LuaValue::extract_first_return_val(lua_table(HashMap::from([(
+            TableKey::String(LuaString::from("x")),
+            LuaValue::new(LuaVal::LuaString(LuaString::from("just added!"))),
+        )])));
+        let actual_table = &env.get_global("my_table").unwrap().0;
+        assert_eq!(actual_table, &expected_table.0)
+    }
+
+    #[test]
+    fn test_exec_stat_table_reassign() {
+        let mut env = Env::new();
+
+        let table = LuaValue::extract_first_return_val(lua_table(HashMap::from([(
+            TableKey::String(LuaString::from("x")),
+            LuaValue::new(LuaVal::LuaInt(999)),
+        )])));
+
+        env.insert_global(String::from("my_table"), table);
+
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Dot((
+                    Box::new(PrefixExp::Var(Var::Name(String::from("my_table")))),
+                    String::from("x"),
+                ))],
+                vec![Expression::LiteralString(
+                    String::from("new value!"),
+                    Span::default(),
+                )],
+                false,
+            ),
+            Span::default(),
+        );
+
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+
+        let expected_table = LuaValue::extract_first_return_val(lua_table(HashMap::from([(
+            TableKey::String(LuaString::from("x")),
+            LuaValue::new(LuaVal::LuaString(LuaString::from("new value!"))),
+        )])));
+        let actual_table = &env.get_global("my_table").unwrap().0;
+        assert_eq!(actual_table, &expected_table.0)
+    }
+
+    #[test]
+    fn test_exec_stat_visibility() {
+        // Test Statement exec method
+        let mut env = Env::new();
+
+        // reassignment in one line (should not know value of "a" in same line)
+        let a: i64 = 10;
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Name("a".to_string())],
+                vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                false,
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                    true,
+                ),
+                Span::default(),
+            )],
+            return_stat: Some(vec![Expression::PrefixExp(
+                Box::new(PrefixExp::Var(Var::Name("a".to_string()))),
+                Span::default(),
+            )]),
+        };
+        let do_block = Statement::DoBlock(block, Span::default());
+        assert_eq!(
+            exec(&do_block, &mut env),
+            Ok(vec![LuaValue::new(LuaVal::LuaInt(20_i64))])
+        );
+        // Exited the environment so accessing global "a"
+        assert_eq!(env.get_global("a").unwrap().0, LuaVal::LuaInt(a));
+    }
+
+    #[test]
+    fn test_exec_stat_func_call() {
+        let mut env = Env::new();
+        let a: i64 = 10;
+        env.insert_global("a".to_string(), lua_integer(a));
+
+        // Simple function with side effect (a = 10.04)
+        let varlist = vec![Var::Name("a".to_string())];
+        let num: f64 = 10.04;
+        let exp_float = Expression::Numeral(Numeral::Float(num), Span::default());
+        let explist = vec![exp_float];
+        let stat = Statement::Assignment((varlist, explist, false), Span::default());
+        let return_stat = Some(vec![var_exp("a"), var_exp("a")]);
+        let block = Block {
+            statements: vec![stat],
+            return_stat,
+        };
+        let par_list = ParList(vec![], false);
+
+        // Define the function in the environment
+        let func_decl = Statement::FunctionDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(par_list, block, Span::default()),
+            ),
+            Span::default(),
+        );
+        exec(&func_decl, &mut env).unwrap();
+        // Function call statement
+        let func_prefix = PrefixExp::Var(Var::Name("f".to_string()));
+        let args = Args::ExpList(vec![]);
+        let func_call = FunctionCall::Standard((Box::new(func_prefix), args));
+        let func_call_stat = Statement::FunctionCall(func_call, Span::default());
+
+        // Return "a" defined inside function
+        // Returned values are thrown away for statement function call
+        assert_eq!(exec(&func_call_stat, &mut env), Ok(vec![]));
+        // After function call, return global "a" which is updated to float value
+        assert_eq!(env.get_global("a"), Some(lua_float(10.04)));
+    }
+
+    #[test]
+    fn test_exec_stat_if() {
+        let mut env = Env::new();
+        let condition = Expression::Numeral(Numeral::Integer(0), Span::default());
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+        let if_stat = Statement::If((condition, block, vec![], None), Span::default());
+        assert_eq!(exec(&if_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(10)));
+    }
+
+    #[test]
+    fn test_exec_stat_else() {
+        let mut env = Env::new();
+        let condition = Expression::False(Span::default());
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+        let else_block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+        let if_stat = Statement::If(
+            (condition, block, vec![], Some(else_block)),
+            Span::default(),
+        );
+        assert_eq!(exec(&if_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(20)));
+    }
+
+    #[test]
+    fn test_exec_stat_elseif() {
+        let mut env = Env::new();
+        let condition = Expression::False(Span::default());
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+        let else_ifs = vec![(
+            Expression::True(Span::default()),
+            Block {
+                statements: vec![Statement::Assignment(
+                    (
+                        vec![Var::Name("a".to_string())],
+                        vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                        false,
+                    ),
+                    Span::default(),
+                )],
+                return_stat: None,
+            },
+        )];
+        let else_block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(30), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+        let if_stat = Statement::If(
+            (condition, block, else_ifs, Some(else_block)),
+            Span::default(),
+        );
+        assert_eq!(exec(&if_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(20)));
+    }
+
+    #[test]
+    fn test_exec_stat_doblock() {
+        let mut env = Env::new();
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: Some(vec![Expression::PrefixExp(
+                Box::new(PrefixExp::Var(Var::Name("a".to_string()))),
+                Span::default(),
+            )]),
+        };
+        let do_block = Statement::DoBlock(block, Span::default());
+        assert_eq!(exec(&do_block, &mut env), Ok(vec![lua_integer(10)]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(10)));
+    }
+
+    #[test]
+    fn test_exec_stat_while_break() {
+        let mut env = Env::new();
+        let condition = Expression::True(Span::default());
+        let block = Block {
+            statements: vec![
+                Statement::Assignment(
+                    (
+                        vec![Var::Name("a".to_string())],
+                        vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                        false,
+                    ),
+                    Span::default(),
+                ),
+                Statement::Break(Span::default()),
+            ],
+            return_stat: None,
+        };
+        let while_stat = Statement::While((condition, block), Span::default());
+        assert_eq!(exec(&while_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(10)));
+        assert_eq!(env.get_global("a"), Some(lua_integer(10)));
+    }
+
+    #[test]
+    fn test_exec_stat_while_return() {
+        let mut env = Env::new();
+        let condition = Expression::True(Span::default());
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: Some(vec![var_exp("a")]),
+        };
+        let while_stat = Statement::While((condition, block), Span::default());
+        assert_eq!(exec(&while_stat, &mut env), Ok(vec![lua_integer(10)]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(10)));
+    }
+
+    #[test]
+    fn test_exec_stat_while() {
+        let mut env = Env::new();
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Name("a".to_string())],
+                vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                false,
+            ),
+            Span::default(),
+        );
+        exec(&stat, &mut env).unwrap();
+        let condition = Expression::BinaryOp(
+            (
+                Box::new(var_exp("a")),
+                BinOp::LessEq,
+                Box::new(Expression::Numeral(Numeral::Integer(15), Span::default())),
+            ),
+            Span::default(),
+        );
+        let block = Block {
+            statements: vec![Statement::Assignment(
+                (
+                    vec![Var::Name("a".to_string())],
+                    vec![Expression::BinaryOp(
+                        (
+                            Box::new(var_exp("a")),
+                            BinOp::Add,
+                            Box::new(Expression::Numeral(Numeral::Integer(2), Span::default())),
+                        ),
+                        Span::default(),
+                    )],
+                    false,
+                ),
+                Span::default(),
+            )],
+            return_stat: None,
+        };
+
+        let while_stat = Statement::While((condition, block), Span::default());
+        assert_eq!(exec(&while_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(16)));
+    }
+
+    #[test]
+    fn test_exec_stat_for_num() {
+        let mut env = Env::new();
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Name("a".to_string())],
+                vec![Expression::Numeral(Numeral::Integer(10), Span::default())],
+                false,
+            ),
+            Span::default(),
+        );
+        exec(&stat, &mut env).unwrap();
+        let for_stat = Statement::ForNum(
+            (
+                "i".to_string(),
+                Expression::Numeral(Numeral::Integer(1), Span::default()),
+                Expression::Numeral(Numeral::Integer(5), Span::default()),
+                None,
+                Block {
+                    statements: vec![Statement::Assignment(
+                        (
+                            vec![Var::Name("a".to_string())],
+                            vec![Expression::BinaryOp(
+                                (Box::new(var_exp("a")), BinOp::Add, Box::new(var_exp("i"))),
+                                Span::default(),
+                            )],
+                            false,
+                        ),
+                        Span::default(),
+                    )],
+                    return_stat: None,
+                },
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&for_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(25)));
+    }
+
+    #[test]
+    fn test_exec_stat_for_generic() {
+        let mut env = Env::new();
+        env.insert_global(
+            "t".to_string(),
+            LuaValue::extract_first_return_val(lua_table(HashMap::from([
+                (TableKey::Number(1), lua_integer(10)),
+                (TableKey::Number(2), lua_integer(20)),
+            ]))),
+        );
+        env.insert_global("a".to_string(), lua_integer(0));
+        // for i, v in ipairs(t) do a = a + i * v end
+        let for_stat = Statement::ForGeneric(
+            (
+                vec!["i".to_string(), "v".to_string()],
+                vec![Expression::PrefixExp(
+                    Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+                        Box::new(PrefixExp::Var(Var::Name("ipairs".to_string()))),
+                        Args::ExpList(vec![var_exp("t")]),
+                    )))),
+                    Span::default(),
+                )],
+                Block {
+                    statements: vec![Statement::Assignment(
+                        (
+                            vec![Var::Name("a".to_string())],
+                            vec![Expression::BinaryOp(
+                                (
+                                    Box::new(var_exp("a")),
+                                    BinOp::Add,
+                                    Box::new(Expression::BinaryOp(
+                                        (
+                                            Box::new(var_exp("i")),
+                                            BinOp::Mult,
+                                            Box::new(var_exp("v")),
+                                        ),
+                                        Span::default(),
+                                    )),
+                                ),
+                                Span::default(),
+                            )],
+                            false,
+                        ),
+                        Span::default(),
+                    )],
+                    return_stat: None,
+                },
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&for_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), Some(lua_integer(50)));
+        // Loop variables are local to the loop
+        assert_eq!(env.get_global("i"), None);
+    }
+
+    #[test]
+    fn test_exec_stat_for_num_break() {
+        let mut env = Env::new();
+        let for_stat = Statement::ForNum(
+            (
+                "i".to_string(),
+                Expression::Numeral(Numeral::Integer(2), Span::default()),
+                Expression::Numeral(Numeral::Integer(0), Span::default()),
+                Some(Expression::Numeral(Numeral::Integer(-1), Span::default())),
+                Block {
+                    statements: vec![
+                        Statement::Break(Span::default()),
+                        Statement::Assignment(
+                            (
+                                vec![Var::Name("a".to_string())],
+                                vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                                false,
+                            ),
+                            Span::default(),
+                        ),
+                    ],
+                    return_stat: None,
+                },
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&for_stat, &mut env), Ok(vec![]));
+        assert_eq!(env.get_global("a"), None);
+    }
+
+    #[test]
+    fn test_exec_stat_for_num_return() {
+        let mut env = Env::new();
+        let for_stat = Statement::ForNum(
+            (
+                "i".to_string(),
+                Expression::Numeral(Numeral::Integer(2), Span::default()),
+                Expression::Numeral(Numeral::Integer(0), Span::default()),
+                Some(Expression::Numeral(Numeral::Integer(-1), Span::default())),
+                Block {
+                    statements: vec![Statement::Assignment(
+                        (
+                            vec![Var::Name("a".to_string())],
+                            vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                            false,
+                        ),
+                        Span::default(),
+                    )],
+                    return_stat: Some(vec![var_exp("a")]),
+                },
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&for_stat, &mut env), Ok(vec![lua_integer(20)]));
+    }
+
+    #[test]
+    fn test_exec_stat_for_num_step_zero() {
+        let mut env = Env::new();
+        let for_stat = Statement::ForNum(
+            (
+                "i".to_string(),
+                Expression::Numeral(Numeral::Integer(2), Span::default()),
+                Expression::Numeral(Numeral::Integer(0), Span::default()),
+                Some(Expression::Numeral(Numeral::Integer(0), Span::default())),
+                Block {
+                    statements: vec![Statement::Assignment(
+                        (
+                            vec![Var::Name("a".to_string())],
+                            vec![Expression::Numeral(Numeral::Integer(20), Span::default())],
+                            false,
+                        ),
+                        Span::default(),
+                    )],
+                    return_stat: None,
+                },
+            ),
+            Span::default(),
+        );
+        assert_eq!(
+            exec(&for_stat, &mut env),
+            Err(ASTExecError::new("Step value in for loop cannot be 0"))
+        );
+    }
+
+    #[test]
+    fn test_exec_stat_func_decl() {
+        let mut env = Env::new();
+        let par_list = ParList(vec![String::from("a"), String::from("b")], false);
+        let block = Block {
+            statements: vec![],
+            return_stat: Some(vec![Expression::BinaryOp(
+                (Box::new(var_exp("a")), BinOp::Add, Box::new(var_exp("b"))),
+                Span::default(),
+            )]),
+        };
+        let func_decl = Statement::FunctionDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(par_list, block, Span::default()),
+            ),
+            Span::default(),
+        );
+        assert_eq!(exec(&func_decl, &mut env), Ok(vec![]));
+        let func = env.get_global("f").unwrap();
+        assert_eq!(func.type_name(), "function");
+        assert_eq!(
+            func.call(vec![lua_integer(1), lua_integer(2)], &mut env),
+            Ok(vec![lua_integer(3)])
+        );
+    }
+
+    #[test]
+    fn test_exec_stat_func_decl_local() {
+        let mut env = Env::new();
+        let par_list = ParList(vec![String::from("a"), String::from("b")], false);
+        let block = Block {
+            statements: vec![],
+            return_stat: Some(vec![Expression::BinaryOp(
+                (Box::new(var_exp("a")), BinOp::Add, Box::new(var_exp("b"))),
+                Span::default(),
+            )]),
+        };
+        let func_decl = Statement::LocalFuncDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(par_list, block, Span::default()),
+            ),
+            Span::default(),
+        );
+        let call = Expression::PrefixExp(
+            Box::new(PrefixExp::FunctionCall(FunctionCall::Standard((
+                Box::new(PrefixExp::Var(Var::Name("f".to_string()))),
+                Args::ExpList(vec![integer_exp(1), integer_exp(2)]),
+            )))),
+            Span::default(),
+        );
+        let chunk = AST(Block {
+            statements: vec![func_decl],
+            return_stat: Some(vec![call]),
+        });
+        assert_eq!(chunk.exec_with_return(&mut env), Ok(vec![lua_integer(3)]));
+        assert_eq!(env.get_global("f"), None);
+    }
+
+    #[test]
+    fn test_exec_func_capture_variables() {
+        let mut env = Env::new();
+
+        let stat = Statement::Assignment(
+            (
+                vec![Var::Name("c".to_string())],
+                vec![Expression::BinaryOp(
+                    (Box::new(var_exp("a")), BinOp::Add, Box::new(var_exp("b"))),
+                    Span::default(),
+                )],
+                false,
+            ),
+            Span::default(),
+        );
+        let func_decl = Statement::FunctionDecl(
+            (
+                "f".to_string(),
+                FuncBody::new(
+                    ParList(vec![], false),
+                    Block {
+                        statements: vec![stat],
+                        return_stat: Some(vec![var_exp("c")]),
+                    },
+                    Span::default(),
+                ),
+            ),
+            Span::default(),
+        );
+        let assignments = Statement::Assignment(
+            (
+                vec![Var::Name("a".to_string()), Var::Name("b".to_string())],
+                vec![integer_exp(10), integer_exp(20)],
+                true,
+            ),
+            Span::default(),
+        );
+        let doblock = Statement::DoBlock(
+            Block {
+                statements: vec![assignments, func_decl],
+                return_stat: None,
+            },
+            Span::default(),
+        );
+
+        // f(100) executes a = 30, b = 20, return test
+        assert_eq!(exec(&doblock, &mut env), Ok(vec![]));
+        // Local variables can't be accessed after exiting scope
+        assert_eq!(env.get_global("a"), None);
+        assert_eq!(env.get_global("b"), None);
+
+        // But function (closure) can access them
+        let args = Args::ExpList(vec![]);
+        let func_call =
+            FunctionCall::Standard((Box::new(PrefixExp::Var(Var::Name("f".to_string()))), args));
+        let exp = Expression::PrefixExp(
+            Box::new(PrefixExp::FunctionCall(func_call)),
+            Span::default(),
+        );
+        let chunk = AST(Block {
+            statements: vec![],
+            return_stat: Some(vec![exp]),
+        });
+        assert_eq!(chunk.exec_with_return(&mut env), Ok(vec![lua_integer(30)]));
+        // a + b = 10 + 20
+    }
+}
@@ -289,4 +289,239 @@ mod tests {
         let src = "assets/fibonacci_fixed.lua";
         test_interpreter(src, expected_output);
     }
+
+    #[test]
+    fn test_load_lua() {
+        let expected_output = "3
+nil broken: Could not parse file: unexpected symbol near 'x = = 1'
+20
+6
+6 nil
+7
+nil attempt to load a text chunk (mode is 'b')
+from file 42
+nil cannot open assets/missing.lua
+from file 42";
+        let src = "assets/load.lua";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_require_lua() {
+        let expected_output = "1 2
+3
+shapes
+true true
+virtual :preload:
+assets/modules/counter.lua
+nil no file 'a/missing.lua'
+\tno file 'b/missing.lua'";
+        let src = "assets/require.lua";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_require_cycle_lua() {
+        let src = "assets/require_cycle.lua";
+        let error_message = "module 'cycle_a' is required in a loop (cycle_a -> cycle_b -> cycle_a)";
+        test_interpreter_error(src, error_message);
+    }
+
+    #[test]
+    fn test_require_missing_lua() {
+        let src = "assets/require_missing.lua";
+        let error_message = "module 'missing' not found:
+\tno field package.preload['missing']
+\tno file 'assets/modules/missing.lua'";
+        test_interpreter_error(src, error_message);
+    }
+
+    #[test]
+    fn test_globals_lua() {
+        let expected_output = "1 true true\n2\ntrue\n140\n3\nred nil\n10\n14\n14 nil\n3\nnil nil";
+        let src = "assets/globals.lua";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_strict_lua() {
+        let src = "assets/strict.lua";
+        let error_message = "undefined global 'undeclared'";
+        test_interpreter_error(src, error_message);
+    }
+
+    #[test]
+    fn test_tailcall_lua() {
+        let src = "assets/tailcall.lua";
+        let expected_output = "1000000\ndone 0\n6 9";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_stack_overflow_lua() {
+        let src = "assets/stack_overflow.lua";
+        let expected_output = "false stack overflow
+false stack overflow
+false C stack overflow
+false boom
+true 7 12
+nil [string \"return not not not not not not not not n...\"]: Could not parse file: chunk has too many syntax levels";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_long_operator_chains() {
+        // Chains are not nesting: they are as long as the source makes them
+        for terms in [250, 20_000] {
+            let buffer = Rc::new(RefCell::new(vec![]));
+            let sum = vec!["1"; terms].join(" + ");
+            let concat = vec!["s"; terms].join(" .. ");
+            let logical = vec!["false"; terms].join(" or ");
+            let src = format!("local s = \"a\" print({sum}, #({concat}), {logical})");
+            run_ast(src.parse::<AST>().unwrap(), Rc::clone(&buffer)).unwrap();
+            assert_eq!(format!("{terms} {terms} false"), buffer.borrow().join("\n"));
+        }
+    }
+
+    #[test]
+    fn test_coroutine_lua() {
+        let src = "assets/coroutine.lua";
+        let expected_output = "1
+2
+3
+suspended
+start 1 2
+true 3
+suspended
+got 10
+true 20
+got 3 4
+true 7 done
+dead
+false cannot resume dead coroutine
+true 1
+true 2
+true 3
+false oops
+dead
+false oops
+false
+true true
+thread true
+false attempt to yield from outside a coroutine
+true 1
+1 2 2
+3 3
+running
+normal
+true 1
+closing nil
+true
+dead
+in block 5
+closed a
+closed on error fail
+false fail
+false cannot resume dead coroutine
+thread key
+in g
+closed before g returns
+1
+false stack overflow
+true x
+0 1 2 end
+false cannot resume dead coroutine
+false cannot resume non-suspended coroutine
+builtin body
+
+true 2
+true again
+true true 42 done false boom
+get x set y 20
+1 2 11 true 21
+false attempt to yield from outside a coroutine";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_gc_lua() {
+        let src = "assets/gc.lua";
+        let expected_output = "true\ntrue\nincremental\ntrue\ngenerational\nincremental";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_weak_lua() {
+        let src = "assets/weak.lua";
+        let expected_output =
+            "1 kept\nnil true strings are not collected\n1 true\n3 2 1\nphoenix 1\n1";
+        test_interpreter(src, expected_output);
+    }
+
+    #[test]
+    fn test_finalizers_run_on_close() {
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let mut env = environment::Env::new();
+        env.insert_global(
+            "print".to_string(),
+            LuaValue::new(moonrust::interpreter::LuaVal::TestPrint(Rc::clone(&buffer))),
+        );
+        let ast = "first = setmetatable({}, {__gc = function() print(\"first\") end})
+            second = setmetatable({}, {__gc = function() print(\"second\") end})"
+            .parse::<AST>()
+            .unwrap();
+        ast.exec(&mut env).unwrap();
+        assert!(buffer.borrow().is_empty());
+
+        drop(env);
+        assert_eq!("second\nfirst", buffer.borrow().join("\n"));
+    }
+
+    #[test]
+    fn test_collectgarbage_invalid_option() {
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let ast = "collectgarbage(\"stop\")".parse::<AST>().unwrap();
+        assert_eq!(
+            run_ast(ast, buffer),
+            Err(ASTExecError::new(
+                "bad argument #1 to 'collectgarbage' (invalid option 'stop')"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_setlocal_keeps_for_state() {
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let ast = "for i = 1, 3 do
+                print(debug.getlocal(1, 1), debug.setlocal(1, 1, 1.5))
+            end"
+        .parse::<AST>()
+        .unwrap();
+        run_ast(ast, Rc::clone(&buffer)).unwrap();
+        assert_eq!(
+            "(for state) nil\n(for state) nil\n(for state) nil",
+            buffer.borrow().join("\n")
+        );
+    }
+
+    #[test]
+    fn test_functions_outlive_ast() {
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let mut env = environment::Env::new();
+        env.insert_global(
+            "print".to_string(),
+            LuaValue::new(moonrust::interpreter::LuaVal::TestPrint(Rc::clone(&buffer))),
+        );
+
+        // The AST defining the function is dropped before the function is called
+        let ast = "function greet(name) print(\"hello \" .. name) end"
+            .parse::<AST>()
+            .unwrap();
+        ast.exec(&mut env).unwrap();
+        drop(ast);
+
+        let ast = "greet(\"moon\")".parse::<AST>().unwrap();
+        ast.exec(&mut env).unwrap();
+        assert_eq!("hello moon", buffer.borrow().join("\n"));
+    }
 }
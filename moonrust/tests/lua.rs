@@ -0,0 +1,283 @@
+#[cfg(test)]
+mod tests {
+    use moonrust::debug::{self, HookEvent, HookMask};
+    use moonrust::interpreter::environment::Env;
+    use moonrust::interpreter::ASTExecError;
+    use moonrust::limits::{Limit, Limits};
+    use moonrust::lua::LuaError;
+    use moonrust::sandbox::{Library, Sandbox};
+    use moonrust::Lua;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_exec_and_globals() {
+        let mut lua = Lua::new();
+        lua.load("x = 10\ny = \"hello\"").exec().unwrap();
+
+        let globals = lua.globals();
+        assert_eq!(globals.get::<i64>("x"), Ok(10));
+        assert_eq!(globals.get::<String>("y"), Ok(String::from("hello")));
+        assert_eq!(globals.get::<Option<i64>>("z"), Ok(None));
+        assert!(globals.contains("x"));
+        assert!(!globals.contains("z"));
+    }
+
+    #[test]
+    fn test_eval_expression() {
+        let mut lua = Lua::new();
+        assert_eq!(lua.load("1 + 2").eval::<i64>(), Ok(3));
+        assert_eq!(lua.load("10 / 4").eval::<f64>(), Ok(2.5));
+        assert_eq!(
+            lua.load("\"a\" .. 1").eval::<String>(),
+            Ok(String::from("a1"))
+        );
+        assert_eq!(lua.load("nil").eval::<Option<bool>>(), Ok(None));
+    }
+
+    #[test]
+    fn test_eval_statements() {
+        let mut lua = Lua::new();
+        let result = lua
+            .load("local a = 1\nlocal b = a + 1\nreturn a, b, \"c\"")
+            .eval::<(i64, i64, String)>();
+        assert_eq!(result, Ok((1, 2, String::from("c"))));
+
+        // Missing values are converted from nil
+        let result = lua.load("return 1").eval::<(i64, Option<i64>)>();
+        assert_eq!(result, Ok((1, None)));
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut lua = Lua::new();
+        lua.load(
+            "function add(a, b)
+                return a + b
+            end
+            function swap(a, b)
+                return b, a
+            end",
+        )
+        .exec()
+        .unwrap();
+
+        assert_eq!(lua.call_function::<_, i64>("add", (1, 2)), Ok(3));
+        assert_eq!(
+            lua.call_function::<_, (String, i64)>("swap", (7, "x")),
+            Ok((String::from("x"), 7))
+        );
+        assert_eq!(
+            lua.call_function::<_, ()>("missing", ()),
+            Err(LuaError::Runtime(String::from(
+                "attempt to call a nil value (global 'missing')"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_chunks_share_globals() {
+        let mut lua = Lua::new();
+        lua.globals().set("limit", 3);
+        lua.load("count = 0").exec().unwrap();
+        for _ in 0..5 {
+            lua.load("if count < limit then count = count + 1 end")
+                .exec()
+                .unwrap();
+        }
+        assert_eq!(lua.globals().get::<i64>("count"), Ok(3));
+
+        // Locals of one chunk are not visible to the next one
+        lua.load("local hidden = 1").exec().unwrap();
+        assert_eq!(lua.load("hidden").eval::<Option<i64>>(), Ok(None));
+    }
+
+    #[test]
+    fn test_functions_outlive_their_chunk() {
+        let mut lua = Lua::new();
+        lua.load(
+            "local step = 2
+            function counter()
+                local n = 0
+                return function()
+                    n = n + step
+                    return n
+                end
+            end",
+        )
+        .exec()
+        .unwrap();
+        lua.load("next_value = counter()").exec().unwrap();
+        assert_eq!(lua.load("next_value()").eval::<i64>(), Ok(2));
+        assert_eq!(lua.load("next_value()").eval::<i64>(), Ok(4));
+    }
+
+    #[test]
+    fn test_tables() {
+        let mut lua = Lua::new();
+        lua.globals().set("list", vec![1, 2, 3]);
+        assert_eq!(lua.load("list[1] + list[3]").eval::<i64>(), Ok(4));
+        assert_eq!(
+            lua.load("{\"a\", \"b\"}").eval::<Vec<String>>(),
+            Ok(vec![String::from("a"), String::from("b")])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut lua = Lua::new();
+        assert_eq!(
+            lua.load("x = 1 + {}").set_name("rules.lua").exec(),
+            Err(LuaError::Runtime(String::from(
+                "rules.lua: Cannot execute opration on values that are not numbers"
+            )))
+        );
+        assert!(matches!(
+            lua.load("x = = 1").set_name("rules.lua").exec(),
+            Err(LuaError::Syntax(msg)) if msg.starts_with("rules.lua: ")
+        ));
+        assert_eq!(
+            lua.load("\"abc\"").eval::<i64>(),
+            Err(LuaError::Conversion(String::from(
+                "cannot convert a string value to i64"
+            )))
+        );
+
+        // The state is still usable after an error
+        assert_eq!(lua.load("2 * 21").eval::<i64>(), Ok(42));
+    }
+
+    #[test]
+    fn test_register_module() {
+        let mut lua = Lua::new();
+        lua.register_module("mathx", |module| {
+            module.set("answer", 42);
+            module.set_function("add", |(a, b): (i64, i64)| Ok(a + b));
+            module.set_function("fail", |()| -> Result<(), LuaError> {
+                Err(LuaError::Runtime(String::from("mathx failed")))
+            });
+        })
+        .unwrap();
+
+        lua.load("local m = require(\"mathx\")\nsum = m.add(m.answer, 8)")
+            .exec()
+            .unwrap();
+        assert_eq!(lua.globals().get::<i64>("sum"), Ok(50));
+
+        // The module is loaded once and cached
+        assert_eq!(
+            lua.load("require(\"mathx\") == require(\"mathx\")")
+                .eval::<bool>(),
+            Ok(true)
+        );
+        assert_eq!(
+            lua.load("require(\"mathx\").fail()")
+                .set_name("main.lua")
+                .exec(),
+            Err(LuaError::Runtime(String::from("main.lua: mathx failed")))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut lua = Lua::new();
+        lua.set_limits(Limits {
+            memory: Some(1 << 20),
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+            ..Limits::default()
+        });
+        assert_eq!(
+            lua.load("local t = {} while true do t[#t + 1] = t end")
+                .exec(),
+            Err(LuaError::Limit(Limit::Memory))
+        );
+        assert_eq!(
+            lua.load("while true do pcall(function() while true do end end) end")
+                .exec(),
+            Err(LuaError::Limit(Limit::Time))
+        );
+
+        // The limits don't change the results of other errors
+        lua.set_limits(Limits {
+            instructions: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(
+            lua.load("error(\"oops\")").set_name("chunk").exec(),
+            Err(LuaError::Runtime(String::from("chunk: oops")))
+        );
+    }
+
+    #[test]
+    fn test_sandboxed_modules() {
+        let sandbox = Sandbox::new()
+            .library(Library::Base)
+            .library(Library::Package)
+            .read_only_globals();
+        let mut lua = Lua::sandboxed(&sandbox);
+        lua.register_module("greet", |module| module.set("greeting", "hello"))
+            .unwrap();
+        // Scripts can't replace the registered module
+        assert!(lua
+            .load("package.preload.greet = function() return {greeting = \"hi\"} end")
+            .exec()
+            .is_err());
+        assert_eq!(
+            lua.load("require(\"greet\").greeting").eval::<String>(),
+            Ok(String::from("hello"))
+        );
+        assert!(lua.load("require(\"os\")").exec().is_err());
+        lua.globals().set("limit", 3);
+        assert_eq!(lua.load("limit").eval::<i64>(), Ok(3));
+
+        // Without the package library, no script could require the module
+        let mut lua = Lua::sandboxed(&Sandbox::new().library(Library::Base));
+        assert_eq!(
+            lua.register_module("greet", |module| module.set("greeting", "hello")),
+            Err(LuaError::Runtime(String::from(
+                "cannot register module 'greet': 'package.preload' is not a table"
+            )))
+        );
+    }
+    #[test]
+    fn test_hooks() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&calls);
+        let mut lua = Lua::new();
+        lua.set_hook(
+            Box::new(move |env: &mut Env, _: HookEvent| {
+                let info = debug::frame_info(env, 0).unwrap();
+                seen.borrow_mut().push((info.short_src, info.line_defined));
+                Ok(())
+            }),
+            HookMask::parse("c", 0),
+        );
+        lua.load("local function f() end\nf()")
+            .set_name("main.lua")
+            .exec()
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            [
+                (String::from("main.lua"), Some(0)),
+                (String::from("main.lua"), Some(1))
+            ]
+        );
+
+        // A hook can stop the script with an error
+        lua.set_hook(
+            Box::new(|_: &mut Env, event: HookEvent| match event {
+                HookEvent::Line(3) => Err(ASTExecError::new("line 3 reached")),
+                _ => Ok(()),
+            }),
+            HookMask::parse("l", 0),
+        );
+        assert_eq!(
+            lua.load("x = 1\nx = 2\nx = 3").set_name("chunk").exec(),
+            Err(LuaError::Runtime(String::from("chunk: line 3 reached")))
+        );
+        lua.remove_hook();
+        assert_eq!(lua.load("x").eval::<i64>(), Ok(2));
+    }
+}